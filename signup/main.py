@@ -361,7 +361,12 @@ def add_repo(repo, context):
                 "content_type": "json",
                 "secret": app.config['BOT_NOTICE_SECRET']
             },
-            "events": [ "issue_comment", "pull_request", "team_add" ]
+            "events": [
+                "issue_comment",
+                "pull_request",
+                "pull_request_review",
+                "team_add",
+            ]
         }
     )
     github.post(
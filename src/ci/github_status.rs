@@ -29,19 +29,19 @@ pub struct Repo {
 pub struct Worker {
     listen: String,
     pipelines: Box<PipelinesConfig>,
-    secret: String,
+    secrets: Vec<String>,
 }
 
 impl Worker {
     pub fn new(
         listen: String,
-        secret: String,
+        secrets: Vec<String>,
         pipelines: Box<PipelinesConfig>,
     ) -> Worker {
         Worker {
             listen: listen,
             pipelines: pipelines,
-            secret: secret,
+            secrets: secrets,
         }
     }}
 
@@ -128,7 +128,7 @@ impl Worker {
         mut res: Response,
         send_event: &Sender<ci::Event>
     ) {
-        let head = github_headers::parse(&mut req, self.secret.as_bytes());
+        let head = github_headers::parse(&mut req, &self.secrets);
         let (x_github_event, body) = match head {
             Some(head) => head,
             None => return,
@@ -0,0 +1,225 @@
+// This file is released under the same terms as Rust itself.
+
+use ci::{self, CiId};
+use crossbeam;
+use hyper::Url;
+use hyper::buffer::BufReader;
+use hyper::header::Headers;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use pipeline;
+use serde_json::{from_slice as json_from_slice};
+use std::io::BufWriter;
+use std::sync::mpsc::{Sender, Receiver};
+use util::gitlab_api;
+use util::gitlab_headers;
+
+pub trait PipelinesConfig: Send + Sync + 'static {
+    fn repo_by_id(&self, CiId) -> Option<Repo>;
+    fn ids_by_repo(&self, &Repo) -> Vec<CiId>;
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    pub owner: String,
+    pub repo: String,
+}
+
+pub struct Worker {
+    listen: String,
+    pipelines: Box<PipelinesConfig>,
+    secrets: Vec<String>,
+}
+
+impl Worker {
+    pub fn new(
+        listen: String,
+        secrets: Vec<String>,
+        pipelines: Box<PipelinesConfig>,
+    ) -> Worker {
+        Worker {
+            listen: listen,
+            pipelines: pipelines,
+            secrets: secrets,
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct PipelineHookDesc {
+    object_attributes: PipelineDesc,
+    project: ProjectDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct PipelineDesc {
+    id: u64,
+    sha: String,
+    status: String,
+}
+#[derive(Deserialize, Serialize)]
+struct ProjectDesc {
+    path_with_namespace: String,
+    web_url: String,
+}
+
+impl pipeline::Worker<
+    ci::Event,
+    ci::Message,
+> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<ci::Message>,
+        mut send_event: Sender<ci::Event>
+    ) {
+        crossbeam::scope(|scope| {
+            let s2 = &*self;
+            let send_event_2 = send_event.clone();
+            scope.spawn(move || {
+                s2.run_webhook(send_event_2);
+            });
+            loop {
+                s2.handle_message(
+                    recv_msg.recv().expect("Pipeline went away"),
+                    &mut send_event,
+                );
+            }
+        })
+    }
+}
+
+impl Worker {
+    fn run_webhook(
+        &self,
+        send_event: Sender<ci::Event>,
+    ) {
+        let mut listener = HttpListener::new(&self.listen[..])
+            .expect("webhook");
+        while let Ok(mut stream) = listener.accept() {
+            let addr = stream.peer_addr()
+                .expect("webhook client address");
+            let mut stream_clone = stream.clone();
+            let mut buf_read = BufReader::new(
+                &mut stream_clone as &mut NetworkStream
+            );
+            let mut buf_write = BufWriter::new(&mut stream);
+            let req = match Request::new(&mut buf_read, addr) {
+                Ok(req) => req,
+                Err(e) => {
+                    warn!("Invalid webhook HTTP: {:?}", e);
+                    continue;
+                }
+            };
+            let mut head = Headers::new();
+            let res = Response::new(&mut buf_write, &mut head);
+            self.handle_webhook(req, res, &send_event);
+        }
+    }
+
+    fn handle_webhook(
+        &self,
+        mut req: Request,
+        mut res: Response,
+        send_event: &Sender<ci::Event>
+    ) {
+        let head = gitlab_headers::parse(&mut req, &self.secrets);
+        let (x_gitlab_event, body) = match head {
+            Some(head) => head,
+            None => return,
+        };
+        match &x_gitlab_event[..] {
+            b"Pipeline Hook" => {
+                let desc = json_from_slice::<PipelineHookDesc>(&body);
+                if let Ok(desc) = desc {
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to GitLab pipeline: {:?}",
+                            e,
+                        );
+                    }
+                    self.handle_pipeline(send_event, desc);
+                } else {
+                    warn!("Got invalid pipeline");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad pipeline: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
+            e => {
+                *res.status_mut() = StatusCode::BadRequest;
+                if let Err(e) = res.send(&[]) {
+                    warn!(
+                        "Failed to send response to GitLab unknown: {:?}",
+                        e,
+                    );
+                }
+                warn!(
+                    "Got Unknown Event {}",
+                    String::from_utf8_lossy(&e)
+                );
+            }
+        }
+    }
+
+    fn handle_pipeline(
+        &self,
+        send_event: &Sender<ci::Event>,
+        desc: PipelineHookDesc,
+    ) {
+        let path = &desc.project.path_with_namespace;
+        let repo = match gitlab_api::split_project_path(path) {
+            Some((owner, repo)) => Repo {
+                owner: owner.to_owned(),
+                repo: repo.to_owned(),
+            },
+            None => {
+                warn!("Got bad project path {}", path);
+                return;
+            }
+        };
+        let ids = self.pipelines.ids_by_repo(&repo);
+        if ids.is_empty() {
+            warn!("Got pipeline for unknown repo: {:?}", repo);
+        }
+        let attrs = &desc.object_attributes;
+        let url = Url::parse(&format!(
+            "{}/-/pipelines/{}",
+            desc.project.web_url,
+            attrs.id,
+        )).ok();
+        for id in ids {
+            let commit = attrs.sha.clone().into();
+            let event = match &attrs.status[..] {
+                "pending" |
+                "running" => ci::Event::BuildStarted(id, commit, url.clone()),
+                "failed" |
+                "canceled" => ci::Event::BuildFailed(id, commit, url.clone()),
+                "success" =>
+                    ci::Event::BuildSucceeded(id, commit, url.clone()),
+                _ => {
+                    info!("Ignoring pipeline status: {}", attrs.status);
+                    return;
+                },
+            };
+            send_event.send(event).expect("pipeline");
+        }
+    }
+
+    fn handle_message(
+        &self,
+        msg: ci::Message,
+        _: &mut Sender<ci::Event>,
+    ) {
+        match msg {
+            // The pipeline is started by GitLab itself on push.
+            // There's nothing to do.
+            ci::Message::StartBuild(_, _) => {}
+        }
+    }
+}
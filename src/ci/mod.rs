@@ -1,6 +1,7 @@
 // This file is released under the same terms as Rust itself.
 
 pub mod github_status;
+pub mod gitlab_pipeline;
 pub mod jenkins;
 
 use config::PipelinesConfig;
@@ -4,19 +4,23 @@ pub mod toml;
 pub mod twelvef;
 
 use ci::CiId;
-use db::DbBox;
+use db;
 use pipeline::{PipelineId, WorkerManager};
+use std::time::Duration;
+use ui::RemoteFormat;
 
 pub trait WorkerBuilder {
     fn start(
         self
-    ) -> (WorkerManager, DbBox);
+    ) -> (WorkerManager, db::Builder);
 }
 
-pub trait PipelinesConfig {
-    fn by_pipeline_id(&self, PipelineId) -> PipelineConfig;
+pub trait PipelinesConfig: Send + Sync {
+    /// `None` if there is no such pipeline.
+    fn by_pipeline_id(&self, PipelineId) -> Option<PipelineConfig>;
     fn by_ci_id(&self, CiId) -> PipelineConfig;
     fn len(&self) -> usize;
+    fn ids(&self) -> Vec<PipelineId>;
 }
 
 #[derive(Clone)]
@@ -25,4 +29,15 @@ pub struct PipelineConfig {
 	pub ci: Vec<(CiId, usize)>,
 	pub ui: usize,
 	pub vcs: usize,
+	/// The most queue entries that will be merged and tested at once.
+	pub batch_size: usize,
+	/// The most batches that will be tested at once, each one stacked
+	/// on top of the one ahead of it.
+	pub depth: usize,
+	/// How long the CI gets to report back before the build fails.
+	pub ci_timeout: Option<Duration>,
+	/// How many times a failed build is tried again before it is reported.
+	pub auto_retry: u32,
+	/// How the front-end names its pull requests' heads.
+	pub remote_format: RemoteFormat,
 }
\ No newline at end of file
@@ -1,8 +1,10 @@
 // This file is released under the same terms as Rust itself.
 
-use ci::{self, CiId, github_status, jenkins};
+use ci::{self, CiId, github_status, gitlab_pipeline, jenkins};
 use config::{PipelineConfig, PipelinesConfig, WorkerBuilder};
-use db::{self, DbBox};
+use db;
+use hooks;
+use hyper::Url;
 use pipeline::{PipelineId, WorkerManager};
 use pipeline::WorkerThread;
 use std::any::Any;
@@ -12,11 +14,20 @@ use std::error::Error;
 use std::fmt::{self, Debug, Display};
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use timer;
 use toml;
-use ui::{self, github};
+use ui::{self, api, bitbucket, gitea, github, gitlab};
+use util::bitbucket_api;
+use util::gitea_api;
+use util::github_auth::Credentials;
+use util::gitlab_api;
 use vcs::{self, git};
+use vcs::gitea as gitea_git;
 use vcs::github as github_git;
+use vcs::gitlab as gitlab_git;
 use view;
 
 pub struct GithubBuilder {
@@ -36,7 +47,12 @@ pub struct GithubBuilder {
         view::Event,
         view::Message,
     >>,
-    db: DbBox,
+    hooks: Option<WorkerThread<
+        hooks::Event,
+        hooks::Message,
+    >>,
+    outbox: Option<hooks::Outbox>,
+    db: db::Builder,
     pipelines: StaticPipelinesConfig,
 }
 
@@ -44,26 +60,13 @@ pub struct GithubBuilder {
 enum CiType {
     Jenkins,
     GithubStatus,
+    GitlabPipeline,
 }
 
 impl GithubBuilder {
     pub fn build_from_file<P: AsRef<Path>>(path: P)
             -> Result<Self, GithubBuilderError> {
-        let path = path.as_ref();
-        let mut config_file = match File::open(&*path) {
-            Ok(config_file) => config_file,
-            Err(e) => return Err(GithubBuilderError::OpenFile(e)),
-        };
-        let mut config_string = String::new();
-        match config_file.read_to_string(&mut config_string) {
-            Ok(_) => {},
-            Err(e) => return Err(GithubBuilderError::ReadFile(e)),
-        }
-        let config_main = match toml::Parser::new(&config_string).parse() {
-            Some(config) => config,
-            None => return Err(GithubBuilderError::Parse),
-        };
-        Self::build_from_toml(config_main)
+        Self::build_from_toml(try!(read_file(path)))
     }
     pub fn build_from_toml(config_main: toml::Table)
             -> Result<Self, GithubBuilderError> {
@@ -77,7 +80,11 @@ impl GithubBuilder {
                 Some(config_projects) => config_projects,
                 None => return Err(GithubBuilderError::NoProjects),
             };
-        if config.lookup("github").is_none() {
+        if config.lookup("github").is_none() &&
+                config.lookup("gitlab").is_none() &&
+                config.lookup("gitea").is_none() &&
+                config.lookup("bitbucket").is_none() &&
+                config.lookup("api").is_none() {
             return Err(GithubBuilderError::NoConfigGithub);
         }
         let mut github_projects =
@@ -90,8 +97,24 @@ impl GithubBuilder {
             StaticGitPipelinesConfig::new();
         let mut github_git_pipelines =
             StaticGithubGitPipelinesConfig::new();
+        let mut gitlab_projects =
+            StaticGitlabProjectsConfig::new();
+        let mut gitlab_pipeline_pipelines =
+            StaticGitlabPipelinePipelinesConfig::new();
+        let mut gitlab_git_pipelines =
+            StaticGitlabGitPipelinesConfig::new();
+        let mut gitea_projects =
+            StaticGiteaProjectsConfig::new();
+        let mut gitea_git_pipelines =
+            StaticGiteaGitPipelinesConfig::new();
+        let mut bitbucket_projects =
+            StaticBitbucketProjectsConfig::new();
+        let mut api_projects =
+            StaticApiProjectsConfig::new();
         let mut view_pipelines =
             StaticViewPipelinesConfig::new();
+        let mut hooks_pipelines =
+            StaticHooksPipelinesConfig::new();
         let mut pipeline_id = PipelineId(0);
         let mut ci_id = CiId(0);
         let mut ci_to_pipeline: HashMap<CiId, (CiType, PipelineId)> = HashMap::new();
@@ -153,6 +176,74 @@ impl GithubBuilder {
                 Ok(()) | Err(SetupError::NotFoundConfig) => {},
                 Err(e) => return Err(GithubBuilderError::GithubGitProject(e)),
             }
+            match gitlab_projects.add_project(
+                name,
+                config,
+                def,
+                pipeline_id
+            ) {
+                Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                Err(e) => return Err(GithubBuilderError::GitlabProject(e)),
+            }
+            match gitlab_pipeline_pipelines.add_pipeline(
+                name,
+                config,
+                def,
+                pipeline_id,
+                &mut ci_id,
+                &mut ci_to_pipeline,
+            ) {
+                Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                Err(e) =>
+                    return Err(GithubBuilderError::GitlabPipelineProject(e)),
+            }
+            match gitlab_git_pipelines.add_pipeline(
+                name,
+                config,
+                def,
+                pipeline_id,
+                false
+            ) {
+                Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                Err(e) => return Err(GithubBuilderError::GitlabGitProject(e)),
+            }
+            match gitea_projects.add_project(
+                name,
+                config,
+                def,
+                pipeline_id
+            ) {
+                Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                Err(e) => return Err(GithubBuilderError::GiteaProject(e)),
+            }
+            match gitea_git_pipelines.add_pipeline(
+                name,
+                config,
+                def,
+                pipeline_id,
+                false
+            ) {
+                Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                Err(e) => return Err(GithubBuilderError::GiteaGitProject(e)),
+            }
+            match bitbucket_projects.add_project(
+                name,
+                config,
+                def,
+                pipeline_id
+            ) {
+                Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                Err(e) =>
+                    return Err(GithubBuilderError::BitbucketProject(e)),
+            }
+            match api_projects.add_project(
+                name,
+                def,
+                pipeline_id
+            ) {
+                Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                Err(e) => return Err(GithubBuilderError::ApiProject(e)),
+            }
             match view_pipelines.add_pipeline(
                 name,
                 config,
@@ -162,6 +253,7 @@ impl GithubBuilder {
                 Ok(()) | Err(SetupError::NotFoundConfig) => {},
                 Err(e) => return Err(GithubBuilderError::ViewProject(e)),
             }
+            hooks_pipelines.add_pipeline(name, pipeline_id, false);
             pipeline_id.0 += 1;
             if def.lookup("try").is_some() {
                 match github_status_pipelines.add_pipeline(
@@ -210,6 +302,41 @@ impl GithubBuilder {
                     Err(e) =>
                         return Err(GithubBuilderError::GithubGitProject(e)),
                 }
+                match gitlab_pipeline_pipelines.add_pipeline(
+                    name,
+                    config,
+                    def,
+                    pipeline_id,
+                    &mut ci_id,
+                    &mut ci_to_pipeline,
+                ) {
+                    Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                    Err(e) => return Err(
+                        GithubBuilderError::GitlabPipelineProject(e)
+                    ),
+                }
+                match gitlab_git_pipelines.add_pipeline(
+                    name,
+                    config,
+                    def,
+                    pipeline_id,
+                    true
+                ) {
+                    Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                    Err(e) =>
+                        return Err(GithubBuilderError::GitlabGitProject(e)),
+                }
+                match gitea_git_pipelines.add_pipeline(
+                    name,
+                    config,
+                    def,
+                    pipeline_id,
+                    true
+                ) {
+                    Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                    Err(e) =>
+                        return Err(GithubBuilderError::GiteaGitProject(e)),
+                }
                 match view_pipelines.add_pipeline(
                     name,
                     config,
@@ -220,14 +347,28 @@ impl GithubBuilder {
                     Err(e) =>
                         return Err(GithubBuilderError::ViewProject(e)),
                 }
+                hooks_pipelines.add_pipeline(name, pipeline_id, true);
                 pipeline_id.0 += 1;
             }
         }
-        let github = match setup_github(config, github_projects) {
-            Ok(github) => Some(WorkerThread::start(github)),
+        // A setup without GitHub projects has no GitHub credentials at all.
+        let credentials = match setup_github_credentials(config) {
+            Ok(credentials) => Some(Arc::new(credentials)),
             Err(SetupError::NotFoundConfig) => None,
             Err(e) => return Err(GithubBuilderError::Github(e)),
         };
+        let github = match credentials {
+            Some(ref credentials) => match setup_github(
+                config,
+                github_projects,
+                credentials.clone(),
+            ) {
+                Ok(github) => Some(WorkerThread::start(github)),
+                Err(SetupError::NotFoundConfig) => None,
+                Err(e) => return Err(GithubBuilderError::Github(e)),
+            },
+            None => None,
+        };
         let github_status =
             match setup_github_status(config, github_status_pipelines) {
                 Ok(github_status) => Some(WorkerThread::start(github_status)),
@@ -244,10 +385,93 @@ impl GithubBuilder {
             Err(SetupError::NotFoundConfig) => None,
             Err(e) => return Err(GithubBuilderError::Git(e)),
         };
-        let github_git = match setup_github_git(config, github_git_pipelines) {
-            Ok(github_git) => Some(WorkerThread::start(github_git)),
+        let github_git = match credentials {
+            Some(credentials) => match setup_github_git(
+                config,
+                github_git_pipelines,
+                credentials,
+            ) {
+                Ok(github_git) => Some(WorkerThread::start(github_git)),
+                Err(SetupError::NotFoundConfig) => None,
+                Err(e) => return Err(GithubBuilderError::GithubGit(e)),
+            },
+            None => None,
+        };
+        let gitlab_client = match setup_gitlab_client(config) {
+            Ok(client) => Some(Arc::new(client)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::Gitlab(e)),
+        };
+        let gitlab = match gitlab_client {
+            Some(ref client) => match setup_gitlab(
+                config,
+                gitlab_projects,
+                client.clone(),
+            ) {
+                Ok(gitlab) => Some(WorkerThread::start(gitlab)),
+                Err(SetupError::NotFoundConfig) => None,
+                Err(e) => return Err(GithubBuilderError::Gitlab(e)),
+            },
+            None => None,
+        };
+        let gitlab_pipeline = match setup_gitlab_pipeline(
+            config,
+            gitlab_pipeline_pipelines,
+        ) {
+            Ok(gitlab_pipeline) => Some(WorkerThread::start(gitlab_pipeline)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::GitlabPipeline(e)),
+        };
+        let gitlab_git = match gitlab_client {
+            Some(client) => match setup_gitlab_git(
+                config,
+                gitlab_git_pipelines,
+                client,
+            ) {
+                Ok(gitlab_git) => Some(WorkerThread::start(gitlab_git)),
+                Err(SetupError::NotFoundConfig) => None,
+                Err(e) => return Err(GithubBuilderError::GitlabGit(e)),
+            },
+            None => None,
+        };
+        let gitea_client = match setup_gitea_client(config) {
+            Ok(client) => Some(Arc::new(client)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::Gitea(e)),
+        };
+        let gitea = match gitea_client {
+            Some(ref client) => match setup_gitea(
+                config,
+                gitea_projects,
+                client.clone(),
+            ) {
+                Ok(gitea) => Some(WorkerThread::start(gitea)),
+                Err(SetupError::NotFoundConfig) => None,
+                Err(e) => return Err(GithubBuilderError::Gitea(e)),
+            },
+            None => None,
+        };
+        let gitea_git = match gitea_client {
+            Some(client) => match setup_gitea_git(
+                config,
+                gitea_git_pipelines,
+                client,
+            ) {
+                Ok(gitea_git) => Some(WorkerThread::start(gitea_git)),
+                Err(SetupError::NotFoundConfig) => None,
+                Err(e) => return Err(GithubBuilderError::GiteaGit(e)),
+            },
+            None => None,
+        };
+        let bitbucket = match setup_bitbucket(config, bitbucket_projects) {
+            Ok(bitbucket) => Some(WorkerThread::start(bitbucket)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::Bitbucket(e)),
+        };
+        let api = match setup_api(config, api_projects) {
+            Ok(api) => Some(WorkerThread::start(api)),
             Err(SetupError::NotFoundConfig) => None,
-            Err(e) => return Err(GithubBuilderError::GithubGit(e)),
+            Err(e) => return Err(GithubBuilderError::Api(e)),
         };
         let view = match setup_view(config, view_pipelines) {
             Ok(view) => Some(WorkerThread::start(view)),
@@ -261,6 +485,30 @@ impl GithubBuilder {
         } else {
             None
         };
+        let gitlab_idx = if let Some(gitlab) = gitlab {
+            uis.push(gitlab);
+            Some(uis.len()-1)
+        } else {
+            None
+        };
+        let gitea_idx = if let Some(gitea) = gitea {
+            uis.push(gitea);
+            Some(uis.len()-1)
+        } else {
+            None
+        };
+        let bitbucket_idx = if let Some(bitbucket) = bitbucket {
+            uis.push(bitbucket);
+            Some(uis.len()-1)
+        } else {
+            None
+        };
+        let api_idx = if let Some(api) = api {
+            uis.push(api);
+            Some(uis.len()-1)
+        } else {
+            None
+        };
         let mut cis = vec![];
         let github_status_idx = if let Some(github_status) = github_status {
             cis.push(github_status);
@@ -274,6 +522,13 @@ impl GithubBuilder {
         } else {
             None
         };
+        let gitlab_pipeline_idx =
+            if let Some(gitlab_pipeline) = gitlab_pipeline {
+                cis.push(gitlab_pipeline);
+                Some(cis.len()-1)
+            } else {
+                None
+            };
         let mut vcss = vec![];
         let git_idx = if let Some(git) = git {
             vcss.push(git);
@@ -287,6 +542,18 @@ impl GithubBuilder {
         } else {
             None
         };
+        let gitlab_git_idx = if let Some(gitlab_git) = gitlab_git {
+            vcss.push(gitlab_git);
+            Some(vcss.len()-1)
+        } else {
+            None
+        };
+        let gitea_git_idx = if let Some(gitea_git) = gitea_git {
+            vcss.push(gitea_git);
+            Some(vcss.len()-1)
+        } else {
+            None
+        };
         let mut pipelines = StaticPipelinesConfig::new();
         for (_name, def) in config_projects.iter() {
             let mut pipeline_id = PipelineId(pipelines.0.len() as i32);
@@ -296,6 +563,7 @@ impl GithubBuilder {
                     let ci_idx = match ci_type {
                         CiType::Jenkins => jenkins_idx,
                         CiType::GithubStatus => github_status_idx,
+                        CiType::GitlabPipeline => gitlab_pipeline_idx,
                     };
                     let ci_idx = if let Some(ci_idx) = ci_idx {
                         ci_idx
@@ -308,9 +576,33 @@ impl GithubBuilder {
             if ci_idxs.len() == 0 {
                 return Err(GithubBuilderError::Dangling);
             }
-            let ui_idx = if def.lookup("github").is_some() {
+            let (ui_idx, remote_format) = if def.lookup("github").is_some() {
                 if let Some(github_idx) = github_idx {
-                    github_idx
+                    (github_idx, github::REMOTE_FORMAT)
+                } else {
+                    return Err(GithubBuilderError::Dangling);
+                }
+            } else if def.lookup("gitlab").is_some() {
+                if let Some(gitlab_idx) = gitlab_idx {
+                    (gitlab_idx, gitlab::REMOTE_FORMAT)
+                } else {
+                    return Err(GithubBuilderError::Dangling);
+                }
+            } else if def.lookup("gitea").is_some() {
+                if let Some(gitea_idx) = gitea_idx {
+                    (gitea_idx, gitea::REMOTE_FORMAT)
+                } else {
+                    return Err(GithubBuilderError::Dangling);
+                }
+            } else if def.lookup("bitbucket").is_some() {
+                if let Some(bitbucket_idx) = bitbucket_idx {
+                    (bitbucket_idx, bitbucket::REMOTE_FORMAT)
+                } else {
+                    return Err(GithubBuilderError::Dangling);
+                }
+            } else if def.lookup("api").is_some() {
+                if let Some(api_idx) = api_idx {
+                    (api_idx, api::REMOTE_FORMAT)
                 } else {
                     return Err(GithubBuilderError::Dangling);
                 }
@@ -329,15 +621,81 @@ impl GithubBuilder {
                 } else {
                     return Err(GithubBuilderError::Dangling);
                 }
+            } else if def.lookup("gitlab").is_some() {
+                if let Some(gitlab_git_idx) = gitlab_git_idx {
+                    gitlab_git_idx
+                } else {
+                    return Err(GithubBuilderError::Dangling);
+                }
+            } else if def.lookup("gitea").is_some() {
+                if let Some(gitea_git_idx) = gitea_git_idx {
+                    gitea_git_idx
+                } else {
+                    return Err(GithubBuilderError::Dangling);
+                }
             } else {
                 return Err(GithubBuilderError::Dangling);
             };
+            let batch_size = match project_integer(
+                def,
+                "batch_size",
+                ProjectArg::BatchSize,
+                1,
+            ) {
+                Ok(batch_size) if batch_size >= 1 => batch_size as usize,
+                Ok(_) => return Err(GithubBuilderError::Project(
+                    SetupError::InvalidArg(ProjectArg::BatchSize, Ty::Integer)
+                )),
+                Err(e) => return Err(GithubBuilderError::Project(e)),
+            };
+            let depth = match project_integer(
+                def,
+                "depth",
+                ProjectArg::Depth,
+                1,
+            ) {
+                Ok(depth) if depth >= 1 => depth as usize,
+                Ok(_) => return Err(GithubBuilderError::Project(
+                    SetupError::InvalidArg(ProjectArg::Depth, Ty::Integer)
+                )),
+                Err(e) => return Err(GithubBuilderError::Project(e)),
+            };
+            let ci_timeout = match project_integer(
+                def,
+                "ci_timeout",
+                ProjectArg::CiTimeout,
+                0,
+            ) {
+                Ok(0) => None,
+                Ok(secs) if secs > 0 => Some(Duration::from_secs(secs as u64)),
+                Ok(_) => return Err(GithubBuilderError::Project(
+                    SetupError::InvalidArg(ProjectArg::CiTimeout, Ty::Integer)
+                )),
+                Err(e) => return Err(GithubBuilderError::Project(e)),
+            };
+            let auto_retry = match project_integer(
+                def,
+                "auto_retry",
+                ProjectArg::AutoRetry,
+                0,
+            ) {
+                Ok(auto_retry) if auto_retry >= 0 => auto_retry as u32,
+                Ok(_) => return Err(GithubBuilderError::Project(
+                    SetupError::InvalidArg(ProjectArg::AutoRetry, Ty::Integer)
+                )),
+                Err(e) => return Err(GithubBuilderError::Project(e)),
+            };
             if def.lookup("try").is_some() {
                 pipelines.0.push(PipelineConfig{
                     pipeline_id: pipeline_id,
                     ci: ci_idxs.clone(),
                     ui: ui_idx,
                     vcs: vcs_idx,
+                    remote_format: remote_format,
+                    batch_size: 1,
+                    depth: 1,
+                    ci_timeout: ci_timeout,
+                    auto_retry: auto_retry,
                 });
             }
             pipelines.0.push(PipelineConfig{
@@ -345,6 +703,11 @@ impl GithubBuilder {
                 ci: ci_idxs,
                 ui: ui_idx,
                 vcs: vcs_idx,
+                remote_format: remote_format,
+                batch_size: batch_size,
+                depth: depth,
+                ci_timeout: ci_timeout,
+                auto_retry: auto_retry,
             });
             pipeline_id.0 = pipeline_id.0 + 1;
         }
@@ -353,28 +716,46 @@ impl GithubBuilder {
             .unwrap_or_else(|| "db.sqlite");
         let db_build = db::Builder::from_str(db_path)
             .expect("to parse db path");
-        let db = db_build
+        // Each pipeline opens its own connection,
+        // but this sets up the tables and makes sure it works.
+        db_build
             .open()
             .expect("to open up db");
+        // The hooks worker keeps its own connections, for the outbox.
+        let (hooks, outbox) = match setup_hooks(
+            config,
+            hooks_pipelines,
+            db::Builder::from_str(db_path).expect("to parse db path"),
+        ) {
+            Ok((hooks, outbox)) =>
+                (Some(WorkerThread::start(hooks)), Some(outbox)),
+            Err(SetupError::NotFoundConfig) => (None, None),
+            Err(e) => return Err(GithubBuilderError::Hooks(e)),
+        };
         Ok(GithubBuilder{
             cis: cis,
             uis: uis,
             vcss: vcss,
             view: view,
-            db: db,
+            hooks: hooks,
+            outbox: outbox,
+            db: db_build,
             pipelines: pipelines,
         })
     }
 }
 
 impl WorkerBuilder for GithubBuilder {
-    fn start(self) -> (WorkerManager, DbBox) {
+    fn start(self) -> (WorkerManager, db::Builder) {
         (
             WorkerManager {
                 cis: self.cis,
                 uis: self.uis,
                 vcss: self.vcss,
                 view: self.view,
+                hooks: self.hooks,
+                outbox: self.outbox,
+                timer: WorkerThread::start(timer::Worker::new()),
                 pipelines: Box::new(self.pipelines),
             },
             self.db,
@@ -453,18 +834,156 @@ macro_rules! toml_arg_default {
     }}
 }
 
+fn project_integer(
+    def: &toml::Value,
+    attr: &str,
+    arg: ProjectArg,
+    default: i64,
+) -> Result<i64, SetupError<ProjectArg>> {
+    match def.lookup(attr) {
+        Some(&toml::Value::Integer(value)) => Ok(value),
+        Some(attr) => {
+            let ty = match *attr {
+                toml::Value::String(_) => Ty::String,
+                toml::Value::Integer(_) => Ty::Integer,
+                toml::Value::Float(_) => Ty::Float,
+                toml::Value::Boolean(_) => Ty::Boolean,
+                toml::Value::Datetime(_) => Ty::Datetime,
+                toml::Value::Array(_) => Ty::Array,
+                toml::Value::Table(_) => Ty::Table,
+            };
+            Err(SetupError::InvalidArg(arg, ty))
+        }
+        None => Ok(default),
+    }
+}
+
+/// Webhook secrets are either one string or a list of them,
+/// so that a new secret can be added before the old one is taken out.
+/// The first of the keys that is there is used.
+fn toml_secrets<T: Debug>(
+    config: &toml::Value,
+    keys: &[&str],
+    arg: T,
+) -> Result<Vec<String>, SetupError<T>> {
+    let value = match keys.iter().filter_map(|key| config.lookup(key)).next() {
+        Some(value) => value,
+        None => return Err(SetupError::NotFoundArg(arg)),
+    };
+    match *value {
+        toml::Value::String(ref secret) => Ok(vec![secret.clone()]),
+        toml::Value::Array(ref secrets) => {
+            let mut result = Vec::new();
+            for secret in secrets {
+                match secret.as_str() {
+                    Some(secret) => result.push(secret.to_owned()),
+                    None => return Err(SetupError::InvalidArg(arg, Ty::String)),
+                }
+            }
+            if result.is_empty() {
+                return Err(SetupError::NotFoundArg(arg));
+            }
+            Ok(result)
+        }
+        _ => Err(SetupError::InvalidArg(arg, Ty::Array)),
+    }
+}
+
+fn read_file<P: AsRef<Path>>(path: P)
+        -> Result<toml::Table, GithubBuilderError> {
+    let path = path.as_ref();
+    let mut config_file = match File::open(&*path) {
+        Ok(config_file) => config_file,
+        Err(e) => return Err(GithubBuilderError::OpenFile(e)),
+    };
+    let mut config_string = String::new();
+    match config_file.read_to_string(&mut config_string) {
+        Ok(_) => {},
+        Err(e) => return Err(GithubBuilderError::ReadFile(e)),
+    }
+    match toml::Parser::new(&config_string).parse() {
+        Some(config) => Ok(config),
+        None => Err(GithubBuilderError::Parse),
+    }
+}
+
+/// Just enough of the config to tell a running instance what to do.
+pub fn admin_from_file<P: AsRef<Path>>(path: P)
+        -> Result<github::admin::Admin, GithubBuilderError> {
+    let config_main = try!(read_file(path));
+    let config = match config_main.get("config") {
+        Some(config) => config,
+        None => return Err(GithubBuilderError::NoConfig),
+    };
+    setup_github_admin(config).map_err(GithubBuilderError::Github)
+}
+
 // Everything under the [config] section.
 
-fn setup_github(config: &toml::Value, projects: StaticGithubProjectsConfig)
-        -> Result<github::Worker, SetupError<GithubArg>> {
+/// Either a personal access token,
+/// or the ID and private key of a GitHub App.
+fn setup_github_credentials(config: &toml::Value)
+        -> Result<Credentials, SetupError<GithubArg>> {
+    let host = toml_arg_default!(config, "github", "host", String,
+        GithubArg::Host,
+        "https://api.github.com"
+    );
+    match config.lookup("github.app_id") {
+        Some(&toml::Value::Integer(app_id)) => {
+            let path = toml_arg!(config, "github", "private_key", String,
+                GithubArg::PrivateKey
+            );
+            let mut private_key = Vec::new();
+            if let Err(e) = File::open(&path)
+                    .and_then(|mut file| file.read_to_end(&mut private_key)) {
+                warn!("Failed to read GitHub App private key {}: {:?}",
+                    path, e);
+                return Err(SetupError::UnreadableArg(GithubArg::PrivateKey));
+            }
+            Ok(Credentials::app(host, app_id as u64, private_key))
+        }
+        Some(_) => Err(SetupError::InvalidArg(GithubArg::AppId, Ty::Integer)),
+        None => Ok(Credentials::token(
+            host,
+            toml_arg!(config, "github", "token", String, GithubArg::Token),
+        )),
+    }
+}
+
+fn setup_github(
+    config: &toml::Value,
+    projects: StaticGithubProjectsConfig,
+    credentials: Arc<Credentials>,
+) -> Result<github::Worker, SetupError<GithubArg>> {
+    let replay_log = if let Some(path) = config.lookup("github.replay_log") {
+        if let Some(path) = path.as_str() {
+            Some(PathBuf::from(path))
+        } else {
+            return Err(
+                SetupError::InvalidArg(GithubArg::ReplayLog, Ty::String)
+            );
+        }
+    } else {
+        None
+    };
+    let admin_listen = try!(setup_github_admin_listen(config));
+    let checks = toml_arg_default!(
+        config,
+        "github",
+        "checks",
+        Boolean,
+        GithubArg::Checks,
+        false
+    );
+    // Check runs can only be created with an installation token.
+    if checks && config.lookup("github.app_id").is_none() {
+        return Err(SetupError::NotFoundArg(GithubArg::AppId));
+    }
     Ok(github::Worker::new(
         toml_arg!(config, "github", "listen", String, GithubArg::Listen),
-        toml_arg_default!(config, "github", "host", String, GithubArg::Host,
-            "https://api.github.com"
-        ),
-        toml_arg!(config, "github", "token", String, GithubArg::Token),
+        credentials,
         toml_arg!(config, "github", "user", String, GithubArg::User),
-        toml_arg!(config, "github", "secret", String, GithubArg::Secret),
+        try!(toml_secrets(config, &["github.secret"], GithubArg::Secret)),
         Box::new(projects),
         db::Builder::from_str(
             &toml_arg_default!(config, "github", "db", String, GithubArg::Db,
@@ -472,9 +991,75 @@ fn setup_github(config: &toml::Value, projects: StaticGithubProjectsConfig)
                     .unwrap_or("db.sqlite").to_owned()
             )[..]
         ).expect("the DB to open"),
+        db::Builder::from_str(
+            config.lookup("db").and_then(toml::Value::as_str)
+                .unwrap_or("db.sqlite")
+        ).expect("the DB to open"),
+        {
+            let sync_interval = toml_arg_default!(
+                config,
+                "github",
+                "sync_interval",
+                Integer,
+                GithubArg::SyncInterval,
+                3600
+            );
+            if sync_interval > 0 {
+                Some(Duration::from_secs(sync_interval as u64))
+            } else {
+                None
+            }
+        },
+        checks,
+        replay_log,
+        admin_listen,
+        Duration::from_secs(toml_arg_default!(
+            config,
+            "github",
+            "permission_ttl",
+            Integer,
+            GithubArg::PermissionTtl,
+            300
+        ) as u64),
     ))
 }
 
+fn setup_github_admin(config: &toml::Value)
+        -> Result<github::admin::Admin, SetupError<GithubArg>> {
+    let secrets =
+        try!(toml_secrets(config, &["github.secret"], GithubArg::Secret));
+    let replay_log = match config.lookup("github.replay_log") {
+        Some(path) => match path.as_str() {
+            Some(path) => Some(PathBuf::from(path)),
+            None => return Err(
+                SetupError::InvalidArg(GithubArg::ReplayLog, Ty::String)
+            ),
+        },
+        None => None,
+    };
+    Ok(github::admin::Admin {
+        admin_listen: try!(setup_github_admin_listen(config)),
+        secret: secrets[0].clone(),
+        replay_log: replay_log,
+    })
+}
+
+/// Anyone who can reach the admin listener can replay webhooks
+/// past the duplicate check, so only take admin requests from this machine.
+fn setup_github_admin_listen(config: &toml::Value)
+        -> Result<Option<String>, SetupError<GithubArg>> {
+    match config.lookup("github.admin_listen") {
+        Some(listen) => match listen.as_str() {
+            Some(listen) if github::admin::is_loopback(listen) =>
+                Ok(Some(listen.to_owned())),
+            _ => Err(
+                SetupError::InvalidArg(GithubArg::AdminListen, Ty::String)
+            ),
+        },
+        None => Ok(None),
+    }
+}
+
 fn setup_github_status(
     config: &toml::Value,
     pipelines: StaticGithubStatusPipelinesConfig
@@ -487,20 +1072,11 @@ fn setup_github_status(
             String,
             GithubStatusArg::Listen
         ),
-        toml_arg_default!(
+        try!(toml_secrets(
             config,
-            "github.status",
-            "secret",
-            String,
-            GithubStatusArg::Secret,
-            toml_arg!(
-                config,
-                "github",
-                "secret",
-                String,
-                GithubStatusArg::Secret
-            )
-        ),
+            &["github.status.secret", "github.secret"],
+            GithubStatusArg::Secret
+        )),
         Box::new(pipelines),
     ))
 }
@@ -564,34 +1140,298 @@ fn setup_git(
 
 fn setup_github_git(
     config: &toml::Value,
-    pipelines: StaticGithubGitPipelinesConfig
+    pipelines: StaticGithubGitPipelinesConfig,
+    credentials: Arc<Credentials>,
 ) -> Result<github_git::Worker, SetupError<GithubGitArg>> {
-    Ok(github_git::Worker::new(
+    let host = toml_arg_default!(
+        config,
+        "github.git",
+        "host",
+        String,
+        GithubGitArg::Host,
         toml_arg_default!(
             config,
-            "github.git",
+            "github",
             "host",
             String,
             GithubGitArg::Host,
+            "https://api.github.com"
+        )
+    );
+    // Use the same credentials as the front-end, unless told otherwise.
+    let credentials = match (
+        config.lookup("github.git.host"),
+        config.lookup("github.git.token"),
+    ) {
+        (None, None) => credentials,
+        _ => Arc::new(Credentials::token(
+            host,
             toml_arg_default!(
                 config,
-                "github",
-                "host",
+                "github.git",
+                "token",
                 String,
-                GithubGitArg::Host,
-                "https://api.github.com"
-            )
-        ),
-        toml_arg_default!(
-            config,
-            "github.git",
-            "token",
-            String,
-            GithubGitArg::Token,
-            toml_arg!(config, "github", "token", String, GithubGitArg::Token)
-        ),
-        Box::new(pipelines),
-    ))
+                GithubGitArg::Token,
+                toml_arg!(
+                    config,
+                    "github",
+                    "token",
+                    String,
+                    GithubGitArg::Token
+                )
+            ),
+        )),
+    };
+    Ok(github_git::Worker::new(
+        credentials,
+        Box::new(pipelines),
+    ))
+}
+
+fn setup_gitlab_client(config: &toml::Value)
+        -> Result<gitlab_api::Client, SetupError<GitlabArg>> {
+    Ok(gitlab_api::Client::new(
+        toml_arg_default!(config, "gitlab", "host", String, GitlabArg::Host,
+            "https://gitlab.com"
+        ),
+        toml_arg!(config, "gitlab", "token", String, GitlabArg::Token),
+    ))
+}
+
+fn setup_gitlab(
+    config: &toml::Value,
+    projects: StaticGitlabProjectsConfig,
+    client: Arc<gitlab_api::Client>,
+) -> Result<gitlab::Worker, SetupError<GitlabArg>> {
+    Ok(gitlab::Worker::new(
+        toml_arg!(config, "gitlab", "listen", String, GitlabArg::Listen),
+        client,
+        toml_arg!(config, "gitlab", "user", String, GitlabArg::User),
+        try!(toml_secrets(config, &["gitlab.secret"], GitlabArg::Secret)),
+        Box::new(projects),
+    ))
+}
+
+fn setup_gitlab_pipeline(
+    config: &toml::Value,
+    pipelines: StaticGitlabPipelinePipelinesConfig
+) -> Result<gitlab_pipeline::Worker, SetupError<GitlabPipelineArg>> {
+    Ok(gitlab_pipeline::Worker::new(
+        toml_arg!(
+            config,
+            "gitlab.pipeline",
+            "listen",
+            String,
+            GitlabPipelineArg::Listen
+        ),
+        try!(toml_secrets(
+            config,
+            &["gitlab.pipeline.secret", "gitlab.secret"],
+            GitlabPipelineArg::Secret
+        )),
+        Box::new(pipelines),
+    ))
+}
+
+fn setup_gitlab_git(
+    config: &toml::Value,
+    pipelines: StaticGitlabGitPipelinesConfig,
+    client: Arc<gitlab_api::Client>,
+) -> Result<gitlab_git::Worker, SetupError<GitlabGitArg>> {
+    let host = toml_arg_default!(
+        config,
+        "gitlab.git",
+        "host",
+        String,
+        GitlabGitArg::Host,
+        toml_arg_default!(
+            config,
+            "gitlab",
+            "host",
+            String,
+            GitlabGitArg::Host,
+            "https://gitlab.com"
+        )
+    );
+    // Use the same token as the front-end, unless told otherwise.
+    let client = match (
+        config.lookup("gitlab.git.host"),
+        config.lookup("gitlab.git.token"),
+    ) {
+        (None, None) => client,
+        _ => Arc::new(gitlab_api::Client::new(
+            host,
+            toml_arg_default!(
+                config,
+                "gitlab.git",
+                "token",
+                String,
+                GitlabGitArg::Token,
+                toml_arg!(
+                    config,
+                    "gitlab",
+                    "token",
+                    String,
+                    GitlabGitArg::Token
+                )
+            ),
+        )),
+    };
+    Ok(gitlab_git::Worker::new(
+        client,
+        Box::new(pipelines),
+        toml_arg_default!(config, "gitlab.git", "executable",
+            String, GitlabGitArg::Executable,
+            "git"
+        ),
+        PathBuf::from(toml_arg_default!(config, "gitlab.git", "path",
+            String, GitlabGitArg::Path,
+            "gitlab-git"
+        )),
+    ))
+}
+
+fn setup_gitea_client(config: &toml::Value)
+        -> Result<gitea_api::Client, SetupError<GiteaArg>> {
+    // Gitea is always self-hosted, so there is no default host.
+    Ok(gitea_api::Client::new(
+        toml_arg!(config, "gitea", "host", String, GiteaArg::Host),
+        toml_arg!(config, "gitea", "token", String, GiteaArg::Token),
+    ))
+}
+
+fn setup_gitea(
+    config: &toml::Value,
+    projects: StaticGiteaProjectsConfig,
+    client: Arc<gitea_api::Client>,
+) -> Result<gitea::Worker, SetupError<GiteaArg>> {
+    Ok(gitea::Worker::new(
+        toml_arg!(config, "gitea", "listen", String, GiteaArg::Listen),
+        client,
+        toml_arg!(config, "gitea", "user", String, GiteaArg::User),
+        try!(toml_secrets(config, &["gitea.secret"], GiteaArg::Secret)),
+        Box::new(projects),
+    ))
+}
+
+fn setup_bitbucket(
+    config: &toml::Value,
+    projects: StaticBitbucketProjectsConfig,
+) -> Result<bitbucket::Worker, SetupError<BitbucketArg>> {
+    let client = bitbucket_api::Client::new(
+        toml_arg!(config, "bitbucket", "host", String, BitbucketArg::Host),
+        toml_arg!(config, "bitbucket", "token", String, BitbucketArg::Token),
+    );
+    Ok(bitbucket::Worker::new(
+        toml_arg!(config, "bitbucket", "listen", String, BitbucketArg::Listen),
+        Arc::new(client),
+        toml_arg!(config, "bitbucket", "user", String, BitbucketArg::User),
+        try!(toml_secrets(
+            config,
+            &["bitbucket.secret"],
+            BitbucketArg::Secret,
+        )),
+        Box::new(projects),
+    ))
+}
+
+fn setup_api(
+    config: &toml::Value,
+    projects: StaticApiProjectsConfig,
+) -> Result<api::Worker, SetupError<ApiArg>> {
+    let callback = match config.lookup("api.callback") {
+        Some(&toml::Value::String(ref callback)) => match Url::parse(
+            callback
+        ) {
+            Ok(callback) => Some(callback),
+            Err(_) => return Err(SetupError::InvalidArg(
+                ApiArg::Callback,
+                Ty::String,
+            )),
+        },
+        Some(_) => return Err(SetupError::InvalidArg(
+            ApiArg::Callback,
+            Ty::String,
+        )),
+        None => None,
+    };
+    Ok(api::Worker::new(
+        toml_arg!(config, "api", "listen", String, ApiArg::Listen),
+        try!(toml_secrets(config, &["api.secret"], ApiArg::Secret)),
+        callback,
+        Box::new(projects),
+    ))
+}
+
+fn setup_hooks(
+    config: &toml::Value,
+    pipelines: StaticHooksPipelinesConfig,
+    db_build: db::Builder,
+) -> Result<(hooks::Worker, hooks::Outbox), SetupError<HooksArg>> {
+    if config.lookup("hooks").is_none() {
+        return Err(SetupError::NotFoundConfig);
+    }
+    let urls = match try!(
+        toml_string_list(config, "hooks.urls", HooksArg::Urls)
+    ) {
+        Some(urls) => urls,
+        None => return Err(SetupError::NotFoundArg(HooksArg::Urls)),
+    };
+    let mut parsed_urls = Vec::new();
+    for url in urls {
+        match Url::parse(&url) {
+            Ok(url) => parsed_urls.push(url),
+            Err(_) => return Err(SetupError::InvalidArg(
+                HooksArg::Urls,
+                Ty::String,
+            )),
+        }
+    }
+    let worker = hooks::Worker::new(
+        parsed_urls.clone(),
+        toml_arg!(config, "hooks", "secret", String, HooksArg::Secret),
+        db_build,
+    );
+    Ok((worker, hooks::Outbox::new(parsed_urls, Box::new(pipelines))))
+}
+
+fn setup_gitea_git(
+    config: &toml::Value,
+    pipelines: StaticGiteaGitPipelinesConfig,
+    client: Arc<gitea_api::Client>,
+) -> Result<gitea_git::Worker, SetupError<GiteaGitArg>> {
+    if config.lookup("gitea.git").is_none() {
+        return Err(SetupError::NotFoundConfig);
+    }
+    // Use the same host and token as the front-end, unless told otherwise.
+    let client = match (
+        config.lookup("gitea.git.host"),
+        config.lookup("gitea.git.token"),
+    ) {
+        (None, None) => client,
+        _ => Arc::new(gitea_api::Client::new(
+            toml_arg_default!(
+                config,
+                "gitea.git",
+                "host",
+                String,
+                GiteaGitArg::Host,
+                toml_arg!(config, "gitea", "host", String, GiteaGitArg::Host)
+            ),
+            toml_arg_default!(
+                config,
+                "gitea.git",
+                "token",
+                String,
+                GiteaGitArg::Token,
+                toml_arg!(config, "gitea", "token", String, GiteaGitArg::Token)
+            ),
+        )),
+    };
+    Ok(gitea_git::Worker::new(
+        client,
+        Box::new(pipelines),
+    ))
 }
 
 fn setup_view(
@@ -646,13 +1486,8 @@ impl StaticPipelinesConfig {
 }
 
 impl PipelinesConfig for StaticPipelinesConfig {
-    fn by_pipeline_id(&self, id: PipelineId) -> PipelineConfig {
-        for cfg in &self.0 {
-            if cfg.pipeline_id == id {
-                return cfg.clone();
-            }
-        }
-        panic!("Invalid pipeline ID: {:?}", id);
+    fn by_pipeline_id(&self, id: PipelineId) -> Option<PipelineConfig> {
+        self.0.iter().find(|cfg| cfg.pipeline_id == id).cloned()
     }
     fn by_ci_id(&self, id: CiId) -> PipelineConfig {
         for cfg in &self.0 {
@@ -667,10 +1502,19 @@ impl PipelinesConfig for StaticPipelinesConfig {
     fn len(&self) -> usize {
         self.0.len()
     }
+    fn ids(&self) -> Vec<PipelineId> {
+        let mut ids: Vec<PipelineId> = Vec::new();
+        for cfg in &self.0 {
+            if !ids.contains(&cfg.pipeline_id) {
+                ids.push(cfg.pipeline_id);
+            }
+        }
+        ids
+    }
 }
 
 struct StaticGithubProjectsConfig(
-    HashMap<github::Repo, github::RepoPipelines>
+    HashMap<(github::Repo, String), github::RepoPipelines>
 );
 
 impl StaticGithubProjectsConfig {
@@ -684,31 +1528,38 @@ impl StaticGithubProjectsConfig {
         def: &toml::Value,
         pipeline_id: PipelineId
     ) -> Result<(), SetupError<GithubProjectArg>> {
-        self.0.insert(
-            github::Repo{
-                owner: toml_arg_default!(
-                    config,
-                    "github",
-                    "owner",
-                    String,
-                    GithubProjectArg::Owner,
-                    toml_arg!(
-                        def,
-                        "github",
-                        "owner",
-                        String,
-                        GithubProjectArg::Owner
-                    )
-                ),
-                repo: toml_arg_default!(
+        // PRs are routed to the project that merges into their base branch.
+        let branch = def.lookup("github.master_branch")
+            .or_else(|| def.lookup("git.master_branch"))
+            .and_then(toml::Value::as_str)
+            .unwrap_or("master")
+            .to_owned();
+        let repo = github::Repo{
+            owner: toml_arg_default!(
+                config,
+                "github",
+                "owner",
+                String,
+                GithubProjectArg::Owner,
+                toml_arg!(
                     def,
                     "github",
-                    "repo",
+                    "owner",
                     String,
-                    GithubProjectArg::Repo,
-                    name
+                    GithubProjectArg::Owner
                 )
-            },
+            ),
+            repo: toml_arg_default!(
+                def,
+                "github",
+                "repo",
+                String,
+                GithubProjectArg::Repo,
+                name
+            )
+        };
+        self.0.insert(
+            (repo, branch),
             github::RepoPipelines{
                 pipeline_id: pipeline_id,
                 try_pipeline_id: if def.lookup("try").is_some() {
@@ -716,22 +1567,88 @@ impl StaticGithubProjectsConfig {
                 } else {
                     None
                 },
+                review_approves: toml_arg_default!(
+                    def,
+                    "github",
+                    "review_approves",
+                    Boolean,
+                    GithubProjectArg::ReviewApproves,
+                    false
+                ),
+                reviewers: try!(toml_access_list(
+                    def,
+                    ("github.reviewers", GithubProjectArg::Reviewers),
+                    ("github.reviewer_teams", GithubProjectArg::ReviewerTeams),
+                )),
+                try_users: try!(toml_access_list(
+                    def,
+                    ("github.try_users", GithubProjectArg::TryUsers),
+                    ("github.try_teams", GithubProjectArg::TryTeams),
+                )),
             }
         );
         Ok(())
     }
 }
 
+/// A list of users and a list of team slugs,
+/// or nothing if neither of them is there.
+fn toml_access_list<T: Debug>(
+    def: &toml::Value,
+    (users_key, users_arg): (&str, T),
+    (teams_key, teams_arg): (&str, T),
+) -> Result<Option<github::AccessList>, SetupError<T>> {
+    let users = try!(toml_string_list(def, users_key, users_arg));
+    let teams = try!(toml_string_list(def, teams_key, teams_arg));
+    if users.is_none() && teams.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(github::AccessList {
+        users: users.unwrap_or_else(Vec::new),
+        teams: teams.unwrap_or_else(Vec::new),
+    }))
+}
+
+fn toml_string_list<T: Debug>(
+    def: &toml::Value,
+    key: &str,
+    arg: T,
+) -> Result<Option<Vec<String>>, SetupError<T>> {
+    let values = match def.lookup(key) {
+        Some(&toml::Value::Array(ref values)) => values,
+        Some(_) => return Err(SetupError::InvalidArg(arg, Ty::Array)),
+        None => return Ok(None),
+    };
+    let mut result = Vec::new();
+    for value in values {
+        match value.as_str() {
+            Some(value) => result.push(value.to_owned()),
+            None => return Err(SetupError::InvalidArg(arg, Ty::String)),
+        }
+    }
+    Ok(Some(result))
+}
+
 impl github::ProjectsConfig for StaticGithubProjectsConfig {
     fn pipelines_by_repo(
         &self,
-        repo: &github::Repo
+        repo: &github::Repo,
+        branch: &str,
     ) -> Option<github::RepoPipelines> {
-        self.0.get(repo).map(Clone::clone)
+        self.0.get(&(repo.clone(), branch.to_owned())).map(Clone::clone)
+    }
+    fn all_pipelines_by_repo(
+        &self,
+        repo: &github::Repo
+    ) -> Vec<github::RepoPipelines> {
+        self.0.iter()
+            .filter(|&(&(ref r, _), _)| r == repo)
+            .map(|(_, pipelines)| pipelines.clone())
+            .collect()
     }
     fn repo_by_pipeline(&self, pipeline_id: PipelineId)
             -> Option<(github::Repo, github::PipelineType)> {
-        for (repo, pipelines) in self.0.iter() {
+        for (&(ref repo, _), pipelines) in self.0.iter() {
             if pipelines.pipeline_id == pipeline_id {
                 return Some((repo.clone(), github::PipelineType::Stage));
             }
@@ -741,6 +1658,15 @@ impl github::ProjectsConfig for StaticGithubProjectsConfig {
         }
         return None;
     }
+    fn repos(&self) -> Vec<github::Repo> {
+        let mut repos: Vec<github::Repo> = Vec::new();
+        for &(ref repo, _) in self.0.keys() {
+            if !repos.contains(repo) {
+                repos.push(repo.clone());
+            }
+        }
+        repos
+    }
 }
 
 
@@ -1101,68 +2027,657 @@ impl github_git::PipelinesConfig for StaticGithubGitPipelinesConfig {
     }
 }
 
-struct StaticViewPipelinesConfig(HashMap<String, PipelineId>);
+struct StaticGitlabProjectsConfig(
+    HashMap<(gitlab::Repo, String), gitlab::RepoPipelines>
+);
 
-impl StaticViewPipelinesConfig {
+impl StaticGitlabProjectsConfig {
     fn new() -> Self {
-        StaticViewPipelinesConfig(HashMap::new())
-    } 
-    fn add_pipeline(
+        StaticGitlabProjectsConfig(HashMap::new())
+    }
+    fn add_project(
         &mut self,
         name: &str,
-        _config: &toml::Value,
-        _def: &toml::Value,
-        pipeline_id: PipelineId,
-    ) -> Result<(), SetupError<ViewProjectArg>> {
-        self.0.insert(name.to_owned(), pipeline_id);
+        config: &toml::Value,
+        def: &toml::Value,
+        pipeline_id: PipelineId
+    ) -> Result<(), SetupError<GitlabProjectArg>> {
+        if def.lookup("gitlab").is_none() {
+            return Err(SetupError::NotFoundConfig);
+        }
+        // MRs are routed to the project that merges into their target.
+        let branch = toml_arg_default!(
+            def,
+            "gitlab",
+            "master_branch",
+            String,
+            GitlabProjectArg::MasterBranch,
+            "master"
+        );
+        let repo = gitlab::Repo{
+            owner: toml_arg_default!(
+                def,
+                "gitlab",
+                "owner",
+                String,
+                GitlabProjectArg::Owner,
+                toml_arg!(
+                    config,
+                    "gitlab",
+                    "owner",
+                    String,
+                    GitlabProjectArg::Owner
+                )
+            ),
+            repo: toml_arg_default!(
+                def,
+                "gitlab",
+                "repo",
+                String,
+                GitlabProjectArg::Repo,
+                name
+            )
+        };
+        self.0.insert(
+            (repo, branch),
+            gitlab::RepoPipelines{
+                pipeline_id: pipeline_id,
+                try_pipeline_id: if def.lookup("try").is_some() {
+                    Some(PipelineId(pipeline_id.0 + 1))
+                } else {
+                    None
+                },
+            }
+        );
         Ok(())
     }
 }
 
-impl view::PipelinesConfig for StaticViewPipelinesConfig {
-    fn pipeline_by_name(&self, name: &str) -> Option<PipelineId> {
-        self.0.get(name).map(|x| *x)
+impl gitlab::ProjectsConfig for StaticGitlabProjectsConfig {
+    fn pipelines_by_repo(
+        &self,
+        repo: &gitlab::Repo,
+        branch: &str,
+    ) -> Option<gitlab::RepoPipelines> {
+        self.0.get(&(repo.clone(), branch.to_owned())).map(Clone::clone)
     }
-    fn all(&self) -> Vec<(Cow<str>, PipelineId)> {
-        self.0.iter().map(|x| (Cow::Borrowed(&x.0[..]), *x.1)).collect()
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(gitlab::Repo, gitlab::PipelineType)> {
+        for (&(ref repo, _), pipelines) in self.0.iter() {
+            if pipelines.pipeline_id == pipeline_id {
+                return Some((repo.clone(), gitlab::PipelineType::Stage));
+            }
+            if pipelines.try_pipeline_id == Some(pipeline_id) {
+                return Some((repo.clone(), gitlab::PipelineType::Try));
+            }
+        }
+        return None;
     }
 }
 
-// Errors and args definitions.
 
-quick_error! {
-    #[derive(Debug)]
-    pub enum GithubBuilderError {
-        OpenFile(err: ::std::io::Error) {
-            cause(err)
-        }
-        ReadFile(err: ::std::io::Error) {
-            cause(err)
-        }
-        Parse {}
-        NoConfig {}
-        NoProjects {}
-        NoConfigGithub {}
-        Dangling {}
-        Github(err: SetupError<GithubArg>) {
-            cause(err)
-        }
-        GithubStatus(err: SetupError<GithubStatusArg>) {
-            cause(err)
-        }
-        Jenkins(err: SetupError<JenkinsArg>) {
-            cause(err)
-        }
-        Git(err: SetupError<GitArg>) {
-            cause(err)
-        }
-        GithubGit(err: SetupError<GithubGitArg>) {
-            cause(err)
-        }
-        View(err: SetupError<ViewArg>) {
-            cause(err)
+struct StaticGitlabPipelinePipelinesConfig(
+    HashMap<CiId, gitlab_pipeline::Repo>
+);
+
+impl StaticGitlabPipelinePipelinesConfig {
+    fn new() -> Self {
+        StaticGitlabPipelinePipelinesConfig(HashMap::new())
+    }
+    fn add_pipeline(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        pipeline_id: PipelineId,
+        ci_id: &mut CiId,
+        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId)>,
+    ) -> Result<(), SetupError<GitlabPipelineProjectArg>> {
+        match def.lookup("gitlab.pipeline") {
+            Some(&toml::Value::Boolean(true)) => {}
+            Some(&toml::Value::Boolean(false)) | None =>
+                return Err(SetupError::NotFoundConfig),
+            Some(_) => return Err(SetupError::InvalidArg(
+                GitlabPipelineProjectArg::Pipeline,
+                Ty::Boolean,
+            )),
         }
-        Project(err: SetupError<ProjectArg>) {
+        let repo = gitlab_pipeline::Repo{
+            owner: toml_arg_default!(
+                def,
+                "gitlab",
+                "owner",
+                String,
+                GitlabPipelineProjectArg::Owner,
+                toml_arg!(
+                    config,
+                    "gitlab",
+                    "owner",
+                    String,
+                    GitlabPipelineProjectArg::Owner
+                )
+            ),
+            repo: toml_arg_default!(
+                def,
+                "gitlab",
+                "repo",
+                String,
+                GitlabPipelineProjectArg::Repo,
+                name
+            ),
+        };
+        self.0.entry(*ci_id).or_insert(repo);
+        ci_to_pipeline.insert(*ci_id, (CiType::GitlabPipeline, pipeline_id));
+        ci_id.0 += 1;
+        Ok(())
+    }
+}
+
+impl gitlab_pipeline::PipelinesConfig for StaticGitlabPipelinePipelinesConfig {
+    fn repo_by_id(&self, id: CiId)
+            -> Option<gitlab_pipeline::Repo> {
+        return self.0.get(&id).map(Clone::clone)
+    }
+    fn ids_by_repo(
+        &self,
+        repo: &gitlab_pipeline::Repo
+    ) -> Vec<CiId> {
+        let mut ret_val = vec![];
+        for (id, i_repo) in self.0.iter() {
+            if repo == i_repo {
+                ret_val.push(*id)
+            }
+        }
+        ret_val
+    }
+}
+
+
+struct StaticGitlabGitPipelinesConfig(
+    HashMap<PipelineId, gitlab_git::Repo>
+);
+
+impl StaticGitlabGitPipelinesConfig {
+    fn new() -> Self {
+        StaticGitlabGitPipelinesConfig(HashMap::new())
+    }
+    fn add_pipeline(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        pipeline_id: PipelineId,
+        is_try: bool,
+    ) -> Result<(), SetupError<GitlabGitProjectArg>> {
+        if def.lookup("gitlab").is_none() {
+            return Err(SetupError::NotFoundConfig);
+        }
+        let repo = gitlab_git::Repo{
+            owner: toml_arg_default!(
+                def,
+                "gitlab",
+                "owner",
+                String,
+                GitlabGitProjectArg::Owner,
+                toml_arg!(
+                    config,
+                    "gitlab",
+                    "owner",
+                    String,
+                    GitlabGitProjectArg::Owner
+                )
+            ),
+            repo: toml_arg_default!(
+                def,
+                "gitlab",
+                "repo",
+                String,
+                GitlabGitProjectArg::Repo,
+                name
+            ),
+            master_branch: toml_arg_default!(
+                def,
+                "gitlab",
+                "master_branch",
+                String,
+                GitlabGitProjectArg::MasterBranch,
+                "master"
+            ),
+            staging_branch: toml_arg_default!(
+                def,
+                if is_try { "try.gitlab" } else { "gitlab" },
+                if is_try { "branch" } else { "staging_branch" },
+                String,
+                GitlabGitProjectArg::StagingBranch,
+                if is_try { "trying" } else { "staging" }
+            ),
+        };
+        self.0.entry(pipeline_id).or_insert(repo);
+        Ok(())
+    }
+}
+
+impl gitlab_git::PipelinesConfig for StaticGitlabGitPipelinesConfig {
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<gitlab_git::Repo> {
+        return self.0.get(&pipeline_id).map(Clone::clone)
+    }
+}
+
+struct StaticGiteaProjectsConfig(
+    HashMap<(gitea::Repo, String), gitea::RepoPipelines>
+);
+
+impl StaticGiteaProjectsConfig {
+    fn new() -> Self {
+        StaticGiteaProjectsConfig(HashMap::new())
+    }
+    fn add_project(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        pipeline_id: PipelineId
+    ) -> Result<(), SetupError<GiteaProjectArg>> {
+        if def.lookup("gitea").is_none() {
+            return Err(SetupError::NotFoundConfig);
+        }
+        // PRs are routed to the project that merges into their base.
+        let branch = toml_arg_default!(
+            def,
+            "gitea",
+            "master_branch",
+            String,
+            GiteaProjectArg::MasterBranch,
+            "master"
+        );
+        let repo = gitea::Repo{
+            owner: toml_arg_default!(
+                def,
+                "gitea",
+                "owner",
+                String,
+                GiteaProjectArg::Owner,
+                toml_arg!(
+                    config,
+                    "gitea",
+                    "owner",
+                    String,
+                    GiteaProjectArg::Owner
+                )
+            ),
+            repo: toml_arg_default!(
+                def,
+                "gitea",
+                "repo",
+                String,
+                GiteaProjectArg::Repo,
+                name
+            )
+        };
+        self.0.insert(
+            (repo, branch),
+            gitea::RepoPipelines{
+                pipeline_id: pipeline_id,
+                try_pipeline_id: if def.lookup("try").is_some() {
+                    Some(PipelineId(pipeline_id.0 + 1))
+                } else {
+                    None
+                },
+            }
+        );
+        Ok(())
+    }
+}
+
+impl gitea::ProjectsConfig for StaticGiteaProjectsConfig {
+    fn pipelines_by_repo(
+        &self,
+        repo: &gitea::Repo,
+        branch: &str,
+    ) -> Option<gitea::RepoPipelines> {
+        self.0.get(&(repo.clone(), branch.to_owned())).map(Clone::clone)
+    }
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(gitea::Repo, gitea::PipelineType)> {
+        for (&(ref repo, _), pipelines) in self.0.iter() {
+            if pipelines.pipeline_id == pipeline_id {
+                return Some((repo.clone(), gitea::PipelineType::Stage));
+            }
+            if pipelines.try_pipeline_id == Some(pipeline_id) {
+                return Some((repo.clone(), gitea::PipelineType::Try));
+            }
+        }
+        return None;
+    }
+}
+
+
+struct StaticBitbucketProjectsConfig(
+    HashMap<(bitbucket::Repo, String), bitbucket::RepoPipelines>
+);
+
+impl StaticBitbucketProjectsConfig {
+    fn new() -> Self {
+        StaticBitbucketProjectsConfig(HashMap::new())
+    }
+    fn add_project(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        pipeline_id: PipelineId
+    ) -> Result<(), SetupError<BitbucketProjectArg>> {
+        if def.lookup("bitbucket").is_none() {
+            return Err(SetupError::NotFoundConfig);
+        }
+        // PRs are routed to the project that merges into their base.
+        let branch = toml_arg_default!(
+            def,
+            "bitbucket",
+            "master_branch",
+            String,
+            BitbucketProjectArg::MasterBranch,
+            "master"
+        );
+        let repo = bitbucket::Repo{
+            owner: toml_arg_default!(
+                def,
+                "bitbucket",
+                "owner",
+                String,
+                BitbucketProjectArg::Owner,
+                toml_arg!(
+                    config,
+                    "bitbucket",
+                    "owner",
+                    String,
+                    BitbucketProjectArg::Owner
+                )
+            ),
+            repo: toml_arg_default!(
+                def,
+                "bitbucket",
+                "repo",
+                String,
+                BitbucketProjectArg::Repo,
+                name
+            )
+        };
+        self.0.insert(
+            (repo, branch),
+            bitbucket::RepoPipelines{
+                pipeline_id: pipeline_id,
+                try_pipeline_id: if def.lookup("try").is_some() {
+                    Some(PipelineId(pipeline_id.0 + 1))
+                } else {
+                    None
+                },
+            }
+        );
+        Ok(())
+    }
+}
+
+impl bitbucket::ProjectsConfig for StaticBitbucketProjectsConfig {
+    fn pipelines_by_repo(
+        &self,
+        repo: &bitbucket::Repo,
+        branch: &str,
+    ) -> Option<bitbucket::RepoPipelines> {
+        self.0.get(&(repo.clone(), branch.to_owned())).map(Clone::clone)
+    }
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(bitbucket::Repo, bitbucket::PipelineType)> {
+        for (&(ref repo, _), pipelines) in self.0.iter() {
+            if pipelines.pipeline_id == pipeline_id {
+                return Some((repo.clone(), bitbucket::PipelineType::Stage));
+            }
+            if pipelines.try_pipeline_id == Some(pipeline_id) {
+                return Some((repo.clone(), bitbucket::PipelineType::Try));
+            }
+        }
+        return None;
+    }
+}
+
+struct StaticApiProjectsConfig(HashMap<String, api::RepoPipelines>);
+
+impl StaticApiProjectsConfig {
+    fn new() -> Self {
+        StaticApiProjectsConfig(HashMap::new())
+    }
+    fn add_project(
+        &mut self,
+        name: &str,
+        def: &toml::Value,
+        pipeline_id: PipelineId
+    ) -> Result<(), SetupError<ApiProjectArg>> {
+        if def.lookup("api").is_none() {
+            return Err(SetupError::NotFoundConfig);
+        }
+        let project = toml_arg_default!(
+            def,
+            "api",
+            "name",
+            String,
+            ApiProjectArg::Name,
+            name
+        );
+        self.0.insert(
+            project,
+            api::RepoPipelines{
+                pipeline_id: pipeline_id,
+                try_pipeline_id: if def.lookup("try").is_some() {
+                    Some(PipelineId(pipeline_id.0 + 1))
+                } else {
+                    None
+                },
+            }
+        );
+        Ok(())
+    }
+}
+
+impl api::ProjectsConfig for StaticApiProjectsConfig {
+    fn pipelines_by_project(&self, project: &str)
+            -> Option<api::RepoPipelines> {
+        self.0.get(project).map(Clone::clone)
+    }
+    fn project_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(String, api::PipelineType)> {
+        for (project, pipelines) in self.0.iter() {
+            if pipelines.pipeline_id == pipeline_id {
+                return Some((project.clone(), api::PipelineType::Stage));
+            }
+            if pipelines.try_pipeline_id == Some(pipeline_id) {
+                return Some((project.clone(), api::PipelineType::Try));
+            }
+        }
+        return None;
+    }
+}
+
+struct StaticGiteaGitPipelinesConfig(
+    HashMap<PipelineId, gitea_git::Repo>
+);
+
+impl StaticGiteaGitPipelinesConfig {
+    fn new() -> Self {
+        StaticGiteaGitPipelinesConfig(HashMap::new())
+    }
+    fn add_pipeline(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        pipeline_id: PipelineId,
+        is_try: bool,
+    ) -> Result<(), SetupError<GiteaGitProjectArg>> {
+        if def.lookup("gitea").is_none() {
+            return Err(SetupError::NotFoundConfig);
+        }
+        let repo = gitea_git::Repo{
+            owner: toml_arg_default!(
+                def,
+                "gitea",
+                "owner",
+                String,
+                GiteaGitProjectArg::Owner,
+                toml_arg!(
+                    config,
+                    "gitea",
+                    "owner",
+                    String,
+                    GiteaGitProjectArg::Owner
+                )
+            ),
+            repo: toml_arg_default!(
+                def,
+                "gitea",
+                "repo",
+                String,
+                GiteaGitProjectArg::Repo,
+                name
+            ),
+            master_branch: toml_arg_default!(
+                def,
+                "gitea",
+                "master_branch",
+                String,
+                GiteaGitProjectArg::MasterBranch,
+                "master"
+            ),
+            staging_branch: toml_arg_default!(
+                def,
+                if is_try { "try.gitea" } else { "gitea" },
+                if is_try { "branch" } else { "staging_branch" },
+                String,
+                GiteaGitProjectArg::StagingBranch,
+                if is_try { "trying" } else { "staging" }
+            ),
+        };
+        self.0.entry(pipeline_id).or_insert(repo);
+        Ok(())
+    }
+}
+
+impl gitea_git::PipelinesConfig for StaticGiteaGitPipelinesConfig {
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<gitea_git::Repo> {
+        return self.0.get(&pipeline_id).map(Clone::clone)
+    }
+}
+
+struct StaticHooksPipelinesConfig(HashMap<PipelineId, (String, bool)>);
+
+impl StaticHooksPipelinesConfig {
+    fn new() -> Self {
+        StaticHooksPipelinesConfig(HashMap::new())
+    }
+    fn add_pipeline(
+        &mut self,
+        name: &str,
+        pipeline_id: PipelineId,
+        is_try: bool,
+    ) {
+        self.0.insert(pipeline_id, (name.to_owned(), is_try));
+    }
+}
+
+impl hooks::PipelinesConfig for StaticHooksPipelinesConfig {
+    fn project_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(String, bool)> {
+        self.0.get(&pipeline_id).map(Clone::clone)
+    }
+}
+
+struct StaticViewPipelinesConfig(HashMap<String, PipelineId>);
+
+impl StaticViewPipelinesConfig {
+    fn new() -> Self {
+        StaticViewPipelinesConfig(HashMap::new())
+    } 
+    fn add_pipeline(
+        &mut self,
+        name: &str,
+        _config: &toml::Value,
+        _def: &toml::Value,
+        pipeline_id: PipelineId,
+    ) -> Result<(), SetupError<ViewProjectArg>> {
+        self.0.insert(name.to_owned(), pipeline_id);
+        Ok(())
+    }
+}
+
+impl view::PipelinesConfig for StaticViewPipelinesConfig {
+    fn pipeline_by_name(&self, name: &str) -> Option<PipelineId> {
+        self.0.get(name).map(|x| *x)
+    }
+    fn all(&self) -> Vec<(Cow<str>, PipelineId)> {
+        self.0.iter().map(|x| (Cow::Borrowed(&x.0[..]), *x.1)).collect()
+    }
+}
+
+// Errors and args definitions.
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum GithubBuilderError {
+        OpenFile(err: ::std::io::Error) {
+            cause(err)
+        }
+        ReadFile(err: ::std::io::Error) {
+            cause(err)
+        }
+        Parse {}
+        NoConfig {}
+        NoProjects {}
+        NoConfigGithub {}
+        Dangling {}
+        Github(err: SetupError<GithubArg>) {
+            cause(err)
+        }
+        GithubStatus(err: SetupError<GithubStatusArg>) {
+            cause(err)
+        }
+        Jenkins(err: SetupError<JenkinsArg>) {
+            cause(err)
+        }
+        Git(err: SetupError<GitArg>) {
+            cause(err)
+        }
+        GithubGit(err: SetupError<GithubGitArg>) {
+            cause(err)
+        }
+        Gitlab(err: SetupError<GitlabArg>) {
+            cause(err)
+        }
+        GitlabPipeline(err: SetupError<GitlabPipelineArg>) {
+            cause(err)
+        }
+        GitlabGit(err: SetupError<GitlabGitArg>) {
+            cause(err)
+        }
+        Gitea(err: SetupError<GiteaArg>) {
+            cause(err)
+        }
+        GiteaGit(err: SetupError<GiteaGitArg>) {
+            cause(err)
+        }
+        Bitbucket(err: SetupError<BitbucketArg>) {
+            cause(err)
+        }
+        Api(err: SetupError<ApiArg>) {
+            cause(err)
+        }
+        View(err: SetupError<ViewArg>) {
+            cause(err)
+        }
+        Hooks(err: SetupError<HooksArg>) {
+            cause(err)
+        }
+        Project(err: SetupError<ProjectArg>) {
             cause(err)
         }
         GithubProject(err: SetupError<GithubProjectArg>) {
@@ -1180,6 +2695,27 @@ quick_error! {
         GithubGitProject(err: SetupError<GithubGitProjectArg>) {
             cause(err)
         }
+        GitlabProject(err: SetupError<GitlabProjectArg>) {
+            cause(err)
+        }
+        GitlabPipelineProject(err: SetupError<GitlabPipelineProjectArg>) {
+            cause(err)
+        }
+        GitlabGitProject(err: SetupError<GitlabGitProjectArg>) {
+            cause(err)
+        }
+        GiteaProject(err: SetupError<GiteaProjectArg>) {
+            cause(err)
+        }
+        GiteaGitProject(err: SetupError<GiteaGitProjectArg>) {
+            cause(err)
+        }
+        BitbucketProject(err: SetupError<BitbucketProjectArg>) {
+            cause(err)
+        }
+        ApiProject(err: SetupError<ApiProjectArg>) {
+            cause(err)
+        }
         ViewProject(err: SetupError<ViewProjectArg>) {
             cause(err)
         }
@@ -1202,9 +2738,16 @@ pub enum GithubArg {
     Listen,
     Host,
     Token,
+    AppId,
+    PrivateKey,
     User,
     Secret,
     Db,
+    SyncInterval,
+    Checks,
+    ReplayLog,
+    AdminListen,
+    PermissionTtl,
 }
 
 #[derive(Debug)]
@@ -1233,6 +2776,66 @@ pub enum GithubGitArg {
     Token,
 }
 
+#[derive(Debug)]
+pub enum GitlabArg {
+    Listen,
+    Host,
+    Token,
+    User,
+    Secret,
+}
+
+#[derive(Debug)]
+pub enum GitlabPipelineArg {
+    Listen,
+    Secret,
+}
+
+#[derive(Debug)]
+pub enum GitlabGitArg {
+    Host,
+    Token,
+    Executable,
+    Path,
+}
+
+#[derive(Debug)]
+pub enum GiteaArg {
+    Listen,
+    Host,
+    Token,
+    User,
+    Secret,
+}
+
+#[derive(Debug)]
+pub enum GiteaGitArg {
+    Host,
+    Token,
+}
+
+#[derive(Debug)]
+pub enum BitbucketArg {
+    Listen,
+    Host,
+    Token,
+    User,
+    Secret,
+}
+
+#[derive(Debug)]
+pub enum ApiArg {
+    Listen,
+    Secret,
+    Callback,
+}
+
+#[derive(Debug)]
+pub enum HooksArg {
+    Urls,
+    Secret,
+}
+
 #[derive(Debug)]
 pub enum ViewArg {
     Listen,
@@ -1247,12 +2850,21 @@ pub enum ViewArg {
 #[derive(Debug)]
 pub enum ProjectArg {
     Project,
+    BatchSize,
+    Depth,
+    CiTimeout,
+    AutoRetry,
 }
 
 #[derive(Debug)]
 pub enum GithubProjectArg {
     Owner,
     Repo,
+    ReviewApproves,
+    Reviewers,
+    ReviewerTeams,
+    TryUsers,
+    TryTeams,
 }
 
 #[derive(Debug)]
@@ -1284,6 +2896,55 @@ pub enum GithubGitProjectArg {
     StagingBranch,
 }
 
+#[derive(Debug)]
+pub enum GitlabProjectArg {
+    Owner,
+    Repo,
+    MasterBranch,
+}
+
+#[derive(Debug)]
+pub enum GitlabPipelineProjectArg {
+    Owner,
+    Repo,
+    Pipeline,
+}
+
+#[derive(Debug)]
+pub enum GitlabGitProjectArg {
+    Owner,
+    Repo,
+    MasterBranch,
+    StagingBranch,
+}
+
+#[derive(Debug)]
+pub enum GiteaProjectArg {
+    Owner,
+    Repo,
+    MasterBranch,
+}
+
+#[derive(Debug)]
+pub enum GiteaGitProjectArg {
+    Owner,
+    Repo,
+    MasterBranch,
+    StagingBranch,
+}
+
+#[derive(Debug)]
+pub enum BitbucketProjectArg {
+    Owner,
+    Repo,
+    MasterBranch,
+}
+
+#[derive(Debug)]
+pub enum ApiProjectArg {
+    Name,
+}
+
 #[derive(Debug)]
 pub enum ViewProjectArg {}
 
@@ -1293,6 +2954,7 @@ pub enum SetupError<T: Debug> {
     NotTableConfig,
     NotFoundArg(T),
     InvalidArg(T, Ty),
+    UnreadableArg(T),
 }
 
 impl<T: Any + Debug> Error for SetupError<T> {
@@ -1302,6 +2964,7 @@ impl<T: Any + Debug> Error for SetupError<T> {
             SetupError::NotTableConfig => "Config is not a table",
             SetupError::NotFoundArg(_) => "Argument not found",
             SetupError::InvalidArg(_, _) => "Argument of wrong type",
+            SetupError::UnreadableArg(_) => "Argument names unreadable file",
         }
     }
 }
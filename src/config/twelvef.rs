@@ -1,14 +1,26 @@
 // This file is released under the same terms as Rust itself.
 
-use ci::{self, github_status, jenkins};
+use ci::{self, github_status, gitlab_pipeline, jenkins};
 use config::{PipelinesConfig, WorkerBuilder};
-use db::{self, DbBox};
+use db;
+use hooks;
+use hyper::Url;
 use pipeline::WorkerManager;
 use pipeline::WorkerThread;
 use std::error::Error;
-use ui::{self, github};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use timer;
+use ui::{self, api, bitbucket, gitea, github, gitlab};
+use util::github_auth::Credentials;
+use util::bitbucket_api;
+use util::gitea_api;
+use util::gitlab_api;
 use vcs::{self, git};
 use vcs::github as github_git;
+use vcs::gitea as gitea_git;
+use vcs::gitlab as gitlab_git;
 use view;
 
 pub struct GithubBuilder {
@@ -28,7 +40,12 @@ pub struct GithubBuilder {
         view::Event,
         view::Message,
     >,
-    db: DbBox,
+    hooks: Option<WorkerThread<
+        hooks::Event,
+        hooks::Message,
+    >>,
+    outbox: Option<hooks::Outbox>,
+    db: db::Builder,
     pipelines: Box<PipelinesConfig>,
 }
 
@@ -53,7 +70,12 @@ impl GithubBuilder {
     }
     pub fn build_from_env<F: Fn(&str) -> Option<String>>(env: F)
             -> Result<Self, GithubBuilderError> {
-        if try_env!(env, "UI_TYPE", UiType) != "github" {
+        let ui_type = try_env!(env, "UI_TYPE", UiType);
+        let known_ui_type = match &ui_type[..] {
+            "github" | "gitlab" | "gitea" | "bitbucket" | "api" => true,
+            _ => false,
+        };
+        if !known_ui_type {
             return Err(GithubBuilderError::NotGithub);
         }
         let db_key = try_env!(env, "PIPELINE_DB", PipelineDb);
@@ -61,40 +83,65 @@ impl GithubBuilder {
             Ok(db_builder) => db_builder,
             Err(e) => return Err(GithubBuilderError::DbConnect(e)),
         };
-        let db = match db_builder.open() {
-            Ok(db) => db,
-            Err(e) => return Err(GithubBuilderError::DbConnect(e)),
-        };
+        if let Err(e) = db_builder.open() {
+            return Err(GithubBuilderError::DbConnect(e));
+        }
         let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
         let pj_builder = match db::Builder::from_str(&pj_key[..]) {
             Ok(pj_builder) => pj_builder,
             Err(e) => return Err(GithubBuilderError::PjConnect(e)),
         };
+        let remote_format = match &ui_type[..] {
+            "gitlab" => gitlab::REMOTE_FORMAT,
+            "gitea" => gitea::REMOTE_FORMAT,
+            "bitbucket" => bitbucket::REMOTE_FORMAT,
+            "api" => api::REMOTE_FORMAT,
+            _ => github::REMOTE_FORMAT,
+        };
         let pipelines: Box<PipelinesConfig> = match pj_builder {
-            db::Builder::Sqlite(d) =>
-                Box::new(try!(sqlite::PipelinesConfig::new(d))),
-            db::Builder::Postgres(d) =>
-                Box::new(try!(postgres::PipelinesConfig::new(d))),
+            db::Builder::Sqlite(d) => Box::new(try!(
+                sqlite::PipelinesConfig::new(d, remote_format)
+            )),
+            db::Builder::Postgres(d) => Box::new(try!(
+                postgres::PipelinesConfig::new(d, remote_format)
+            )),
+        };
+        let app = try!(setup_github_app(&env));
+        let ui = match &ui_type[..] {
+            "gitlab" => try!(setup_gitlab(&env)),
+            "gitea" => try!(setup_gitea(&env)),
+            "bitbucket" => try!(setup_bitbucket(&env)),
+            "api" => try!(setup_api(&env)),
+            _ => try!(setup_github(&env, app.clone())),
+        };
+        let (hooks, outbox) = match try!(setup_hooks(&env)) {
+            Some((hooks, outbox)) => (Some(hooks), Some(outbox)),
+            None => (None, None),
         };
         Ok(GithubBuilder{
             ci: try!(setup_ci(&env)),
-            ui: try!(setup_github(&env)),
-            vcs: try!(setup_vcs(&env)),
+            ui: ui,
+            vcs: try!(setup_vcs(&env, app)),
             view: try!(setup_view(&env)),
-            db: db,
+            hooks: hooks,
+            outbox: outbox,
+            db: db_builder,
             pipelines: pipelines,
         })
     }
 }
 
 impl WorkerBuilder for GithubBuilder {
-    fn start(self) -> (WorkerManager, DbBox) {
+    fn start(self) -> (WorkerManager, db::Builder) {
         (
             WorkerManager {
                 cis: vec![self.ci],
                 uis: vec![self.ui],
                 vcss: vec![self.vcs],
                 view: Some(self.view),
+                hooks: self.hooks,
+                outbox: self.outbox,
+                timer: WorkerThread::start(timer::Worker::new()),
                 pipelines: self.pipelines,
             },
             self.db,
@@ -102,7 +149,33 @@ impl WorkerBuilder for GithubBuilder {
     }
 }
 
-fn setup_github<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+/// The GitHub App to act as, if there is one instead of a token.
+/// The private key is given in PEM form.
+fn setup_github_app<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+    Option<Arc<Credentials>>,
+    GithubBuilderError,
+> {
+    let app_id = match env("UI_GITHUB_APP_ID") {
+        Some(app_id) => match app_id.parse() {
+            Ok(app_id) => app_id,
+            Err(_) => return Err(GithubBuilderError::InvalidKey(
+                GithubBuilderKey::UiGithubAppId
+            )),
+        },
+        None => return Ok(None),
+    };
+    Ok(Some(Arc::new(Credentials::app(
+        try_env!(env, "UI_GITHUB_HOST", UiGithubHost),
+        app_id,
+        try_env!(env, "UI_GITHUB_PRIVATE_KEY", UiGithubPrivateKey)
+            .into_bytes(),
+    ))))
+}
+
+fn setup_github<F: Fn(&str) -> Option<String>>(
+    env: &F,
+    app: Option<Arc<Credentials>>,
+) -> Result<
     WorkerThread<ui::Event, ui::Message>,
     GithubBuilderError,
 > {
@@ -122,17 +195,249 @@ fn setup_github<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
         Ok(gh_builder) => gh_builder,
         Err(e) => return Err(GithubBuilderError::GhConnect(e)),
     };
+    let db_key = try_env!(env, "PIPELINE_DB", PipelineDb);
+    let db_builder = match db::Builder::from_str(&db_key[..]) {
+        Ok(db_builder) => db_builder,
+        Err(e) => return Err(GithubBuilderError::DbConnect(e)),
+    };
+    let checks = match env("UI_GITHUB_CHECKS") {
+        Some(value) => match value.parse() {
+            Ok(checks) => checks,
+            Err(_) => return Err(GithubBuilderError::InvalidKey(
+                GithubBuilderKey::UiGithubChecks
+            )),
+        },
+        None => false,
+    };
+    // Check runs can only be created with an installation token.
+    if checks && app.is_none() {
+        return Err(GithubBuilderError::MissingKey(
+            GithubBuilderKey::UiGithubAppId
+        ));
+    }
+    let credentials = match app {
+        Some(app) => app,
+        None => Arc::new(Credentials::token(
+            try_env!(env, "UI_GITHUB_HOST", UiGithubHost),
+            try_env!(env, "UI_GITHUB_TOKEN", UiGithubToken),
+        )),
+    };
     Ok(WorkerThread::start(github::Worker::new(
         try_env!(env, "UI_GITHUB_LISTEN", UiGithubListen),
-        try_env!(env, "UI_GITHUB_HOST", UiGithubHost),
-        try_env!(env, "UI_GITHUB_TOKEN", UiGithubToken),
+        credentials,
         try_env!(env, "UI_GITHUB_USER", UiGithubUser),
-        try_env!(env, "UI_GITHUB_SECRET", UiGithubSecret),
+        secrets(try_env!(env, "UI_GITHUB_SECRET", UiGithubSecret)),
         projects,
         gh_builder,
+        db_builder,
+        match env("UI_GITHUB_SYNC_INTERVAL") {
+            Some(ref value) if value == "0" => None,
+            Some(value) => match value.parse() {
+                Ok(secs) => Some(Duration::from_secs(secs)),
+                Err(_) => return Err(GithubBuilderError::InvalidKey(
+                    GithubBuilderKey::UiGithubSyncInterval
+                )),
+            },
+            None => Some(Duration::from_secs(3600)),
+        },
+        checks,
+        env("UI_GITHUB_REPLAY_LOG").map(PathBuf::from),
+        try!(setup_github_admin_listen(env)),
+        match env("UI_GITHUB_PERMISSION_TTL") {
+            Some(value) => match value.parse() {
+                Ok(secs) => Duration::from_secs(secs),
+                Err(_) => return Err(GithubBuilderError::InvalidKey(
+                    GithubBuilderKey::UiGithubPermissionTtl
+                )),
+            },
+            None => Duration::from_secs(300),
+        },
+    )))
+}
+
+fn setup_gitlab<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+    WorkerThread<ui::Event, ui::Message>,
+    GithubBuilderError,
+> {
+    let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
+    let pj_builder = match db::Builder::from_str(&pj_key[..]) {
+        Ok(pj_builder) => pj_builder,
+        Err(e) => return Err(GithubBuilderError::PjConnect(e)),
+    };
+    let projects: Box<gitlab::ProjectsConfig> = match pj_builder {
+        db::Builder::Sqlite(d) =>
+            Box::new(try!(sqlite::GitlabProjectsConfig::new(d))),
+        db::Builder::Postgres(d) =>
+            Box::new(try!(postgres::GitlabProjectsConfig::new(d))),
+    };
+    Ok(WorkerThread::start(gitlab::Worker::new(
+        try_env!(env, "UI_GITLAB_LISTEN", UiGitlabListen),
+        Arc::new(gitlab_api::Client::new(
+            try_env!(env, "UI_GITLAB_HOST", UiGitlabHost),
+            try_env!(env, "UI_GITLAB_TOKEN", UiGitlabToken),
+        )),
+        try_env!(env, "UI_GITLAB_USER", UiGitlabUser),
+        secrets(try_env!(env, "UI_GITLAB_SECRET", UiGitlabSecret)),
+        projects,
+    )))
+}
+
+fn setup_gitea<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+    WorkerThread<ui::Event, ui::Message>,
+    GithubBuilderError,
+> {
+    let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
+    let pj_builder = match db::Builder::from_str(&pj_key[..]) {
+        Ok(pj_builder) => pj_builder,
+        Err(e) => return Err(GithubBuilderError::PjConnect(e)),
+    };
+    let projects: Box<gitea::ProjectsConfig> = match pj_builder {
+        db::Builder::Sqlite(d) =>
+            Box::new(try!(sqlite::GiteaProjectsConfig::new(d))),
+        db::Builder::Postgres(d) =>
+            Box::new(try!(postgres::GiteaProjectsConfig::new(d))),
+    };
+    Ok(WorkerThread::start(gitea::Worker::new(
+        try_env!(env, "UI_GITEA_LISTEN", UiGiteaListen),
+        Arc::new(gitea_api::Client::new(
+            try_env!(env, "UI_GITEA_HOST", UiGiteaHost),
+            try_env!(env, "UI_GITEA_TOKEN", UiGiteaToken),
+        )),
+        try_env!(env, "UI_GITEA_USER", UiGiteaUser),
+        secrets(try_env!(env, "UI_GITEA_SECRET", UiGiteaSecret)),
+        projects,
+    )))
+}
+
+fn setup_bitbucket<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+    WorkerThread<ui::Event, ui::Message>,
+    GithubBuilderError,
+> {
+    let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
+    let pj_builder = match db::Builder::from_str(&pj_key[..]) {
+        Ok(pj_builder) => pj_builder,
+        Err(e) => return Err(GithubBuilderError::PjConnect(e)),
+    };
+    let projects: Box<bitbucket::ProjectsConfig> = match pj_builder {
+        db::Builder::Sqlite(d) =>
+            Box::new(try!(sqlite::BitbucketProjectsConfig::new(d))),
+        db::Builder::Postgres(d) =>
+            Box::new(try!(postgres::BitbucketProjectsConfig::new(d))),
+    };
+    Ok(WorkerThread::start(bitbucket::Worker::new(
+        try_env!(env, "UI_BITBUCKET_LISTEN", UiBitbucketListen),
+        Arc::new(bitbucket_api::Client::new(
+            try_env!(env, "UI_BITBUCKET_HOST", UiBitbucketHost),
+            try_env!(env, "UI_BITBUCKET_TOKEN", UiBitbucketToken),
+        )),
+        try_env!(env, "UI_BITBUCKET_USER", UiBitbucketUser),
+        secrets(try_env!(env, "UI_BITBUCKET_SECRET", UiBitbucketSecret)),
+        projects,
+    )))
+}
+
+fn setup_api<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+    WorkerThread<ui::Event, ui::Message>,
+    GithubBuilderError,
+> {
+    let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
+    let pj_builder = match db::Builder::from_str(&pj_key[..]) {
+        Ok(pj_builder) => pj_builder,
+        Err(e) => return Err(GithubBuilderError::PjConnect(e)),
+    };
+    let projects: Box<api::ProjectsConfig> = match pj_builder {
+        db::Builder::Sqlite(d) =>
+            Box::new(try!(sqlite::ApiProjectsConfig::new(d))),
+        db::Builder::Postgres(d) =>
+            Box::new(try!(postgres::ApiProjectsConfig::new(d))),
+    };
+    // Without a callback, statuses are only shown on the dashboard.
+    let callback = match env("UI_API_CALLBACK") {
+        Some(callback) => match Url::parse(&callback) {
+            Ok(callback) => Some(callback),
+            Err(_) => return Err(GithubBuilderError::InvalidKey(
+                GithubBuilderKey::UiApiCallback
+            )),
+        },
+        None => None,
+    };
+    Ok(WorkerThread::start(api::Worker::new(
+        try_env!(env, "UI_API_LISTEN", UiApiListen),
+        secrets(try_env!(env, "UI_API_SECRET", UiApiSecret)),
+        callback,
+        projects,
     )))
 }
 
+/// Just enough of the environment to tell a running instance what to do.
+pub fn admin_from_os_env()
+        -> Result<github::admin::Admin, GithubBuilderError> {
+    admin_from_env(|var| {
+        let var = "AELITA_".to_owned() + var;
+        ::std::env::var(&var[..]).ok()
+    })
+}
+
+pub fn admin_from_env<F: Fn(&str) -> Option<String>>(env: F)
+        -> Result<github::admin::Admin, GithubBuilderError> {
+    let secret = secrets(try_env!(env, "UI_GITHUB_SECRET", UiGithubSecret))
+        .into_iter()
+        .next();
+    let secret = match secret {
+        Some(secret) => secret,
+        None => return Err(GithubBuilderError::InvalidKey(
+            GithubBuilderKey::UiGithubSecret
+        )),
+    };
+    Ok(github::admin::Admin {
+        admin_listen: try!(setup_github_admin_listen(&env)),
+        secret: secret,
+        replay_log: env("UI_GITHUB_REPLAY_LOG").map(PathBuf::from),
+    })
+}
+
+/// Anyone who can reach the admin listener can replay webhooks
+/// past the duplicate check, so only take admin requests from this machine.
+fn setup_github_admin_listen<F: Fn(&str) -> Option<String>>(env: &F)
+        -> Result<Option<String>, GithubBuilderError> {
+    match env("UI_GITHUB_ADMIN_LISTEN") {
+        Some(ref listen) if !github::admin::is_loopback(listen) => Err(
+            GithubBuilderError::InvalidKey(
+                GithubBuilderKey::UiGithubAdminListen
+            )
+        ),
+        listen => Ok(listen),
+    }
+}
+
+/// Webhook secrets are separated by commas,
+/// so that a new one can be added before the old one is taken out.
+fn secrets(value: String) -> Vec<String> {
+    comma_list(&value)
+}
+
+/// Users and team slugs are separated by commas in the project DB.
+fn access_list(
+    users: Option<String>,
+    teams: Option<String>,
+) -> Option<github::AccessList> {
+    if users.is_none() && teams.is_none() {
+        return None;
+    }
+    Some(github::AccessList {
+        users: users.map(|users| comma_list(&users)).unwrap_or_else(Vec::new),
+        teams: teams.map(|teams| comma_list(&teams)).unwrap_or_else(Vec::new),
+    })
+}
+
+fn comma_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
 fn setup_ci<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
     WorkerThread<ci::Event, ci::Message>,
     GithubBuilderError,
@@ -140,6 +445,7 @@ fn setup_ci<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
     match &try_env!(env, "CI_TYPE", CiType)[..] {
         "jenkins" => setup_jenkins(env),
         "github_status" => setup_github_status(env),
+        "gitlab_pipeline" => setup_gitlab_pipeline(env),
         _ => Err(GithubBuilderError::InvalidKey(GithubBuilderKey::CiType)),
     }
 }
@@ -187,23 +493,53 @@ fn setup_github_status<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
     };
     Ok(WorkerThread::start(github_status::Worker::new(
         try_env!(env, "CI_GITHUB_LISTEN", CiGithubListen),
-        try_env!(env, "CI_GITHUB_SECRET", CiGithubSecret),
+        secrets(try_env!(env, "CI_GITHUB_SECRET", CiGithubSecret)),
+        pipelines,
+    )))
+}
+
+fn setup_gitlab_pipeline<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+    WorkerThread<ci::Event, ci::Message>,
+    GithubBuilderError,
+> {
+    let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
+    let pj_builder = match db::Builder::from_str(&pj_key[..]) {
+        Ok(pj_builder) => pj_builder,
+        Err(e) => return Err(GithubBuilderError::PjConnect(e)),
+    };
+    let pipelines: Box<gitlab_pipeline::PipelinesConfig> = match pj_builder {
+        db::Builder::Sqlite(d) =>
+            Box::new(try!(sqlite::GitlabPipelinePipelinesConfig::new(d))),
+        db::Builder::Postgres(d) =>
+            Box::new(try!(postgres::GitlabPipelinePipelinesConfig::new(d))),
+    };
+    Ok(WorkerThread::start(gitlab_pipeline::Worker::new(
+        try_env!(env, "CI_GITLAB_LISTEN", CiGitlabListen),
+        secrets(try_env!(env, "CI_GITLAB_SECRET", CiGitlabSecret)),
         pipelines,
     )))
 }
 
-fn setup_vcs<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+fn setup_vcs<F: Fn(&str) -> Option<String>>(
+    env: &F,
+    app: Option<Arc<Credentials>>,
+) -> Result<
     WorkerThread<vcs::Event, vcs::Message>,
     GithubBuilderError,
 > {
     match &try_env!(env, "VCS_TYPE", VcsType)[..] {
         "git" => setup_git(env),
-        "github" => setup_github_git(env),
+        "github" => setup_github_git(env, app),
+        "gitlab" => setup_gitlab_git(env),
+        "gitea" => setup_gitea_git(env),
         _ => Err(GithubBuilderError::InvalidKey(GithubBuilderKey::VcsType)),
     }
 }
 
-fn setup_github_git<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+fn setup_github_git<F: Fn(&str) -> Option<String>>(
+    env: &F,
+    app: Option<Arc<Credentials>>,
+) -> Result<
     WorkerThread<vcs::Event, vcs::Message>,
     GithubBuilderError,
 > {
@@ -218,9 +554,71 @@ fn setup_github_git<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
         db::Builder::Postgres(d) =>
             Box::new(try!(postgres::GithubGitPipelinesConfig::new(d))),
     };
+    // A token of its own wins over the app.
+    let credentials = match (env("VCS_GITHUB_TOKEN"), app) {
+        (Some(token), _) => Arc::new(Credentials::token(
+            try_env!(env, "VCS_GITHUB_HOST", VcsGithubHost),
+            token,
+        )),
+        (None, Some(app)) => app,
+        (None, None) => return Err(GithubBuilderError::MissingKey(
+            GithubBuilderKey::VcsGithubToken
+        )),
+    };
     Ok(WorkerThread::start(github_git::Worker::new(
-        try_env!(env, "VCS_GITHUB_HOST", VcsGithubHost),
-        try_env!(env, "VCS_GITHUB_TOKEN", VcsGithubToken),
+        credentials,
+        pipelines,
+    )))
+}
+
+fn setup_gitlab_git<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+    WorkerThread<vcs::Event, vcs::Message>,
+    GithubBuilderError,
+> {
+    let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
+    let pj_builder = match db::Builder::from_str(&pj_key[..]) {
+        Ok(pj_builder) => pj_builder,
+        Err(e) => return Err(GithubBuilderError::PjConnect(e)),
+    };
+    let pipelines: Box<gitlab_git::PipelinesConfig> = match pj_builder {
+        db::Builder::Sqlite(d) =>
+            Box::new(try!(sqlite::GitlabGitPipelinesConfig::new(d))),
+        db::Builder::Postgres(d) =>
+            Box::new(try!(postgres::GitlabGitPipelinesConfig::new(d))),
+    };
+    Ok(WorkerThread::start(gitlab_git::Worker::new(
+        Arc::new(gitlab_api::Client::new(
+            try_env!(env, "VCS_GITLAB_HOST", VcsGitlabHost),
+            try_env!(env, "VCS_GITLAB_TOKEN", VcsGitlabToken),
+        )),
+        pipelines,
+        env("VCS_GITLAB_EXECUTABLE").unwrap_or("git".to_owned()),
+        PathBuf::from(
+            env("VCS_GITLAB_PATH").unwrap_or("gitlab-git".to_owned())
+        ),
+    )))
+}
+
+fn setup_gitea_git<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+    WorkerThread<vcs::Event, vcs::Message>,
+    GithubBuilderError,
+> {
+    let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
+    let pj_builder = match db::Builder::from_str(&pj_key[..]) {
+        Ok(pj_builder) => pj_builder,
+        Err(e) => return Err(GithubBuilderError::PjConnect(e)),
+    };
+    let pipelines: Box<gitea_git::PipelinesConfig> = match pj_builder {
+        db::Builder::Sqlite(d) =>
+            Box::new(try!(sqlite::GiteaGitPipelinesConfig::new(d))),
+        db::Builder::Postgres(d) =>
+            Box::new(try!(postgres::GiteaGitPipelinesConfig::new(d))),
+    };
+    Ok(WorkerThread::start(gitea_git::Worker::new(
+        Arc::new(gitea_api::Client::new(
+            try_env!(env, "VCS_GITEA_HOST", VcsGiteaHost),
+            try_env!(env, "VCS_GITEA_TOKEN", VcsGiteaToken),
+        )),
         pipelines,
     )))
 }
@@ -277,6 +675,51 @@ fn setup_view<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
     )))
 }
 
+/// Outbound webhooks are only sent if there are URLs to send them to.
+fn setup_hooks<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
+    Option<(WorkerThread<hooks::Event, hooks::Message>, hooks::Outbox)>,
+    GithubBuilderError,
+> {
+    let urls = match env("HOOKS_URLS") {
+        Some(urls) => comma_list(&urls),
+        None => return Ok(None),
+    };
+    let mut parsed_urls = Vec::new();
+    for url in urls {
+        match Url::parse(&url) {
+            Ok(url) => parsed_urls.push(url),
+            Err(_) => return Err(GithubBuilderError::InvalidKey(
+                GithubBuilderKey::HooksUrls
+            )),
+        }
+    }
+    let db_key = try_env!(env, "PIPELINE_DB", PipelineDb);
+    let db_builder = match db::Builder::from_str(&db_key[..]) {
+        Ok(db_builder) => db_builder,
+        Err(e) => return Err(GithubBuilderError::DbConnect(e)),
+    };
+    let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
+    let pj_builder = match db::Builder::from_str(&pj_key[..]) {
+        Ok(pj_builder) => pj_builder,
+        Err(e) => return Err(GithubBuilderError::PjConnect(e)),
+    };
+    let pipelines: Box<hooks::PipelinesConfig> = match pj_builder {
+        db::Builder::Sqlite(d) =>
+            Box::new(try!(sqlite::HooksPipelinesConfig::new(d))),
+        db::Builder::Postgres(d) =>
+            Box::new(try!(postgres::HooksPipelinesConfig::new(d))),
+    };
+    let worker = hooks::Worker::new(
+        parsed_urls.clone(),
+        try_env!(env, "HOOKS_SECRET", HooksSecret),
+        db_builder,
+    );
+    Ok(Some((
+        WorkerThread::start(worker),
+        hooks::Outbox::new(parsed_urls, pipelines),
+    )))
+}
+
 mod sqlite {
     use config::PipelinesConfig as TPipelinesConfig;
     use config::PipelineConfig;
@@ -286,6 +729,8 @@ mod sqlite {
     use std::error::Error;
     use std::path::PathBuf;
     use std::sync::Mutex;
+    use std::time::Duration;
+    use ui::RemoteFormat;
     use ui::github::{self, ProjectsConfig as TGithubProjectsConfig};
     use ci::CiId;
     use ci::jenkins;
@@ -296,12 +741,25 @@ mod sqlite {
     use vcs::git::PipelinesConfig as TGitPipelinesConfig;
     use vcs::github as github_git;
     use vcs::github::PipelinesConfig as TGithubGitPipelinesConfig;
+    use ui::gitlab::{self, ProjectsConfig as TGitlabProjectsConfig};
+    use ci::gitlab_pipeline;
+    use ci::gitlab_pipeline::PipelinesConfig as TGitlabPipelinePipelinesConfig;
+    use vcs::gitlab as gitlab_git;
+    use vcs::gitlab::PipelinesConfig as TGitlabGitPipelinesConfig;
+    use ui::gitea::{self, ProjectsConfig as TGiteaProjectsConfig};
+    use vcs::gitea as gitea_git;
+    use vcs::gitea::PipelinesConfig as TGiteaGitPipelinesConfig;
+    use ui::bitbucket::{self, ProjectsConfig as TBitbucketProjectsConfig};
+    use ui::api::{self, ProjectsConfig as TApiProjectsConfig};
     use view::{PipelinesConfig as TViewPipelinesConfig};
+    use hooks::{PipelinesConfig as THooksPipelinesConfig};
+    use super::access_list;
     pub struct PipelinesConfig {
         conn: Mutex<Connection>,
+        remote_format: RemoteFormat,
     }
     impl PipelinesConfig {
-        pub fn new(path: PathBuf)
+        pub fn new(path: PathBuf, remote_format: RemoteFormat)
                 -> Result<Self, Box<Error + Send + Sync + 'static>>
         {
             let conn = try!(Connection::open(&path));
@@ -309,6 +767,10 @@ mod sqlite {
                 CREATE TABLE IF NOT EXISTS twelvef_config_pipeline (
                     pipeline_id INTEGER PRIMARY KEY,
                     name TEXT,
+                    batch_size INTEGER,
+                    depth INTEGER,
+                    ci_timeout INTEGER,
+                    auto_retry INTEGER,
                     UNIQUE (name)
                 );
                 CREATE TABLE IF NOT EXISTS twelvef_config_pipeline_ci (
@@ -318,11 +780,15 @@ mod sqlite {
             "###));
             Ok(PipelinesConfig{
                 conn: Mutex::new(conn),
+                remote_format: remote_format,
             })
         }
     }
     impl TPipelinesConfig for PipelinesConfig {
-        fn by_pipeline_id(&self, pipeline_id: PipelineId) -> PipelineConfig {
+        fn by_pipeline_id(
+            &self,
+            pipeline_id: PipelineId,
+        ) -> Option<PipelineConfig> {
             let mut ci = Vec::new();
             let conn = self.conn.lock().unwrap();
             let sql = r###"
@@ -338,14 +804,43 @@ mod sqlite {
             for row in rows {
                 ci.push((CiId(row.expect("Get pipeline value")), 0));
             }
+            let sql = r###"
+                SELECT
+                    COALESCE(batch_size, 1),
+                    COALESCE(depth, 1),
+                    ci_timeout,
+                    COALESCE(auto_retry, 0)
+                FROM twelvef_config_pipeline
+                WHERE pipeline_id = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("Prepare pipeline batch size query");
+            let mut rows = stmt
+                .query_map(&[ &pipeline_id.0 ], |row| (
+                    row.get::<_, i32>(0) as usize,
+                    row.get::<_, i32>(1) as usize,
+                    row.get::<_, Option<i32>>(2),
+                    row.get::<_, i32>(3) as u32,
+                ))
+                .expect("Get pipeline batch size");
+            let (batch_size, depth, ci_timeout, auto_retry) = rows.next()
+                .map(|row| row.expect("SQLite to work"))
+                .unwrap_or((1, 1, None, 0));
             let ui = 0;
             let vcs = 0;
-            PipelineConfig{
+            // Pipelines without a row of their own get the defaults.
+            Some(PipelineConfig{
                 pipeline_id: pipeline_id,
                 ci: ci,
                 ui: ui,
                 vcs: vcs,
-            }
+                remote_format: self.remote_format,
+                batch_size: batch_size,
+                depth: depth,
+                ci_timeout: ci_timeout
+                    .map(|secs| Duration::from_secs(secs as u64)),
+                auto_retry: auto_retry,
+            })
         }
         fn by_ci_id(&self, ci_id: CiId) -> PipelineConfig {
             let pipeline_id = {
@@ -364,6 +859,7 @@ mod sqlite {
             };
             let pipeline_id = PipelineId(pipeline_id);
             self.by_pipeline_id(pipeline_id)
+                .expect("Every pipeline to have a config")
         }
         fn len(&self) -> usize {
             let conn = self.conn.lock().unwrap();
@@ -378,6 +874,19 @@ mod sqlite {
                 .expect("Get pipeline count");
             rows.next().map(|row| row.expect("SQLite to work")).unwrap()
         }
+        fn ids(&self) -> Vec<PipelineId> {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT pipeline_id
+                FROM twelvef_config_pipeline
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("Prepare pipeline IDs query");
+            let rows = stmt
+                .query_map(&[], |row| PipelineId(row.get::<_, i32>(0)))
+                .expect("Get pipeline IDs");
+            rows.map(|row| row.expect("SQLite to work")).collect()
+        }
     }
     pub struct GithubProjectsConfig {
         conn: Mutex<Connection>,
@@ -393,38 +902,130 @@ mod sqlite {
                     try_pipeline_id INTEGER NULL,
                     owner TEXT,
                     repo TEXT,
-                    UNIQUE (owner, repo)
+                    branch TEXT NOT NULL DEFAULT 'master',
+                    review_approves INTEGER NOT NULL DEFAULT 0,
+                    reviewers TEXT NULL,
+                    reviewer_teams TEXT NULL,
+                    try_users TEXT NULL,
+                    try_teams TEXT NULL,
+                    UNIQUE (owner, repo, branch)
                 );
             "###));
+            // Tables from before projects had a branch are unique by repo,
+            // so they have to be rebuilt rather than altered.
+            let has_branch = {
+                let mut stmt = try!(conn.prepare(
+                    "PRAGMA table_info(twelvef_github_projects)"
+                ));
+                let names = try!(stmt.query_map(&[], |row| {
+                    row.get::<_, String>(1)
+                }));
+                let mut has_branch = false;
+                for name in names {
+                    if try!(name) == "branch" {
+                        has_branch = true;
+                    }
+                }
+                has_branch
+            };
+            if !has_branch {
+                try!(conn.execute_batch(r###"
+                    BEGIN;
+                    ALTER TABLE twelvef_github_projects
+                        RENAME TO twelvef_github_projects_old;
+                    CREATE TABLE twelvef_github_projects (
+                        pipeline_id INTEGER PRIMARY KEY,
+                        try_pipeline_id INTEGER NULL,
+                        owner TEXT,
+                        repo TEXT,
+                        branch TEXT NOT NULL DEFAULT 'master',
+                        review_approves INTEGER NOT NULL DEFAULT 0,
+                        reviewers TEXT NULL,
+                        reviewer_teams TEXT NULL,
+                        try_users TEXT NULL,
+                        try_teams TEXT NULL,
+                        UNIQUE (owner, repo, branch)
+                    );
+                    INSERT INTO twelvef_github_projects
+                        (pipeline_id, try_pipeline_id, owner, repo)
+                        SELECT pipeline_id, try_pipeline_id, owner, repo
+                        FROM twelvef_github_projects_old;
+                    DROP TABLE twelvef_github_projects_old;
+                    COMMIT;
+                "###));
+            }
             Ok(GithubProjectsConfig{
                 conn: Mutex::new(conn),
             })
         }
     }
     impl TGithubProjectsConfig for GithubProjectsConfig {
-        fn pipelines_by_repo(&self, repo: &github::Repo)
+        fn pipelines_by_repo(&self, repo: &github::Repo, branch: &str)
                 -> Option<github::RepoPipelines>
         {
             let conn = self.conn.lock().unwrap();
             let sql = r###"
-                SELECT pipeline_id, try_pipeline_id
+                SELECT pipeline_id, try_pipeline_id, review_approves,
+                    reviewers, reviewer_teams, try_users, try_teams
                 FROM twelvef_github_projects
-                WHERE owner = ? AND repo = ?
+                WHERE owner = ? AND repo = ? AND branch = ?
             "###;
             let mut stmt = conn.prepare(&sql)
                 .expect("prepare pipelines query");
             let mut rows = stmt
-                .query_map(&[&repo.owner, &repo.repo], |row| {
+                .query_map(&[&repo.owner, &repo.repo, &branch], |row| {
                     github::RepoPipelines{
                         pipeline_id:
                             PipelineId(row.get::<_, i32>(0)),
                         try_pipeline_id:
                             row.get::<_, Option<i32>>(1).map(PipelineId),
+                        review_approves: row.get::<_, bool>(2),
+                        reviewers: access_list(
+                            row.get::<_, Option<String>>(3),
+                            row.get::<_, Option<String>>(4),
+                        ),
+                        try_users: access_list(
+                            row.get::<_, Option<String>>(5),
+                            row.get::<_, Option<String>>(6),
+                        ),
                     }
                 })
                 .expect("get pipelines");
             rows.next().map(|row| row.expect("sqlite to work"))
         }
+        fn all_pipelines_by_repo(&self, repo: &github::Repo)
+                -> Vec<github::RepoPipelines>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT pipeline_id, try_pipeline_id, review_approves,
+                    reviewers, reviewer_teams, try_users, try_teams
+                FROM twelvef_github_projects
+                WHERE owner = ? AND repo = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare pipelines query");
+            let rows = stmt
+                .query_map(&[&repo.owner, &repo.repo], |row| {
+                    github::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
+                        review_approves: row.get::<_, bool>(2),
+                        reviewers: access_list(
+                            row.get::<_, Option<String>>(3),
+                            row.get::<_, Option<String>>(4),
+                        ),
+                        try_users: access_list(
+                            row.get::<_, Option<String>>(5),
+                            row.get::<_, Option<String>>(6),
+                        ),
+                    }
+                })
+                .expect("get pipelines");
+            rows.map(|row| row.expect("sqlite to work")).collect()
+        }
         fn repo_by_pipeline(&self, pipeline_id: PipelineId)
                 -> Option<(github::Repo, github::PipelineType)>
         {
@@ -457,6 +1058,24 @@ mod sqlite {
                 .expect("get repo");
             rows.next().map(|row| row.expect("sqlite to work"))
         }
+        fn repos(&self) -> Vec<github::Repo> {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT DISTINCT owner, repo
+                FROM twelvef_github_projects
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare repos query");
+            let rows = stmt
+                .query_map(&[], |row| {
+                    github::Repo{
+                        owner: row.get::<_, String>(0),
+                        repo: row.get::<_, String>(1),
+                    }
+                })
+                .expect("get repos");
+            rows.map(|row| row.expect("sqlite to work")).collect()
+        }
     }
     pub struct JenkinsPipelinesConfig {
         conn: Mutex<Connection>,
@@ -705,96 +1324,1379 @@ mod sqlite {
             rows.next().map(|row| row.expect("sqlite to work"))
         }
     }
-    pub struct ViewPipelinesConfig {
+    pub struct GitlabProjectsConfig {
         conn: Mutex<Connection>,
     }
-    impl ViewPipelinesConfig {
+    impl GitlabProjectsConfig {
         pub fn new(path: PathBuf)
                 -> Result<Self, Box<Error + Send + Sync + 'static>>
         {
             let conn = try!(Connection::open(path));
-            Ok(ViewPipelinesConfig{
+            try!(conn.execute_batch(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_gitlab_projects (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    try_pipeline_id INTEGER NULL,
+                    owner TEXT,
+                    repo TEXT,
+                    branch TEXT NOT NULL DEFAULT 'master',
+                    UNIQUE (owner, repo, branch)
+                );
+            "###));
+            Ok(GitlabProjectsConfig{
                 conn: Mutex::new(conn),
             })
         }
     }
-    impl TViewPipelinesConfig for ViewPipelinesConfig {
-        fn pipeline_by_name(&self, name: &str)
-                -> Option<PipelineId>
+    impl TGitlabProjectsConfig for GitlabProjectsConfig {
+        fn pipelines_by_repo(&self, repo: &gitlab::Repo, branch: &str)
+                -> Option<gitlab::RepoPipelines>
         {
             let conn = self.conn.lock().unwrap();
             let sql = r###"
-                SELECT pipeline_id
-                FROM twelvef_config_pipeline
-                WHERE name = ?
+                SELECT pipeline_id, try_pipeline_id
+                FROM twelvef_gitlab_projects
+                WHERE owner = ? AND repo = ? AND branch = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare pipelines query");
+            let mut rows = stmt
+                .query_map(&[&repo.owner, &repo.repo, &branch], |row| {
+                    gitlab::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
+                    }
+                })
+                .expect("get pipelines");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(gitlab::Repo, gitlab::PipelineType)>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT owner, repo, pipeline_id
+                FROM twelvef_gitlab_projects
+                WHERE pipeline_id = ? OR try_pipeline_id = ?
             "###;
             let mut stmt = conn.prepare(&sql)
                 .expect("prepare repo query");
             let mut rows = stmt
-                .query_map(&[&name], |row| {
-                    PipelineId(row.get::<_, i32>(0))
+                .query_map(&[&pipeline_id.0, &pipeline_id.0], |row| {
+                    let pipeline_type =
+                        if row.get::<_, i32>(2) == pipeline_id.0 {
+                            gitlab::PipelineType::Stage
+                        } else {
+                            gitlab::PipelineType::Try
+                        };
+                    (
+                        gitlab::Repo{
+                            owner:
+                                row.get::<_, String>(0),
+                            repo:
+                                row.get::<_, String>(1),
+                        },
+                        pipeline_type
+                    )
                 })
                 .expect("get repo");
             rows.next().map(|row| row.expect("sqlite to work"))
         }
-        fn all(&self) -> Vec<(Cow<str>, PipelineId)> {
+    }
+    pub struct GitlabPipelinePipelinesConfig {
+        conn: Mutex<Connection>,
+    }
+    impl GitlabPipelinePipelinesConfig {
+        pub fn new(path: PathBuf)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let conn = try!(Connection::open(path));
+            try!(conn.execute_batch(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_gitlab_pipeline_pipelines (
+                    ci_id INTEGER PRIMARY KEY,
+                    owner TEXT,
+                    repo TEXT
+                );
+            "###));
+            Ok(GitlabPipelinePipelinesConfig{
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+    impl TGitlabPipelinePipelinesConfig for GitlabPipelinePipelinesConfig {
+        fn repo_by_id(&self, id: CiId)
+                -> Option<gitlab_pipeline::Repo>
+        {
             let conn = self.conn.lock().unwrap();
             let sql = r###"
-                SELECT name, pipeline_id
-                FROM twelvef_config_pipeline
+                SELECT owner, repo
+                FROM twelvef_gitlab_pipeline_pipelines
+                WHERE ci_id = ?
             "###;
             let mut stmt = conn.prepare(&sql)
-                .expect("prepare repos query");
+                .expect("prepare repo query");
+            let mut rows = stmt
+                .query_map(&[&id.0], |row| {
+                    gitlab_pipeline::Repo{
+                        owner:
+                            row.get::<_, String>(0),
+                        repo:
+                            row.get::<_, String>(1),
+                    }
+                })
+                .expect("get repo");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+        fn ids_by_repo(&self, repo: &gitlab_pipeline::Repo)
+                -> Vec<CiId>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT ci_id
+                FROM twelvef_gitlab_pipeline_pipelines
+                WHERE owner = ? AND repo = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare pipelines query");
             let rows = stmt
-                .query_map(&[], |row| (
-                    Cow::Owned(row.get::<_, String>(0)),
-                    PipelineId(row.get::<_, i32>(1)),
-                ))
-                .expect("get repos");
+                .query_map(&[&repo.owner, &repo.repo], |row| {
+                    CiId(row.get::<_, i32>(0))
+                })
+                .expect("get pipelines");
             let rows = rows.map(|row| row.expect("sqlite to work")).collect();
             rows
         }
     }
-}
-
-mod postgres {
-    use config::PipelinesConfig as TPipelinesConfig;
-    use config::PipelineConfig;
-    use pipeline::PipelineId;
-    use postgres::{Connection, TlsMode};
-    use postgres::params::{ConnectParams, IntoConnectParams};
-    use std::borrow::Cow;
-    use std::error::Error;
-    use ui::github::{self, ProjectsConfig as TGithubProjectsConfig};
-    use ci::CiId;
-    use ci::jenkins;
-    use ci::jenkins::PipelinesConfig as TJenkinsPipelinesConfig;
-    use ci::github_status;
-    use ci::github_status::PipelinesConfig as TGithubStatusPipelinesConfig;
-    use vcs::git;
-    use vcs::git::PipelinesConfig as TGitPipelinesConfig;
-    use vcs::github as github_git;
-    use vcs::github::PipelinesConfig as TGithubGitPipelinesConfig;
-    use view::{PipelinesConfig as TViewPipelinesConfig};
-    pub struct PipelinesConfig {
-        params: ConnectParams,
+    pub struct GitlabGitPipelinesConfig {
+        conn: Mutex<Connection>,
     }
-    impl PipelinesConfig {
-        pub fn new<Q: IntoConnectParams>(params: Q)
+    impl GitlabGitPipelinesConfig {
+        pub fn new(path: PathBuf)
                 -> Result<Self, Box<Error + Send + Sync + 'static>>
         {
-            let result = PipelinesConfig{
-                params: try!(params.into_connect_params()),
-            };
-            try!(try!(result.conn()).batch_execute(r###"
-                CREATE TABLE IF NOT EXISTS twelvef_config_pipeline (
-                    pipeline_id SERIAL PRIMARY KEY,
-                    name TEXT,
-                    UNIQUE (name)
+            let conn = try!(Connection::open(path));
+            try!(conn.execute_batch(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_gitlab_git_pipelines (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    owner TEXT,
+                    repo TEXT,
+                    master_branch TEXT,
+                    staging_branch TEXT
                 );
-                CREATE TABLE IF NOT EXISTS twelvef_config_pipeline_ci (
-                    ci_id SERIAL PRIMARY KEY,
-                    pipeline_id INTEGER
+            "###));
+            Ok(GitlabGitPipelinesConfig{
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+    impl TGitlabGitPipelinesConfig for GitlabGitPipelinesConfig {
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<gitlab_git::Repo>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT
+                    owner,
+                    repo,
+                    master_branch,
+                    staging_branch
+                FROM twelvef_gitlab_git_pipelines
+                WHERE pipeline_id = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare repo query");
+            let mut rows = stmt
+                .query_map(&[&pipeline_id.0], |row| {
+                    gitlab_git::Repo{
+                        owner:
+                            row.get::<_, String>(0),
+                        repo:
+                            row.get::<_, String>(1),
+                        master_branch:
+                            row.get::<_, String>(2),
+                        staging_branch:
+                            row.get::<_, String>(3),
+                    }
+                })
+                .expect("get repo");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+    }
+    pub struct GiteaProjectsConfig {
+        conn: Mutex<Connection>,
+    }
+    impl GiteaProjectsConfig {
+        pub fn new(path: PathBuf)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let conn = try!(Connection::open(path));
+            try!(conn.execute_batch(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_gitea_projects (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    try_pipeline_id INTEGER NULL,
+                    owner TEXT,
+                    repo TEXT,
+                    branch TEXT NOT NULL DEFAULT 'master',
+                    UNIQUE (owner, repo, branch)
+                );
+            "###));
+            Ok(GiteaProjectsConfig{
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+    impl TGiteaProjectsConfig for GiteaProjectsConfig {
+        fn pipelines_by_repo(&self, repo: &gitea::Repo, branch: &str)
+                -> Option<gitea::RepoPipelines>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT pipeline_id, try_pipeline_id
+                FROM twelvef_gitea_projects
+                WHERE owner = ? AND repo = ? AND branch = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare pipelines query");
+            let mut rows = stmt
+                .query_map(&[&repo.owner, &repo.repo, &branch], |row| {
+                    gitea::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
+                    }
+                })
+                .expect("get pipelines");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(gitea::Repo, gitea::PipelineType)>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT owner, repo, pipeline_id
+                FROM twelvef_gitea_projects
+                WHERE pipeline_id = ? OR try_pipeline_id = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare repo query");
+            let mut rows = stmt
+                .query_map(&[&pipeline_id.0, &pipeline_id.0], |row| {
+                    let pipeline_type =
+                        if row.get::<_, i32>(2) == pipeline_id.0 {
+                            gitea::PipelineType::Stage
+                        } else {
+                            gitea::PipelineType::Try
+                        };
+                    (
+                        gitea::Repo{
+                            owner:
+                                row.get::<_, String>(0),
+                            repo:
+                                row.get::<_, String>(1),
+                        },
+                        pipeline_type
+                    )
+                })
+                .expect("get repo");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+    }
+    pub struct BitbucketProjectsConfig {
+        conn: Mutex<Connection>,
+    }
+    impl BitbucketProjectsConfig {
+        pub fn new(path: PathBuf)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let conn = try!(Connection::open(path));
+            try!(conn.execute_batch(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_bitbucket_projects (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    try_pipeline_id INTEGER NULL,
+                    owner TEXT,
+                    repo TEXT,
+                    branch TEXT NOT NULL DEFAULT 'master',
+                    UNIQUE (owner, repo, branch)
+                );
+            "###));
+            Ok(BitbucketProjectsConfig{
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+    impl TBitbucketProjectsConfig for BitbucketProjectsConfig {
+        fn pipelines_by_repo(&self, repo: &bitbucket::Repo, branch: &str)
+                -> Option<bitbucket::RepoPipelines>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT pipeline_id, try_pipeline_id
+                FROM twelvef_bitbucket_projects
+                WHERE owner = ? AND repo = ? AND branch = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare pipelines query");
+            let mut rows = stmt
+                .query_map(&[&repo.owner, &repo.repo, &branch], |row| {
+                    bitbucket::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
+                    }
+                })
+                .expect("get pipelines");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(bitbucket::Repo, bitbucket::PipelineType)>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT owner, repo, pipeline_id
+                FROM twelvef_bitbucket_projects
+                WHERE pipeline_id = ? OR try_pipeline_id = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare repo query");
+            let mut rows = stmt
+                .query_map(&[&pipeline_id.0, &pipeline_id.0], |row| {
+                    let pipeline_type =
+                        if row.get::<_, i32>(2) == pipeline_id.0 {
+                            bitbucket::PipelineType::Stage
+                        } else {
+                            bitbucket::PipelineType::Try
+                        };
+                    (
+                        bitbucket::Repo{
+                            owner:
+                                row.get::<_, String>(0),
+                            repo:
+                                row.get::<_, String>(1),
+                        },
+                        pipeline_type
+                    )
+                })
+                .expect("get repo");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+    }
+    pub struct ApiProjectsConfig {
+        conn: Mutex<Connection>,
+    }
+    impl ApiProjectsConfig {
+        pub fn new(path: PathBuf)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let conn = try!(Connection::open(path));
+            try!(conn.execute_batch(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_api_projects (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    try_pipeline_id INTEGER NULL,
+                    name TEXT,
+                    UNIQUE (name)
+                );
+            "###));
+            Ok(ApiProjectsConfig{
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+    impl TApiProjectsConfig for ApiProjectsConfig {
+        fn pipelines_by_project(&self, project: &str)
+                -> Option<api::RepoPipelines>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT pipeline_id, try_pipeline_id
+                FROM twelvef_api_projects
+                WHERE name = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare pipelines query");
+            let mut rows = stmt
+                .query_map(&[&project], |row| {
+                    api::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
+                    }
+                })
+                .expect("get pipelines");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+        fn project_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(String, api::PipelineType)>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT name, pipeline_id
+                FROM twelvef_api_projects
+                WHERE pipeline_id = ? OR try_pipeline_id = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare project query");
+            let mut rows = stmt
+                .query_map(&[&pipeline_id.0, &pipeline_id.0], |row| {
+                    let pipeline_type =
+                        if row.get::<_, i32>(1) == pipeline_id.0 {
+                            api::PipelineType::Stage
+                        } else {
+                            api::PipelineType::Try
+                        };
+                    (row.get::<_, String>(0), pipeline_type)
+                })
+                .expect("get project");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+    }
+    pub struct GiteaGitPipelinesConfig {
+        conn: Mutex<Connection>,
+    }
+    impl GiteaGitPipelinesConfig {
+        pub fn new(path: PathBuf)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let conn = try!(Connection::open(path));
+            try!(conn.execute_batch(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_gitea_git_pipelines (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    owner TEXT,
+                    repo TEXT,
+                    master_branch TEXT,
+                    staging_branch TEXT
+                );
+            "###));
+            Ok(GiteaGitPipelinesConfig{
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+    impl TGiteaGitPipelinesConfig for GiteaGitPipelinesConfig {
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<gitea_git::Repo>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT
+                    owner,
+                    repo,
+                    master_branch,
+                    staging_branch
+                FROM twelvef_gitea_git_pipelines
+                WHERE pipeline_id = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare repo query");
+            let mut rows = stmt
+                .query_map(&[&pipeline_id.0], |row| {
+                    gitea_git::Repo{
+                        owner:
+                            row.get::<_, String>(0),
+                        repo:
+                            row.get::<_, String>(1),
+                        master_branch:
+                            row.get::<_, String>(2),
+                        staging_branch:
+                            row.get::<_, String>(3),
+                    }
+                })
+                .expect("get repo");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+    }
+    pub struct HooksPipelinesConfig {
+        conn: Mutex<Connection>,
+    }
+    impl HooksPipelinesConfig {
+        pub fn new(path: PathBuf)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let conn = try!(Connection::open(path));
+            Ok(HooksPipelinesConfig{
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+    impl THooksPipelinesConfig for HooksPipelinesConfig {
+        fn project_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(String, bool)>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT name
+                FROM twelvef_config_pipeline
+                WHERE pipeline_id = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare name query");
+            let mut rows = stmt
+                .query_map(&[&pipeline_id.0], |row| {
+                    (row.get::<_, String>(0), false)
+                })
+                .expect("get name");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+    }
+    pub struct ViewPipelinesConfig {
+        conn: Mutex<Connection>,
+    }
+    impl ViewPipelinesConfig {
+        pub fn new(path: PathBuf)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let conn = try!(Connection::open(path));
+            Ok(ViewPipelinesConfig{
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+    impl TViewPipelinesConfig for ViewPipelinesConfig {
+        fn pipeline_by_name(&self, name: &str)
+                -> Option<PipelineId>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT pipeline_id
+                FROM twelvef_config_pipeline
+                WHERE name = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare repo query");
+            let mut rows = stmt
+                .query_map(&[&name], |row| {
+                    PipelineId(row.get::<_, i32>(0))
+                })
+                .expect("get repo");
+            rows.next().map(|row| row.expect("sqlite to work"))
+        }
+        fn all(&self) -> Vec<(Cow<str>, PipelineId)> {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT name, pipeline_id
+                FROM twelvef_config_pipeline
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare repos query");
+            let rows = stmt
+                .query_map(&[], |row| (
+                    Cow::Owned(row.get::<_, String>(0)),
+                    PipelineId(row.get::<_, i32>(1)),
+                ))
+                .expect("get repos");
+            let rows = rows.map(|row| row.expect("sqlite to work")).collect();
+            rows
+        }
+    }
+}
+
+mod postgres {
+    use config::PipelinesConfig as TPipelinesConfig;
+    use config::PipelineConfig;
+    use pipeline::PipelineId;
+    use postgres::{Connection, TlsMode};
+    use postgres::params::{ConnectParams, IntoConnectParams};
+    use std::borrow::Cow;
+    use std::error::Error;
+    use std::time::Duration;
+    use ui::RemoteFormat;
+    use ui::github::{self, ProjectsConfig as TGithubProjectsConfig};
+    use ci::CiId;
+    use ci::jenkins;
+    use ci::jenkins::PipelinesConfig as TJenkinsPipelinesConfig;
+    use ci::github_status;
+    use ci::github_status::PipelinesConfig as TGithubStatusPipelinesConfig;
+    use vcs::git;
+    use vcs::git::PipelinesConfig as TGitPipelinesConfig;
+    use vcs::github as github_git;
+    use vcs::github::PipelinesConfig as TGithubGitPipelinesConfig;
+    use ui::gitlab::{self, ProjectsConfig as TGitlabProjectsConfig};
+    use ci::gitlab_pipeline;
+    use ci::gitlab_pipeline::PipelinesConfig as TGitlabPipelinePipelinesConfig;
+    use vcs::gitlab as gitlab_git;
+    use vcs::gitlab::PipelinesConfig as TGitlabGitPipelinesConfig;
+    use ui::gitea::{self, ProjectsConfig as TGiteaProjectsConfig};
+    use vcs::gitea as gitea_git;
+    use vcs::gitea::PipelinesConfig as TGiteaGitPipelinesConfig;
+    use ui::bitbucket::{self, ProjectsConfig as TBitbucketProjectsConfig};
+    use ui::api::{self, ProjectsConfig as TApiProjectsConfig};
+    use view::{PipelinesConfig as TViewPipelinesConfig};
+    use hooks::{PipelinesConfig as THooksPipelinesConfig};
+    use super::access_list;
+    pub struct PipelinesConfig {
+        params: ConnectParams,
+        remote_format: RemoteFormat,
+    }
+    impl PipelinesConfig {
+        pub fn new<Q: IntoConnectParams>(
+            params: Q,
+            remote_format: RemoteFormat,
+        ) -> Result<Self, Box<Error + Send + Sync + 'static>> {
+            let result = PipelinesConfig{
+                params: try!(params.into_connect_params()),
+                remote_format: remote_format,
+            };
+            try!(try!(result.conn()).batch_execute(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_config_pipeline (
+                    pipeline_id SERIAL PRIMARY KEY,
+                    name TEXT,
+                    batch_size INTEGER,
+                    depth INTEGER,
+                    ci_timeout INTEGER,
+                    auto_retry INTEGER,
+                    UNIQUE (name)
+                );
+                CREATE TABLE IF NOT EXISTS twelvef_config_pipeline_ci (
+                    ci_id SERIAL PRIMARY KEY,
+                    pipeline_id INTEGER
+                );
+            "###));
+            Ok(result)
+        }
+        fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
+            Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
+        }
+    }
+    impl TPipelinesConfig for PipelinesConfig {
+        fn by_pipeline_id(
+            &self,
+            pipeline_id: PipelineId,
+        ) -> Option<PipelineConfig> {
+            retry!{{
+                let mut ci = Vec::new();
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT ci_id
+                    FROM twelvef_config_pipeline_ci
+                    WHERE pipeline_id = $1
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(
+                    stmt.query(&[ &pipeline_id.0 ])
+                );
+                let rows = rows.iter();
+                let rows = rows.map(|row| row.get::<_, i32>(0));
+                for row in rows {
+                    ci.push((CiId(row), 0));
+                }
+                let sql = r###"
+                    SELECT
+                        COALESCE(batch_size, 1),
+                        COALESCE(depth, 1),
+                        ci_timeout,
+                        COALESCE(auto_retry, 0)
+                    FROM twelvef_config_pipeline
+                    WHERE pipeline_id = $1
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(
+                    stmt.query(&[ &pipeline_id.0 ])
+                );
+                let (batch_size, depth, ci_timeout, auto_retry) = rows.iter()
+                    .map(|row| (
+                        row.get::<_, i32>(0) as usize,
+                        row.get::<_, i32>(1) as usize,
+                        row.get::<_, Option<i32>>(2),
+                        row.get::<_, i32>(3) as u32,
+                    ))
+                    .next()
+                    .unwrap_or((1, 1, None, 0));
+                let ui = 0;
+                let vcs = 0;
+                // Pipelines without a row of their own get the defaults.
+                Some(PipelineConfig{
+                    pipeline_id: pipeline_id,
+                    ci: ci,
+                    ui: ui,
+                    vcs: vcs,
+                    remote_format: self.remote_format,
+                    batch_size: batch_size,
+                    depth: depth,
+                    ci_timeout: ci_timeout
+                        .map(|secs| Duration::from_secs(secs as u64)),
+                    auto_retry: auto_retry,
+                })
+            }}
+        }
+        fn by_ci_id(&self, ci_id: CiId) -> PipelineConfig {
+            let pipeline_id = (|| {
+                retry!{{
+                    let conn = retry_unwrap!(self.conn());
+                    let sql = r###"
+                        SELECT pipeline_id
+                        FROM twelvef_config_pipeline_ci
+                        WHERE ci_id = $1
+                    "###;
+                    let stmt = retry_unwrap!(conn.prepare(&sql));
+                    let rows = retry_unwrap!(
+                        stmt.query(&[ &ci_id.0 ])
+                    );
+                    let rows = rows.iter();
+                    let mut rows = rows.map(|row| PipelineId(row.get::<_, i32>(0)));
+                    rows.next().unwrap()
+                }}
+            })();
+            self.by_pipeline_id(pipeline_id)
+                .expect("Every pipeline to have a config")
+        }
+        fn len(&self) -> usize {
+            let conn = self.conn().unwrap();
+            let sql = r###"
+                SELECT COUNT(*)
+                FROM twelvef_config_pipeline
+            "###;
+            let stmt = conn.prepare(&sql)
+                .expect("Prepare pipeline count query");
+            let rows = stmt
+                .query(&[])
+                .expect("Get pipeline count");
+            let rows = rows.iter();
+            let mut rows = rows.map(|row| row.get::<_, i64>(0) as usize);
+            rows.next().unwrap()
+        }
+        fn ids(&self) -> Vec<PipelineId> {
+            let conn = self.conn().unwrap();
+            let sql = r###"
+                SELECT pipeline_id
+                FROM twelvef_config_pipeline
+            "###;
+            let stmt = conn.prepare(&sql)
+                .expect("Prepare pipeline IDs query");
+            let rows = stmt
+                .query(&[])
+                .expect("Get pipeline IDs");
+            let rows = rows.iter();
+            rows.map(|row| PipelineId(row.get::<_, i32>(0))).collect()
+        }
+    }
+    pub struct GithubProjectsConfig {
+        params: ConnectParams,
+    }
+    impl GithubProjectsConfig {
+        pub fn new<Q: IntoConnectParams>(params: Q)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let result = GithubProjectsConfig{
+                params: try!(params.into_connect_params()),
+            };
+            try!(try!(result.conn()).batch_execute(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_github_projects (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    try_pipeline_id INTEGER NULL,
+                    owner TEXT,
+                    repo TEXT,
+                    branch TEXT NOT NULL DEFAULT 'master',
+                    review_approves BOOLEAN NOT NULL DEFAULT FALSE,
+                    reviewers TEXT NULL,
+                    reviewer_teams TEXT NULL,
+                    try_users TEXT NULL,
+                    try_teams TEXT NULL,
+                    UNIQUE (owner, repo, branch)
+                );
+            "###));
+            // Tables from before projects had a branch are unique by repo.
+            let conn = try!(result.conn());
+            let has_branch = !try!(conn.query(r###"
+                SELECT 1
+                FROM information_schema.columns
+                WHERE table_name = 'twelvef_github_projects'
+                    AND column_name = 'branch'
+            "###, &[])).is_empty();
+            if !has_branch {
+                try!(conn.batch_execute(r###"
+                    ALTER TABLE twelvef_github_projects
+                        DROP CONSTRAINT twelvef_github_projects_owner_repo_key,
+                        ADD COLUMN branch TEXT NOT NULL DEFAULT 'master',
+                        ADD COLUMN review_approves BOOLEAN NOT NULL
+                            DEFAULT FALSE,
+                        ADD COLUMN reviewers TEXT NULL,
+                        ADD COLUMN reviewer_teams TEXT NULL,
+                        ADD COLUMN try_users TEXT NULL,
+                        ADD COLUMN try_teams TEXT NULL,
+                        ADD UNIQUE (owner, repo, branch);
+                "###));
+            }
+            Ok(result)
+        }
+        fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
+            Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
+        }
+    }
+    impl TGithubProjectsConfig for GithubProjectsConfig {
+        fn pipelines_by_repo(&self, repo: &github::Repo, branch: &str)
+                -> Option<github::RepoPipelines>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT pipeline_id, try_pipeline_id, review_approves,
+                    reviewers, reviewer_teams, try_users, try_teams
+                    FROM twelvef_github_projects
+                    WHERE owner = $1 AND repo = $2 AND branch = $3
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(
+                    stmt.query(&[&repo.owner, &repo.repo, &branch])
+                );
+                let rows = rows.iter();
+                let mut rows = rows.map(|row| {
+                    github::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
+                        review_approves: row.get::<_, bool>(2),
+                        reviewers: access_list(
+                            row.get::<_, Option<String>>(3),
+                            row.get::<_, Option<String>>(4),
+                        ),
+                        try_users: access_list(
+                            row.get::<_, Option<String>>(5),
+                            row.get::<_, Option<String>>(6),
+                        ),
+                    }
+                });
+                rows.next()
+            }}
+        }
+        fn all_pipelines_by_repo(&self, repo: &github::Repo)
+                -> Vec<github::RepoPipelines>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT pipeline_id, try_pipeline_id, review_approves,
+                    reviewers, reviewer_teams, try_users, try_teams
+                    FROM twelvef_github_projects
+                    WHERE owner = $1 AND repo = $2
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(
+                    stmt.query(&[&repo.owner, &repo.repo])
+                );
+                let rows = rows.iter();
+                rows.map(|row| {
+                    github::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
+                        review_approves: row.get::<_, bool>(2),
+                        reviewers: access_list(
+                            row.get::<_, Option<String>>(3),
+                            row.get::<_, Option<String>>(4),
+                        ),
+                        try_users: access_list(
+                            row.get::<_, Option<String>>(5),
+                            row.get::<_, Option<String>>(6),
+                        ),
+                    }
+                }).collect()
+            }}
+        }
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(github::Repo, github::PipelineType)>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT owner, repo, pipeline_id
+                    FROM twelvef_github_projects
+                    WHERE pipeline_id = $1 OR try_pipeline_id = $2
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(
+                    &[&pipeline_id.0, &pipeline_id.0]
+                ));
+                let rows = rows.iter();
+                let mut rows = rows.map(|row| {
+                    let pipeline_type =
+                        if row.get::<_, i32>(2) == pipeline_id.0 {
+                            github::PipelineType::Stage
+                        } else {
+                            github::PipelineType::Try
+                        };
+                    (
+                        github::Repo{
+                            owner:
+                                row.get::<_, String>(0),
+                            repo:
+                                row.get::<_, String>(1),
+                        },
+                        pipeline_type
+                    )
+                });
+                rows.next()
+            }}
+        }
+        fn repos(&self) -> Vec<github::Repo> {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT DISTINCT owner, repo
+                    FROM twelvef_github_projects
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(&[]));
+                let rows = rows.iter();
+                rows.map(|row| {
+                    github::Repo{
+                        owner: row.get::<_, String>(0),
+                        repo: row.get::<_, String>(1),
+                    }
+                }).collect()
+            }}
+        }
+    }
+    pub struct JenkinsPipelinesConfig {
+        params: ConnectParams,
+    }
+    impl JenkinsPipelinesConfig {
+        pub fn new<Q: IntoConnectParams>(params: Q)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let result = JenkinsPipelinesConfig{
+                params: try!(params.into_connect_params()),
+            };
+            try!(try!(result.conn()).batch_execute(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_jenkins_pipelines (
+                    ci_id SERIAL PRIMARY KEY,
+                    name TEXT,
+                    token TEXT
+                );
+            "###));
+            Ok(result)
+        }
+        fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
+            Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
+        }
+    }
+    impl TJenkinsPipelinesConfig for JenkinsPipelinesConfig {
+        fn job_by_id(&self, id: CiId)
+                -> Option<jenkins::Job>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT name, token
+                    FROM twelvef_jenkins_pipelines
+                    WHERE ci_id = $1
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(&[&id.0]));
+                let rows = rows.iter();
+                let mut rows = rows.map(|row| {
+                    jenkins::Job{
+                        name:
+                            row.get::<_, String>(0),
+                        token:
+                            row.get::<_, String>(1),
+                    }
+                });
+                rows.next()
+            }}
+        }
+        fn ids_by_job_name(&self, job: &str)
+                -> Vec<CiId>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT ci_id
+                    FROM twelvef_jenkins_pipelines
+                    WHERE name = $1
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(&[&job]));
+                let rows = rows.iter();
+                let rows = rows.map(|row| {
+                    CiId(row.get::<_, i32>(0))
+                });
+                let rows = rows.collect();
+                rows
+            }}
+        }
+    }
+    pub struct GithubStatusPipelinesConfig {
+        params: ConnectParams,
+    }
+    impl GithubStatusPipelinesConfig {
+        pub fn new<Q: IntoConnectParams>(params: Q)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let result = GithubStatusPipelinesConfig{
+                params: try!(params.into_connect_params()),
+            };
+            try!(try!(result.conn()).batch_execute(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_github_status_pipelines (
+                    ci_id SERIAL PRIMARY KEY,
+                    owner TEXT,
+                    repo TEXT,
+                    context TEXT
+                );
+            "###));
+            Ok(result)
+        }
+        fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
+            Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
+        }
+    }
+    impl TGithubStatusPipelinesConfig for GithubStatusPipelinesConfig {
+        fn repo_by_id(&self, id: CiId)
+                -> Option<github_status::Repo>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT owner, repo, context
+                    FROM twelvef_github_status_pipelines
+                    WHERE ci_id = $1
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(&[&id.0]));
+                let rows = rows.iter();
+                let mut rows = rows.map(|row| {
+                    github_status::Repo{
+                        owner:
+                            row.get::<_, String>(0),
+                        repo:
+                            row.get::<_, String>(1),
+                        context:
+                            row.get::<_, String>(2),
+                    }
+                });
+                rows.next()
+            }}
+        }
+        fn ids_by_repo(&self, repo: &github_status::Repo)
+                -> Vec<CiId>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT ci_id
+                    FROM twelvef_github_status_pipelines
+                    WHERE owner = $1 AND repo = $2 AND context = $3
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(
+                    stmt.query(&[&repo.owner, &repo.repo, &repo.context])
+                );
+                let rows = rows.iter();
+                let rows = rows.map(|row| {
+                    CiId(row.get::<_, i32>(0))
+                });
+                let rows = rows.collect();
+                rows
+            }}
+        }
+    }
+    pub struct GithubGitPipelinesConfig {
+        params: ConnectParams,
+    }
+    impl GithubGitPipelinesConfig {
+        pub fn new<Q: IntoConnectParams>(params: Q)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let result = GithubGitPipelinesConfig{
+                params: try!(params.into_connect_params()),
+            };
+            try!(try!(result.conn()).batch_execute(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_github_git_pipelines (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    owner TEXT,
+                    repo TEXT,
+                    master_branch TEXT,
+                    staging_branch TEXT,
+                    push_to_master BOOLEAN
+                );
+            "###));
+            Ok(result)
+        }
+        fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
+            Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
+        }
+    }
+    impl TGithubGitPipelinesConfig for GithubGitPipelinesConfig {
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<github_git::Repo>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT
+                        owner,
+                        repo,
+                        master_branch,
+                        staging_branch,
+                        push_to_master
+                    FROM twelvef_github_git_pipelines
+                    WHERE pipeline_id = $1
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(&[&pipeline_id.0]));
+                let rows = rows.iter();
+                let mut rows = rows.map(|row| {
+                    github_git::Repo{
+                        owner:
+                            row.get::<_, String>(0),
+                        repo:
+                            row.get::<_, String>(1),
+                        master_branch:
+                            row.get::<_, String>(2),
+                        staging_branch:
+                            row.get::<_, String>(3),
+                        push_to_master:
+                            row.get::<_, bool>(4),
+                    }
+                });
+                rows.next()
+            }}
+        }
+    }
+    pub struct GitPipelinesConfig {
+        params: ConnectParams,
+    }
+    impl GitPipelinesConfig {
+        pub fn new<Q: IntoConnectParams>(params: Q)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let result = GitPipelinesConfig{
+                params: try!(params.into_connect_params()),
+            };
+            try!(try!(result.conn()).batch_execute(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_git_pipelines (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    path TEXT,
+                    origin TEXT,
+                    master_branch TEXT,
+                    staging_branch TEXT,
+                    push_to_master TEXT
+                );
+            "###));
+            Ok(result)
+        }
+        fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
+            Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
+        }
+    }
+    impl TGitPipelinesConfig for GitPipelinesConfig {
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<git::Repo>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT
+                        path,
+                        origin,
+                        master_branch,
+                        staging_branch,
+                        push_to_master
+                    FROM twelvef_git_pipelines
+                    WHERE pipeline_id = $1
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(&[&pipeline_id.0]));
+                let rows = rows.iter();
+                let mut rows = rows.map(|row| {
+                    git::Repo{
+                        path:
+                            row.get::<_, String>(0),
+                        origin:
+                            row.get::<_, String>(1),
+                        master_branch:
+                            row.get::<_, String>(2),
+                        staging_branch:
+                            row.get::<_, String>(3),
+                        push_to_master:
+                            row.get::<_, bool>(4),
+                    }
+                });
+                rows.next()
+            }}
+        }
+    }
+    pub struct GitlabProjectsConfig {
+        params: ConnectParams,
+    }
+    impl GitlabProjectsConfig {
+        pub fn new<Q: IntoConnectParams>(params: Q)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let result = GitlabProjectsConfig{
+                params: try!(params.into_connect_params()),
+            };
+            try!(try!(result.conn()).batch_execute(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_gitlab_projects (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    try_pipeline_id INTEGER NULL,
+                    owner TEXT,
+                    repo TEXT,
+                    branch TEXT NOT NULL DEFAULT 'master',
+                    UNIQUE (owner, repo, branch)
+                );
+            "###));
+            Ok(result)
+        }
+        fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
+            Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
+        }
+    }
+    impl TGitlabProjectsConfig for GitlabProjectsConfig {
+        fn pipelines_by_repo(&self, repo: &gitlab::Repo, branch: &str)
+                -> Option<gitlab::RepoPipelines>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT pipeline_id, try_pipeline_id
+                    FROM twelvef_gitlab_projects
+                    WHERE owner = $1 AND repo = $2 AND branch = $3
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(
+                    stmt.query(&[&repo.owner, &repo.repo, &branch])
+                );
+                let rows = rows.iter();
+                let mut rows = rows.map(|row| {
+                    gitlab::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
+                    }
+                });
+                rows.next()
+            }}
+        }
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(gitlab::Repo, gitlab::PipelineType)>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT owner, repo, pipeline_id
+                    FROM twelvef_gitlab_projects
+                    WHERE pipeline_id = $1 OR try_pipeline_id = $2
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(
+                    &[&pipeline_id.0, &pipeline_id.0]
+                ));
+                let rows = rows.iter();
+                let mut rows = rows.map(|row| {
+                    let pipeline_type =
+                        if row.get::<_, i32>(2) == pipeline_id.0 {
+                            gitlab::PipelineType::Stage
+                        } else {
+                            gitlab::PipelineType::Try
+                        };
+                    (
+                        gitlab::Repo{
+                            owner:
+                                row.get::<_, String>(0),
+                            repo:
+                                row.get::<_, String>(1),
+                        },
+                        pipeline_type
+                    )
+                });
+                rows.next()
+            }}
+        }
+    }
+    pub struct GitlabPipelinePipelinesConfig {
+        params: ConnectParams,
+    }
+    impl GitlabPipelinePipelinesConfig {
+        pub fn new<Q: IntoConnectParams>(params: Q)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let result = GitlabPipelinePipelinesConfig{
+                params: try!(params.into_connect_params()),
+            };
+            try!(try!(result.conn()).batch_execute(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_gitlab_pipeline_pipelines (
+                    ci_id SERIAL PRIMARY KEY,
+                    owner TEXT,
+                    repo TEXT
+                );
+            "###));
+            Ok(result)
+        }
+        fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
+            Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
+        }
+    }
+    impl TGitlabPipelinePipelinesConfig for GitlabPipelinePipelinesConfig {
+        fn repo_by_id(&self, id: CiId)
+                -> Option<gitlab_pipeline::Repo>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT owner, repo
+                    FROM twelvef_gitlab_pipeline_pipelines
+                    WHERE ci_id = $1
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(&[&id.0]));
+                let rows = rows.iter();
+                let mut rows = rows.map(|row| {
+                    gitlab_pipeline::Repo{
+                        owner:
+                            row.get::<_, String>(0),
+                        repo:
+                            row.get::<_, String>(1),
+                    }
+                });
+                rows.next()
+            }}
+        }
+        fn ids_by_repo(&self, repo: &gitlab_pipeline::Repo)
+                -> Vec<CiId>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT ci_id
+                    FROM twelvef_gitlab_pipeline_pipelines
+                    WHERE owner = $1 AND repo = $2
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(
+                    stmt.query(&[&repo.owner, &repo.repo])
+                );
+                let rows = rows.iter();
+                let rows = rows.map(|row| {
+                    CiId(row.get::<_, i32>(0))
+                });
+                let rows = rows.collect();
+                rows
+            }}
+        }
+    }
+    pub struct GitlabGitPipelinesConfig {
+        params: ConnectParams,
+    }
+    impl GitlabGitPipelinesConfig {
+        pub fn new<Q: IntoConnectParams>(params: Q)
+                -> Result<Self, Box<Error + Send + Sync + 'static>>
+        {
+            let result = GitlabGitPipelinesConfig{
+                params: try!(params.into_connect_params()),
+            };
+            try!(try!(result.conn()).batch_execute(r###"
+                CREATE TABLE IF NOT EXISTS twelvef_gitlab_git_pipelines (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    owner TEXT,
+                    repo TEXT,
+                    master_branch TEXT,
+                    staging_branch TEXT
                 );
             "###));
             Ok(result)
@@ -803,88 +2705,58 @@ mod postgres {
             Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
         }
     }
-    impl TPipelinesConfig for PipelinesConfig {
-        fn by_pipeline_id(&self, pipeline_id: PipelineId) -> PipelineConfig {
+    impl TGitlabGitPipelinesConfig for GitlabGitPipelinesConfig {
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<gitlab_git::Repo>
+        {
             retry!{{
-                let mut ci = Vec::new();
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
-                    SELECT ci_id
-                    FROM twelvef_config_pipeline_ci
+                    SELECT
+                        owner,
+                        repo,
+                        master_branch,
+                        staging_branch
+                    FROM twelvef_gitlab_git_pipelines
                     WHERE pipeline_id = $1
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
-                let rows = retry_unwrap!(
-                    stmt.query(&[ &pipeline_id.0 ])
-                );
+                let rows = retry_unwrap!(stmt.query(&[&pipeline_id.0]));
                 let rows = rows.iter();
-                let rows = rows.map(|row| row.get::<_, i32>(0));
-                for row in rows {
-                    ci.push((CiId(row), 0));
-                }
-                let ui = 0;
-                let vcs = 0;
-                PipelineConfig{
-                    pipeline_id: pipeline_id,
-                    ci: ci,
-                    ui: ui,
-                    vcs: vcs,
-                }
+                let mut rows = rows.map(|row| {
+                    gitlab_git::Repo{
+                        owner:
+                            row.get::<_, String>(0),
+                        repo:
+                            row.get::<_, String>(1),
+                        master_branch:
+                            row.get::<_, String>(2),
+                        staging_branch:
+                            row.get::<_, String>(3),
+                    }
+                });
+                rows.next()
             }}
         }
-        fn by_ci_id(&self, ci_id: CiId) -> PipelineConfig {
-            let pipeline_id = (|| {
-                retry!{{
-                    let conn = retry_unwrap!(self.conn());
-                    let sql = r###"
-                        SELECT pipeline_id
-                        FROM twelvef_config_pipeline_ci
-                        WHERE ci_id = $1
-                    "###;
-                    let stmt = retry_unwrap!(conn.prepare(&sql));
-                    let rows = retry_unwrap!(
-                        stmt.query(&[ &ci_id.0 ])
-                    );
-                    let rows = rows.iter();
-                    let mut rows = rows.map(|row| PipelineId(row.get::<_, i32>(0)));
-                    rows.next().unwrap()
-                }}
-            })();
-            self.by_pipeline_id(pipeline_id)
-        }
-        fn len(&self) -> usize {
-            let conn = self.conn().unwrap();
-            let sql = r###"
-                SELECT COUNT(*)
-                FROM twelvef_config_pipeline
-            "###;
-            let stmt = conn.prepare(&sql)
-                .expect("Prepare pipeline count query");
-            let rows = stmt
-                .query(&[])
-                .expect("Get pipeline count");
-            let rows = rows.iter();
-            let mut rows = rows.map(|row| row.get::<_, i64>(0) as usize);
-            rows.next().unwrap()
-        }
     }
-    pub struct GithubProjectsConfig {
+    pub struct GiteaProjectsConfig {
         params: ConnectParams,
     }
-    impl GithubProjectsConfig {
+    impl GiteaProjectsConfig {
         pub fn new<Q: IntoConnectParams>(params: Q)
                 -> Result<Self, Box<Error + Send + Sync + 'static>>
         {
-            let result = GithubProjectsConfig{
+            let result = GiteaProjectsConfig{
                 params: try!(params.into_connect_params()),
             };
             try!(try!(result.conn()).batch_execute(r###"
-                CREATE TABLE IF NOT EXISTS twelvef_github_projects (
+                CREATE TABLE IF NOT EXISTS twelvef_gitea_projects (
                     pipeline_id INTEGER PRIMARY KEY,
                     try_pipeline_id INTEGER NULL,
                     owner TEXT,
                     repo TEXT,
-                    UNIQUE (owner, repo)
+                    branch TEXT NOT NULL DEFAULT 'master',
+                    UNIQUE (owner, repo, branch)
                 );
             "###));
             Ok(result)
@@ -893,24 +2765,24 @@ mod postgres {
             Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
         }
     }
-    impl TGithubProjectsConfig for GithubProjectsConfig {
-        fn pipelines_by_repo(&self, repo: &github::Repo)
-                -> Option<github::RepoPipelines>
+    impl TGiteaProjectsConfig for GiteaProjectsConfig {
+        fn pipelines_by_repo(&self, repo: &gitea::Repo, branch: &str)
+                -> Option<gitea::RepoPipelines>
         {
             retry!{{
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
                     SELECT pipeline_id, try_pipeline_id
-                    FROM twelvef_github_projects
-                    WHERE owner = $1 AND repo = $2
+                    FROM twelvef_gitea_projects
+                    WHERE owner = $1 AND repo = $2 AND branch = $3
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
                 let rows = retry_unwrap!(
-                    stmt.query(&[&repo.owner, &repo.repo])
+                    stmt.query(&[&repo.owner, &repo.repo, &branch])
                 );
                 let rows = rows.iter();
                 let mut rows = rows.map(|row| {
-                    github::RepoPipelines{
+                    gitea::RepoPipelines{
                         pipeline_id:
                             PipelineId(row.get::<_, i32>(0)),
                         try_pipeline_id:
@@ -921,13 +2793,13 @@ mod postgres {
             }}
         }
         fn repo_by_pipeline(&self, pipeline_id: PipelineId)
-                -> Option<(github::Repo, github::PipelineType)>
+                -> Option<(gitea::Repo, gitea::PipelineType)>
         {
             retry!{{
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
                     SELECT owner, repo, pipeline_id
-                    FROM twelvef_github_projects
+                    FROM twelvef_gitea_projects
                     WHERE pipeline_id = $1 OR try_pipeline_id = $2
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
@@ -938,12 +2810,12 @@ mod postgres {
                 let mut rows = rows.map(|row| {
                     let pipeline_type =
                         if row.get::<_, i32>(2) == pipeline_id.0 {
-                            github::PipelineType::Stage
+                            gitea::PipelineType::Stage
                         } else {
-                            github::PipelineType::Try
+                            gitea::PipelineType::Try
                         };
                     (
-                        github::Repo{
+                        gitea::Repo{
                             owner:
                                 row.get::<_, String>(0),
                             repo:
@@ -956,21 +2828,24 @@ mod postgres {
             }}
         }
     }
-    pub struct JenkinsPipelinesConfig {
+    pub struct BitbucketProjectsConfig {
         params: ConnectParams,
     }
-    impl JenkinsPipelinesConfig {
+    impl BitbucketProjectsConfig {
         pub fn new<Q: IntoConnectParams>(params: Q)
                 -> Result<Self, Box<Error + Send + Sync + 'static>>
         {
-            let result = JenkinsPipelinesConfig{
+            let result = BitbucketProjectsConfig{
                 params: try!(params.into_connect_params()),
             };
             try!(try!(result.conn()).batch_execute(r###"
-                CREATE TABLE IF NOT EXISTS twelvef_jenkins_pipelines (
-                    ci_id SERIAL PRIMARY KEY,
-                    name TEXT,
-                    token TEXT
+                CREATE TABLE IF NOT EXISTS twelvef_bitbucket_projects (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    try_pipeline_id INTEGER NULL,
+                    owner TEXT,
+                    repo TEXT,
+                    branch TEXT NOT NULL DEFAULT 'master',
+                    UNIQUE (owner, repo, branch)
                 );
             "###));
             Ok(result)
@@ -979,68 +2854,85 @@ mod postgres {
             Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
         }
     }
-    impl TJenkinsPipelinesConfig for JenkinsPipelinesConfig {
-        fn job_by_id(&self, id: CiId)
-                -> Option<jenkins::Job>
+    impl TBitbucketProjectsConfig for BitbucketProjectsConfig {
+        fn pipelines_by_repo(&self, repo: &bitbucket::Repo, branch: &str)
+                -> Option<bitbucket::RepoPipelines>
         {
             retry!{{
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
-                    SELECT name, token
-                    FROM twelvef_jenkins_pipelines
-                    WHERE ci_id = $1
+                    SELECT pipeline_id, try_pipeline_id
+                    FROM twelvef_bitbucket_projects
+                    WHERE owner = $1 AND repo = $2 AND branch = $3
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
-                let rows = retry_unwrap!(stmt.query(&[&id.0]));
+                let rows = retry_unwrap!(
+                    stmt.query(&[&repo.owner, &repo.repo, &branch])
+                );
                 let rows = rows.iter();
                 let mut rows = rows.map(|row| {
-                    jenkins::Job{
-                        name:
-                            row.get::<_, String>(0),
-                        token:
-                            row.get::<_, String>(1),
+                    bitbucket::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
                     }
                 });
                 rows.next()
             }}
         }
-        fn ids_by_job_name(&self, job: &str)
-                -> Vec<CiId>
+        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(bitbucket::Repo, bitbucket::PipelineType)>
         {
             retry!{{
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
-                    SELECT ci_id
-                    FROM twelvef_jenkins_pipelines
-                    WHERE name = $1
+                    SELECT owner, repo, pipeline_id
+                    FROM twelvef_bitbucket_projects
+                    WHERE pipeline_id = $1 OR try_pipeline_id = $2
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
-                let rows = retry_unwrap!(stmt.query(&[&job]));
+                let rows = retry_unwrap!(stmt.query(
+                    &[&pipeline_id.0, &pipeline_id.0]
+                ));
                 let rows = rows.iter();
-                let rows = rows.map(|row| {
-                    CiId(row.get::<_, i32>(0))
+                let mut rows = rows.map(|row| {
+                    let pipeline_type =
+                        if row.get::<_, i32>(2) == pipeline_id.0 {
+                            bitbucket::PipelineType::Stage
+                        } else {
+                            bitbucket::PipelineType::Try
+                        };
+                    (
+                        bitbucket::Repo{
+                            owner:
+                                row.get::<_, String>(0),
+                            repo:
+                                row.get::<_, String>(1),
+                        },
+                        pipeline_type
+                    )
                 });
-                let rows = rows.collect();
-                rows
+                rows.next()
             }}
         }
     }
-    pub struct GithubStatusPipelinesConfig {
+    pub struct ApiProjectsConfig {
         params: ConnectParams,
     }
-    impl GithubStatusPipelinesConfig {
+    impl ApiProjectsConfig {
         pub fn new<Q: IntoConnectParams>(params: Q)
                 -> Result<Self, Box<Error + Send + Sync + 'static>>
         {
-            let result = GithubStatusPipelinesConfig{
+            let result = ApiProjectsConfig{
                 params: try!(params.into_connect_params()),
             };
             try!(try!(result.conn()).batch_execute(r###"
-                CREATE TABLE IF NOT EXISTS twelvef_github_status_pipelines (
-                    ci_id SERIAL PRIMARY KEY,
-                    owner TEXT,
-                    repo TEXT,
-                    context TEXT
+                CREATE TABLE IF NOT EXISTS twelvef_api_projects (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    try_pipeline_id INTEGER NULL,
+                    name TEXT,
+                    UNIQUE (name)
                 );
             "###));
             Ok(result)
@@ -1049,74 +2941,76 @@ mod postgres {
             Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
         }
     }
-    impl TGithubStatusPipelinesConfig for GithubStatusPipelinesConfig {
-        fn repo_by_id(&self, id: CiId)
-                -> Option<github_status::Repo>
+    impl TApiProjectsConfig for ApiProjectsConfig {
+        fn pipelines_by_project(&self, project: &str)
+                -> Option<api::RepoPipelines>
         {
             retry!{{
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
-                    SELECT owner, repo, context
-                    FROM twelvef_github_status_pipelines
-                    WHERE ci_id = $1
+                    SELECT pipeline_id, try_pipeline_id
+                    FROM twelvef_api_projects
+                    WHERE name = $1
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
-                let rows = retry_unwrap!(stmt.query(&[&id.0]));
+                let rows = retry_unwrap!(stmt.query(&[&project]));
                 let rows = rows.iter();
                 let mut rows = rows.map(|row| {
-                    github_status::Repo{
-                        owner:
-                            row.get::<_, String>(0),
-                        repo:
-                            row.get::<_, String>(1),
-                        context:
-                            row.get::<_, String>(2),
+                    api::RepoPipelines{
+                        pipeline_id:
+                            PipelineId(row.get::<_, i32>(0)),
+                        try_pipeline_id:
+                            row.get::<_, Option<i32>>(1).map(PipelineId),
                     }
                 });
                 rows.next()
             }}
         }
-        fn ids_by_repo(&self, repo: &github_status::Repo)
-                -> Vec<CiId>
+        fn project_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(String, api::PipelineType)>
         {
             retry!{{
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
-                    SELECT ci_id
-                    FROM twelvef_github_status_pipelines
-                    WHERE owner = $1 AND repo = $2 AND context = $3
+                    SELECT name, pipeline_id
+                    FROM twelvef_api_projects
+                    WHERE pipeline_id = $1 OR try_pipeline_id = $2
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
-                let rows = retry_unwrap!(
-                    stmt.query(&[&repo.owner, &repo.repo, &repo.context])
-                );
+                let rows = retry_unwrap!(stmt.query(
+                    &[&pipeline_id.0, &pipeline_id.0]
+                ));
                 let rows = rows.iter();
-                let rows = rows.map(|row| {
-                    CiId(row.get::<_, i32>(0))
+                let mut rows = rows.map(|row| {
+                    let pipeline_type =
+                        if row.get::<_, i32>(1) == pipeline_id.0 {
+                            api::PipelineType::Stage
+                        } else {
+                            api::PipelineType::Try
+                        };
+                    (row.get::<_, String>(0), pipeline_type)
                 });
-                let rows = rows.collect();
-                rows
+                rows.next()
             }}
         }
     }
-    pub struct GithubGitPipelinesConfig {
+    pub struct GiteaGitPipelinesConfig {
         params: ConnectParams,
     }
-    impl GithubGitPipelinesConfig {
+    impl GiteaGitPipelinesConfig {
         pub fn new<Q: IntoConnectParams>(params: Q)
                 -> Result<Self, Box<Error + Send + Sync + 'static>>
         {
-            let result = GithubGitPipelinesConfig{
+            let result = GiteaGitPipelinesConfig{
                 params: try!(params.into_connect_params()),
             };
             try!(try!(result.conn()).batch_execute(r###"
-                CREATE TABLE IF NOT EXISTS twelvef_github_git_pipelines (
+                CREATE TABLE IF NOT EXISTS twelvef_gitea_git_pipelines (
                     pipeline_id INTEGER PRIMARY KEY,
                     owner TEXT,
                     repo TEXT,
                     master_branch TEXT,
-                    staging_branch TEXT,
-                    push_to_master BOOLEAN
+                    staging_branch TEXT
                 );
             "###));
             Ok(result)
@@ -1125,9 +3019,9 @@ mod postgres {
             Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
         }
     }
-    impl TGithubGitPipelinesConfig for GithubGitPipelinesConfig {
+    impl TGiteaGitPipelinesConfig for GiteaGitPipelinesConfig {
         fn repo_by_pipeline(&self, pipeline_id: PipelineId)
-                -> Option<github_git::Repo>
+                -> Option<gitea_git::Repo>
         {
             retry!{{
                 let conn = retry_unwrap!(self.conn());
@@ -1136,16 +3030,15 @@ mod postgres {
                         owner,
                         repo,
                         master_branch,
-                        staging_branch,
-                        push_to_master
-                    FROM twelvef_github_git_pipelines
+                        staging_branch
+                    FROM twelvef_gitea_git_pipelines
                     WHERE pipeline_id = $1
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
                 let rows = retry_unwrap!(stmt.query(&[&pipeline_id.0]));
                 let rows = rows.iter();
                 let mut rows = rows.map(|row| {
-                    github_git::Repo{
+                    gitea_git::Repo{
                         owner:
                             row.get::<_, String>(0),
                         repo:
@@ -1154,72 +3047,44 @@ mod postgres {
                             row.get::<_, String>(2),
                         staging_branch:
                             row.get::<_, String>(3),
-                        push_to_master:
-                            row.get::<_, bool>(4),
                     }
                 });
                 rows.next()
             }}
         }
     }
-    pub struct GitPipelinesConfig {
+    pub struct HooksPipelinesConfig {
         params: ConnectParams,
     }
-    impl GitPipelinesConfig {
+    impl HooksPipelinesConfig {
         pub fn new<Q: IntoConnectParams>(params: Q)
                 -> Result<Self, Box<Error + Send + Sync + 'static>>
         {
-            let result = GitPipelinesConfig{
+            let result = HooksPipelinesConfig{
                 params: try!(params.into_connect_params()),
             };
-            try!(try!(result.conn()).batch_execute(r###"
-                CREATE TABLE IF NOT EXISTS twelvef_git_pipelines (
-                    pipeline_id INTEGER PRIMARY KEY,
-                    path TEXT,
-                    origin TEXT,
-                    master_branch TEXT,
-                    staging_branch TEXT,
-                    push_to_master TEXT
-                );
-            "###));
             Ok(result)
         }
         fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
             Ok(try!(Connection::connect(self.params.clone(), TlsMode::None)))
         }
     }
-    impl TGitPipelinesConfig for GitPipelinesConfig {
-        fn repo_by_pipeline(&self, pipeline_id: PipelineId)
-                -> Option<git::Repo>
+    impl THooksPipelinesConfig for HooksPipelinesConfig {
+        fn project_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(String, bool)>
         {
             retry!{{
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
-                    SELECT
-                        path,
-                        origin,
-                        master_branch,
-                        staging_branch,
-                        push_to_master
-                    FROM twelvef_git_pipelines
+                    SELECT name
+                    FROM twelvef_config_pipeline
                     WHERE pipeline_id = $1
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
                 let rows = retry_unwrap!(stmt.query(&[&pipeline_id.0]));
                 let rows = rows.iter();
                 let mut rows = rows.map(|row| {
-                    git::Repo{
-                        path:
-                            row.get::<_, String>(0),
-                        origin:
-                            row.get::<_, String>(1),
-                        master_branch:
-                            row.get::<_, String>(2),
-                        staging_branch:
-                            row.get::<_, String>(3),
-                        push_to_master:
-                            row.get::<_, bool>(4),
-                    }
+                    (row.get::<_, String>(0), false)
                 });
                 rows.next()
             }}
@@ -1313,19 +3178,51 @@ pub enum GithubBuilderKey {
     UiGithubListen,
     UiGithubHost,
     UiGithubToken,
+    UiGithubAppId,
+    UiGithubPrivateKey,
     UiGithubUser,
     UiGithubSecret,
+    UiGithubSyncInterval,
+    UiGithubChecks,
+    UiGithubAdminListen,
+    UiGithubPermissionTtl,
+    UiGitlabListen,
+    UiGitlabHost,
+    UiGitlabToken,
+    UiGitlabUser,
+    UiGitlabSecret,
+    UiGiteaListen,
+    UiGiteaHost,
+    UiGiteaToken,
+    UiGiteaUser,
+    UiGiteaSecret,
+    UiBitbucketListen,
+    UiBitbucketHost,
+    UiBitbucketToken,
+    UiBitbucketUser,
+    UiBitbucketSecret,
+    UiApiListen,
+    UiApiSecret,
+    UiApiCallback,
     CiJenkinsListen,
     CiJenkinsHost,
     CiJenkinsUser,
     CiJenkinsToken,
     CiGithubListen,
     CiGithubSecret,
+    CiGitlabListen,
+    CiGitlabSecret,
     VcsGithubHost,
     VcsGithubToken,
+    VcsGitlabHost,
+    VcsGitlabToken,
+    VcsGiteaHost,
+    VcsGiteaToken,
     VcsGitExecutable,
     VcsGitName,
     VcsGitEmail,
     ViewListen,
     ViewSecret,
+    HooksUrls,
+    HooksSecret,
 }
\ No newline at end of file
@@ -15,6 +15,7 @@ use pipeline::PipelineId;
 use postgres::params::{ConnectParams, IntoConnectParams};
 use std::error::Error;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use vcs::Commit;
 
 pub enum Builder {
@@ -79,6 +80,15 @@ impl Db for DbBox {
             DbBox::Postgres(ref mut d) => d.pop_queue(pipeline_id),
         }
     }
+    fn peek_queue(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.peek_queue(pipeline_id),
+            DbBox::Postgres(ref mut d) => d.peek_queue(pipeline_id),
+        }
+    }
     fn list_queue(
         &mut self,
         pipeline_id: PipelineId,
@@ -91,19 +101,19 @@ impl Db for DbBox {
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
-        running_entry: RunningEntry,
+        running: Vec<RunningEntry>,
     ) -> Result<(), Box<Error + Send + Sync>> {
         match *self {
             DbBox::Sqlite(ref mut d) =>
-                d.put_running(pipeline_id, running_entry),
+                d.put_running(pipeline_id, running),
             DbBox::Postgres(ref mut d) =>
-                d.put_running(pipeline_id, running_entry),
+                d.put_running(pipeline_id, running),
         }
     }
     fn take_running(
         &mut self,
         pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
         match *self {
             DbBox::Sqlite(ref mut d) => d.take_running(pipeline_id),
             DbBox::Postgres(ref mut d) => d.take_running(pipeline_id),
@@ -112,7 +122,7 @@ impl Db for DbBox {
     fn peek_running(
         &mut self,
         pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
         match *self {
             DbBox::Sqlite(ref mut d) => d.peek_running(pipeline_id),
             DbBox::Postgres(ref mut d) => d.peek_running(pipeline_id),
@@ -186,39 +196,187 @@ impl Db for DbBox {
                 d.cancel_by_pr_different_commit(pipeline_id, pr, commit),
         }
     }
+    fn add_failed(
+        &mut self,
+        pipeline_id: PipelineId,
+        queue_entry: QueueEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.add_failed(pipeline_id, queue_entry),
+            DbBox::Postgres(ref mut d) =>
+                d.add_failed(pipeline_id, queue_entry),
+        }
+    }
+    fn take_failed_by_pr(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.take_failed_by_pr(pipeline_id, pr),
+            DbBox::Postgres(ref mut d) =>
+                d.take_failed_by_pr(pipeline_id, pr),
+        }
+    }
+    fn set_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+        priority: Option<i32>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.set_tree_closed(pipeline_id, priority),
+            DbBox::Postgres(ref mut d) =>
+                d.set_tree_closed(pipeline_id, priority),
+        }
+    }
+    fn get_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<i32>, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.get_tree_closed(pipeline_id),
+            DbBox::Postgres(ref mut d) => d.get_tree_closed(pipeline_id),
+        }
+    }
+    fn add_history(
+        &mut self,
+        pipeline_id: PipelineId,
+        history_entry: HistoryEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.add_history(pipeline_id, history_entry),
+            DbBox::Postgres(ref mut d) =>
+                d.add_history(pipeline_id, history_entry),
+        }
+    }
+    fn list_history(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Vec<HistoryEntry>, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.list_history(pipeline_id),
+            DbBox::Postgres(ref mut d) => d.list_history(pipeline_id),
+        }
+    }
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
         ci_state: CiState,
         commit: &Commit,
+        url: Option<&Url>,
     ) -> Result<(), Box<Error + Send + Sync>> {
         match *self {
             DbBox::Sqlite(ref mut d) =>
-                d.set_ci_state(ci_id, ci_state, commit),
+                d.set_ci_state(ci_id, ci_state, commit, url),
             DbBox::Postgres(ref mut d) =>
-                d.set_ci_state(ci_id, ci_state, commit),
+                d.set_ci_state(ci_id, ci_state, commit, url),
         }
     }
     fn clear_ci_state(
         &mut self,
         ci_id: CiId,
+        commit: &Commit,
     ) -> Result<(), Box<Error + Send + Sync>> {
         match *self {
             DbBox::Sqlite(ref mut d) =>
-                d.clear_ci_state(ci_id),
+                d.clear_ci_state(ci_id, commit),
             DbBox::Postgres(ref mut d) =>
-                d.clear_ci_state(ci_id),
+                d.clear_ci_state(ci_id, commit),
         }
     }
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+        commit: &Commit,
+    ) -> Result<Option<(CiState, Option<Url>)>, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.get_ci_state(ci_id, commit),
+            DbBox::Postgres(ref mut d) =>
+                d.get_ci_state(ci_id, commit),
+        }
+    }
+    fn add_outbox(
+        &mut self,
+        outbox_entry: OutboxEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.add_outbox(outbox_entry),
+            DbBox::Postgres(ref mut d) => d.add_outbox(outbox_entry),
+        }
+    }
+    fn list_outbox(
+        &mut self,
+        due: SystemTime,
+    ) -> Result<Vec<(OutboxId, OutboxEntry)>, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.list_outbox(due),
+            DbBox::Postgres(ref mut d) => d.list_outbox(due),
+        }
+    }
+    fn remove_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.remove_outbox(outbox_id),
+            DbBox::Postgres(ref mut d) => d.remove_outbox(outbox_id),
+        }
+    }
+    fn postpone_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+        attempts: u32,
+        next_attempt_at: SystemTime,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.postpone_outbox(outbox_id, attempts, next_attempt_at),
+            DbBox::Postgres(ref mut d) =>
+                d.postpone_outbox(outbox_id, attempts, next_attempt_at),
+        }
+    }
+    fn add_delegation(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.add_delegation(pipeline_id, pr, user, pull_commit),
+            DbBox::Postgres(ref mut d) =>
+                d.add_delegation(pipeline_id, pr, user, pull_commit),
+        }
+    }
+    fn is_delegated(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.is_delegated(pipeline_id, pr, user),
+            DbBox::Postgres(ref mut d) =>
+                d.is_delegated(pipeline_id, pr, user),
+        }
+    }
+    fn remove_delegations(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        keep_commit: Option<&Commit>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
         match *self {
             DbBox::Sqlite(ref mut d) =>
-                d.get_ci_state(ci_id),
+                d.remove_delegations(pipeline_id, pr, keep_commit),
             DbBox::Postgres(ref mut d) =>
-                d.get_ci_state(ci_id),
+                d.remove_delegations(pipeline_id, pr, keep_commit),
         }
     }
 }
@@ -241,23 +399,30 @@ pub trait Db: Sized {
         &mut self,
         PipelineId,
     ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>>;
+    /// Get the entry that pop_queue would return, without removing it.
+    fn peek_queue(
+        &mut self,
+        PipelineId,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>>;
     fn list_queue(
         &mut self,
         PipelineId,
     ) -> Result<Vec<QueueEntry>, Box<Error + Send + Sync>>;
+    /// Replace the running candidates of a pipeline.
+    /// They are stored in order, starting from the head of the queue.
     fn put_running(
         &mut self,
         PipelineId,
-        RunningEntry,
+        Vec<RunningEntry>,
     ) -> Result<(), Box<Error + Send + Sync>>;
     fn take_running(
         &mut self,
         PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>>;
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>>;
     fn peek_running(
         &mut self,
         PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>>;
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>>;
     fn add_pending(
         &mut self,
         PipelineId,
@@ -277,6 +442,9 @@ pub trait Db: Sized {
         &mut self,
         PipelineId,
     ) -> Result<Vec<PendingEntry>, Box<Error + Send + Sync>>;
+    /// Remove all queued and running entries in the given pipeline
+    /// with the same PR number, and mark the running candidates
+    /// they were removed from as canceled.
     fn cancel_by_pr(
         &mut self,
         PipelineId,
@@ -291,23 +459,111 @@ pub trait Db: Sized {
         &Pr,
         &Commit,
     ) -> Result<bool, Box<Error + Send + Sync>>;
-    /// Set the state of a CI job.
+    /// Remember the entry of a pull request that failed to build,
+    /// replacing any earlier one for the same PR number.
+    fn add_failed(
+        &mut self,
+        PipelineId,
+        QueueEntry,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// Remove and return the failed entry for the given PR number.
+    fn take_failed_by_pr(
+        &mut self,
+        PipelineId,
+        &Pr,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>>;
+    /// Close the tree to queue entries below the given priority,
+    /// or open it again with None.
+    fn set_tree_closed(
+        &mut self,
+        PipelineId,
+        Option<i32>,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// Get the priority the tree is closed below, if it is closed.
+    fn get_tree_closed(
+        &mut self,
+        PipelineId,
+    ) -> Result<Option<i32>, Box<Error + Send + Sync>>;
+    /// Record a queue entry that has left the pipeline for good.
+    fn add_history(
+        &mut self,
+        PipelineId,
+        HistoryEntry,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// List the recorded history of a pipeline, newest first.
+    fn list_history(
+        &mut self,
+        PipelineId,
+    ) -> Result<Vec<HistoryEntry>, Box<Error + Send + Sync>>;
+    /// Set the state of a CI job building the given commit.
     fn set_ci_state(
         &mut self,
         CiId,
         CiState,
         &Commit,
+        Option<&Url>,
     ) -> Result<(), Box<Error + Send + Sync>>;
-    /// Set the state of a CI job.
+    /// Forget the state of a CI job building the given commit.
     fn clear_ci_state(
         &mut self,
         CiId,
+        &Commit,
     ) -> Result<(), Box<Error + Send + Sync>>;
-    /// Get a count of completed / remaining CI jobs.
+    /// Get the state of a CI job building the given commit,
+    /// along with the URL it reported.
     fn get_ci_state(
         &mut self,
         CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>>;
+        &Commit,
+    ) -> Result<Option<(CiState, Option<Url>)>, Box<Error + Send + Sync>>;
+    /// Store a webhook delivery until it is sent.
+    fn add_outbox(
+        &mut self,
+        OutboxEntry,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// List the webhook deliveries that are due at the given time,
+    /// oldest first.
+    fn list_outbox(
+        &mut self,
+        SystemTime,
+    ) -> Result<Vec<(OutboxId, OutboxEntry)>, Box<Error + Send + Sync>>;
+    /// Forget a webhook delivery, once it has been sent or given up on.
+    fn remove_outbox(
+        &mut self,
+        OutboxId,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// Record a failed attempt at a webhook delivery,
+    /// and when to try it again.
+    fn postpone_outbox(
+        &mut self,
+        OutboxId,
+        u32,
+        SystemTime,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// Let a user approve a pull request, for as long as its head
+    /// is the given commit.
+    fn add_delegation(
+        &mut self,
+        PipelineId,
+        &Pr,
+        &str,
+        &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// Check whether a user may approve a pull request.
+    fn is_delegated(
+        &mut self,
+        PipelineId,
+        &Pr,
+        &str,
+    ) -> Result<bool, Box<Error + Send + Sync>>;
+    /// Revoke the delegations on a pull request,
+    /// except for those granted on the given commit.
+    fn remove_delegations(
+        &mut self,
+        PipelineId,
+        &Pr,
+        Option<&Commit>,
+    ) -> Result<(), Box<Error + Send + Sync>>;
 }
 
 pub trait Transaction {
@@ -335,6 +591,49 @@ impl CiState {
     }
 }
 
+/// How a queue entry left the pipeline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(i32)]
+pub enum HistoryStatus {
+    Completed = 1,
+    Failed = 2,
+    TimedOut = 3,
+    Unmergeable = 4,
+    Unmoveable = 5,
+    Canceled = 6,
+}
+
+impl HistoryStatus {
+    /// `None` if the stored status is not one this version knows about.
+    pub fn from_i32(this: i32) -> Option<HistoryStatus> {
+        match this {
+            1 => Some(HistoryStatus::Completed),
+            2 => Some(HistoryStatus::Failed),
+            3 => Some(HistoryStatus::TimedOut),
+            4 => Some(HistoryStatus::Unmergeable),
+            5 => Some(HistoryStatus::Unmoveable),
+            6 => Some(HistoryStatus::Canceled),
+            _ => None,
+        }
+    }
+}
+
+/// A record of a queue entry that has left the pipeline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub pr: Pr,
+    pub pull_commit: Commit,
+    pub merge_commit: Option<Commit>,
+    pub message: String,
+    /// The user who approved it.
+    pub approver: String,
+    pub status: HistoryStatus,
+    /// Whatever the CI jobs that built the merge commit reported.
+    pub ci_urls: Vec<Url>,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: SystemTime,
+}
+
 /// An item not yet in the build queue
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PendingEntry {
@@ -350,15 +649,71 @@ pub struct QueueEntry {
     pub commit: Commit,
     pub pr: Pr,
     pub message: String,
+    /// Entries with a higher priority are popped first.
+    /// Equal priorities are popped in the order they were pushed.
+    pub priority: i32,
+    /// The user who approved it.
+    pub approver: String,
 }
 
-/// An item in the build queue that is currently running
+/// A batch of items in the build queue that is currently running.
+/// Each one is merged on top of the one ahead of it in the queue.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RunningEntry {
-    pub pull_commit: Commit,
+    /// The pull requests being merged into staging, in merge order.
+    /// Canceled pull requests are removed from this list.
+    pub entries: Vec<QueueEntry>,
+    /// Which of the pipeline's staging branches this batch is built in.
+    pub staging: usize,
+    /// The commit the next pull request is merged on top of,
+    /// or None if it is merged on top of master.
+    pub base_commit: Option<Commit>,
     pub merge_commit: Option<Commit>,
-    pub pr: Pr,
-    pub message: String,
+    /// When the CI was asked to build the merge commit,
+    /// if the pipeline has a CI timeout.
+    pub started_at: Option<SystemTime>,
+    /// How many times the CI has been asked to build the merge commit
+    /// again after it failed.
+    pub retries: u32,
     pub canceled: bool,
     pub built: bool,
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutboxId(pub i32);
+
+/// A webhook delivery that has not been sent yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutboxEntry {
+    pub url: Url,
+    /// The JSON to post, exactly as it is signed.
+    pub body: String,
+    /// How many times sending it has failed.
+    pub attempts: u32,
+    pub next_attempt_at: SystemTime,
+}
+
+/// Timestamps are stored as whole seconds since the Unix epoch.
+fn time_to_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn secs_to_time(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs as u64)
+}
+
+/// Lists of URLs are stored one per line.
+fn urls_to_text(urls: &[Url]) -> String {
+    urls.iter()
+        .map(|url| url.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn text_to_urls(text: &str) -> Vec<Url> {
+    text.lines()
+        .filter_map(|url| Url::parse(url).ok())
+        .collect()
+}
@@ -1,12 +1,14 @@
 // This file is released under the same terms as Rust itself.
 
 use ci::CiId;
-use db::{self, CiState, Db, PendingEntry, QueueEntry, RunningEntry};
+use db::{self, CiState, Db, HistoryEntry, HistoryStatus, PendingEntry};
+use db::{OutboxEntry, OutboxId, QueueEntry, RunningEntry};
 use hyper::Url;
 use postgres::{Connection, TlsMode};
 use postgres::params::{ConnectParams, IntoConnectParams};
 use postgres::transaction::Transaction as PgTransaction;
 use std::error::Error;
+use std::time::SystemTime;
 use ui::Pr;
 use pipeline::PipelineId;
 use vcs::Commit;
@@ -22,7 +24,11 @@ impl PostgresDb {
         let result = PostgresDb{
             params: try!(params.into_connect_params()),
         };
-        try!(try!(result.conn()).batch_execute(r###"
+        let conn = try!(result.conn());
+        try!(conn.batch_execute(r###"
+            CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER
+            );
             CREATE TABLE IF NOT EXISTS ci_state (
                 ci_id SERIAL PRIMARY KEY,
                 ci_state INTEGER,
@@ -44,6 +50,30 @@ impl PostgresDb {
                 canceled BOOLEAN,
                 built BOOLEAN
             );
+            CREATE TABLE IF NOT EXISTS failed (
+                pipeline_id INTEGER,
+                pr TEXT,
+                message TEXT,
+                pull_commit TEXT,
+                priority INTEGER,
+                PRIMARY KEY (pipeline_id, pr)
+            );
+            CREATE TABLE IF NOT EXISTS tree_closed (
+                pipeline_id INTEGER PRIMARY KEY,
+                priority INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS history (
+                id SERIAL PRIMARY KEY,
+                pipeline_id INTEGER,
+                pr TEXT,
+                message TEXT,
+                pull_commit TEXT,
+                merge_commit TEXT,
+                status INTEGER,
+                ci_urls TEXT,
+                started_at BIGINT,
+                finished_at BIGINT
+            );
             CREATE TABLE IF NOT EXISTS pending (
                 id SERIAL PRIMARY KEY,
                 pipeline_id INTEGER,
@@ -52,7 +82,15 @@ impl PostgresDb {
                 title TEXT,
                 url TEXT
             );
+            CREATE TABLE IF NOT EXISTS outbox (
+                id SERIAL PRIMARY KEY,
+                url TEXT,
+                body TEXT,
+                attempts INTEGER,
+                next_attempt_at BIGINT
+            );
         "###));
+        try!(migrate(&conn));
         Ok(result)
     }
     fn conn(&self) -> Result<Connection, Box<Error + Send + Sync>> {
@@ -60,6 +98,85 @@ impl PostgresDb {
     }
 }
 
+/// Bring the tables created by `PostgresDb::open` up to date,
+/// running each migration the database has not seen yet.
+fn migrate(conn: &Connection) -> Result<(), Box<Error + Send + Sync>> {
+    let rows = try!(conn.query("SELECT MAX(version) FROM schema_version", &[]));
+    let version = rows.iter()
+        .map(|row| row.get::<_, Option<i32>>(0))
+        .next()
+        .and_then(|version| version)
+        .unwrap_or(0) as usize;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = try!(conn.transaction());
+        try!(transaction.batch_execute(migration));
+        try!(transaction.execute("DELETE FROM schema_version", &[]));
+        try!(transaction.execute(
+            "INSERT INTO schema_version (version) VALUES ($1)",
+            &[&((i + 1) as i32)],
+        ));
+        try!(transaction.commit());
+    }
+    Ok(())
+}
+
+/// Changes to the tables since the first release.
+/// A database that has run the first N of these is at version N.
+const MIGRATIONS: &'static [&'static str] = &[
+    // 1: queue priority
+    r###"
+        ALTER TABLE queue ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+    "###,
+    // 2: CI URLs, and stacks of running batches
+    r###"
+        ALTER TABLE ci_state DROP CONSTRAINT ci_state_pkey;
+        ALTER TABLE ci_state ALTER COLUMN ci_id DROP DEFAULT;
+        ALTER TABLE ci_state ADD COLUMN ci_url TEXT;
+        ALTER TABLE ci_state ADD PRIMARY KEY (ci_id, ci_commit);
+        CREATE TABLE running_pr (
+            pipeline_id INTEGER,
+            staging INTEGER,
+            position INTEGER,
+            pr TEXT,
+            message TEXT,
+            pull_commit TEXT,
+            priority INTEGER NOT NULL DEFAULT 0
+        );
+        INSERT INTO running_pr
+            (pipeline_id, staging, position, pr, message, pull_commit)
+            SELECT pipeline_id, 0, 0, pr, message, pull_commit
+            FROM running;
+        ALTER TABLE running DROP CONSTRAINT running_pkey;
+        ALTER TABLE running
+            DROP COLUMN pr,
+            DROP COLUMN message,
+            DROP COLUMN pull_commit,
+            ADD COLUMN staging INTEGER NOT NULL DEFAULT 0,
+            ADD COLUMN position INTEGER NOT NULL DEFAULT 0,
+            ADD COLUMN base_commit TEXT,
+            ADD COLUMN started_at BIGINT,
+            ADD COLUMN retries INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE running ADD PRIMARY KEY (pipeline_id, staging);
+    "###,
+    // 3: who approved each entry
+    r###"
+        ALTER TABLE queue ADD COLUMN approver TEXT NOT NULL DEFAULT '';
+        ALTER TABLE running_pr ADD COLUMN approver TEXT NOT NULL DEFAULT '';
+        ALTER TABLE failed ADD COLUMN approver TEXT NOT NULL DEFAULT '';
+        ALTER TABLE history ADD COLUMN approver TEXT NOT NULL DEFAULT '';
+    "###,
+    // 4: users allowed to approve a single PR
+    r###"
+        CREATE TABLE delegations (
+            pipeline_id INTEGER,
+            pr TEXT,
+            user_login TEXT,
+            pull_commit TEXT,
+            PRIMARY KEY (pipeline_id, pr, user_login)
+        );
+    "###,
+];
+
 impl Db for PostgresDb {
     fn transaction<T: db::Transaction>(
         &mut self,
@@ -94,6 +211,16 @@ impl Db for PostgresDb {
         ).pop_queue(pipeline_id);
         result
     }
+    fn peek_queue(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).peek_queue(pipeline_id);
+        result
+    }
     fn list_queue(
         &mut self,
         pipeline_id: PipelineId,
@@ -107,18 +234,18 @@ impl Db for PostgresDb {
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
-        running_entry: RunningEntry,
+        running: Vec<RunningEntry>,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let conn = try!(self.conn());
         let result = PostgresTransaction::new(
             try!(conn.transaction())
-        ).put_running(pipeline_id, running_entry);
+        ).put_running(pipeline_id, running);
         result
     }
     fn take_running(
         &mut self,
         pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
         let conn = try!(self.conn());
         let result = PostgresTransaction::new(
             try!(conn.transaction())
@@ -128,7 +255,7 @@ impl Db for PostgresDb {
     fn peek_running(
         &mut self,
         pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
         let conn = try!(self.conn());
         let result = PostgresTransaction::new(
             try!(conn.transaction())
@@ -201,38 +328,189 @@ impl Db for PostgresDb {
         ).cancel_by_pr_different_commit(pipeline_id, pr, commit);
         result
     }
+    fn add_failed(
+        &mut self,
+        pipeline_id: PipelineId,
+        queue_entry: QueueEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).add_failed(pipeline_id, queue_entry);
+        result
+    }
+    fn take_failed_by_pr(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).take_failed_by_pr(pipeline_id, pr);
+        result
+    }
+    fn set_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+        priority: Option<i32>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).set_tree_closed(pipeline_id, priority);
+        result
+    }
+    fn get_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<i32>, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).get_tree_closed(pipeline_id);
+        result
+    }
+    fn add_history(
+        &mut self,
+        pipeline_id: PipelineId,
+        history_entry: HistoryEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).add_history(pipeline_id, history_entry);
+        result
+    }
+    fn list_history(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Vec<HistoryEntry>, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).list_history(pipeline_id);
+        result
+    }
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
         ci_state: CiState,
         commit: &Commit,
+        url: Option<&Url>,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let conn = try!(self.conn());
         let result = PostgresTransaction::new(
             try!(conn.transaction())
-        ).set_ci_state(ci_id, ci_state, commit);
+        ).set_ci_state(ci_id, ci_state, commit, url);
         result
     }
     fn clear_ci_state(
         &mut self,
         ci_id: CiId,
+        commit: &Commit,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let conn = try!(self.conn());
         let result = PostgresTransaction::new(
             try!(conn.transaction())
-        ).clear_ci_state(ci_id);
+        ).clear_ci_state(ci_id, commit);
         result
     }
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+        commit: &Commit,
+    ) -> Result<Option<(CiState, Option<Url>)>, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).get_ci_state(ci_id, commit);
+        result
+    }
+    fn add_outbox(
+        &mut self,
+        outbox_entry: OutboxEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).add_outbox(outbox_entry);
+        result
+    }
+    fn list_outbox(
+        &mut self,
+        due: SystemTime,
+    ) -> Result<Vec<(OutboxId, OutboxEntry)>, Box<Error + Send + Sync>> {
         let conn = try!(self.conn());
         let result = PostgresTransaction::new(
             try!(conn.transaction())
-        ).get_ci_state(ci_id);
+        ).list_outbox(due);
         result
     }
+    fn remove_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).remove_outbox(outbox_id);
+        result
+    }
+    fn postpone_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+        attempts: u32,
+        next_attempt_at: SystemTime,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).postpone_outbox(outbox_id, attempts, next_attempt_at);
+        result
+    }
+    fn add_delegation(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        // The UI calls this outside of any pipeline transaction.
+        let conn = try!(self.conn());
+        let mut transaction = PostgresTransaction::new(
+            try!(conn.transaction())
+        );
+        try!(transaction.add_delegation(pipeline_id, pr, user, pull_commit));
+        try!(transaction.conn.commit());
+        Ok(())
+    }
+    fn is_delegated(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).is_delegated(pipeline_id, pr, user);
+        result
+    }
+    fn remove_delegations(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        keep_commit: Option<&Commit>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let mut transaction = PostgresTransaction::new(
+            try!(conn.transaction())
+        );
+        try!(transaction.remove_delegations(pipeline_id, pr, keep_commit));
+        try!(transaction.conn.commit());
+        Ok(())
+    }
 }
 
 
@@ -252,17 +530,20 @@ impl<'a> Db for PostgresTransaction<'a> {
     fn push_queue(
         &mut self,
         pipeline_id: PipelineId,
-        QueueEntry{pr, commit, message}: QueueEntry
+        QueueEntry{pr, commit, message, priority, approver}: QueueEntry
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
-            INSERT INTO queue (pr, pipeline_id, pull_commit, message)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO queue
+                (pr, pipeline_id, pull_commit, message, priority, approver)
+            VALUES ($1, $2, $3, $4, $5, $6)
         "###;
         try!(self.conn.execute(sql, &[
             &pr.as_str(),
             &pipeline_id.0,
             &commit.as_str(),
             &message,
+            &priority,
+            &approver,
         ]));
         Ok(())
     }
@@ -273,10 +554,10 @@ impl<'a> Db for PostgresTransaction<'a> {
         let trans = try!(self.conn
             .transaction());
         let sql = r###"
-            SELECT id, pr, pull_commit, message
+            SELECT id, pr, pull_commit, message, priority, approver
             FROM queue
             WHERE pipeline_id = $1
-            ORDER BY id ASC LIMIT 1
+            ORDER BY priority DESC, id ASC LIMIT 1
         "###;
         let item = {
             let stmt = try!(trans.prepare(sql));
@@ -288,6 +569,8 @@ impl<'a> Db for PostgresTransaction<'a> {
                     pr: Pr::from(row.get::<_, String>(1)),
                     commit: Commit::from(row.get::<_, String>(2)),
                     message: row.get::<_, String>(3),
+                    priority: row.get::<_, i32>(4),
+                    approver: row.get::<_, String>(5),
                 },
             ));
             rows.next()
@@ -301,15 +584,37 @@ impl<'a> Db for PostgresTransaction<'a> {
         try!(trans.commit());
         Ok(item.map(|item| item.1))
     }
+    fn peek_queue(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT pr, pull_commit, message, priority, approver
+            FROM queue
+            WHERE pipeline_id = $1
+            ORDER BY priority DESC, id ASC LIMIT 1
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[&pipeline_id.0]));
+        let rows = rows.iter();
+        let mut rows = rows.map(|row| QueueEntry {
+            pr: Pr::from(row.get::<_, String>(0)),
+            commit: Commit::from(row.get::<_, String>(1)),
+            message: row.get::<_, String>(2),
+            priority: row.get::<_, i32>(3),
+            approver: row.get::<_, String>(4),
+        });
+        Ok(rows.next())
+    }
     fn list_queue(
         &mut self,
         pipeline_id: PipelineId,
     ) -> Result<Vec<QueueEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT pr, pull_commit, message
+            SELECT pr, pull_commit, message, priority, approver
             FROM queue
             WHERE pipeline_id = $1
-            ORDER BY id ASC
+            ORDER BY priority DESC, id ASC
         "###;
         let stmt = try!(self.conn.prepare(&sql));
         let rows = try!(stmt.query(&[&pipeline_id.0]));
@@ -318,6 +623,8 @@ impl<'a> Db for PostgresTransaction<'a> {
             pr: Pr::from(row.get::<_, String>(0)),
             commit: Commit::from(row.get::<_, String>(1)),
             message: row.get::<_, String>(2),
+            priority: row.get::<_, i32>(3),
+            approver: row.get::<_, String>(4),
         });
         let rows: Vec<QueueEntry> = rows.collect();
         Ok(rows)
@@ -325,101 +632,157 @@ impl<'a> Db for PostgresTransaction<'a> {
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
-        RunningEntry{
-            pr,
-            pull_commit,
-            merge_commit,
-            message,
-            canceled,
-            built,
-        }: RunningEntry
+        running: Vec<RunningEntry>,
     ) -> Result<(), Box<Error + Send + Sync>> {
+        let trans = try!(self.conn.transaction());
         let sql = r###"
-            INSERT INTO running
-                (
-                    pipeline_id,
-                    pr,
-                    pull_commit,
-                    merge_commit,
-                    message,
-                    canceled,
-                    built
-                )
-            VALUES
-                ($1, $2, $3, $4, $5, $6, $7)
-            ON CONFLICT (pipeline_id) DO UPDATE SET
-                pr = $2,
-                pull_commit = $3,
-                merge_commit = $4,
-                message = $5,
-                canceled = $6,
-                built = $7
+            DELETE FROM running WHERE pipeline_id = $1
         "###;
-        try!(self.conn.execute(sql, &[
-            &pipeline_id.0,
-            &pr.as_str(),
-            &pull_commit.as_str(),
-            &merge_commit.as_ref().map(Commit::as_str),
-            &message,
-            &canceled,
-            &built,
-        ]));
+        try!(trans.execute(sql, &[&pipeline_id.0]));
+        let sql = r###"
+            DELETE FROM running_pr WHERE pipeline_id = $1
+        "###;
+        try!(trans.execute(sql, &[&pipeline_id.0]));
+        for (position, running_entry) in running.into_iter().enumerate() {
+            let RunningEntry{
+                entries,
+                staging,
+                base_commit,
+                merge_commit,
+                started_at,
+                retries,
+                canceled,
+                built,
+            } = running_entry;
+            let sql = r###"
+                INSERT INTO running
+                    (
+                        pipeline_id,
+                        staging,
+                        position,
+                        base_commit,
+                        merge_commit,
+                        started_at,
+                        retries,
+                        canceled,
+                        built
+                    )
+                VALUES
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "###;
+            try!(trans.execute(sql, &[
+                &pipeline_id.0,
+                &(staging as i32),
+                &(position as i32),
+                &base_commit.as_ref().map(Commit::as_str),
+                &merge_commit.as_ref().map(Commit::as_str),
+                &started_at.map(db::time_to_secs),
+                &(retries as i32),
+                &canceled,
+                &built,
+            ]));
+            let sql = r###"
+                INSERT INTO running_pr
+                    (
+                        pipeline_id,
+                        staging,
+                        position,
+                        pr,
+                        message,
+                        pull_commit,
+                        priority,
+                        approver
+                    )
+                VALUES
+                    ($1, $2, $3, $4, $5, $6, $7, $8)
+            "###;
+            for (position, entry) in entries.into_iter().enumerate() {
+                try!(trans.execute(sql, &[
+                    &pipeline_id.0,
+                    &(staging as i32),
+                    &(position as i32),
+                    &entry.pr.as_str(),
+                    &entry.message,
+                    &entry.commit.as_str(),
+                    &entry.priority,
+                    &entry.approver,
+                ]));
+            }
+        }
+        try!(trans.commit());
         Ok(())
     }
     fn take_running(
         &mut self,
         pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
+        let running = try!(self.peek_running(pipeline_id));
         let trans = try!(self.conn.transaction());
         let sql = r###"
-            SELECT pr, pull_commit, merge_commit, message, canceled, built
-            FROM running
-            WHERE pipeline_id = $1
+            DELETE FROM running WHERE pipeline_id = $1
         "###;
-        let entry = {
-            let stmt = try!(trans.prepare(&sql));
-            let rows = try!(stmt.query(&[&pipeline_id.0]));
-            let rows = rows.iter();
-            let mut rows = rows.map(|row| RunningEntry {
-                pr: Pr::from(row.get::<_, String>(0)),
-                pull_commit: Commit::from(row.get::<_, String>(1)),
-                merge_commit: row.get::<_, Option<String>>(2).map(
-                    |v| Commit::from(v)
-                ),
-                message: row.get(3),
-                canceled: row.get(4),
-                built: row.get(5),
-            });
-            rows.next()
-        };
+        try!(trans.execute(sql, &[&pipeline_id.0]));
         let sql = r###"
-            DELETE FROM running WHERE pipeline_id = $1
+            DELETE FROM running_pr WHERE pipeline_id = $1
         "###;
         try!(trans.execute(sql, &[&pipeline_id.0]));
         try!(trans.commit());
-        Ok(entry)
+        Ok(running)
     }
     fn peek_running(
         &mut self,
         pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT pr, pull_commit, merge_commit, message, canceled, built
+            SELECT
+                staging,
+                base_commit,
+                merge_commit,
+                started_at,
+                retries,
+                canceled,
+                built
             FROM running
             WHERE pipeline_id = $1
+            ORDER BY position ASC
         "###;
         let stmt = try!(self.conn.prepare(&sql));
         let rows = try!(stmt.query(&[&pipeline_id.0]));
         let rows = rows.iter();
-        let mut rows = rows.map(|row| RunningEntry {
-            pr: Pr::from(row.get::<_, String>(0)),
-            pull_commit: Commit::from(row.get::<_, String>(1)),
+        let rows = rows.map(|row| RunningEntry {
+            entries: Vec::new(),
+            staging: row.get::<_, i32>(0) as usize,
+            base_commit: row.get::<_, Option<String>>(1).map(Commit::from),
             merge_commit: row.get::<_, Option<String>>(2).map(Commit::from),
-            message: row.get(3),
-            canceled: row.get(4),
-            built: row.get(5),
+            started_at: row.get::<_, Option<i64>>(3).map(db::secs_to_time),
+            retries: row.get::<_, Option<i32>>(4).unwrap_or(0) as u32,
+            canceled: row.get(5),
+            built: row.get(6),
         });
-        Ok(rows.next())
+        let mut running: Vec<RunningEntry> = rows.collect();
+        let sql = r###"
+            SELECT staging, pr, pull_commit, message, priority, approver
+            FROM running_pr
+            WHERE pipeline_id = $1
+            ORDER BY position ASC
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[&pipeline_id.0]));
+        for row in rows.iter() {
+            let staging = row.get::<_, i32>(0) as usize;
+            let item = QueueEntry {
+                pr: Pr::from(row.get::<_, String>(1)),
+                commit: Commit::from(row.get::<_, String>(2)),
+                message: row.get::<_, String>(3),
+                priority: row.get::<_, i32>(4),
+                approver: row.get::<_, String>(5),
+            };
+            if let Some(running_entry) = running.iter_mut()
+                    .find(|r| r.staging == staging) {
+                running_entry.entries.push(item);
+            }
+        }
+        Ok(running)
     }
     fn add_pending(
         &mut self,
@@ -536,6 +899,18 @@ impl<'a> Db for PostgresTransaction<'a> {
         let sql = r###"
             UPDATE running
             SET canceled = TRUE
+            WHERE pipeline_id = $1 AND staging IN (
+                SELECT staging
+                FROM running_pr
+                WHERE pipeline_id = $1 AND pr = $2
+            )
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0,
+            &pr.as_str(),
+        ]));
+        let sql = r###"
+            DELETE FROM running_pr
             WHERE pipeline_id = $1 AND pr = $2
         "###;
         try!(self.conn.execute(sql, &[
@@ -561,6 +936,19 @@ impl<'a> Db for PostgresTransaction<'a> {
         let sql = r###"
             UPDATE running
             SET canceled = TRUE
+            WHERE pipeline_id = $1 AND staging IN (
+                SELECT staging
+                FROM running_pr
+                WHERE pipeline_id = $1 AND pr = $2 AND pull_commit <> $3
+            )
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0,
+            &pr.as_str(),
+            &commit.as_str(),
+        ]));
+        let sql = r###"
+            DELETE FROM running_pr
             WHERE pipeline_id = $1 AND pr = $2 AND pull_commit <> $3
         "###;
         let affected_rows_running = try!(self.conn.execute(sql, &[
@@ -579,57 +967,383 @@ impl<'a> Db for PostgresTransaction<'a> {
         ]));
         Ok(affected_rows_queue != 0 || affected_rows_running != 0)
     }
+    fn add_failed(
+        &mut self,
+        pipeline_id: PipelineId,
+        QueueEntry{pr, commit, message, priority, approver}: QueueEntry
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            INSERT INTO failed
+                (pipeline_id, pr, pull_commit, message, priority, approver)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (pipeline_id, pr) DO UPDATE SET
+                pull_commit = $3,
+                message = $4,
+                priority = $5,
+                approver = $6
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0,
+            &pr.as_str(),
+            &commit.as_str(),
+            &message,
+            &priority,
+            &approver,
+        ]));
+        Ok(())
+    }
+    fn take_failed_by_pr(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        let trans = try!(self.conn.transaction());
+        let sql = r###"
+            SELECT pr, pull_commit, message, priority, approver
+            FROM failed
+            WHERE pipeline_id = $1 AND pr = $2
+        "###;
+        let entry = {
+            let stmt = try!(trans.prepare(&sql));
+            let rows = try!(stmt.query(&[
+                &pipeline_id.0,
+                &pr.as_str(),
+            ]));
+            let rows = rows.iter();
+            let mut rows = rows.map(|row| QueueEntry {
+                pr: Pr::from(row.get::<_, String>(0)),
+                commit: Commit::from(row.get::<_, String>(1)),
+                message: row.get::<_, String>(2),
+                priority: row.get::<_, i32>(3),
+                approver: row.get::<_, String>(4),
+            });
+            rows.next()
+        };
+        if entry.is_some() {
+            let sql = r###"
+                DELETE FROM failed WHERE pipeline_id = $1 AND pr = $2
+            "###;
+            try!(trans.execute(sql, &[
+                &pipeline_id.0,
+                &pr.as_str(),
+            ]));
+            try!(trans.commit());
+        }
+        Ok(entry)
+    }
+    fn set_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+        priority: Option<i32>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        if let Some(priority) = priority {
+            let sql = r###"
+                INSERT INTO tree_closed (pipeline_id, priority)
+                VALUES ($1, $2)
+                ON CONFLICT (pipeline_id) DO UPDATE SET
+                    priority = $2
+            "###;
+            try!(self.conn.execute(sql, &[&pipeline_id.0, &priority]));
+        } else {
+            let sql = r###"
+                DELETE FROM tree_closed WHERE pipeline_id = $1
+            "###;
+            try!(self.conn.execute(sql, &[&pipeline_id.0]));
+        }
+        Ok(())
+    }
+    fn get_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<i32>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT priority
+            FROM tree_closed
+            WHERE pipeline_id = $1
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[&pipeline_id.0]));
+        let priority = rows.iter().map(|row| row.get::<_, i32>(0)).next();
+        Ok(priority)
+    }
+    fn add_history(
+        &mut self,
+        pipeline_id: PipelineId,
+        history_entry: HistoryEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let HistoryEntry{
+            pr,
+            pull_commit,
+            merge_commit,
+            message,
+            approver,
+            status,
+            ci_urls,
+            started_at,
+            finished_at,
+        } = history_entry;
+        let sql = r###"
+            INSERT INTO history
+                (
+                    pipeline_id,
+                    pr,
+                    message,
+                    pull_commit,
+                    merge_commit,
+                    status,
+                    ci_urls,
+                    started_at,
+                    finished_at,
+                    approver
+                )
+            VALUES
+                ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0,
+            &pr.as_str(),
+            &message,
+            &pull_commit.as_str(),
+            &merge_commit.as_ref().map(Commit::as_str),
+            &(status as i32),
+            &db::urls_to_text(&ci_urls),
+            &started_at.map(db::time_to_secs),
+            &db::time_to_secs(finished_at),
+            &approver,
+        ]));
+        Ok(())
+    }
+    fn list_history(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Vec<HistoryEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT
+                pr,
+                message,
+                pull_commit,
+                merge_commit,
+                status,
+                ci_urls,
+                started_at,
+                finished_at,
+                approver
+            FROM history
+            WHERE pipeline_id = $1
+            ORDER BY id DESC
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[&pipeline_id.0]));
+        let rows = rows.iter();
+        let rows = rows.filter_map(|row| {
+            let status = row.get::<_, i32>(4);
+            let status = match HistoryStatus::from_i32(status) {
+                Some(status) => status,
+                None => {
+                    warn!(
+                        "Skipping history entry with unknown status {}",
+                        status
+                    );
+                    return None;
+                }
+            };
+            Some(HistoryEntry {
+                pr: Pr::from(row.get::<_, String>(0)),
+                message: row.get::<_, String>(1),
+                approver: row.get::<_, String>(8),
+                pull_commit: Commit::from(row.get::<_, String>(2)),
+                merge_commit: row.get::<_, Option<String>>(3)
+                    .map(Commit::from),
+                status: status,
+                ci_urls: db::text_to_urls(&row.get::<_, String>(5)),
+                started_at: row.get::<_, Option<i64>>(6)
+                    .map(db::secs_to_time),
+                finished_at: db::secs_to_time(row.get::<_, i64>(7)),
+            })
+        });
+        Ok(rows.collect())
+    }
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
         ci_state: CiState,
         commit: &Commit,
+        url: Option<&Url>,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
-            INSERT INTO ci_state (ci_id, ci_state, ci_commit)
-            VALUES ($1, $2, $3)
-            ON CONFLICT (ci_id) DO UPDATE SET
+            INSERT INTO ci_state (ci_id, ci_state, ci_commit, ci_url)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (ci_id, ci_commit) DO UPDATE SET
                 ci_state = $2,
-                ci_commit = $3
+                ci_url = $4
         "###;
         try!(self.conn.execute(sql, &[
             &ci_id.0,
             &(ci_state as i32),
             &commit.as_str(),
+            &url.map(|url| url.to_string()),
         ]));
         Ok(())
     }
     fn clear_ci_state(
         &mut self,
         ci_id: CiId,
+        commit: &Commit,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
             DELETE FROM ci_state
-            WHERE ci_id = $1
+            WHERE ci_id = $1 AND ci_commit = $2
         "###;
         try!(self.conn.execute(sql, &[
             &ci_id.0,
+            &commit.as_str(),
         ]));
         Ok(())
     }
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+        commit: &Commit,
+    ) -> Result<Option<(CiState, Option<Url>)>, Box<Error + Send + Sync>> {
         let sql = r###"
             SELECT 
-                ci_state, ci_commit
+                ci_state, ci_url
             FROM ci_state
-            WHERE ci_id = $1
+            WHERE ci_id = $1 AND ci_commit = $2
         "###;
         let stmt = try!(self.conn.prepare(&sql));
-        let rows = try!(stmt.query(&[ &ci_id.0 ]));
+        let rows = try!(stmt.query(&[ &ci_id.0, &commit.as_str() ]));
         let rows = rows.iter();
         let mut rows = rows.map(|row| (
             CiState::from_i32(row.get::<_, i32>(0)),
-            Commit::from(row.get::<_, String>(1)),
+            row.get::<_, Option<String>>(1)
+                .and_then(|url| Url::parse(&url).ok()),
         ));
         let value = rows.next();
         Ok(value)
     }
-}
\ No newline at end of file
+    fn add_outbox(
+        &mut self,
+        outbox_entry: OutboxEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let OutboxEntry{url, body, attempts, next_attempt_at} = outbox_entry;
+        let sql = r###"
+            INSERT INTO outbox
+                (url, body, attempts, next_attempt_at)
+            VALUES
+                ($1, $2, $3, $4)
+        "###;
+        try!(self.conn.execute(sql, &[
+            &url.as_str(),
+            &body,
+            &(attempts as i32),
+            &db::time_to_secs(next_attempt_at),
+        ]));
+        Ok(())
+    }
+    fn list_outbox(
+        &mut self,
+        due: SystemTime,
+    ) -> Result<Vec<(OutboxId, OutboxEntry)>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT id, url, body, attempts, next_attempt_at
+            FROM outbox
+            WHERE next_attempt_at <= $1
+            ORDER BY id ASC
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[&db::time_to_secs(due)]));
+        let rows = rows.iter();
+        let rows = rows.map(|row| (
+            OutboxId(row.get::<_, i32>(0)),
+            OutboxEntry {
+                url: Url::parse(&row.get::<_, String>(1)).unwrap(),
+                body: row.get::<_, String>(2),
+                attempts: row.get::<_, i32>(3) as u32,
+                next_attempt_at: db::secs_to_time(row.get::<_, i64>(4)),
+            },
+        ));
+        Ok(rows.collect())
+    }
+    fn remove_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            DELETE FROM outbox WHERE id = $1
+        "###;
+        try!(self.conn.execute(sql, &[&outbox_id.0]));
+        Ok(())
+    }
+    fn postpone_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+        attempts: u32,
+        next_attempt_at: SystemTime,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            UPDATE outbox
+            SET attempts = $1, next_attempt_at = $2
+            WHERE id = $3
+        "###;
+        try!(self.conn.execute(sql, &[
+            &(attempts as i32),
+            &db::time_to_secs(next_attempt_at),
+            &outbox_id.0,
+        ]));
+        Ok(())
+    }
+    fn add_delegation(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            INSERT INTO delegations
+                (pipeline_id, pr, user_login, pull_commit)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (pipeline_id, pr, user_login)
+            DO UPDATE SET pull_commit = $4
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0, &pr.as_str(), &user, &pull_commit.as_str(),
+        ]));
+        Ok(())
+    }
+    fn is_delegated(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT COUNT(*)
+            FROM delegations
+            WHERE pipeline_id = $1 AND pr = $2 AND user_login = $3
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[&pipeline_id.0, &pr.as_str(), &user]));
+        let rows = rows.iter();
+        let mut rows = rows.map(|row| row.get::<_, i64>(0));
+        Ok(rows.next().unwrap_or(0) != 0)
+    }
+    fn remove_delegations(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        keep_commit: Option<&Commit>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            DELETE FROM delegations
+            WHERE pipeline_id = $1 AND pr = $2 AND pull_commit <> $3
+        "###;
+        // No commit is the same as this one, so they all go.
+        let keep_commit = keep_commit.map(Commit::as_str).unwrap_or("");
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0, &pr.as_str(), &keep_commit,
+        ]));
+        Ok(())
+    }
+}
@@ -1,16 +1,21 @@
 // This file is released under the same terms as Rust itself.
 
 use ci::CiId;
-use db::{self, CiState, Db, PendingEntry, QueueEntry, RunningEntry};
+use db::{self, CiState, Db, HistoryEntry, HistoryStatus, PendingEntry};
+use db::{OutboxEntry, OutboxId, QueueEntry, RunningEntry};
 use hyper::Url;
 use pipeline::PipelineId;
 use rusqlite::{self, Connection};
 use std::convert::AsRef;
 use std::error::Error;
 use std::path::Path;
+use std::time::SystemTime;
 use ui::Pr;
 use vcs::Commit;
 
+/// How long to wait for another connection's write to finish.
+const BUSY_TIMEOUT_MS: u32 = 30_000;
+
 pub struct SqliteDb {
     conn: Connection,
 }
@@ -18,7 +23,16 @@ pub struct SqliteDb {
 impl SqliteDb {
     pub fn open<Q: AsRef<Path>>(path: Q) -> rusqlite::Result<Self> {
         let conn = try!(Connection::open(path));
+        // Every pipeline thread has a connection of its own,
+        // so wait for the others to finish writing instead of failing.
+        try!(conn.execute_batch(&format!(r###"
+            PRAGMA journal_mode = WAL;
+            PRAGMA busy_timeout = {};
+        "###, BUSY_TIMEOUT_MS)));
         try!(conn.execute_batch(r###"
+            CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER
+            );
             CREATE TABLE IF NOT EXISTS ci_state (
                 ci_id INTEGER,
                 ci_state INTEGER,
@@ -40,6 +54,30 @@ impl SqliteDb {
                 canceled INT,
                 built INT
             );
+            CREATE TABLE IF NOT EXISTS failed (
+                pipeline_id INTEGER,
+                pr TEXT,
+                message TEXT,
+                pull_commit TEXT,
+                priority INTEGER,
+                PRIMARY KEY (pipeline_id, pr)
+            );
+            CREATE TABLE IF NOT EXISTS tree_closed (
+                pipeline_id INTEGER PRIMARY KEY,
+                priority INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS history (
+                id INTEGER PRIMARY KEY,
+                pipeline_id INTEGER,
+                pr TEXT,
+                message TEXT,
+                pull_commit TEXT,
+                merge_commit TEXT,
+                status INTEGER,
+                ci_urls TEXT,
+                started_at INTEGER,
+                finished_at INTEGER
+            );
             CREATE TABLE IF NOT EXISTS pending (
                 id INTEGER PRIMARY KEY,
                 pipeline_id INTEGER,
@@ -48,13 +86,113 @@ impl SqliteDb {
                 title TEXT,
                 url TEXT
             );
+            CREATE TABLE IF NOT EXISTS outbox (
+                id INTEGER PRIMARY KEY,
+                url TEXT,
+                body TEXT,
+                attempts INTEGER,
+                next_attempt_at INTEGER
+            );
         "###));
-        Ok(SqliteDb{
+        let mut result = SqliteDb{
             conn: conn,
-        })
+        };
+        try!(result.migrate());
+        Ok(result)
+    }
+    /// Bring the tables created above up to date,
+    /// running each migration the database has not seen yet.
+    fn migrate(&mut self) -> rusqlite::Result<()> {
+        let version = try!(self.conn.query_row(
+            "SELECT MAX(version) FROM schema_version",
+            &[],
+            |row| row.get::<_, Option<i32>>(0),
+        )).unwrap_or(0) as usize;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = try!(self.conn.transaction());
+            try!(transaction.execute_batch(migration));
+            try!(transaction.execute("DELETE FROM schema_version", &[]));
+            try!(transaction.execute(
+                "INSERT INTO schema_version (version) VALUES (?)",
+                &[&((i + 1) as i32)],
+            ));
+            try!(transaction.commit());
+        }
+        Ok(())
     }
 }
 
+/// Changes to the tables since the first release.
+/// A database that has run the first N of these is at version N.
+const MIGRATIONS: &'static [&'static str] = &[
+    // 1: queue priority
+    r###"
+        ALTER TABLE queue ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+    "###,
+    // 2: CI URLs, and stacks of running batches
+    r###"
+        ALTER TABLE ci_state RENAME TO ci_state_old;
+        CREATE TABLE ci_state (
+            ci_id INTEGER,
+            ci_state INTEGER,
+            ci_commit STRING,
+            ci_url TEXT,
+            PRIMARY KEY (ci_id, ci_commit)
+        );
+        INSERT OR REPLACE INTO ci_state (ci_id, ci_state, ci_commit)
+            SELECT ci_id, ci_state, ci_commit FROM ci_state_old;
+        DROP TABLE ci_state_old;
+        ALTER TABLE running RENAME TO running_old;
+        CREATE TABLE running (
+            pipeline_id INTEGER,
+            staging INTEGER,
+            position INTEGER,
+            base_commit TEXT,
+            merge_commit TEXT,
+            started_at INTEGER,
+            retries INTEGER NOT NULL DEFAULT 0,
+            canceled INT,
+            built INT,
+            PRIMARY KEY (pipeline_id, staging)
+        );
+        CREATE TABLE running_pr (
+            pipeline_id INTEGER,
+            staging INTEGER,
+            position INTEGER,
+            pr TEXT,
+            message TEXT,
+            pull_commit TEXT,
+            priority INTEGER NOT NULL DEFAULT 0
+        );
+        INSERT INTO running
+            (pipeline_id, staging, position, merge_commit, canceled, built)
+            SELECT pipeline_id, 0, 0, merge_commit, canceled, built
+            FROM running_old;
+        INSERT INTO running_pr
+            (pipeline_id, staging, position, pr, message, pull_commit)
+            SELECT pipeline_id, 0, 0, pr, message, pull_commit
+            FROM running_old;
+        DROP TABLE running_old;
+    "###,
+    // 3: who approved each entry
+    r###"
+        ALTER TABLE queue ADD COLUMN approver TEXT NOT NULL DEFAULT '';
+        ALTER TABLE running_pr ADD COLUMN approver TEXT NOT NULL DEFAULT '';
+        ALTER TABLE failed ADD COLUMN approver TEXT NOT NULL DEFAULT '';
+        ALTER TABLE history ADD COLUMN approver TEXT NOT NULL DEFAULT '';
+    "###,
+    // 4: users allowed to approve a single PR
+    r###"
+        CREATE TABLE delegations (
+            pipeline_id INTEGER,
+            pr TEXT,
+            user_login TEXT,
+            pull_commit TEXT,
+            PRIMARY KEY (pipeline_id, pr, user_login)
+        );
+    "###,
+];
+
 
 impl Db for SqliteDb {
     fn transaction<T: db::Transaction>(
@@ -85,6 +223,14 @@ impl Db for SqliteDb {
             try!(self.conn.transaction())
         ).pop_queue(pipeline_id)
     }
+    fn peek_queue(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).peek_queue(pipeline_id)
+    }
     fn list_queue(
         &mut self,
         pipeline_id: PipelineId,
@@ -96,16 +242,16 @@ impl Db for SqliteDb {
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
-        running_entry: RunningEntry,
+        running: Vec<RunningEntry>,
     ) -> Result<(), Box<Error + Send + Sync>> {
         SqliteTransaction::new(
             try!(self.conn.transaction())
-        ).put_running(pipeline_id, running_entry)
+        ).put_running(pipeline_id, running)
     }
     fn take_running(
         &mut self,
         pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
         SqliteTransaction::new(
             try!(self.conn.transaction())
         ).take_running(pipeline_id)
@@ -113,7 +259,7 @@ impl Db for SqliteDb {
     fn peek_running(
         &mut self,
         pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
         SqliteTransaction::new(
             try!(self.conn.transaction())
         ).peek_running(pipeline_id)
@@ -172,31 +318,158 @@ impl Db for SqliteDb {
             try!(self.conn.transaction())
         ).cancel_by_pr_different_commit(pipeline_id, pr, commit)
     }
+    fn add_failed(
+        &mut self,
+        pipeline_id: PipelineId,
+        queue_entry: QueueEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).add_failed(pipeline_id, queue_entry)
+    }
+    fn take_failed_by_pr(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).take_failed_by_pr(pipeline_id, pr)
+    }
+    fn set_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+        priority: Option<i32>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).set_tree_closed(pipeline_id, priority)
+    }
+    fn get_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<i32>, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).get_tree_closed(pipeline_id)
+    }
+    fn add_history(
+        &mut self,
+        pipeline_id: PipelineId,
+        history_entry: HistoryEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).add_history(pipeline_id, history_entry)
+    }
+    fn list_history(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Vec<HistoryEntry>, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).list_history(pipeline_id)
+    }
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
         ci_state: CiState,
         commit: &Commit,
+        url: Option<&Url>,
     ) -> Result<(), Box<Error + Send + Sync>> {
         SqliteTransaction::new(
             try!(self.conn.transaction())
-        ).set_ci_state(ci_id, ci_state, commit)
+        ).set_ci_state(ci_id, ci_state, commit, url)
     }
     fn clear_ci_state(
         &mut self,
         ci_id: CiId,
+        commit: &Commit,
     ) -> Result<(), Box<Error + Send + Sync>> {
         SqliteTransaction::new(
             try!(self.conn.transaction())
-        ).clear_ci_state(ci_id)
+        ).clear_ci_state(ci_id, commit)
     }
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+        commit: &Commit,
+    ) -> Result<Option<(CiState, Option<Url>)>, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).get_ci_state(ci_id, commit)
+    }
+    fn add_outbox(
+        &mut self,
+        outbox_entry: OutboxEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).add_outbox(outbox_entry)
+    }
+    fn list_outbox(
+        &mut self,
+        due: SystemTime,
+    ) -> Result<Vec<(OutboxId, OutboxEntry)>, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).list_outbox(due)
+    }
+    fn remove_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).remove_outbox(outbox_id)
+    }
+    fn postpone_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+        attempts: u32,
+        next_attempt_at: SystemTime,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).postpone_outbox(outbox_id, attempts, next_attempt_at)
+    }
+    fn add_delegation(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        // The UI calls this outside of any pipeline transaction.
+        let mut transaction = SqliteTransaction::new(
+            try!(self.conn.transaction())
+        );
+        try!(transaction.add_delegation(pipeline_id, pr, user, pull_commit));
+        try!(transaction.conn.commit());
+        Ok(())
+    }
+    fn is_delegated(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
         SqliteTransaction::new(
             try!(self.conn.transaction())
-        ).get_ci_state(ci_id)
+        ).is_delegated(pipeline_id, pr, user)
+    }
+    fn remove_delegations(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        keep_commit: Option<&Commit>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let mut transaction = SqliteTransaction::new(
+            try!(self.conn.transaction())
+        );
+        try!(transaction.remove_delegations(pipeline_id, pr, keep_commit));
+        try!(transaction.conn.commit());
+        Ok(())
     }
 }
 
@@ -217,17 +490,20 @@ impl<'a> Db for SqliteTransaction<'a> {
     fn push_queue(
         &mut self,
         pipeline_id: PipelineId,
-        QueueEntry{pr, commit, message}: QueueEntry,
+        QueueEntry{pr, commit, message, priority, approver}: QueueEntry,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
-            INSERT INTO queue (pr, pipeline_id, pull_commit, message)
-            VALUES (?, ?, ?, ?)
+            INSERT INTO queue
+                (pr, pipeline_id, pull_commit, message, priority, approver)
+            VALUES (?, ?, ?, ?, ?, ?)
         "###;
         try!(self.conn.execute(sql, &[
             &pr.as_str(),
             &pipeline_id.0,
             &commit.as_str(),
             &message,
+            &priority,
+            &approver,
         ]));
         Ok(())
     }
@@ -236,10 +512,10 @@ impl<'a> Db for SqliteTransaction<'a> {
         pipeline_id: PipelineId,
     ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT id, pr, pull_commit, message
+            SELECT id, pr, pull_commit, message, priority, approver
             FROM queue
             WHERE pipeline_id = ?
-            ORDER BY id ASC LIMIT 1
+            ORDER BY priority DESC, id ASC LIMIT 1
         "###;
         let item = {
             let mut stmt = try!(self.conn.prepare(sql));
@@ -250,6 +526,8 @@ impl<'a> Db for SqliteTransaction<'a> {
                     pr: Pr::from(row.get::<_, String>(1)),
                     commit: Commit::from(row.get::<_, String>(2)),
                     message: row.get::<_, String>(3),
+                    priority: row.get::<_, i32>(4),
+                    approver: row.get::<_, String>(5),
                 },
             )));
             rows.next()
@@ -266,21 +544,48 @@ impl<'a> Db for SqliteTransaction<'a> {
             Ok(None)
         }
     }
+    fn peek_queue(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT pr, pull_commit, message, priority, approver
+            FROM queue
+            WHERE pipeline_id = ?
+            ORDER BY priority DESC, id ASC LIMIT 1
+        "###;
+        let mut stmt = try!(self.conn.prepare(&sql));
+        let mut rows = try!(stmt.query_map(&[&pipeline_id.0], |row| QueueEntry {
+                pr: Pr::from(row.get::<_, String>(0)),
+                commit: Commit::from(row.get::<_, String>(1)),
+                message: row.get::<_, String>(2),
+                priority: row.get::<_, i32>(3),
+                approver: row.get::<_, String>(4),
+            })
+        );
+        match rows.next() {
+            Some(Err(e)) => Err(e.into()),
+            Some(Ok(item)) => Ok(Some(item)),
+            None => Ok(None),
+        }
+    }
     fn list_queue(
         &mut self,
         pipeline_id: PipelineId,
     ) -> Result<Vec<QueueEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT pr, pull_commit, message
+            SELECT pr, pull_commit, message, priority, approver
             FROM queue
             WHERE pipeline_id = ?
-            ORDER BY id ASC
+            ORDER BY priority DESC, id ASC
         "###;
         let mut stmt = try!(self.conn.prepare(&sql));
         let rows = try!(stmt.query_map(&[&pipeline_id.0], |row| QueueEntry {
                 pr: Pr::from(row.get::<_, String>(0)),
                 commit: Commit::from(row.get::<_, String>(1)),
                 message: row.get::<_, String>(2),
+                priority: row.get::<_, i32>(3),
+                approver: row.get::<_, String>(4),
             })
         );
         let mut v = vec![];
@@ -295,100 +600,169 @@ impl<'a> Db for SqliteTransaction<'a> {
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
-        RunningEntry{
-            pr,
-            pull_commit,
-            merge_commit,
-            message,
-            canceled,
-            built,
-        }: RunningEntry
+        running: Vec<RunningEntry>,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
-            REPLACE INTO running
-                (
-                    pipeline_id,
-                    pr,
-                    pull_commit,
-                    merge_commit,
-                    message,
-                    canceled,
-                    built
-                )
-            VALUES
-                (?, ?, ?, ?, ?, ?, ?)
+            DELETE FROM running WHERE pipeline_id = ?
         "###;
-        try!(self.conn.execute(sql, &[
-            &pipeline_id.0,
-            &pr.as_str(),
-            &pull_commit.as_str(),
-            &merge_commit.as_ref().map(Commit::as_str),
-            &message,
-            &canceled,
-            &built,
-        ]));
+        try!(self.conn.execute(sql, &[&pipeline_id.0]));
+        let sql = r###"
+            DELETE FROM running_pr WHERE pipeline_id = ?
+        "###;
+        try!(self.conn.execute(sql, &[&pipeline_id.0]));
+        for (position, running_entry) in running.into_iter().enumerate() {
+            let RunningEntry{
+                entries,
+                staging,
+                base_commit,
+                merge_commit,
+                started_at,
+                retries,
+                canceled,
+                built,
+            } = running_entry;
+            let sql = r###"
+                INSERT INTO running
+                    (
+                        pipeline_id,
+                        staging,
+                        position,
+                        base_commit,
+                        merge_commit,
+                        started_at,
+                        retries,
+                        canceled,
+                        built
+                    )
+                VALUES
+                    (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "###;
+            try!(self.conn.execute(sql, &[
+                &pipeline_id.0,
+                &(staging as i32),
+                &(position as i32),
+                &base_commit.as_ref().map(Commit::as_str),
+                &merge_commit.as_ref().map(Commit::as_str),
+                &started_at.map(db::time_to_secs),
+                &(retries as i32),
+                &canceled,
+                &built,
+            ]));
+            let sql = r###"
+                INSERT INTO running_pr
+                    (
+                        pipeline_id,
+                        staging,
+                        position,
+                        pr,
+                        message,
+                        pull_commit,
+                        priority,
+                        approver
+                    )
+                VALUES
+                    (?, ?, ?, ?, ?, ?, ?, ?)
+            "###;
+            for (position, entry) in entries.into_iter().enumerate() {
+                try!(self.conn.execute(sql, &[
+                    &pipeline_id.0,
+                    &(staging as i32),
+                    &(position as i32),
+                    &entry.pr.as_str(),
+                    &entry.message,
+                    &entry.commit.as_str(),
+                    &entry.priority,
+                    &entry.approver,
+                ]));
+            }
+        }
         Ok(())
     }
     fn take_running(
         &mut self,
         pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
+        let running = try!(self.peek_running(pipeline_id));
+        let sql = r###"
+            DELETE FROM running WHERE pipeline_id = ?
+        "###;
+        try!(self.conn.execute(sql, &[&pipeline_id.0]));
         let sql = r###"
-            SELECT pr, pull_commit, merge_commit, message, canceled, built
+            DELETE FROM running_pr WHERE pipeline_id = ?
+        "###;
+        try!(self.conn.execute(sql, &[&pipeline_id.0]));
+        Ok(running)
+    }
+    fn peek_running(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT
+                staging,
+                base_commit,
+                merge_commit,
+                started_at,
+                retries,
+                canceled,
+                built
             FROM running
             WHERE pipeline_id = ?
+            ORDER BY position ASC
         "###;
-        let entry = {
+        let mut running = vec![];
+        {
             let mut stmt = try!(self.conn.prepare(&sql));
-            let mut rows = try!(stmt
+            let rows = try!(stmt
                 .query_map(&[&pipeline_id.0], |row| RunningEntry {
-                    pr: Pr::from(row.get::<_, String>(0)),
-                    pull_commit: Commit::from(row.get::<_, String>(1)),
+                    entries: Vec::new(),
+                    staging: row.get::<_, i32>(0) as usize,
+                    base_commit: row.get::<_, Option<String>>(1)
+                        .map(Commit::from),
                     merge_commit: row.get::<_, Option<String>>(2)
                         .map(Commit::from),
-                    message: row.get(3),
-                    canceled: row.get(4),
-                    built: row.get(5),
+                    started_at: row.get::<_, Option<i64>>(3)
+                        .map(db::secs_to_time),
+                    retries: row.get::<_, Option<i32>>(4).unwrap_or(0) as u32,
+                    canceled: row.get(5),
+                    built: row.get(6),
                 })
             );
-            match rows.next() {
-                Some(Err(e)) => return Err(e.into()),
-                Some(Ok(item)) => Some(item),
-                None => None,
+            for item in rows {
+                match item {
+                    Ok(item) => running.push(item),
+                    Err(e) => return Err(e.into()),
+                }
             }
-        };
-        let sql = r###"
-            DELETE FROM running WHERE pipeline_id = ?
-        "###;
-        try!(self.conn.execute(sql, &[&pipeline_id.0]));
-        Ok(entry)
-    }
-    fn peek_running(
-        &mut self,
-        pipeline_id: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+        }
         let sql = r###"
-            SELECT pr, pull_commit, merge_commit, message, canceled, built
-            FROM running
+            SELECT staging, pr, pull_commit, message, priority, approver
+            FROM running_pr
             WHERE pipeline_id = ?
+            ORDER BY position ASC
         "###;
         let mut stmt = try!(self.conn.prepare(&sql));
-        let mut rows = try!(stmt
-            .query_map(&[&pipeline_id.0], |row| RunningEntry {
-                pr: Pr::from(row.get::<_, String>(0)),
-                pull_commit: Commit::from(row.get::<_, String>(1)),
-                merge_commit: row.get::<_, Option<String>>(2)
-                    .map(Commit::from),
-                message: row.get(3),
-                canceled: row.get(4),
-                built: row.get(5),
-            })
-        );
-        match rows.next() {
-            Some(Err(e)) => Err(e.into()),
-            Some(Ok(item)) => Ok(Some(item)),
-            None => Ok(None),
+        let rows = try!(stmt.query_map(&[&pipeline_id.0], |row| (
+            row.get::<_, i32>(0) as usize,
+            QueueEntry {
+                pr: Pr::from(row.get::<_, String>(1)),
+                commit: Commit::from(row.get::<_, String>(2)),
+                message: row.get::<_, String>(3),
+                priority: row.get::<_, i32>(4),
+                approver: row.get::<_, String>(5),
+            },
+        )));
+        for item in rows {
+            let (staging, item) = match item {
+                Ok(item) => item,
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(running_entry) = running.iter_mut()
+                    .find(|r| r.staging == staging) {
+                running_entry.entries.push(item);
+            }
         }
+        Ok(running)
     }
     fn add_pending(
         &mut self,
@@ -516,6 +890,19 @@ impl<'a> Db for SqliteTransaction<'a> {
         let sql = r###"
             UPDATE running
             SET canceled = 1
+            WHERE pipeline_id = ? AND staging IN (
+                SELECT staging
+                FROM running_pr
+                WHERE pipeline_id = ? AND pr = ?
+            )
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0,
+            &pipeline_id.0,
+            &pr.as_str(),
+        ]));
+        let sql = r###"
+            DELETE FROM running_pr
             WHERE pipeline_id = ? AND pr = ?
         "###;
         try!(self.conn.execute(sql, &[
@@ -541,6 +928,20 @@ impl<'a> Db for SqliteTransaction<'a> {
         let sql = r###"
             UPDATE running
             SET canceled = 1
+            WHERE pipeline_id = ? AND staging IN (
+                SELECT staging
+                FROM running_pr
+                WHERE pipeline_id = ? AND pr = ? AND pull_commit <> ?
+            )
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0,
+            &pipeline_id.0,
+            &pr.as_str(),
+            &commit.as_str(),
+        ]));
+        let sql = r###"
+            DELETE FROM running_pr
             WHERE pipeline_id = ? AND pr = ? AND pull_commit <> ?
         "###;
         let affected_rows_running = try!(self.conn.execute(sql, &[
@@ -559,59 +960,264 @@ impl<'a> Db for SqliteTransaction<'a> {
         ]));
         Ok(affected_rows_queue != 0 || affected_rows_running != 0)
     }
+    fn add_failed(
+        &mut self,
+        pipeline_id: PipelineId,
+        QueueEntry{pr, commit, message, priority, approver}: QueueEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            REPLACE INTO failed
+                (pipeline_id, pr, pull_commit, message, priority, approver)
+            VALUES (?, ?, ?, ?, ?, ?)
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0,
+            &pr.as_str(),
+            &commit.as_str(),
+            &message,
+            &priority,
+            &approver,
+        ]));
+        Ok(())
+    }
+    fn take_failed_by_pr(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT pr, pull_commit, message, priority, approver
+            FROM failed
+            WHERE pipeline_id = ? AND pr = ?
+        "###;
+        let entry = {
+            let mut stmt = try!(self.conn.prepare(&sql));
+            let mut rows = try!(stmt
+                .query_map(&[
+                    &pipeline_id.0,
+                    &pr.as_str(),
+                ], |row| QueueEntry {
+                    pr: Pr::from(row.get::<_, String>(0)),
+                    commit: Commit::from(row.get::<_, String>(1)),
+                    message: row.get::<_, String>(2),
+                    priority: row.get::<_, i32>(3),
+                    approver: row.get::<_, String>(4),
+                })
+            );
+            match rows.next() {
+                Some(Err(e)) => return Err(e.into()),
+                Some(Ok(item)) => Some(item),
+                None => None,
+            }
+        };
+        if entry.is_some() {
+            let sql = r###"
+                DELETE FROM failed WHERE pipeline_id = ? AND pr = ?
+            "###;
+            try!(self.conn.execute(sql, &[
+                &pipeline_id.0,
+                &pr.as_str(),
+            ]));
+        }
+        Ok(entry)
+    }
+    fn set_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+        priority: Option<i32>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        if let Some(priority) = priority {
+            let sql = r###"
+                REPLACE INTO tree_closed (pipeline_id, priority)
+                VALUES (?, ?)
+            "###;
+            try!(self.conn.execute(sql, &[&pipeline_id.0, &priority]));
+        } else {
+            let sql = r###"
+                DELETE FROM tree_closed WHERE pipeline_id = ?
+            "###;
+            try!(self.conn.execute(sql, &[&pipeline_id.0]));
+        }
+        Ok(())
+    }
+    fn get_tree_closed(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<i32>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT priority
+            FROM tree_closed
+            WHERE pipeline_id = ?
+        "###;
+        let mut stmt = try!(self.conn.prepare(&sql));
+        let mut rows = try!(stmt
+            .query_map(&[&pipeline_id.0], |row| row.get::<_, i32>(0))
+        );
+        match rows.next() {
+            Some(Err(e)) => Err(e.into()),
+            Some(Ok(priority)) => Ok(Some(priority)),
+            None => Ok(None),
+        }
+    }
+    fn add_history(
+        &mut self,
+        pipeline_id: PipelineId,
+        history_entry: HistoryEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let HistoryEntry{
+            pr,
+            pull_commit,
+            merge_commit,
+            message,
+            approver,
+            status,
+            ci_urls,
+            started_at,
+            finished_at,
+        } = history_entry;
+        let sql = r###"
+            INSERT INTO history
+                (
+                    pipeline_id,
+                    pr,
+                    message,
+                    pull_commit,
+                    merge_commit,
+                    status,
+                    ci_urls,
+                    started_at,
+                    finished_at,
+                    approver
+                )
+            VALUES
+                (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0,
+            &pr.as_str(),
+            &message,
+            &pull_commit.as_str(),
+            &merge_commit.as_ref().map(Commit::as_str),
+            &(status as i32),
+            &db::urls_to_text(&ci_urls),
+            &started_at.map(db::time_to_secs),
+            &db::time_to_secs(finished_at),
+            &approver,
+        ]));
+        Ok(())
+    }
+    fn list_history(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<Vec<HistoryEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT
+                pr,
+                message,
+                pull_commit,
+                merge_commit,
+                status,
+                ci_urls,
+                started_at,
+                finished_at,
+                approver
+            FROM history
+            WHERE pipeline_id = ?
+            ORDER BY id DESC
+        "###;
+        let mut stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query_map(&[&pipeline_id.0], |row| {
+            let status = row.get::<_, i32>(4);
+            HistoryStatus::from_i32(status).map(|status| HistoryEntry {
+                pr: Pr::from(row.get::<_, String>(0)),
+                message: row.get::<_, String>(1),
+                approver: row.get::<_, String>(8),
+                pull_commit: Commit::from(row.get::<_, String>(2)),
+                merge_commit: row.get::<_, Option<String>>(3)
+                    .map(Commit::from),
+                status: status,
+                ci_urls: db::text_to_urls(&row.get::<_, String>(5)),
+                started_at: row.get::<_, Option<i64>>(6)
+                    .map(db::secs_to_time),
+                finished_at: db::secs_to_time(row.get::<_, i64>(7)),
+            }).ok_or(status)
+        }));
+        let mut v = vec![];
+        for item in rows {
+            match item {
+                Ok(Ok(item)) => v.push(item),
+                Ok(Err(status)) => {
+                    warn!(
+                        "Skipping history entry with unknown status {}",
+                        status
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(v)
+    }
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
         ci_state: CiState,
         commit: &Commit,
+        url: Option<&Url>,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
             REPLACE INTO ci_state
                 (
                     ci_id,
                     ci_state,
-                    ci_commit
+                    ci_commit,
+                    ci_url
                 )
             VALUES
-                (?, ?, ?)
+                (?, ?, ?, ?)
         "###;
         try!(self.conn.execute(sql, &[
             &ci_id.0,
             &(ci_state as i32),
             &commit.as_str(),
+            &url.map(|url| url.to_string()),
         ]));
         Ok(())
     }
     fn clear_ci_state(
         &mut self,
         ci_id: CiId,
+        commit: &Commit,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
             DELETE FROM ci_state
-            WHERE ci_id = ?
+            WHERE ci_id = ? AND ci_commit = ?
         "###;
         try!(self.conn.execute(sql, &[
             &ci_id.0,
+            &commit.as_str(),
         ]));
         Ok(())
     }
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+        commit: &Commit,
+    ) -> Result<Option<(CiState, Option<Url>)>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT ci_state, ci_commit
+            SELECT ci_state, ci_url
             FROM ci_state
-            WHERE ci_id = ?
+            WHERE ci_id = ? AND ci_commit = ?
         "###;
         let entry = {
             let mut stmt = try!(self.conn.prepare(&sql));
             let mut rows = try!(stmt
                 .query_map(&[
                     &ci_id.0,
+                    &commit.as_str(),
                 ], |row| (
                     CiState::from_i32(row.get::<_, i32>(0)),
-                    Commit::from(row.get::<_, String>(1)),
+                    row.get::<_, Option<String>>(1)
+                        .and_then(|url| Url::parse(&url).ok()),
                 ))
             );
             match rows.next() {
@@ -622,4 +1228,319 @@ impl<'a> Db for SqliteTransaction<'a> {
         };
         Ok(entry)
     }
-}
\ No newline at end of file
+    fn add_outbox(
+        &mut self,
+        outbox_entry: OutboxEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let OutboxEntry{url, body, attempts, next_attempt_at} = outbox_entry;
+        let sql = r###"
+            INSERT INTO outbox
+                (url, body, attempts, next_attempt_at)
+            VALUES
+                (?, ?, ?, ?)
+        "###;
+        try!(self.conn.execute(sql, &[
+            &url.as_str(),
+            &body,
+            &(attempts as i32),
+            &db::time_to_secs(next_attempt_at),
+        ]));
+        Ok(())
+    }
+    fn list_outbox(
+        &mut self,
+        due: SystemTime,
+    ) -> Result<Vec<(OutboxId, OutboxEntry)>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT id, url, body, attempts, next_attempt_at
+            FROM outbox
+            WHERE next_attempt_at <= ?
+            ORDER BY id ASC
+        "###;
+        let mut stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query_map(&[&db::time_to_secs(due)], |row| (
+                OutboxId(row.get::<_, i32>(0)),
+                OutboxEntry {
+                    url: Url::parse(&row.get::<_, String>(1)).unwrap(),
+                    body: row.get::<_, String>(2),
+                    attempts: row.get::<_, i32>(3) as u32,
+                    next_attempt_at: db::secs_to_time(row.get::<_, i64>(4)),
+                },
+            ))
+        );
+        let mut v = vec![];
+        for item in rows {
+            match item {
+                Ok(item) => v.push(item),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(v)
+    }
+    fn remove_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            DELETE FROM outbox WHERE id = ?
+        "###;
+        try!(self.conn.execute(sql, &[&outbox_id.0]));
+        Ok(())
+    }
+    fn postpone_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+        attempts: u32,
+        next_attempt_at: SystemTime,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            UPDATE outbox
+            SET attempts = ?, next_attempt_at = ?
+            WHERE id = ?
+        "###;
+        try!(self.conn.execute(sql, &[
+            &(attempts as i32),
+            &db::time_to_secs(next_attempt_at),
+            &outbox_id.0,
+        ]));
+        Ok(())
+    }
+    fn add_delegation(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            REPLACE INTO delegations
+                (pipeline_id, pr, user_login, pull_commit)
+            VALUES (?, ?, ?, ?)
+        "###;
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0, &pr.as_str(), &user, &pull_commit.as_str(),
+        ]));
+        Ok(())
+    }
+    fn is_delegated(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT COUNT(*)
+            FROM delegations
+            WHERE pipeline_id = ? AND pr = ? AND user_login = ?
+        "###;
+        let count = try!(self.conn.query_row(
+            sql,
+            &[&pipeline_id.0, &pr.as_str(), &user],
+            |row| row.get::<_, i64>(0),
+        ));
+        Ok(count != 0)
+    }
+    fn remove_delegations(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        keep_commit: Option<&Commit>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            DELETE FROM delegations
+            WHERE pipeline_id = ? AND pr = ? AND pull_commit <> ?
+        "###;
+        // No commit is the same as this one, so they all go.
+        let keep_commit = keep_commit.map(Commit::as_str).unwrap_or("");
+        try!(self.conn.execute(sql, &[
+            &pipeline_id.0, &pr.as_str(), &keep_commit,
+        ]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use db::{Db, HistoryEntry, HistoryStatus, OutboxEntry, QueueEntry};
+    use db::RunningEntry;
+    use hyper::Url;
+    use pipeline::PipelineId;
+    use rusqlite::Connection;
+    use std::env;
+    use std::fs;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use super::{MIGRATIONS, SqliteDb, SqliteTransaction};
+    use ui::Pr;
+    use vcs::Commit;
+
+    #[test]
+    fn test_migrate_first_release() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        let path = env::temp_dir()
+            .join(format!("aelita-test-migrate-{}.db", nanos));
+        let _ = fs::remove_file(&path);
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(r###"
+                CREATE TABLE ci_state (
+                    ci_id INTEGER,
+                    ci_state INTEGER,
+                    ci_commit STRING
+                );
+                CREATE TABLE queue (
+                    id INTEGER PRIMARY KEY,
+                    pipeline_id INTEGER,
+                    pr TEXT,
+                    message TEXT,
+                    pull_commit TEXT
+                );
+                CREATE TABLE running (
+                    pipeline_id INTEGER PRIMARY KEY,
+                    pr TEXT,
+                    message TEXT,
+                    pull_commit TEXT,
+                    merge_commit TEXT,
+                    canceled INT,
+                    built INT
+                );
+                CREATE TABLE pending (
+                    id INTEGER PRIMARY KEY,
+                    pipeline_id INTEGER,
+                    pr TEXT,
+                    pull_commit TEXT,
+                    title TEXT,
+                    url TEXT
+                );
+                INSERT INTO queue (pipeline_id, pr, message, pull_commit)
+                    VALUES (0, '2', 'queued', 'b');
+                INSERT INTO running
+                    (pipeline_id, pr, message, pull_commit, merge_commit,
+                        canceled, built)
+                    VALUES (0, '1', 'running', 'a', 'm', 0, 0);
+            "###).unwrap();
+        }
+        // Opening twice must not run any migration again.
+        drop(SqliteDb::open(&path).unwrap());
+        let mut db = SqliteDb::open(&path).unwrap();
+        let version = db.conn.query_row(
+            "SELECT version FROM schema_version",
+            &[],
+            |row| row.get::<_, i32>(0),
+        ).unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+        {
+            let mut t = SqliteTransaction::new(db.conn.transaction().unwrap());
+            assert_eq!(t.peek_queue(PipelineId(0)).unwrap(), Some(QueueEntry{
+                commit: Commit::from("b".to_owned()),
+                pr: Pr::from("2".to_owned()),
+                message: "queued".to_owned(),
+                priority: 0,
+                approver: String::new(),
+            }));
+            assert_eq!(t.peek_running(PipelineId(0)).unwrap(), vec![
+                RunningEntry{
+                    entries: vec![QueueEntry{
+                        commit: Commit::from("a".to_owned()),
+                        pr: Pr::from("1".to_owned()),
+                        message: "running".to_owned(),
+                        priority: 0,
+                        approver: String::new(),
+                    }],
+                    staging: 0,
+                    base_commit: None,
+                    merge_commit: Some(Commit::from("m".to_owned())),
+                    started_at: None,
+                    retries: 0,
+                    canceled: false,
+                    built: false,
+                },
+            ]);
+        }
+        drop(db);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_outbox() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let entry = |url: &str, next_attempt_at| OutboxEntry{
+            url: Url::parse(url).unwrap(),
+            body: format!(r#"{{"url":"{}"}}"#, url),
+            attempts: 0,
+            next_attempt_at: next_attempt_at,
+        };
+        let a = entry("http://a.example.com/", at(10));
+        let b = entry("http://b.example.com/", at(20));
+        let mut db = SqliteDb::open(":memory:").unwrap();
+        let mut t = SqliteTransaction::new(db.conn.transaction().unwrap());
+        t.add_outbox(a.clone()).unwrap();
+        t.add_outbox(b.clone()).unwrap();
+        assert!(t.list_outbox(at(5)).unwrap().is_empty());
+        let due = t.list_outbox(at(20)).unwrap();
+        assert_eq!(
+            due.iter().map(|x| x.1.clone()).collect::<Vec<_>>(),
+            vec![a.clone(), b.clone()]
+        );
+        let (a_id, b_id) = (due[0].0, due[1].0);
+        t.postpone_outbox(a_id, 1, at(30)).unwrap();
+        let postponed = OutboxEntry{
+            attempts: 1,
+            next_attempt_at: at(30),
+            ..a
+        };
+        assert_eq!(t.list_outbox(at(20)).unwrap(), vec![(b_id, b.clone())]);
+        assert_eq!(
+            t.list_outbox(at(30)).unwrap(),
+            vec![(a_id, postponed), (b_id, b.clone())]
+        );
+        t.remove_outbox(a_id).unwrap();
+        assert_eq!(t.list_outbox(at(30)).unwrap(), vec![(b_id, b)]);
+    }
+
+    #[test]
+    fn test_history_skips_unknown_status() {
+        let entry = HistoryEntry{
+            pr: Pr::from("1".to_owned()),
+            pull_commit: Commit::from("a".to_owned()),
+            merge_commit: Some(Commit::from("m".to_owned())),
+            message: "merged".to_owned(),
+            approver: "luser".to_owned(),
+            status: HistoryStatus::Completed,
+            ci_urls: vec![],
+            started_at: None,
+            finished_at: UNIX_EPOCH + Duration::from_secs(10),
+        };
+        let mut db = SqliteDb::open(":memory:").unwrap();
+        let mut t = SqliteTransaction::new(db.conn.transaction().unwrap());
+        t.add_history(PipelineId(0), entry.clone()).unwrap();
+        t.conn.execute(r###"
+            INSERT INTO history (pipeline_id, pr, status, finished_at)
+            VALUES (0, '2', 99, 20)
+        "###, &[]).unwrap();
+        assert_eq!(t.list_history(PipelineId(0)).unwrap(), vec![entry]);
+    }
+
+    #[test]
+    fn test_delegations() {
+        let pr_a = Pr::from("1".to_owned());
+        let pr_b = Pr::from("2".to_owned());
+        let commit_a = Commit::from("a".to_owned());
+        let commit_b = Commit::from("b".to_owned());
+        let mut db = SqliteDb::open(":memory:").unwrap();
+        db.add_delegation(PipelineId(0), &pr_a, "helper", &commit_a).unwrap();
+        db.add_delegation(PipelineId(0), &pr_b, "helper", &commit_b).unwrap();
+        assert!(db.is_delegated(PipelineId(0), &pr_a, "helper").unwrap());
+        assert!(!db.is_delegated(PipelineId(0), &pr_a, "other").unwrap());
+        assert!(!db.is_delegated(PipelineId(1), &pr_a, "helper").unwrap());
+        // The head is still the commit it was delegated on.
+        db.remove_delegations(PipelineId(0), &pr_a, Some(&commit_a)).unwrap();
+        assert!(db.is_delegated(PipelineId(0), &pr_a, "helper").unwrap());
+        db.remove_delegations(PipelineId(0), &pr_a, Some(&commit_b)).unwrap();
+        assert!(!db.is_delegated(PipelineId(0), &pr_a, "helper").unwrap());
+        assert!(db.is_delegated(PipelineId(0), &pr_b, "helper").unwrap());
+        db.remove_delegations(PipelineId(0), &pr_b, None).unwrap();
+        assert!(!db.is_delegated(PipelineId(0), &pr_b, "helper").unwrap());
+    }
+}
@@ -0,0 +1,452 @@
+// This file is released under the same terms as Rust itself.
+
+/*! Outbound webhooks, for dashboards and chat bots.
+
+    Every status that a pipeline sends to a front-end is also written to
+    the `outbox` table, once for each configured URL, in the same
+    transaction as the change that caused it, so a restart does not lose
+    any. The worker only drains the outbox. Each URL gets a thread of its
+    own, so one that is down does not hold up deliveries to the others.
+ */
+
+use crossbeam;
+use db::{self, Db, DbBox, OutboxEntry, OutboxId, Transaction};
+use hyper;
+use hyper::Url;
+use hyper::client::Client;
+use hyper::header::{ContentType, Headers, UserAgent};
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use serde_json;
+use std::cmp;
+use std::error::Error;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ui::{self, Pr};
+use ui::api::status_parts;
+use util::{USER_AGENT, MIN_DELAY_SEC, MAX_DELAY_SEC};
+use util::api_headers;
+
+/// How often to look for new deliveries.
+const POLL_SEC: u64 = 2;
+
+/// How long to wait for an endpoint to answer.
+const TIMEOUT_SEC: u64 = 30;
+
+/// The longest to wait between two attempts at the same delivery.
+const MAX_BACKOFF_SEC: u64 = 60*60;
+
+/// Deliveries that have failed this many times are dropped.
+/// With the backoff above, that is about a day after the first attempt.
+const MAX_ATTEMPTS: u32 = 36;
+
+pub trait PipelinesConfig: Send + Sync + 'static {
+    /// The name of the project a pipeline belongs to,
+    /// and whether it is the project's try pipeline.
+    fn project_by_pipeline(&self, PipelineId) -> Option<(String, bool)>;
+}
+
+#[derive(Clone)]
+pub enum Event {}
+
+#[derive(Clone)]
+pub enum Message {}
+
+/// Where the pipelines write the deliveries to.
+pub struct Outbox {
+    urls: Vec<Url>,
+    pipelines: Box<PipelinesConfig>,
+}
+
+impl Outbox {
+    pub fn new(urls: Vec<Url>, pipelines: Box<PipelinesConfig>) -> Outbox {
+        Outbox {
+            urls: urls,
+            pipelines: pipelines,
+        }
+    }
+
+    /// Queue a status for every URL, as part of the caller's transaction.
+    /// Statuses that can't be turned into JSON are logged and dropped,
+    /// since failing the transaction would hold up the pipeline.
+    pub fn add<D: Db>(
+        &self,
+        db: &mut D,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: &ui::Status,
+        details: &ui::Details,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let body = match self.hook_body(
+            pipeline_id,
+            pr,
+            status,
+            details,
+            SystemTime::now(),
+        ) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to make hook for PR {}: {:?}", pr, e);
+                return Ok(());
+            }
+        };
+        for url in &self.urls {
+            try!(db.add_outbox(OutboxEntry {
+                url: url.clone(),
+                body: body.clone(),
+                attempts: 0,
+                next_attempt_at: UNIX_EPOCH,
+            }));
+        }
+        Ok(())
+    }
+
+    fn hook_body(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: &ui::Status,
+        details: &ui::Details,
+        now: SystemTime,
+    ) -> Result<String, HookError> {
+        let (pipeline, is_try) =
+            match self.pipelines.project_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => return Err(HookError::Pipeline(pipeline_id)),
+            };
+        let (state, pull_commit, merge_commit, url) = status_parts(status);
+        // Not every status carries a build, but the details might.
+        let url = url.or_else(|| details.ci_urls.last().map(|x| &x.1));
+        Ok(try!(serde_json::to_string(&HookDesc {
+            pipeline: pipeline,
+            is_try: is_try,
+            pr: pr.to_string(),
+            state: state.to_owned(),
+            pull_commit: pull_commit.map(ToString::to_string),
+            merge_commit: merge_commit.map(ToString::to_string),
+            ci_url: url.map(ToString::to_string),
+            timestamp: now.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        })))
+    }
+}
+
+pub struct Worker {
+    urls: Vec<Url>,
+    secret: String,
+    db_build: db::Builder,
+    client: Client,
+}
+
+impl Worker {
+    pub fn new(
+        urls: Vec<Url>,
+        secret: String,
+        db_build: db::Builder,
+    ) -> Worker {
+        let mut client = Client::new();
+        client.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SEC)));
+        client.set_write_timeout(Some(Duration::from_secs(TIMEOUT_SEC)));
+        Worker {
+            urls: urls,
+            secret: secret,
+            db_build: db_build,
+            client: client,
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct HookDesc {
+    pipeline: String,
+    #[serde(rename="try")]
+    is_try: bool,
+    pr: String,
+    state: String,
+    pull_commit: Option<String>,
+    merge_commit: Option<String>,
+    ci_url: Option<String>,
+    timestamp: u64,
+}
+
+impl pipeline::Worker<Event, Message> for Worker {
+    fn run(
+        &self,
+        _: Receiver<Message>,
+        _: Sender<Event>,
+    ) {
+        crossbeam::scope(|scope| {
+            for url in &self.urls {
+                scope.spawn(move || self.drain(url));
+            }
+        })
+    }
+}
+
+impl Worker {
+    fn open_db(&self) -> DbBox {
+        loop {
+            match self.db_build.open() {
+                Ok(db) => return db,
+                Err(e) => {
+                    warn!("Failed to open the DB: {:?}", e);
+                    thread::sleep(Duration::new(MAX_DELAY_SEC, 0));
+                }
+            }
+        }
+    }
+
+    /// Keep delivering to one URL, with a connection of its own.
+    fn drain(&self, url: &Url) {
+        let mut db = self.open_db();
+        loop {
+            let wait = self.deliver(&mut db, url);
+            thread::sleep(wait);
+        }
+    }
+
+    /// Try every delivery to `url` that is due, oldest first,
+    /// and say how long to wait before looking again.
+    ///
+    /// After a failure, the URL is left alone until the failed delivery
+    /// is due again, so the ones behind it are still sent in order,
+    /// and do not each wait out the timeout in the meantime.
+    fn deliver(&self, db: &mut DbBox, url: &Url) -> Duration {
+        let now = SystemTime::now();
+        let due = match db.transaction(DueTransaction(now)) {
+            Ok(due) => due,
+            Err(e) => {
+                warn!("Failed to read the outbox: {:?}", e);
+                return Duration::from_secs(POLL_SEC);
+            }
+        };
+        for (outbox_id, entry) in due {
+            if &entry.url != url {
+                continue;
+            }
+            let (result, wait) = match self.send(&entry) {
+                Ok(()) => (db.transaction(RemoveTransaction(outbox_id)), None),
+                Err(e) => {
+                    let attempts = entry.attempts + 1;
+                    warn!(
+                        "Failed to send hook to {} ({} attempts): {:?}",
+                        entry.url,
+                        attempts,
+                        e
+                    );
+                    if attempts >= MAX_ATTEMPTS {
+                        warn!("Giving up on hook to {}", entry.url);
+                        (db.transaction(RemoveTransaction(outbox_id)), None)
+                    } else {
+                        let wait = backoff(attempts);
+                        (db.transaction(PostponeTransaction(
+                            outbox_id,
+                            attempts,
+                            now + wait,
+                        )), Some(wait))
+                    }
+                }
+            };
+            if let Err(e) = result {
+                warn!("Failed to update the outbox: {:?}", e);
+            }
+            if let Some(wait) = wait {
+                return wait;
+            }
+        }
+        Duration::from_secs(POLL_SEC)
+    }
+
+    fn send(&self, entry: &OutboxEntry) -> Result<(), HookError> {
+        let mut headers = Headers::new();
+        headers.set(ContentType::json());
+        headers.set(UserAgent(USER_AGENT.to_owned()));
+        api_headers::sign(&mut headers, &self.secret, entry.body.as_bytes());
+        let resp = try!(
+            self.client.post(entry.url.clone())
+                .headers(headers)
+                .body(&entry.body[..])
+                .send()
+        );
+        if !resp.status.is_success() {
+            return Err(HookError::HttpStatus(resp.status));
+        }
+        Ok(())
+    }
+}
+
+/// How long to wait before the next attempt, after `attempts` failures.
+fn backoff(attempts: u32) -> Duration {
+    let secs = if attempts < 32 {
+        MIN_DELAY_SEC << attempts
+    } else {
+        MAX_BACKOFF_SEC
+    };
+    Duration::from_secs(cmp::min(secs, MAX_BACKOFF_SEC))
+}
+
+struct DueTransaction(SystemTime);
+
+impl Transaction for DueTransaction {
+    type Return = Vec<(OutboxId, OutboxEntry)>;
+    fn run<D: Db>(
+        self,
+        db: &mut D,
+    ) -> Result<Vec<(OutboxId, OutboxEntry)>, Box<Error + Send + Sync>> {
+        db.list_outbox(self.0)
+    }
+}
+
+struct RemoveTransaction(OutboxId);
+
+impl Transaction for RemoveTransaction {
+    type Return = ();
+    fn run<D: Db>(self, db: &mut D) -> Result<(), Box<Error + Send + Sync>> {
+        db.remove_outbox(self.0)
+    }
+}
+
+struct PostponeTransaction(OutboxId, u32, SystemTime);
+
+impl Transaction for PostponeTransaction {
+    type Return = ();
+    fn run<D: Db>(self, db: &mut D) -> Result<(), Box<Error + Send + Sync>> {
+        db.postpone_outbox(self.0, self.1, self.2)
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum HookError {
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// JSON error
+        Json(err: serde_json::error::Error) {
+            cause(err)
+            from()
+        }
+        /// Project not found for pipeline
+        Pipeline(pipeline_id: PipelineId) {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ci::CiId;
+    use db::{self, Db};
+    use hyper::Url;
+    use pipeline::PipelineId;
+    use serde_json::{self, Value};
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{MAX_BACKOFF_SEC, Outbox, PipelinesConfig, backoff};
+    use ui::{Details, Pr, Status};
+    use vcs::Commit;
+
+    struct OneProject;
+
+    impl PipelinesConfig for OneProject {
+        fn project_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Option<(String, bool)> {
+            match pipeline_id {
+                PipelineId(0) => Some(("testp".to_owned(), false)),
+                PipelineId(1) => Some(("testp".to_owned(), true)),
+                _ => None,
+            }
+        }
+    }
+
+    fn outbox() -> Outbox {
+        Outbox::new(
+            vec![
+                Url::parse("http://a.example.com/").unwrap(),
+                Url::parse("http://b.example.com/").unwrap(),
+            ],
+            Box::new(OneProject),
+        )
+    }
+
+    #[test]
+    fn test_hook_body() {
+        let ci_url = Url::parse("http://ci.example.com/1").unwrap();
+        let body = outbox().hook_body(
+            PipelineId(1),
+            &Pr::from("3".to_owned()),
+            &Status::StartingBuild(
+                Commit::from("a".to_owned()),
+                Commit::from("b".to_owned()),
+            ),
+            &Details {
+                queue_position: None,
+                ci_urls: vec![(CiId(0), ci_url)],
+            },
+            UNIX_EPOCH + Duration::from_secs(1000),
+        ).unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+        let expected: Value = serde_json::from_str(r#"{
+            "pipeline": "testp",
+            "try": true,
+            "pr": "3",
+            "state": "starting_build",
+            "pull_commit": "a",
+            "merge_commit": "b",
+            "ci_url": "http://ci.example.com/1",
+            "timestamp": 1000
+        }"#).unwrap();
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_hook_body_unknown_pipeline() {
+        assert!(outbox().hook_body(
+            PipelineId(5),
+            &Pr::from("3".to_owned()),
+            &Status::Invalidated,
+            &Details::default(),
+            UNIX_EPOCH,
+        ).is_err());
+    }
+
+    #[test]
+    fn test_add_to_every_url() {
+        let mut db = db::Builder::from_str(":memory:").unwrap().open().unwrap();
+        outbox().add(
+            &mut db,
+            PipelineId(0),
+            &Pr::from("3".to_owned()),
+            &Status::Approved(Commit::from("a".to_owned())),
+            &Details::default(),
+        ).unwrap();
+        // Statuses for unknown pipelines are dropped, not failed.
+        outbox().add(
+            &mut db,
+            PipelineId(5),
+            &Pr::from("3".to_owned()),
+            &Status::Invalidated,
+            &Details::default(),
+        ).unwrap();
+        let due = db.list_outbox(UNIX_EPOCH).unwrap();
+        let urls: Vec<&str> = due.iter().map(|x| x.1.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec!["http://a.example.com/", "http://b.example.com/"]
+        );
+        assert_eq!(due[0].1.body, due[1].1.body);
+        assert_eq!(due[0].1.attempts, 0);
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(4), Duration::from_secs(16));
+        assert_eq!(backoff(20), Duration::from_secs(MAX_BACKOFF_SEC));
+        assert_eq!(backoff(40), Duration::from_secs(MAX_BACKOFF_SEC));
+    }
+}
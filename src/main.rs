@@ -2,7 +2,6 @@
 
 //! An implementation of the Common Sense Rule of Software Engineering
 
-#![feature(mpsc_select)]
 #![feature(proc_macro)]
 #![recursion_limit = "5000"]
 
@@ -36,17 +35,22 @@ extern crate void;
 mod ci;
 mod config;
 mod db;
+mod hooks;
 mod pipeline;
+mod timer;
 mod ui;
 mod view;
 mod vcs;
 
-use config::WorkerBuilder;
-use db::Db;
-use pipeline::{Ci, Event, GetPipelineId, Pipeline, Ui, Vcs};
+use config::{PipelinesConfig, WorkerBuilder};
+use db::{Db, DbBox};
+use pipeline::{Ci, Event, GetPipelineId, Pipeline, PipelineId};
+use pipeline::{Timer, Ui, Vcs, WorkerSenders};
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::env::args;
 use std::error::Error;
+use std::sync::mpsc::{channel, Receiver, Sender};
 
 fn main() {
     env_logger::init().unwrap();
@@ -56,7 +60,9 @@ fn main() {
         .map(Cow::Owned)
         .unwrap_or(Cow::Borrowed("config.toml"));
     let config_path = &*config_path;
-    if config_path == "-12" {
+    if let Some(command) = args.next() {
+        admin(config_path, &command, args.collect());
+    } else if config_path == "-12" {
         let worker_builder =
             config::twelvef::GithubBuilder::build_from_os_env()
             .unwrap();
@@ -69,91 +75,175 @@ fn main() {
     }
 }
 
+/// Tell a running instance what to do:
+///
+///  * `replay DELIVERY` or `replay SINCE UNTIL` sends logged webhooks again,
+///    given either one delivery ID or a range of UNIX times.
+///  * `flush-permissions` forgets who can write to which repos.
+fn admin(config_path: &str, command: &str, args: Vec<String>) {
+    use ui::github::replay::Selection;
+    let admin = if config_path == "-12" {
+        config::twelvef::admin_from_os_env().unwrap()
+    } else {
+        config::toml::admin_from_file(config_path).unwrap()
+    };
+    match (command, args.len()) {
+        ("replay", 1) => {
+            let selection = Selection::Delivery(args[0].clone());
+            let count = admin.replay(&selection).unwrap();
+            info!("Replayed {} deliveries", count);
+        }
+        ("replay", 2) => {
+            let selection = Selection::Between(
+                args[0].parse().expect("the start to be a UNIX time"),
+                args[1].parse().expect("the end to be a UNIX time"),
+            );
+            let count = admin.replay(&selection).unwrap();
+            info!("Replayed {} deliveries", count);
+        }
+        ("flush-permissions", 0) => {
+            admin.flush_permissions().unwrap();
+            info!("Flushed permissions");
+        }
+        _ => panic!(
+            "Usage: aelita CONFIG (replay (DELIVERY | SINCE UNTIL) | \
+             flush-permissions)"
+        ),
+    }
+}
+
 fn run_workers<B: WorkerBuilder>(builder: B) -> ! {
-    use std::sync::mpsc::{Select, Handle};
-    let (workers, mut db) = builder.start();
+    let (workers, db_builder) = builder.start();
     debug!(
-        "Created {} pipelines, {} CIs, {} UIs, and {} VCSs (View: {})",
+        "Created {} pipelines, {} CIs, {} UIs, and {} VCSs \
+         (View: {}, Hooks: {})",
         workers.pipelines.len(),
         workers.cis.len(),
         workers.uis.len(),
         workers.vcss.len(),
         workers.view.is_some(),
+        workers.hooks.is_some(),
     );
-    unsafe {
-        let select = Select::new();
-        let mut ci_handles: Vec<Handle<ci::Event>> =
-            workers.cis.iter().map(|worker| {
-                select.handle(&worker.recv_event)
-            }).collect();
-        let mut ui_handles: Vec<Handle<ui::Event>> =
-            workers.uis.iter().map(|worker| {
-                select.handle(&worker.recv_event)
-            }).collect();
-        let mut vcs_handles: Vec<Handle<vcs::Event>> =
-            workers.vcss.iter().map(|worker| {
-                select.handle(&worker.recv_event)
-            }).collect();
-        let mut view_handle: Option<Handle<view::Event>> =
-            workers.view.as_ref().map(|worker| {
-                select.handle(&worker.recv_event)
+    let (recv_event, senders, pipelines) = workers.split();
+    let pipelines = &*pipelines;
+    let db_builder = &db_builder;
+    crossbeam::scope(|scope| {
+        let mut executors: HashMap<PipelineId, Sender<Event>> =
+            HashMap::new();
+        let start = |pipeline_id: PipelineId| {
+            let (send_event, recv_event) = channel();
+            let senders = senders.clone();
+            scope.spawn(move || {
+                run_pipeline(
+                    pipeline_id,
+                    &senders,
+                    pipelines,
+                    db_builder,
+                    recv_event,
+                );
             });
-        // We cannot call add while collecting because the handle is moved.
-        for h in &mut ci_handles { h.add(); }
-        for h in &mut ui_handles { h.add(); }
-        for h in &mut vcs_handles { h.add(); }
-        if let Some(ref mut h) = view_handle { h.add(); }
-        let mut pending: Option<Event> = None;
-        'outer: loop {
-            if let Some(event) = pending.take() {
-                let pipeline_id = event.pipeline_id(&*workers.pipelines);
-                let pipeline = workers.pipeline_by_id(pipeline_id);
-                if let Some(pipeline) = pipeline {
-                    let result = db.transaction(PipelineTransaction{
-                        pipeline: pipeline,
-                        event: event,
-                    });
-                    if let Err(e) = result {
-                        warn!("Event handling failed: {:?}", e);
-                    }
-                }
-            }
-            let id = select.wait();
-            for h in &mut ci_handles {
-                if h.id() == id {
-                    pending = h.recv().map(Event::CiEvent).ok();
-                    continue 'outer;
-                }
-            }
-            for h in &mut ui_handles { 
-                if h.id() == id {
-                    pending = h.recv().map(Event::UiEvent).ok();
-                    continue 'outer;
-                }
+            send_event
+        };
+        // Start them all now, so they can recover
+        // anything that was in flight when we last stopped.
+        for pipeline_id in pipelines.ids() {
+            executors.insert(pipeline_id, start(pipeline_id));
+        }
+        for event in recv_event.iter() {
+            dispatch(&mut executors, pipelines, event, &start);
+        }
+    });
+    panic!("Every worker went away");
+}
+
+/// Send an event to the thread of the pipeline it belongs to,
+/// starting that thread if this is the first event for the pipeline.
+/// Events for pipelines that are not configured are logged and dropped.
+fn dispatch<P, F>(
+    executors: &mut HashMap<PipelineId, Sender<Event>>,
+    pipelines: &P,
+    event: Event,
+    start: F,
+) where P: PipelinesConfig + ?Sized,
+        F: FnOnce(PipelineId) -> Sender<Event> {
+    let pipeline_id = event.pipeline_id(pipelines);
+    if !executors.contains_key(&pipeline_id) {
+        if pipelines.by_pipeline_id(pipeline_id).is_none() {
+            warn!("Got an event for unknown {:?}", pipeline_id);
+            return;
+        }
+        executors.insert(pipeline_id, start(pipeline_id));
+    }
+    if executors[&pipeline_id].send(event).is_err() {
+        warn!("The thread for {:?} went away", pipeline_id);
+    }
+}
+
+/// Handle the events for one pipeline, in order.
+///
+/// Every pipeline gets its own thread and database connection,
+/// so one that is stuck retrying does not hold up the others.
+fn run_pipeline(
+    pipeline_id: PipelineId,
+    senders: &WorkerSenders,
+    pipelines: &PipelinesConfig,
+    db_builder: &db::Builder,
+    recv_event: Receiver<Event>,
+) {
+    let mut db = open_db(db_builder);
+    let pipeline = senders.pipeline_by_id(pipelines, pipeline_id);
+    if let Some(pipeline) = pipeline {
+        let result = db.transaction(RecoverTransaction{
+            pipeline: pipeline,
+        });
+        if let Err(e) = result {
+            warn!("Recovering {:?} failed: {:?}", pipeline_id, e);
+        }
+    }
+    for event in recv_event.iter() {
+        let pipeline = senders.pipeline_by_id(pipelines, pipeline_id);
+        if let Some(pipeline) = pipeline {
+            let result = db.transaction(PipelineTransaction{
+                pipeline: pipeline,
+                event: event,
+            });
+            if let Err(e) = result {
+                warn!("Event handling failed: {:?}", e);
             }
-            for h in &mut vcs_handles { 
-                if h.id() == id {
-                    pending = h.recv().map(Event::VcsEvent).ok();
-                    continue 'outer;
-                }
+        }
+    }
+}
+
+fn open_db(db_builder: &db::Builder) -> DbBox {
+    use std::thread;
+    use std::time::Duration;
+    use util::MAX_DELAY_SEC;
+    loop {
+        match db_builder.open() {
+            Ok(db) => return db,
+            Err(e) => {
+                warn!("Failed to open the DB: {:?}", e);
+                thread::sleep(Duration::new(MAX_DELAY_SEC, 0));
             }
         }
     }
 }
 
-struct PipelineTransaction<'cntx, C, U, V>
+struct PipelineTransaction<'cntx, C, U, V, T>
     where C: Ci + 'cntx,
           U: Ui + 'cntx,
-          V: Vcs + 'cntx {
-    pipeline: Pipeline<'cntx, C, U, V>,
+          V: Vcs + 'cntx,
+          T: Timer + 'cntx {
+    pipeline: Pipeline<'cntx, C, U, V, T>,
     event: Event,
 }
 
-impl<'cntx, C, U, V> db::Transaction
-        for PipelineTransaction<'cntx, C, U, V>
+impl<'cntx, C, U, V, T> db::Transaction
+        for PipelineTransaction<'cntx, C, U, V, T>
     where C: Ci + 'cntx,
           U: Ui + 'cntx,
-          V: Vcs + 'cntx {
+          V: Vcs + 'cntx,
+          T: Timer + 'cntx {
     type Return = ();
     fn run<D: Db>(
         mut self,
@@ -179,4 +269,130 @@ impl<'cntx, C, U, V> db::Transaction
         }
         result
     }
-}
\ No newline at end of file
+}
+
+struct RecoverTransaction<'cntx, C, U, V, T>
+    where C: Ci + 'cntx,
+          U: Ui + 'cntx,
+          V: Vcs + 'cntx,
+          T: Timer + 'cntx {
+    pipeline: Pipeline<'cntx, C, U, V, T>,
+}
+
+impl<'cntx, C, U, V, T> db::Transaction
+        for RecoverTransaction<'cntx, C, U, V, T>
+    where C: Ci + 'cntx,
+          U: Ui + 'cntx,
+          V: Vcs + 'cntx,
+          T: Timer + 'cntx {
+    type Return = ();
+    fn run<D: Db>(
+        mut self,
+        db: &mut D
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.pipeline.recover(db)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ci::CiId;
+    use config::{PipelineConfig, PipelinesConfig};
+    use pipeline::{Event, PipelineId};
+    use std::collections::HashMap;
+    use std::sync::mpsc::{Receiver, Sender, channel};
+    use std::thread;
+    use super::dispatch;
+    use ui::{self, Pr};
+
+    struct MemoryPipelines(Vec<PipelineId>);
+    impl PipelinesConfig for MemoryPipelines {
+        fn by_pipeline_id(&self, id: PipelineId) -> Option<PipelineConfig> {
+            if !self.0.contains(&id) {
+                return None;
+            }
+            Some(PipelineConfig{
+                pipeline_id: id,
+                ci: vec![],
+                ui: 0,
+                vcs: 0,
+                batch_size: 1,
+                depth: 1,
+                ci_timeout: None,
+                auto_retry: 0,
+                remote_format: ui::github::REMOTE_FORMAT,
+            })
+        }
+        fn by_ci_id(&self, _: CiId) -> PipelineConfig {
+            unimplemented!()
+        }
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+        fn ids(&self) -> Vec<PipelineId> {
+            self.0.clone()
+        }
+    }
+
+    fn canceled(pipeline_id: PipelineId, pr: &str) -> Event {
+        let pr = Pr::from(pr.to_owned());
+        Event::UiEvent(ui::Event::Canceled(pipeline_id, pr))
+    }
+
+    /// Start a thread that reports back the PRs of the events it handles,
+    /// and which pipeline it was started for.
+    fn start(
+        report: &Sender<(PipelineId, PipelineId, Pr)>,
+        pipeline_id: PipelineId,
+    ) -> Sender<Event> {
+        let (send_event, recv_event): (_, Receiver<Event>) = channel();
+        let report = report.clone();
+        thread::spawn(move || {
+            for event in recv_event.iter() {
+                if let Event::UiEvent(ui::Event::Canceled(id, pr)) = event {
+                    report.send((pipeline_id, id, pr)).unwrap();
+                }
+            }
+        });
+        send_event
+    }
+
+    #[test]
+    fn test_dispatch_to_own_thread() {
+        let pipelines = MemoryPipelines(vec![PipelineId(0), PipelineId(1)]);
+        let (report, recv_report) = channel();
+        let mut executors = HashMap::new();
+        for &(id, pr) in &[
+            (PipelineId(0), "a"),
+            (PipelineId(1), "b"),
+            (PipelineId(0), "c"),
+        ] {
+            dispatch(&mut executors, &pipelines, canceled(id, pr), |id| {
+                start(&report, id)
+            });
+        }
+        assert_eq!(executors.len(), 2);
+        let mut reports: Vec<_> = (0..3)
+            .map(|_| recv_report.recv().unwrap())
+            .collect();
+        reports.sort_by_key(|r| ((r.0).0, r.2.to_string()));
+        assert_eq!(reports, vec![
+            (PipelineId(0), PipelineId(0), Pr::from("a".to_owned())),
+            (PipelineId(0), PipelineId(0), Pr::from("c".to_owned())),
+            (PipelineId(1), PipelineId(1), Pr::from("b".to_owned())),
+        ]);
+    }
+
+    #[test]
+    fn test_dispatch_unknown_pipeline() {
+        let pipelines = MemoryPipelines(vec![PipelineId(0)]);
+        let mut executors = HashMap::new();
+        dispatch(
+            &mut executors,
+            &pipelines,
+            canceled(PipelineId(7), "a"),
+            |_| panic!("No thread should be started for an unknown pipeline"),
+        );
+        assert!(executors.is_empty());
+    }
+}
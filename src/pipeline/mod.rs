@@ -2,10 +2,17 @@
 
 use ci::{self, CiId};
 use config::{PipelineConfig, PipelinesConfig};
-use db::{CiState, Db, PendingEntry, QueueEntry, RunningEntry};
+use db::{CiState, Db, HistoryEntry, HistoryStatus, PendingEntry};
+use db::{QueueEntry, RunningEntry};
+use hooks;
+use hyper::Url;
+use std::cmp;
 use std::error::Error;
+use std::sync::Arc;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
+use std::time::{Duration, SystemTime};
+use timer;
 use ui::{self, Pr};
 use vcs::{self, Commit, Remote};
 use view;
@@ -27,28 +34,118 @@ pub struct WorkerManager {
         view::Event,
         view::Message,
     >>,
+    /// Sends the statuses that the pipelines write to the outbox.
+    pub hooks: Option<WorkerThread<
+        hooks::Event,
+        hooks::Message,
+    >>,
+    pub outbox: Option<hooks::Outbox>,
+    pub timer: WorkerThread<
+        timer::Event,
+        timer::Message,
+    >,
     pub pipelines: Box<PipelinesConfig>,
 }
 
 impl WorkerManager {
-    pub fn pipeline_by_id<'a>(
+    /// Merge the events from every worker into one channel,
+    /// and keep only the sending side of their message channels,
+    /// which every pipeline thread can have its own copy of.
+    pub fn split(self) -> (
+        Receiver<Event>,
+        WorkerSenders,
+        Box<PipelinesConfig>,
+    ) {
+        let (send_event, recv_event) = channel();
+        let senders = WorkerSenders{
+            cis: self.cis.iter().map(|w| w.send_msg.clone()).collect(),
+            uis: self.uis.iter().map(|w| w.send_msg.clone()).collect(),
+            vcss: self.vcss.iter().map(|w| w.send_msg.clone()).collect(),
+            timer: self.timer.send_msg.clone(),
+            outbox: self.outbox.map(Arc::new),
+        };
+        for worker in self.cis {
+            forward(worker.recv_event, send_event.clone(), Event::CiEvent);
+        }
+        for worker in self.uis {
+            forward(worker.recv_event, send_event.clone(), Event::UiEvent);
+        }
+        for worker in self.vcss {
+            forward(worker.recv_event, send_event.clone(), Event::VcsEvent);
+        }
+        forward(self.timer.recv_event, send_event, Event::TimerEvent);
+        (recv_event, senders, self.pipelines)
+    }
+}
+
+fn forward<E, F>(recv_event: Receiver<E>, send_event: Sender<Event>, f: F)
+    where E: Send + 'static,
+          F: Fn(E) -> Event + Send + 'static
+{
+    thread::spawn(move || {
+        for event in recv_event.iter() {
+            if send_event.send(f(event)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+#[derive(Clone)]
+pub struct WorkerSenders {
+    pub cis: Vec<Sender<ci::Message>>,
+    pub uis: Vec<Sender<ui::Message>>,
+    pub vcss: Vec<Sender<vcs::Message>>,
+    pub timer: Sender<timer::Message>,
+    pub outbox: Option<Arc<hooks::Outbox>>,
+}
+
+impl WorkerSenders {
+    pub fn pipeline_by_id<'a, P: PipelinesConfig + ?Sized>(
         &'a self,
+        pipelines: &P,
         pipeline_id: PipelineId,
     ) -> Option<
         Pipeline<
             'a,
-            WorkerThread<ci::Event, ci::Message>,
-            WorkerThread<ui::Event, ui::Message>,
-            WorkerThread<vcs::Event, vcs::Message>,
+            Sender<ci::Message>,
+            Sender<ui::Message>,
+            Sender<vcs::Message>,
+            Sender<timer::Message>,
         >
     > {
-        let PipelineConfig{ci, ui, vcs, pipeline_id: _} = self.pipelines.by_pipeline_id(pipeline_id);
+        let PipelineConfig{
+            ci,
+            ui,
+            vcs,
+            batch_size,
+            depth,
+            ci_timeout,
+            auto_retry,
+            remote_format,
+            pipeline_id: _
+        } = match pipelines.by_pipeline_id(pipeline_id) {
+            Some(config) => config,
+            None => return None,
+        };
         if let (Some(ui), Some(vcs)) = (
             self.uis.get(ui),
             self.vcss.get(vcs)
         ) {
             let ci = ci.iter().flat_map(|&(id, idx)| self.cis.get(idx).map(|ci| (id, ci))).collect();
-            Some(Pipeline::new(pipeline_id, ci, ui, vcs))
+            Some(Pipeline::new(
+                pipeline_id,
+                ci,
+                ui,
+                vcs,
+                &self.timer,
+                self.outbox.as_ref().map(|outbox| &**outbox),
+                batch_size,
+                depth,
+                ci_timeout,
+                auto_retry,
+                remote_format,
+            ))
         } else {
             None
         }
@@ -85,44 +182,77 @@ pub trait Ci {
     fn start_build(&self, ci_id: CiId, commit: Commit);
 }
 
-impl Ci for WorkerThread<ci::Event, ci::Message> {
+impl Ci for Sender<ci::Message> {
     fn start_build(&self, ci_id: CiId, commit: Commit) {
-        self.send_msg.send(ci::Message::StartBuild(ci_id, commit))
+        self.send(ci::Message::StartBuild(ci_id, commit))
             .unwrap();
     }
 }
 
 pub trait Ui {
     fn send_result(&self, PipelineId, Pr, ui::Status);
+    fn send_result_details(
+        &self,
+        pipeline_id: PipelineId,
+        pr: Pr,
+        status: ui::Status,
+        _details: ui::Details,
+    ) {
+        self.send_result(pipeline_id, pr, status);
+    }
 }
 
-impl Ui for WorkerThread<ui::Event, ui::Message> {
+impl Ui for Sender<ui::Message> {
     fn send_result(
         &self,
         pipeline_id: PipelineId,
         pr: Pr,
         status: ui::Status,
     ) {
-        self.send_msg.send(ui::Message::SendResult(pipeline_id, pr, status))
+        self.send_result_details(
+            pipeline_id,
+            pr,
+            status,
+            ui::Details::default(),
+        );
+    }
+    fn send_result_details(
+        &self,
+        pipeline_id: PipelineId,
+        pr: Pr,
+        status: ui::Status,
+        details: ui::Details,
+    ) {
+        self.send(ui::Message::SendResult(pipeline_id, pr, status, details))
             .unwrap();
     }
 }
 
 pub trait Vcs {
-    fn merge_to_staging(&self, PipelineId, Commit, String, Remote);
+    fn merge_to_staging(
+        &self,
+        PipelineId,
+        usize,
+        Option<Commit>,
+        Commit,
+        String,
+        Remote,
+    );
     fn move_staging_to_master(&self, PipelineId, Commit);
 }
 
-impl Vcs for WorkerThread<vcs::Event, vcs::Message> {
+impl Vcs for Sender<vcs::Message> {
     fn merge_to_staging(
         &self,
         pipeline_id: PipelineId,
+        staging: usize,
+        base_commit: Option<Commit>,
         pull_commit: Commit,
         message: String,
         remote: Remote,
     ) {
-        self.send_msg.send(vcs::Message::MergeToStaging(
-            pipeline_id, pull_commit, message, remote
+        self.send(vcs::Message::MergeToStaging(
+            pipeline_id, staging, base_commit, pull_commit, message, remote
         )).unwrap();
     }
     fn move_staging_to_master(
@@ -130,33 +260,61 @@ impl Vcs for WorkerThread<vcs::Event, vcs::Message> {
         pipeline_id: PipelineId,
         merge_commit: Commit,
     ) {
-        self.send_msg.send(vcs::Message::MoveStagingToMaster(
+        self.send(vcs::Message::MoveStagingToMaster(
             pipeline_id, merge_commit
         )).unwrap();
     }
 }
 
+pub trait Timer {
+    fn start_timer(&self, PipelineId, Commit, Duration);
+}
+
+impl Timer for Sender<timer::Message> {
+    fn start_timer(
+        &self,
+        pipeline_id: PipelineId,
+        merge_commit: Commit,
+        timeout: Duration,
+    ) {
+        self.send(timer::Message::StartTimer(
+            pipeline_id, merge_commit, timeout
+        )).unwrap();
+    }
+}
+
 // TODO: When Rust starts enforcing lifetimes on type aliases,
 // use a type alias with something like:
 //
 //     pub type WorkerPipeline<'cntx> =
 //         Pipeline<
 //             'cntx,
-//             WorkerThread<ci::Event, ci::Message>,
-//             WorkerThread<ui::Event, ui::Message>,
-//             WorkerThread<vcs::Event, vcs::Message>,
+//             Sender<ci::Message>,
+//             Sender<ui::Message>,
+//             Sender<vcs::Message>,
+//             Sender<timer::Message>,
 //         >;
 //
 // That way, we can avoid all these ackward generics in main.
-pub struct Pipeline<'cntx, C, U, V>
+pub struct Pipeline<'cntx, C, U, V, T>
 where C: Ci + 'cntx,
       U: Ui + 'cntx,
-      V: Vcs + 'cntx
+      V: Vcs + 'cntx,
+      T: Timer + 'cntx
 {
     pub id: PipelineId,
     pub ci: Vec<(CiId, &'cntx C)>,
     pub ui: &'cntx U,
     pub vcs: &'cntx V,
+    pub timer: &'cntx T,
+    /// Every status sent to the UI is also queued here,
+    /// if there are outbound webhooks.
+    pub outbox: Option<&'cntx hooks::Outbox>,
+    pub batch_size: usize,
+    pub depth: usize,
+    pub ci_timeout: Option<Duration>,
+    pub auto_retry: u32,
+    pub remote_format: ui::RemoteFormat,
 }
 
 #[derive(Clone)]
@@ -164,6 +322,7 @@ pub enum Event {
     UiEvent(ui::Event),
     VcsEvent(vcs::Event),
     CiEvent(ci::Event),
+    TimerEvent(timer::Event),
 }
 
 pub trait GetPipelineId {
@@ -176,40 +335,163 @@ impl GetPipelineId for Event {
             Event::UiEvent(ref e) => e.pipeline_id(config),
             Event::CiEvent(ref e) => e.pipeline_id(config),
             Event::VcsEvent(ref e) => e.pipeline_id(config),
+            Event::TimerEvent(ref e) => e.pipeline_id(config),
         }
     }
 }
 
-impl<'cntx, C, U, V> Pipeline<'cntx, C, U, V>
+impl<'cntx, C, U, V, T> Pipeline<'cntx, C, U, V, T>
 where C: Ci + 'cntx,
       U: Ui + 'cntx,
-      V: Vcs + 'cntx
+      V: Vcs + 'cntx,
+      T: Timer + 'cntx
 {
     pub fn new(
         id: PipelineId,
         ci: Vec<(CiId, &'cntx C)>,
         ui: &'cntx U,
         vcs: &'cntx V,
+        timer: &'cntx T,
+        outbox: Option<&'cntx hooks::Outbox>,
+        batch_size: usize,
+        depth: usize,
+        ci_timeout: Option<Duration>,
+        auto_retry: u32,
+        remote_format: ui::RemoteFormat,
     ) -> Self {
         Pipeline {
             id: id,
             ci: ci,
             ui: ui,
             vcs: vcs,
+            timer: timer,
+            outbox: outbox,
+            batch_size: batch_size,
+            depth: depth,
+            ci_timeout: ci_timeout,
+            auto_retry: auto_retry,
+            remote_format: remote_format,
         }
     }
     pub fn handle_event<D: Db>(
         &mut self,
         db: &mut D,
         event: Event,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let mut running = match event {
+            Event::UiEvent(event) => {
+                try!(self.handle_ui_event(db, event));
+                try!(db.take_running(self.id))
+            }
+            Event::VcsEvent(event) => {
+                let mut running = try!(db.take_running(self.id));
+                try!(self.handle_vcs_event(db, &mut running, event));
+                running
+            }
+            Event::CiEvent(event) => {
+                let mut running = try!(db.take_running(self.id));
+                try!(self.handle_ci_event(db, &mut running, event));
+                running
+            }
+            Event::TimerEvent(event) => {
+                let mut running = try!(db.take_running(self.id));
+                try!(self.handle_timer_event(db, &mut running, event));
+                running
+            }
+        };
+        self.put_running(db, running)
+    }
+    /// Pick up where the pipeline left off before a restart,
+    /// since any messages to and from the workers were lost with it.
+    pub fn recover<D: Db>(
+        &mut self,
+        db: &mut D,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let mut running = try!(db.take_running(self.id));
+        for i in 0..cmp::min(running.len(), self.depth) {
+            match (running[i].built, running[i].merge_commit.clone()) {
+                (true, Some(merge_commit)) => {
+                    self.vcs.move_staging_to_master(self.id, merge_commit);
+                }
+                (false, Some(merge_commit)) => {
+                    try!(self.restart_builds(
+                        db,
+                        &mut running[i],
+                        &merge_commit,
+                    ));
+                }
+                (built, None) => {
+                    if built {
+                        warn!("Built candidate without a merge commit");
+                    }
+                    // Whatever is left of it, and everything behind it,
+                    // still has to be merged.
+                    try!(self.restack(db, &mut running, i));
+                    break;
+                }
+            }
+        }
+        self.put_running(db, running)
+    }
+    /// Start as many queued entries as there is room for,
+    /// and store the running candidates.
+    fn put_running<D: Db>(
+        &mut self,
+        db: &mut D,
+        mut running: Vec<RunningEntry>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        try!(self.restack_canceled(db, &mut running));
+        let tree_closed = try!(db.get_tree_closed(self.id));
+        while running.len() < self.depth {
+            let mut entries = Vec::new();
+            while entries.len() < self.batch_size {
+                if let Some(priority) = tree_closed {
+                    // The queue is sorted by priority,
+                    // so nothing after the next entry can go in either.
+                    let next = try!(db.peek_queue(self.id));
+                    if next.map(|n| n.priority < priority).unwrap_or(true) {
+                        break;
+                    }
+                }
+                if let Some(next) = try!(db.pop_queue(self.id)) {
+                    entries.push(next);
+                } else {
+                    break;
+                }
+            }
+            if entries.is_empty() {
+                break;
+            }
+            let staging = free_staging(&running);
+            running.push(RunningEntry{
+                entries: entries,
+                staging: staging,
+                base_commit: None,
+                merge_commit: None,
+                started_at: None,
+                retries: 0,
+                canceled: false,
+                built: false,
+            });
+            let last = running.len() - 1;
+            self.start_candidate(&mut running, last);
+        }
+        db.put_running(self.id, running)
+    }
+    fn handle_ui_event<D: Db>(
+        &mut self,
+        db: &mut D,
+        event: ui::Event,
     ) -> Result<(), Box<Error + Send + Sync>> {
         match event {
-            Event::UiEvent(ui::Event::Approved(
+            ui::Event::Approved(
                 pipeline_id,
                 pr,
                 commit,
                 message,
-            )) => {
+                priority,
+                approver,
+            ) => {
                 assert_eq!(&pipeline_id, &self.id);
                 let commit = match (
                     commit,
@@ -218,11 +500,11 @@ where C: Ci + 'cntx,
                 ) {
                     (Some(reviewed_pr), Some(current_pr)) => {
                         if reviewed_pr != current_pr {
-                            self.ui.send_result(
-                                self.id,
+                            try!(self.send_result(
+                                db,
                                 pr.clone(),
                                 ui::Status::Invalidated,
-                            );
+                            ));
                             None
                         } else {
                             Some(reviewed_pr)
@@ -235,31 +517,34 @@ where C: Ci + 'cntx,
                         Some(current_pr)
                     }
                     (None, None) => {
-                        self.ui.send_result(
-                            self.id,
+                        try!(self.send_result(
+                            db,
                             pr.clone(),
                             ui::Status::NoCommit,
-                        );
+                        ));
                         None
                     }
                 };
                 if let Some(commit) = commit {
-                    self.ui.send_result(
-                        self.id,
-                        pr.clone(),
-                        ui::Status::Approved(commit.clone()),
-                    );
                     try!(db.cancel_by_pr(self.id, &pr));
+                    try!(db.take_failed_by_pr(self.id, &pr));
                     try!(db.push_queue(self.id, QueueEntry{
-                        commit: commit,
-                        pr: pr,
+                        commit: commit.clone(),
+                        pr: pr.clone(),
                         message: message,
+                        priority: priority,
+                        approver: approver,
                     }));
+                    let details = try!(self.queued_details(db, &pr));
+                    try!(self.send_result_details(
+                        db,
+                        pr,
+                        ui::Status::Approved(commit),
+                        details,
+                    ));
                 }
             },
-            Event::UiEvent(ui::Event::Opened(
-                pipeline_id, pr, commit, title, url
-            )) => {
+            ui::Event::Opened(pipeline_id, pr, commit, title, url) => {
                 assert_eq!(&pipeline_id, &self.id);
                 try!(db.add_pending(self.id, PendingEntry{
                     commit: commit,
@@ -268,20 +553,21 @@ where C: Ci + 'cntx,
                     url: url,
                 }));
             },
-            Event::UiEvent(ui::Event::Changed(
-                pipeline_id, pr, commit, title, url
-            )) => {
+            ui::Event::Changed(pipeline_id, pr, commit, title, url) => {
                 assert_eq!(&pipeline_id, &self.id);
+                try!(self.add_canceled_history(db, &pr, Some(&commit)));
+                // Delegations only cover the commit they were granted on.
+                try!(db.remove_delegations(self.id, &pr, Some(&commit)));
                 if try!(db.cancel_by_pr_different_commit(
                     self.id,
                     &pr,
                     &commit,
                 )) {
-                    self.ui.send_result(
-                        self.id,
+                    try!(self.send_result(
+                        db,
                         pr.clone(),
                         ui::Status::Invalidated,
-                    );
+                    ));
                 }
                 try!(db.add_pending(self.id, PendingEntry{
                     commit: commit,
@@ -290,287 +576,794 @@ where C: Ci + 'cntx,
                     url: url,
                 }));
             },
-            Event::UiEvent(ui::Event::Closed(pipeline_id, pr)) => {
+            ui::Event::Closed(pipeline_id, pr) => {
                 assert_eq!(&pipeline_id, &self.id);
                 try!(db.take_pending_by_pr(self.id, &pr));
+                try!(db.take_failed_by_pr(self.id, &pr));
+                try!(self.add_canceled_history(db, &pr, None));
                 try!(db.cancel_by_pr(self.id, &pr));
+                try!(db.remove_delegations(self.id, &pr, None));
             },
-            Event::UiEvent(ui::Event::Canceled(pipeline_id, pr)) => {
+            ui::Event::Canceled(pipeline_id, pr) => {
                 assert_eq!(&pipeline_id, &self.id);
+                try!(db.take_failed_by_pr(self.id, &pr));
+                try!(self.add_canceled_history(db, &pr, None));
                 try!(db.cancel_by_pr(self.id, &pr));
             },
-            Event::VcsEvent(vcs::Event::MergedToStaging(
+            ui::Event::TreeClosed(pipeline_id, priority) => {
+                assert_eq!(&pipeline_id, &self.id);
+                try!(db.set_tree_closed(self.id, Some(priority)));
+            },
+            ui::Event::TreeOpened(pipeline_id) => {
+                assert_eq!(&pipeline_id, &self.id);
+                try!(db.set_tree_closed(self.id, None));
+            },
+            ui::Event::Retry(pipeline_id, pr) => {
+                assert_eq!(&pipeline_id, &self.id);
+                let current = try!(db.peek_pending_by_pr(self.id, &pr))
+                    .map(|p| p.commit);
+                match try!(db.take_failed_by_pr(self.id, &pr)) {
+                    Some(ref entry) if current.as_ref()
+                            .map(|c| c != &entry.commit)
+                            .unwrap_or(false) => {
+                        // New commits were pushed since it failed.
+                        try!(self.send_result(
+                            db,
+                            pr,
+                            ui::Status::Invalidated,
+                        ));
+                    }
+                    Some(entry) => {
+                        let commit = entry.commit.clone();
+                        try!(db.push_queue(self.id, entry));
+                        let details = try!(self.queued_details(db, &pr));
+                        try!(self.send_result_details(
+                            db,
+                            pr,
+                            ui::Status::Approved(commit),
+                            details,
+                        ));
+                    }
+                    None => {
+                        try!(self.send_result(
+                            db,
+                            pr,
+                            ui::Status::NothingToRetry,
+                        ));
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+    fn handle_vcs_event<D: Db>(
+        &mut self,
+        db: &mut D,
+        running: &mut Vec<RunningEntry>,
+        event: vcs::Event,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match event {
+            vcs::Event::MergedToStaging(
                 pipeline_id,
+                base_commit,
                 pull_commit,
-                merge_commit
-            )) => {
+                merge_commit,
+            ) => {
                 assert_eq!(&pipeline_id, &self.id);
-                if let Some(mut running) = try!(db.take_running(self.id)) {
-                    if running.pull_commit != pull_commit {
+                let (i, position) = match self.find_merging(
+                    running,
+                    &base_commit,
+                    &pull_commit,
+                ) {
+                    Some(found) => found,
+                    None => {
                         warn!("VCS merged event with wrong commit");
-                    } else if running.merge_commit.is_some() {
-                        warn!("VCS merged event with running commit");
-                    } else if running.canceled {
-                        // Drop it on the floor. It's canceled.
-                    } else if running.built {
-                        warn!("Got merge finished after finished building!");
-                    } else {
-                        running.merge_commit = Some(merge_commit.clone());
-                        for &(ci_id, ci) in &self.ci {
-                            try!(db.clear_ci_state(ci_id));
-                            ci.start_build(
-                                ci_id,
-                                merge_commit.clone(),
-                            );
-                        }
-                        self.ui.send_result(
-                            self.id,
-                            running.pr.clone(),
+                        return Ok(());
+                    }
+                };
+                if position + 1 < running[i].entries.len() {
+                    // Merge the next pull request on top of this one.
+                    running[i].base_commit = Some(merge_commit.clone());
+                    let next = &running[i].entries[position + 1];
+                    self.vcs.merge_to_staging(
+                        self.id,
+                        running[i].staging,
+                        Some(merge_commit),
+                        next.commit.clone(),
+                        next.message.clone(),
+                        next.pr.remote(self.remote_format),
+                    );
+                } else {
+                    running[i].merge_commit = Some(merge_commit.clone());
+                    try!(self.start_builds(db, &mut running[i], &merge_commit));
+                    for entry in &running[i].entries {
+                        try!(self.send_result(
+                            db,
+                            entry.pr.clone(),
                             ui::Status::StartingBuild(
-                                pull_commit,
-                                merge_commit,
+                                entry.commit.clone(),
+                                merge_commit.clone(),
                             ),
-                        );
-                        try!(db.put_running(self.id, running));
+                        ));
                     }
-                } else {
-                    warn!("VCS merged event with no queued PR");
+                    // The next candidate is stacked on top of this one.
+                    self.start_candidate(running, i + 1);
                 }
             },
-            Event::VcsEvent(vcs::Event::FailedMergeToStaging(
+            vcs::Event::FailedMergeToStaging(
                 pipeline_id,
+                base_commit,
                 pull_commit,
-            )) => {
+            ) => {
                 assert_eq!(&pipeline_id, &self.id);
-                if let Some(running) = try!(db.take_running(self.id)) {
-                    if running.pull_commit != pull_commit {
-                        warn!("VCS merged event with wrong commit");
-                    } else if running.merge_commit.is_some() {
-                        warn!("VCS merged event with running commit");
-                    } else if running.built {
-                        warn!("Got merge failed after finished building!");
-                    } else if running.canceled {
-                        // Drop it on the floor. It's canceled.
-                    } else {
-                        self.ui.send_result(
-                            self.id,
-                            running.pr.clone(),
-                            ui::Status::Unmergeable(pull_commit),
-                        );
+                let (i, position) = match self.find_merging(
+                    running,
+                    &base_commit,
+                    &pull_commit,
+                ) {
+                    Some(found) => found,
+                    None => {
+                        warn!("VCS merge failed event with wrong commit");
+                        return Ok(());
                     }
-                } else {
-                    warn!("VCS merged event with no queued PR");
+                };
+                let entry = running[i].entries.remove(position);
+                try!(self.add_history(
+                    db,
+                    &[entry.clone()],
+                    None,
+                    None,
+                    HistoryStatus::Unmergeable,
+                ));
+                try!(self.send_result(
+                    db,
+                    entry.pr,
+                    ui::Status::Unmergeable(pull_commit),
+                ));
+                if running[i].entries.is_empty() {
+                    running.remove(i);
                 }
+                // Merge the rest of the batch again without it.
+                try!(self.restack(db, running, i));
             },
-            Event::CiEvent(ci::Event::BuildStarted(
-                _ci_id,
-                building_commit,
-                url,
-            )) => {
-                if let Some(running) = try!(db.peek_running(self.id)) {
-                    if let Some(merged_commit) = running.merge_commit {
-                        if merged_commit != building_commit {
-                            warn!("Building a different commit");
-                        } else if running.canceled {
-                            // Drop it on the floor. It's canceled.
-                        } else if running.built {
-                            warn!("Got CI build started after done building!");
-                        } else {
-                            self.ui.send_result(
-                                self.id,
-                                running.pr.clone(),
-                                ui::Status::Testing(
-                                    running.pull_commit.clone(),
-                                    building_commit,
-                                    url,
-                                ),
-                            );
-                        }
-                    } else {
-                        warn!("Building a commit that never merged");
-                    }
-                } else {
-                    warn!("CI build started event with no queued PR");
+            vcs::Event::FailedMoveToMaster(pipeline_id, merge_commit) => {
+                assert_eq!(&pipeline_id, &self.id);
+                if !self.is_built_head(running, &merge_commit) {
+                    warn!("VCS move event with wrong commit");
+                    return Ok(());
+                }
+                let head = running.remove(0);
+                try!(self.add_history(
+                    db,
+                    &head.entries,
+                    Some(&merge_commit),
+                    head.started_at,
+                    HistoryStatus::Unmoveable,
+                ));
+                try!(self.clear_ci_states(db, &merge_commit));
+                for entry in head.entries {
+                    try!(self.send_result(
+                        db,
+                        entry.pr,
+                        ui::Status::Unmoveable(
+                            entry.commit,
+                            merge_commit.clone(),
+                        ),
+                    ));
                 }
+                // Everything behind it was stacked on a commit that never
+                // landed, so it has to be merged again.
+                try!(self.restack(db, running, 0));
             },
-            Event::CiEvent(ci::Event::BuildFailed(
-                _ci_id,
-                built_commit,
-                url,
-            )) => {
-                if let Some(running) = try!(db.take_running(self.id)) {
-                    if let Some(ref merged_commit) = running.merge_commit {
-                        if merged_commit != &built_commit {
-                            warn!("Finished building a different commit");
-                            try!(db.put_running(self.id, running.clone()));
-                        } else if running.canceled {
-                            // Drop it on the floor. It's canceled.
-                        } else if running.built {
-                            warn!("Got duplicate BuildFailed event");
-                            // Put it back
-                            try!(db.put_running(self.id, running.clone()));
-                        } else {
-                            for &(ci_id, _) in &self.ci {
-                                if let Some((_state, commit)) = try!(db.get_ci_state(ci_id)) {
-                                    assert_eq!(built_commit, commit);
-                                    try!(db.clear_ci_state(ci_id));
-                                }
-                            }
-                            self.ui.send_result(
-                                self.id,
-                                running.pr.clone(),
-                                ui::Status::Failure(
-                                    running.pull_commit.clone(),
-                                    merged_commit.clone(),
-                                    url,
-                                ),
-                            );
-                        }
-                    } else {
-                        warn!("Finished building a commit that never merged");
-                    }
-                } else {
-                    warn!("CI build failed event with no queued PR");
+            vcs::Event::MovedToMaster(pipeline_id, merge_commit) => {
+                assert_eq!(&pipeline_id, &self.id);
+                if !self.is_built_head(running, &merge_commit) {
+                    warn!("VCS move event with wrong commit");
+                    return Ok(());
+                }
+                // Canceled entries are already gone from the batch,
+                // so everything left over has landed.
+                let head = running.remove(0);
+                try!(self.add_history(
+                    db,
+                    &head.entries,
+                    Some(&merge_commit),
+                    head.started_at,
+                    HistoryStatus::Completed,
+                ));
+                try!(self.clear_ci_states(db, &merge_commit));
+                for entry in head.entries {
+                    try!(self.send_result(
+                        db,
+                        entry.pr,
+                        ui::Status::Completed(
+                            entry.commit,
+                            merge_commit.clone(),
+                        ),
+                    ));
                 }
+                // The candidates behind it were already stacked on top of
+                // it. One more of them is now close enough to the head
+                // to be started, and the new head may be done building.
+                if running.len() >= self.depth {
+                    let last = self.depth - 1;
+                    self.start_candidate(running, last);
+                }
+                try!(self.check_head(db, running));
             },
-            Event::CiEvent(ci::Event::BuildSucceeded(
-                ci_id,
-                built_commit,
-                url,
-            )) => {
-                if let Some(mut running) = try!(db.take_running(self.id)) {
-                    if let Some(ref merged_commit) = running.merge_commit {
-                        if merged_commit != &built_commit {
-                            warn!("Finished building a different commit");
-                            try!(db.put_running(self.id, running.clone()));
-                        } else if running.canceled {
-                            // Canceled; drop on the floor.
-                        } else if running.built {
-                            warn!("Got duplicate BuildSucceeded event");
-                            // Put it back.
-                            try!(db.put_running(self.id, running.clone()));
-                        } else {
-                            try!(db.set_ci_state(
-                                ci_id,
-                                CiState::Succeeded,
-                                &built_commit,
-                            ));
-                            let not_succeeded_count = self.ci.iter()
-                                .filter(|&&(ci_id, _)| retry!{{
-                                    if let Some((state, commit)) = retry_unwrap!(db.get_ci_state(ci_id)) {
-                                        assert_eq!(built_commit, commit);
-                                        state != CiState::Succeeded
-                                    } else {
-                                        true
-                                    }
-                                }}).count();
-                            if not_succeeded_count == 0 {
-                                self.vcs.move_staging_to_master(
-                                    self.id,
-                                    merged_commit.clone(),
-                                );
-                                self.ui.send_result(
-                                    self.id,
-                                    running.pr.clone(),
-                                    ui::Status::Success(
-                                        running.pull_commit.clone(),
-                                        merged_commit.clone(),
-                                        url,
-                                    ),
-                                );
-                                // Put it back with it marked as built.
-                                running.built = true;
-                            }
-                        }
-                    } else {
-                        warn!("Finished building a commit that never merged");
+        }
+        Ok(())
+    }
+    fn handle_ci_event<D: Db>(
+        &mut self,
+        db: &mut D,
+        running: &mut Vec<RunningEntry>,
+        event: ci::Event,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let (ci_id, built_commit, url, state) = match event {
+            ci::Event::BuildStarted(ci_id, building_commit, url) => {
+                let candidate = running.iter().find(|candidate| {
+                    candidate.merge_commit.as_ref() == Some(&building_commit)
+                });
+                if let Some(candidate) = candidate {
+                    if candidate.built {
+                        warn!("Got CI build started after done building!");
+                        return Ok(());
+                    }
+                    let mut ci_urls = try!(self.ci_urls(db, &building_commit));
+                    if let Some(ref url) = url {
+                        ci_urls.retain(|&(id, _)| id != ci_id);
+                        ci_urls.push((ci_id, url.clone()));
+                    }
+                    let details = ui::Details{
+                        queue_position: None,
+                        ci_urls: ci_urls,
+                    };
+                    for entry in &candidate.entries {
+                        try!(self.send_result_details(
+                            db,
+                            entry.pr.clone(),
+                            ui::Status::Testing(
+                                entry.commit.clone(),
+                                building_commit.clone(),
+                                url.clone(),
+                            ),
+                            details.clone(),
+                        ));
                     }
-                    try!(db.put_running(self.id, running.clone()));
                 } else {
-                    warn!("CI build succeeded event with no queued PR");
+                    warn!("Building a commit that is not running");
                 }
+                return Ok(());
             },
-            Event::VcsEvent(vcs::Event::FailedMoveToMaster(
-                pipeline_id,
-                merge_commit,
-            )) => {
+            ci::Event::BuildFailed(ci_id, built_commit, url) => {
+                (ci_id, built_commit, url, CiState::Failed)
+            },
+            ci::Event::BuildSucceeded(ci_id, built_commit, url) => {
+                (ci_id, built_commit, url, CiState::Succeeded)
+            },
+        };
+        let built = running.iter()
+            .find(|candidate| {
+                candidate.merge_commit.as_ref() == Some(&built_commit)
+            })
+            .map(|candidate| candidate.built);
+        match built {
+            None => {
+                warn!("Finished building a commit that is not running");
+            }
+            Some(true) => {
+                warn!("Got duplicate CI build finished event");
+            }
+            Some(false) => {
+                // Candidates behind the head hang on to their results
+                // until everything ahead of them has landed.
+                try!(db.set_ci_state(
+                    ci_id,
+                    state,
+                    &built_commit,
+                    url.as_ref(),
+                ));
+                try!(self.check_head(db, running));
+            }
+        }
+        Ok(())
+    }
+    fn handle_timer_event<D: Db>(
+        &mut self,
+        db: &mut D,
+        running: &mut Vec<RunningEntry>,
+        event: timer::Event,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match event {
+            timer::Event::TimedOut(pipeline_id, merge_commit) => {
                 assert_eq!(&pipeline_id, &self.id);
-                if let Some(running) = try!(db.take_running(self.id)) {
-                    if let Some(running_merge_commit) = running.merge_commit {
-                        if running_merge_commit != merge_commit {
-                            warn!("VCS move event with wrong commit");
-                        } else if running.canceled {
-                            // Drop it on the floor. It's canceled.
-                        } else if !running.built {
-                            warn!("Failed move to master before built!");
-                        } else {
-                            self.ui.send_result(
-                                self.id,
-                                running.pr,
-                                ui::Status::Unmoveable(
-                                    running.pull_commit,
-                                    running_merge_commit,
-                                ),
-                            );
-                        }
-                    } else {
-                        warn!("VCS move event with commit that never ran");
+                let i = running.iter().position(|candidate| {
+                    !candidate.built &&
+                        candidate.merge_commit.as_ref() == Some(&merge_commit)
+                });
+                let i = match i {
+                    Some(i) => i,
+                    // The build finished, or was thrown away, in time.
+                    None => return Ok(()),
+                };
+                if let (Some(started_at), Some(ci_timeout)) =
+                        (running[i].started_at, self.ci_timeout) {
+                    let elapsed = started_at.elapsed()
+                        .unwrap_or(Duration::from_secs(0));
+                    if elapsed < ci_timeout {
+                        // The build was retried after this timer was set.
+                        return Ok(());
                     }
-                } else {
-                    warn!("VCS move event with no queued PR");
                 }
+                if try!(self.ci_reported(db, &merge_commit)) {
+                    // It finished in time, and is waiting for the
+                    // candidates ahead of it to land.
+                    return Ok(());
+                }
+                let candidate = running.remove(i);
+                try!(self.add_history(
+                    db,
+                    &candidate.entries,
+                    Some(&merge_commit),
+                    candidate.started_at,
+                    HistoryStatus::TimedOut,
+                ));
+                try!(self.clear_ci_states(db, &merge_commit));
+                for entry in candidate.entries {
+                    try!(db.add_failed(self.id, entry.clone()));
+                    try!(self.send_result(
+                        db,
+                        entry.pr,
+                        ui::Status::TimedOut(
+                            entry.commit,
+                            merge_commit.clone(),
+                        ),
+                    ));
+                }
+                // Everything behind it was stacked on top of it.
+                try!(self.restack(db, running, i));
             },
-            Event::VcsEvent(vcs::Event::MovedToMaster(
-                pipeline_id,
-                merge_commit,
-            )) => {
-                assert_eq!(&pipeline_id, &self.id);
-                if let Some(running) = try!(db.take_running(self.id)) {
-                    if let Some(running_merge_commit) = running.merge_commit {
-                        if running_merge_commit != merge_commit {
-                            warn!("VCS move event with wrong commit");
-                        } else if running.canceled {
-                            // Drop it on the floor. It's canceled.
-                        } else if !running.built {
-                            warn!("Moved to master before done building!");
-                        } else {
-                            self.ui.send_result(
-                                self.id,
-                                running.pr,
-                                ui::Status::Completed(
-                                    running.pull_commit,
-                                    running_merge_commit,
-                                ),
-                            );
-                        }
-                    } else {
-                        warn!("VCS move event with commit that never ran");
-                    }
-                } else {
-                    warn!("VCS move event with no queued PR");
+        }
+        Ok(())
+    }
+    /// Find the candidate and entry that a merge result belongs to.
+    /// Results for merges that have since been abandoned are ignored,
+    /// by checking that they were merged on top of the expected commit.
+    fn find_merging(
+        &self,
+        running: &[RunningEntry],
+        base_commit: &Option<Commit>,
+        pull_commit: &Commit,
+    ) -> Option<(usize, usize)> {
+        for (i, candidate) in running.iter().enumerate() {
+            if i >= self.depth {
+                break;
+            }
+            if i > 0 && running[i - 1].merge_commit.is_none() {
+                break;
+            }
+            if candidate.merge_commit.is_some() ||
+                    &candidate.base_commit != base_commit {
+                continue;
+            }
+            let position = candidate.entries.iter()
+                .position(|entry| &entry.commit == pull_commit);
+            if let Some(position) = position {
+                return Some((i, position));
+            }
+        }
+        None
+    }
+    fn is_built_head(
+        &self,
+        running: &[RunningEntry],
+        merge_commit: &Commit,
+    ) -> bool {
+        running.first().map(|head| {
+            head.built && head.merge_commit.as_ref() == Some(merge_commit)
+        }).unwrap_or(false)
+    }
+    /// Act on the CI results for the head of the queue, once every CI job
+    /// has succeeded or any one of them has failed.
+    fn check_head<D: Db>(
+        &mut self,
+        db: &mut D,
+        running: &mut Vec<RunningEntry>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let merge_commit = match running.first() {
+            Some(head) if !head.built => match head.merge_commit {
+                Some(ref merge_commit) => merge_commit.clone(),
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        let mut failed = None;
+        let mut succeeded_count = 0;
+        let mut succeeded_url = None;
+        for &(ci_id, _) in &self.ci {
+            match try!(db.get_ci_state(ci_id, &merge_commit)) {
+                Some((CiState::Failed, url)) => {
+                    failed = Some(url);
+                    break;
+                }
+                Some((CiState::Succeeded, url)) => {
+                    succeeded_count += 1;
+                    succeeded_url = url;
                 }
+                None => {}
             }
         }
-        if try!(db.peek_running(self.id)).is_none() {
-            if let Some(next) = try!(db.pop_queue(self.id)) {
-                self.vcs.merge_to_staging(
-                    self.id,
-                    next.commit.clone(),
-                    next.message.clone(),
-                    next.pr.remote(),
-                );
-                try!(db.put_running(self.id, RunningEntry{
-                    pr: next.pr,
-                    message: next.message,
-                    pull_commit: next.commit,
+        if let Some(url) = failed {
+            if running[0].retries < self.auto_retry {
+                // Build the same merge commit again,
+                // in case the failure was a fluke.
+                running[0].retries += 1;
+                let details = ui::Details{
+                    queue_position: None,
+                    ci_urls: try!(self.ci_urls(db, &merge_commit)),
+                };
+                try!(self.start_builds(db, &mut running[0], &merge_commit));
+                for entry in &running[0].entries {
+                    try!(self.send_result_details(
+                        db,
+                        entry.pr.clone(),
+                        ui::Status::Retrying(
+                            entry.commit.clone(),
+                            merge_commit.clone(),
+                            url.clone(),
+                        ),
+                        details.clone(),
+                    ));
+                }
+                return Ok(());
+            }
+            if running[0].entries.len() > 1 {
+                try!(self.clear_ci_states(db, &merge_commit));
+                // Bisect: test the first half on its own,
+                // with the second half stacked on top of it.
+                let half = running[0].entries.len() / 2;
+                let rest = running[0].entries.split_off(half);
+                let staging = free_staging(running);
+                running.insert(1, RunningEntry{
+                    entries: rest,
+                    staging: staging,
+                    base_commit: None,
                     merge_commit: None,
+                    started_at: None,
+                    retries: 0,
                     canceled: false,
                     built: false,
-                }));
+                });
+            } else {
+                let head = running.remove(0);
+                try!(self.add_history(
+                    db,
+                    &head.entries,
+                    Some(&merge_commit),
+                    head.started_at,
+                    HistoryStatus::Failed,
+                ));
+                let details = ui::Details{
+                    queue_position: None,
+                    ci_urls: try!(self.ci_urls(db, &merge_commit)),
+                };
+                try!(self.clear_ci_states(db, &merge_commit));
+                for entry in head.entries {
+                    try!(db.add_failed(self.id, entry.clone()));
+                    try!(self.send_result_details(
+                        db,
+                        entry.pr,
+                        ui::Status::Failure(
+                            entry.commit,
+                            merge_commit.clone(),
+                            url.clone(),
+                        ),
+                        details.clone(),
+                    ));
+                }
+            }
+            try!(self.restack(db, running, 0));
+        } else if succeeded_count == self.ci.len() {
+            self.vcs.move_staging_to_master(self.id, merge_commit.clone());
+            let details = ui::Details{
+                queue_position: None,
+                ci_urls: try!(self.ci_urls(db, &merge_commit)),
+            };
+            for entry in &running[0].entries {
+                try!(self.send_result_details(
+                    db,
+                    entry.pr.clone(),
+                    ui::Status::Success(
+                        entry.commit.clone(),
+                        merge_commit.clone(),
+                        succeeded_url.clone(),
+                    ),
+                    details.clone(),
+                ));
             }
+            running[0].built = true;
         }
         Ok(())
     }
+    /// Throw away the merges of every candidate from the given one on,
+    /// and start merging them again.
+    fn restack<D: Db>(
+        &mut self,
+        db: &mut D,
+        running: &mut Vec<RunningEntry>,
+        from: usize,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        for candidate in running.iter_mut().skip(from) {
+            if let Some(merge_commit) = candidate.merge_commit.take() {
+                try!(self.clear_ci_states(db, &merge_commit));
+            }
+            candidate.base_commit = None;
+            candidate.started_at = None;
+            candidate.retries = 0;
+            candidate.canceled = false;
+            candidate.built = false;
+        }
+        self.start_candidate(running, from);
+        Ok(())
+    }
+    /// Merge whatever is left of canceled candidates again,
+    /// along with everything stacked on top of them.
+    fn restack_canceled<D: Db>(
+        &mut self,
+        db: &mut D,
+        running: &mut Vec<RunningEntry>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        if let Some(head) = running.first_mut() {
+            if head.built {
+                // It is already being moved to master; let it land.
+                head.canceled = false;
+            }
+        }
+        let first = match running.iter().position(|c| c.canceled) {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        let mut i = first;
+        while i < running.len() {
+            if running[i].canceled && running[i].entries.is_empty() {
+                let candidate = running.remove(i);
+                if let Some(merge_commit) = candidate.merge_commit {
+                    try!(self.clear_ci_states(db, &merge_commit));
+                }
+            } else {
+                i += 1;
+            }
+        }
+        self.restack(db, running, first)
+    }
+    /// Start merging a candidate into its staging branch,
+    /// if it is close enough to the head of the queue
+    /// and the candidate ahead of it has been merged.
+    fn start_candidate(&self, running: &mut Vec<RunningEntry>, i: usize) {
+        if i >= self.depth || i >= running.len() {
+            return;
+        }
+        let base_commit = if i == 0 {
+            None
+        } else {
+            match running[i - 1].merge_commit {
+                Some(ref merge_commit) => Some(merge_commit.clone()),
+                None => return,
+            }
+        };
+        let candidate = &mut running[i];
+        if candidate.entries.is_empty() {
+            return;
+        }
+        self.vcs.merge_to_staging(
+            self.id,
+            candidate.staging,
+            base_commit.clone(),
+            candidate.entries[0].commit.clone(),
+            candidate.entries[0].message.clone(),
+            candidate.entries[0].pr.remote(self.remote_format),
+        );
+        candidate.base_commit = base_commit;
+    }
+    /// Ask every CI to build a candidate's merge commit,
+    /// forgetting about any earlier results for it.
+    fn start_builds<D: Db>(
+        &self,
+        db: &mut D,
+        candidate: &mut RunningEntry,
+        merge_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        for &(ci_id, ci) in &self.ci {
+            try!(db.clear_ci_state(ci_id, merge_commit));
+            ci.start_build(ci_id, merge_commit.clone());
+        }
+        if let Some(ci_timeout) = self.ci_timeout {
+            candidate.started_at = Some(SystemTime::now());
+            self.timer.start_timer(self.id, merge_commit.clone(), ci_timeout);
+        }
+        Ok(())
+    }
+    /// Start the builds of a recovered candidate again,
+    /// except for the ones that already reported back.
+    fn restart_builds<D: Db>(
+        &self,
+        db: &mut D,
+        candidate: &mut RunningEntry,
+        merge_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let mut restarted = false;
+        for &(ci_id, ci) in &self.ci {
+            if try!(db.get_ci_state(ci_id, merge_commit)).is_none() {
+                ci.start_build(ci_id, merge_commit.clone());
+                restarted = true;
+            }
+        }
+        if let (true, Some(ci_timeout)) = (restarted, self.ci_timeout) {
+            candidate.started_at = Some(SystemTime::now());
+            self.timer.start_timer(self.id, merge_commit.clone(), ci_timeout);
+        }
+        Ok(())
+    }
+    /// Record queue entries that have left the pipeline for good,
+    /// along with whatever the CI reported for their merge commit.
+    /// This has to happen before the CI states are cleared.
+    fn add_history<D: Db>(
+        &self,
+        db: &mut D,
+        entries: &[QueueEntry],
+        merge_commit: Option<&Commit>,
+        started_at: Option<SystemTime>,
+        status: HistoryStatus,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let ci_urls: Vec<Url> = match merge_commit {
+            Some(merge_commit) => try!(self.ci_urls(db, merge_commit))
+                .into_iter()
+                .map(|(_, url)| url)
+                .collect(),
+            None => Vec::new(),
+        };
+        let finished_at = SystemTime::now();
+        for entry in entries {
+            try!(db.add_history(self.id, HistoryEntry{
+                pr: entry.pr.clone(),
+                pull_commit: entry.commit.clone(),
+                merge_commit: merge_commit.cloned(),
+                message: entry.message.clone(),
+                approver: entry.approver.clone(),
+                status: status,
+                ci_urls: ci_urls.clone(),
+                started_at: started_at,
+                finished_at: finished_at,
+            }));
+        }
+        Ok(())
+    }
+    /// Record the queued and running entries of a pull request as canceled,
+    /// except for the ones with the given commit.
+    fn add_canceled_history<D: Db>(
+        &self,
+        db: &mut D,
+        pr: &Pr,
+        keep_commit: Option<&Commit>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let canceled = |entry: &QueueEntry| {
+            &entry.pr == pr && Some(&entry.commit) != keep_commit
+        };
+        let queued: Vec<QueueEntry> = try!(db.list_queue(self.id))
+            .into_iter()
+            .filter(|entry| canceled(entry))
+            .collect();
+        try!(self.add_history(
+            db,
+            &queued,
+            None,
+            None,
+            HistoryStatus::Canceled,
+        ));
+        for candidate in try!(db.peek_running(self.id)) {
+            let entries: Vec<QueueEntry> = candidate.entries.into_iter()
+                .filter(|entry| canceled(entry))
+                .collect();
+            try!(self.add_history(
+                db,
+                &entries,
+                candidate.merge_commit.as_ref(),
+                candidate.started_at,
+                HistoryStatus::Canceled,
+            ));
+        }
+        Ok(())
+    }
+    /// Whether the CI has said enough about a merge commit to act on it:
+    /// any one job has failed, or every job has succeeded.
+    fn ci_reported<D: Db>(
+        &self,
+        db: &mut D,
+        merge_commit: &Commit,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let mut succeeded_count = 0;
+        for &(ci_id, _) in &self.ci {
+            match try!(db.get_ci_state(ci_id, merge_commit)) {
+                Some((CiState::Failed, _)) => return Ok(true),
+                Some((CiState::Succeeded, _)) => succeeded_count += 1,
+                None => {}
+            }
+        }
+        Ok(succeeded_count == self.ci.len())
+    }
+    /// The links to the builds of a merge commit that have reported back.
+    fn ci_urls<D: Db>(
+        &self,
+        db: &mut D,
+        merge_commit: &Commit,
+    ) -> Result<Vec<(CiId, Url)>, Box<Error + Send + Sync>> {
+        let mut ci_urls = Vec::new();
+        for &(ci_id, _) in &self.ci {
+            let state = try!(db.get_ci_state(ci_id, merge_commit));
+            if let Some((_, Some(url))) = state {
+                ci_urls.push((ci_id, url));
+            }
+        }
+        Ok(ci_urls)
+    }
+    /// Where a pull request that was just queued ended up.
+    fn queued_details<D: Db>(
+        &self,
+        db: &mut D,
+        pr: &Pr,
+    ) -> Result<ui::Details, Box<Error + Send + Sync>> {
+        let queue_position = try!(db.list_queue(self.id))
+            .iter()
+            .position(|entry| &entry.pr == pr)
+            .map(|i| i + 1);
+        Ok(ui::Details{
+            queue_position: queue_position,
+            ci_urls: Vec::new(),
+        })
+    }
+    /// Tell the UI about a pull request, and queue the same status for
+    /// the outbound webhooks as part of this transaction.
+    fn send_result<D: Db>(
+        &self,
+        db: &mut D,
+        pr: Pr,
+        status: ui::Status,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.send_result_details(db, pr, status, ui::Details::default())
+    }
+    fn send_result_details<D: Db>(
+        &self,
+        db: &mut D,
+        pr: Pr,
+        status: ui::Status,
+        details: ui::Details,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        if let Some(outbox) = self.outbox {
+            try!(outbox.add(db, self.id, &pr, &status, &details));
+        }
+        self.ui.send_result_details(self.id, pr, status, details);
+        Ok(())
+    }
+    fn clear_ci_states<D: Db>(
+        &self,
+        db: &mut D,
+        commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        for &(ci_id, _) in &self.ci {
+            try!(db.clear_ci_state(ci_id, commit));
+        }
+        Ok(())
+    }
+}
+
+/// The lowest staging branch that no candidate is using.
+fn free_staging(running: &[RunningEntry]) -> usize {
+    (0..)
+        .find(|staging| {
+            !running.iter().any(|candidate| candidate.staging == *staging)
+        })
+        .expect("There to be a free staging branch")
 }
 
 #[cfg(test)] mod test;
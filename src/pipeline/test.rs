@@ -1,32 +1,47 @@
 // This file is released under the same terms as Rust itself.
 
-use super::{Ci, Vcs, Ui};
+use super::{Ci, Timer, Vcs, Ui};
 use ci::{self, CiId};
-use db::{CiState, Db, PendingEntry, QueueEntry, RunningEntry};
+use db::{CiState, Db, HistoryEntry, HistoryStatus, PendingEntry};
+use db::{OutboxEntry, OutboxId, QueueEntry, RunningEntry};
+use hooks::{self, Outbox};
 use hyper::Url;
 use hyper::client::IntoUrl;
 use pipeline::{Event, Pipeline, PipelineId};
+use serde_json::{self, Value};
 use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
 use std::error::Error;
 use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use timer;
 use ui::{self, Pr};
 use vcs::{self, Commit, Remote};
 
 struct MemoryDb {
     queue: VecDeque<QueueEntry>,
-    running: Option<RunningEntry>,
+    running: Vec<RunningEntry>,
     pending: Vec<PendingEntry>,
-    cis: HashMap<CiId, (CiState, Commit)>,
+    failed: Vec<QueueEntry>,
+    tree_closed: Option<i32>,
+    history: Vec<HistoryEntry>,
+    cis: HashMap<(CiId, Commit), (CiState, Option<Url>)>,
+    outbox: Vec<(OutboxId, OutboxEntry)>,
+    delegations: Vec<(Pr, String, Commit)>,
 }
 
 impl MemoryDb {
     fn new() -> Self {
         MemoryDb{
             queue: VecDeque::new(),
-            running: None,
+            running: Vec::new(),
             pending: Vec::new(),
+            failed: Vec::new(),
+            tree_closed: None,
+            history: Vec::new(),
             cis: HashMap::new(),
+            outbox: Vec::new(),
+            delegations: Vec::new(),
         }
     }
 }
@@ -44,32 +59,51 @@ impl Db for MemoryDb {
         &mut self,
         _: PipelineId
     ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
-        Ok(self.queue.pop_front())
+        let mut best: Option<usize> = None;
+        for (i, entry) in self.queue.iter().enumerate() {
+            if best.map(|b| entry.priority > self.queue[b].priority)
+                .unwrap_or(true) {
+                best = Some(i);
+            }
+        }
+        Ok(best.and_then(|i| self.queue.remove(i)))
+    }
+    fn peek_queue(
+        &mut self,
+        _: PipelineId
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        let mut best: Option<&QueueEntry> = None;
+        for entry in &self.queue {
+            if best.map(|b| entry.priority > b.priority).unwrap_or(true) {
+                best = Some(entry);
+            }
+        }
+        Ok(best.cloned())
     }
     fn list_queue(
         &mut self,
         _: PipelineId,
     ) -> Result<Vec<QueueEntry>, Box<Error + Send + Sync>> {
-        unimplemented!()
+        Ok(self.queue.iter().cloned().collect())
     }
     fn put_running(
         &mut self,
         _: PipelineId,
-        entry: RunningEntry,
+        running: Vec<RunningEntry>,
     ) -> Result<(), Box<Error + Send + Sync>> {
-        self.running = Some(entry);
+        self.running = running;
         Ok(())
     }
     fn take_running(
         &mut self,
         _: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
-        Ok(mem::replace(&mut self.running, None))
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
+        Ok(mem::replace(&mut self.running, Vec::new()))
     }
     fn peek_running(
         &mut self,
         _: PipelineId,
-    ) -> Result<Option<RunningEntry>, Box<Error + Send + Sync>> {
+    ) -> Result<Vec<RunningEntry>, Box<Error + Send + Sync>> {
         Ok(self.running.clone())
     }
     fn add_pending(
@@ -130,8 +164,10 @@ impl Db for MemoryDb {
         let queue = mem::replace(&mut self.queue, VecDeque::new());
         let filtered = queue.into_iter().filter(|entry| entry.pr != *pr);
         self.queue.extend(filtered);
-        if let Some(ref mut running) = self.running {
-            if running.pr == *pr {
+        for running in &mut self.running {
+            let len_orig = running.entries.len();
+            running.entries.retain(|entry| entry.pr != *pr);
+            if len_orig != running.entries.len() {
                 running.canceled = true;
             }
         }
@@ -150,45 +186,174 @@ impl Db for MemoryDb {
         );
         self.queue.extend(filtered);
         let mut canceled = len_orig != self.queue.len();
-        if let Some(ref mut running) = self.running {
-            if running.pr == *pr && running.pull_commit != *commit {
+        for running in &mut self.running {
+            let len_orig = running.entries.len();
+            running.entries.retain(|entry|
+                entry.pr != *pr || entry.commit == *commit
+            );
+            if len_orig != running.entries.len() {
                 running.canceled = true;
                 canceled = true;
             }
         }
         Ok(canceled)
     }
+    fn add_failed(
+        &mut self,
+        _: PipelineId,
+        entry: QueueEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.failed.retain(|failed| failed.pr != entry.pr);
+        self.failed.push(entry);
+        Ok(())
+    }
+    fn take_failed_by_pr(
+        &mut self,
+        _: PipelineId,
+        pr: &Pr,
+    ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
+        let i = self.failed.iter().position(|entry| entry.pr == *pr);
+        Ok(i.map(|i| self.failed.remove(i)))
+    }
+    fn set_tree_closed(
+        &mut self,
+        _: PipelineId,
+        priority: Option<i32>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.tree_closed = priority;
+        Ok(())
+    }
+    fn get_tree_closed(
+        &mut self,
+        _: PipelineId,
+    ) -> Result<Option<i32>, Box<Error + Send + Sync>> {
+        Ok(self.tree_closed)
+    }
+    fn add_history(
+        &mut self,
+        _: PipelineId,
+        entry: HistoryEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.history.push(entry);
+        Ok(())
+    }
+    fn list_history(
+        &mut self,
+        _: PipelineId,
+    ) -> Result<Vec<HistoryEntry>, Box<Error + Send + Sync>> {
+        Ok(self.history.iter().rev().cloned().collect())
+    }
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
         ci_state: CiState,
         commit: &Commit,
+        url: Option<&Url>,
     ) -> Result<(), Box<Error + Send + Sync>> {
-        self.cis.insert(ci_id, (ci_state, commit.clone()));
+        self.cis.insert(
+            (ci_id, commit.clone()),
+            (ci_state, url.cloned()),
+        );
         Ok(())
     }
     fn clear_ci_state(
         &mut self,
         ci_id: CiId,
+        commit: &Commit,
     ) -> Result<(), Box<Error + Send + Sync>> {
-        self.cis.remove(&ci_id);
+        self.cis.remove(&(ci_id, commit.clone()));
         Ok(())
     }
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
-        Ok(self.cis.get(&ci_id).cloned())
+        commit: &Commit,
+    ) -> Result<Option<(CiState, Option<Url>)>, Box<Error + Send + Sync>> {
+        Ok(self.cis.get(&(ci_id, commit.clone())).cloned())
+    }
+    fn add_outbox(
+        &mut self,
+        entry: OutboxEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let id = self.outbox.last().map(|&(id, _)| id.0 + 1).unwrap_or(0);
+        self.outbox.push((OutboxId(id), entry));
+        Ok(())
+    }
+    fn list_outbox(
+        &mut self,
+        due: SystemTime,
+    ) -> Result<Vec<(OutboxId, OutboxEntry)>, Box<Error + Send + Sync>> {
+        Ok(self.outbox.iter()
+            .filter(|&&(_, ref entry)| entry.next_attempt_at <= due)
+            .cloned()
+            .collect())
+    }
+    fn remove_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.outbox.retain(|&(id, _)| id != outbox_id);
+        Ok(())
+    }
+    fn postpone_outbox(
+        &mut self,
+        outbox_id: OutboxId,
+        attempts: u32,
+        next_attempt_at: SystemTime,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        for &mut (id, ref mut entry) in &mut self.outbox {
+            if id == outbox_id {
+                entry.attempts = attempts;
+                entry.next_attempt_at = next_attempt_at;
+            }
+        }
+        Ok(())
+    }
+    fn add_delegation(
+        &mut self,
+        _: PipelineId,
+        pr: &Pr,
+        user: &str,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.delegations.retain(|&(ref p, ref u, _)| p != pr || u != user);
+        self.delegations.push(
+            (pr.clone(), user.to_owned(), pull_commit.clone())
+        );
+        Ok(())
+    }
+    fn is_delegated(
+        &mut self,
+        _: PipelineId,
+        pr: &Pr,
+        user: &str,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        Ok(self.delegations.iter().any(|&(ref p, ref u, _)| {
+            p == pr && u == user
+        }))
+    }
+    fn remove_delegations(
+        &mut self,
+        _: PipelineId,
+        pr: &Pr,
+        keep_commit: Option<&Commit>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.delegations.retain(|&(ref p, _, ref c)| {
+            p != pr || Some(c) == keep_commit
+        });
+        Ok(())
     }
 }
 
 struct MemoryUi {
     results: Vec<(Pr, ui::Status)>,
+    details: Vec<(Pr, ui::Details)>,
 }
 impl MemoryUi {
     fn new() -> RefCell<MemoryUi> {
         RefCell::new(MemoryUi{
             results: Vec::new(),
+            details: Vec::new(),
         })
     }
 }
@@ -201,16 +366,30 @@ impl Ui for RefCell<MemoryUi> {
     ) {
         self.borrow_mut().results.push((pr, status));
     }
+    fn send_result_details(
+        &self,
+        pipeline_id: PipelineId,
+        pr: Pr,
+        status: ui::Status,
+        details: ui::Details,
+    ) {
+        self.borrow_mut().details.push((pr.clone(), details));
+        self.send_result(pipeline_id, pr, status);
+    }
 }
 
 struct MemoryVcs {
     staging: Option<Commit>,
+    staging_base: Option<Commit>,
+    staging_slot: usize,
     master: Option<Commit>,
 }
 impl MemoryVcs {
     fn new() -> RefCell<MemoryVcs> {
         RefCell::new(MemoryVcs{
             staging: None,
+            staging_base: None,
+            staging_slot: 0,
             master: None,
         })
     }
@@ -219,11 +398,16 @@ impl Vcs for RefCell<MemoryVcs> {
     fn merge_to_staging(
         &self,
         _: PipelineId,
+        staging: usize,
+        base_commit: Option<Commit>,
         pull_commit: Commit,
         _message: String,
         _remote: Remote,
     ) {
-        self.borrow_mut().staging = Some(pull_commit)
+        let mut this = self.borrow_mut();
+        this.staging_slot = staging;
+        this.staging_base = base_commit;
+        this.staging = Some(pull_commit);
     }
     fn move_staging_to_master(&self, _: PipelineId, commit: Commit) {
         self.borrow_mut().master = Some(commit)
@@ -246,6 +430,22 @@ impl Ci for RefCell<MemoryCi> {
     }
 }
 
+struct MemoryTimer {
+    timers: Vec<(Commit, Duration)>,
+}
+impl MemoryTimer {
+    fn new() -> RefCell<MemoryTimer> {
+        RefCell::new(MemoryTimer{
+            timers: Vec::new(),
+        })
+    }
+}
+impl Timer for RefCell<MemoryTimer> {
+    fn start_timer(&self, _: PipelineId, commit: Commit, timeout: Duration) {
+        self.borrow_mut().timers.push((commit, timeout));
+    }
+}
+
 
 fn handle_event(
     ui: &mut RefCell<MemoryUi>,
@@ -258,7 +458,14 @@ fn handle_event(
         ui: ui,
         vcs: vcs,
         ci: vec![(CiId(1), ci)],
+        timer: &MemoryTimer::new(),
+        outbox: None,
         id: PipelineId(0),
+        batch_size: 1,
+        depth: 1,
+        ci_timeout: None,
+        auto_retry: 0,
+        remote_format: ui::github::REMOTE_FORMAT,
     }.handle_event(db, event).unwrap();
 }
 
@@ -274,10 +481,206 @@ fn handle_event_2_ci(
         ui: ui,
         vcs: vcs,
         ci: vec![(CiId(1), ci1), (CiId(2), ci2)],
+        timer: &MemoryTimer::new(),
+        outbox: None,
+        id: PipelineId(0),
+        batch_size: 1,
+        depth: 1,
+        ci_timeout: None,
+        auto_retry: 0,
+        remote_format: ui::github::REMOTE_FORMAT,
+    }.handle_event(db, event).unwrap();
+}
+
+fn handle_event_batch(
+    ui: &mut RefCell<MemoryUi>,
+    vcs: &mut RefCell<MemoryVcs>,
+    ci: &mut RefCell<MemoryCi>,
+    db: &mut MemoryDb,
+    batch_size: usize,
+    event: Event,
+) {
+    Pipeline{
+        ui: ui,
+        vcs: vcs,
+        ci: vec![(CiId(1), ci)],
+        timer: &MemoryTimer::new(),
+        outbox: None,
+        id: PipelineId(0),
+        batch_size: batch_size,
+        depth: 1,
+        ci_timeout: None,
+        auto_retry: 0,
+        remote_format: ui::github::REMOTE_FORMAT,
+    }.handle_event(db, event).unwrap();
+}
+
+fn handle_event_depth(
+    ui: &mut RefCell<MemoryUi>,
+    vcs: &mut RefCell<MemoryVcs>,
+    ci: &mut RefCell<MemoryCi>,
+    db: &mut MemoryDb,
+    batch_size: usize,
+    depth: usize,
+    event: Event,
+) {
+    Pipeline{
+        ui: ui,
+        vcs: vcs,
+        ci: vec![(CiId(1), ci)],
+        timer: &MemoryTimer::new(),
+        outbox: None,
+        id: PipelineId(0),
+        batch_size: batch_size,
+        depth: depth,
+        ci_timeout: None,
+        auto_retry: 0,
+        remote_format: ui::github::REMOTE_FORMAT,
+    }.handle_event(db, event).unwrap();
+}
+
+fn handle_event_timeout(
+    ui: &mut RefCell<MemoryUi>,
+    vcs: &mut RefCell<MemoryVcs>,
+    ci: &mut RefCell<MemoryCi>,
+    timer: &mut RefCell<MemoryTimer>,
+    db: &mut MemoryDb,
+    event: Event,
+) {
+    Pipeline{
+        ui: ui,
+        vcs: vcs,
+        ci: vec![(CiId(1), ci)],
+        timer: timer,
+        outbox: None,
+        id: PipelineId(0),
+        batch_size: 1,
+        depth: 1,
+        ci_timeout: Some(Duration::from_secs(60)),
+        auto_retry: 0,
+        remote_format: ui::github::REMOTE_FORMAT,
+    }.handle_event(db, event).unwrap();
+}
+
+fn handle_event_timeout_depth(
+    ui: &mut RefCell<MemoryUi>,
+    vcs: &mut RefCell<MemoryVcs>,
+    ci: &mut RefCell<MemoryCi>,
+    timer: &mut RefCell<MemoryTimer>,
+    db: &mut MemoryDb,
+    depth: usize,
+    event: Event,
+) {
+    Pipeline{
+        ui: ui,
+        vcs: vcs,
+        ci: vec![(CiId(1), ci)],
+        timer: timer,
+        outbox: None,
+        id: PipelineId(0),
+        batch_size: 1,
+        depth: depth,
+        ci_timeout: Some(Duration::from_secs(60)),
+        auto_retry: 0,
+        remote_format: ui::github::REMOTE_FORMAT,
+    }.handle_event(db, event).unwrap();
+}
+
+fn handle_event_auto_retry(
+    ui: &mut RefCell<MemoryUi>,
+    vcs: &mut RefCell<MemoryVcs>,
+    ci: &mut RefCell<MemoryCi>,
+    db: &mut MemoryDb,
+    auto_retry: u32,
+    event: Event,
+) {
+    Pipeline{
+        ui: ui,
+        vcs: vcs,
+        ci: vec![(CiId(1), ci)],
+        timer: &MemoryTimer::new(),
+        outbox: None,
         id: PipelineId(0),
+        batch_size: 1,
+        depth: 1,
+        ci_timeout: None,
+        auto_retry: auto_retry,
+        remote_format: ui::github::REMOTE_FORMAT,
     }.handle_event(db, event).unwrap();
 }
 
+fn recover(
+    ui: &mut RefCell<MemoryUi>,
+    vcs: &mut RefCell<MemoryVcs>,
+    ci: &mut RefCell<MemoryCi>,
+    db: &mut MemoryDb,
+) {
+    Pipeline{
+        ui: ui,
+        vcs: vcs,
+        ci: vec![(CiId(1), ci)],
+        timer: &MemoryTimer::new(),
+        outbox: None,
+        id: PipelineId(0),
+        batch_size: 1,
+        depth: 1,
+        ci_timeout: None,
+        auto_retry: 0,
+        remote_format: ui::github::REMOTE_FORMAT,
+    }.recover(db).unwrap();
+}
+
+fn recover_2_ci(
+    ui: &mut RefCell<MemoryUi>,
+    vcs: &mut RefCell<MemoryVcs>,
+    ci1: &mut RefCell<MemoryCi>,
+    ci2: &mut RefCell<MemoryCi>,
+    db: &mut MemoryDb,
+) {
+    Pipeline{
+        ui: ui,
+        vcs: vcs,
+        ci: vec![(CiId(1), ci1), (CiId(2), ci2)],
+        timer: &MemoryTimer::new(),
+        outbox: None,
+        id: PipelineId(0),
+        batch_size: 1,
+        depth: 1,
+        ci_timeout: None,
+        auto_retry: 0,
+        remote_format: ui::github::REMOTE_FORMAT,
+    }.recover(db).unwrap();
+}
+
+struct MemoryHooksPipelines;
+impl hooks::PipelinesConfig for MemoryHooksPipelines {
+    fn project_by_pipeline(&self, _: PipelineId) -> Option<(String, bool)> {
+        Some(("testp".to_owned(), false))
+    }
+}
+
+fn handle_event_outbox(
+    ui: &mut RefCell<MemoryUi>,
+    vcs: &mut RefCell<MemoryVcs>,
+    ci: &mut RefCell<MemoryCi>,
+    db: &mut MemoryDb,
+    outbox: &Outbox,
+    event: Event,
+) {
+    Pipeline{
+        ui: ui,
+        vcs: vcs,
+        ci: vec![(CiId(1), ci)],
+        timer: &MemoryTimer::new(),
+        outbox: Some(outbox),
+        id: PipelineId(0),
+        batch_size: 1,
+        depth: 1,
+        ci_timeout: None,
+        auto_retry: 0,
+        remote_format: ui::github::REMOTE_FORMAT,
+    }.handle_event(db, event).unwrap();
+}
 
 fn memory_commit_a() -> Commit {
     Commit::from("A".to_owned())
@@ -318,9 +721,11 @@ fn handle_add_to_queue() {
             memory_pr_a(),
             Some(memory_commit_a()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         )),
     );
-    assert_eq!(db.running.unwrap().pull_commit, memory_commit_a());
+    assert_eq!(db.running[0].entries[0].commit, memory_commit_a());
     assert!(db.queue.is_empty());
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
 }
@@ -341,9 +746,11 @@ fn handle_add_to_queue_by_pending_none() {
             memory_pr_a(),
             None,
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         )),
     );
-    assert!(db.running.is_none());
+    assert!(db.running.is_empty());
     assert!(db.queue.is_empty());
     assert!(vcs.borrow().staging.is_none());
     assert_eq!(ui.borrow().results[0].1, ui::Status::NoCommit);
@@ -378,9 +785,11 @@ fn handle_add_to_queue_by_pending_some() {
             memory_pr_a(),
             None,
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         )),
     );
-    assert_eq!(db.running.unwrap().pull_commit, memory_commit_a());
+    assert_eq!(db.running[0].entries[0].commit, memory_commit_a());
     assert!(db.queue.is_empty());
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
 }
@@ -427,9 +836,11 @@ fn handle_add_to_queue_by_pending_changed() {
             memory_pr_a(),
             None,
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         )),
     );
-    assert_eq!(db.running.unwrap().pull_commit, memory_commit_b());
+    assert_eq!(db.running[0].entries[0].commit, memory_commit_b());
     assert!(db.queue.is_empty());
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_b());
 }
@@ -450,6 +861,8 @@ fn handle_add_two_to_queue() {
             memory_pr_a(),
             Some(memory_commit_a()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
     handle_event(
@@ -462,10 +875,12 @@ fn handle_add_two_to_queue() {
             memory_pr_b(),
             Some(memory_commit_b()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert!(!db.running.clone().unwrap().canceled);
-    assert_eq!(db.running.unwrap().pull_commit, memory_commit_a());
+    assert!(!db.running[0].canceled);
+    assert_eq!(db.running[0].entries[0].commit, memory_commit_a());
     assert_eq!(db.queue.front().unwrap().commit, memory_commit_b());
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
 }
@@ -486,6 +901,8 @@ fn handle_add_two_same_pr_to_queue() {
             memory_pr_a(),
             Some(memory_commit_a()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
     handle_event(
@@ -498,12 +915,15 @@ fn handle_add_two_same_pr_to_queue() {
             memory_pr_a(),
             Some(memory_commit_b()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert!(db.running.clone().unwrap().canceled);
-    assert_eq!(db.running.unwrap().pull_commit, memory_commit_a());
-    assert_eq!(db.queue.front().unwrap().commit, memory_commit_b());
-    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
+    assert!(!db.running[0].canceled);
+    assert_eq!(db.running[0].entries.len(), 1);
+    assert_eq!(db.running[0].entries[0].commit, memory_commit_b());
+    assert!(db.queue.is_empty());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_b());
 }
 
 #[test]
@@ -522,6 +942,8 @@ fn handle_add_three_same_pr_to_queue() {
             memory_pr_a(),
             Some(memory_commit_a()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
     handle_event(
@@ -534,6 +956,8 @@ fn handle_add_three_same_pr_to_queue() {
             memory_pr_a(),
             Some(memory_commit_b()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
     handle_event(
@@ -546,12 +970,15 @@ fn handle_add_three_same_pr_to_queue() {
             memory_pr_a(),
             Some(memory_commit_c()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert!(db.running.clone().unwrap().canceled);
-    assert_eq!(db.running.unwrap().pull_commit, memory_commit_a());
-    assert_eq!(db.queue.front().unwrap().commit, memory_commit_c());
-    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
+    assert!(!db.running[0].canceled);
+    assert_eq!(db.running[0].entries.len(), 1);
+    assert_eq!(db.running[0].entries[0].commit, memory_commit_c());
+    assert!(db.queue.is_empty());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
 }
 
 #[test]
@@ -560,14 +987,22 @@ fn handle_merge_failed_notify_user() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     handle_event(
         &mut ui,
         &mut vcs,
@@ -575,10 +1010,11 @@ fn handle_merge_failed_notify_user() {
         &mut db,
         Event::VcsEvent(vcs::Event::FailedMergeToStaging(
             PipelineId(0),
+            None,
             memory_commit_a()
         ))
     );
-    assert!(db.running.is_none());
+    assert!(db.running.is_empty());
     assert!(db.queue.is_empty());
     assert!(ci.borrow().build.is_none());
     assert!(vcs.borrow().master.is_none());
@@ -594,18 +1030,28 @@ fn handle_merge_failed_notify_user_merge_next_commit() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     db.push_queue(PipelineId(0), QueueEntry{
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "M!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
     }).unwrap();
     handle_event(
         &mut ui,
@@ -614,17 +1060,26 @@ fn handle_merge_failed_notify_user_merge_next_commit() {
         &mut db,
         Event::VcsEvent(vcs::Event::FailedMergeToStaging(
             PipelineId(0),
+            None,
             memory_commit_a()
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_c(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_b(),
+            message: "M!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_b(),
-        message: "M!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    });
+    }]);
     assert!(db.queue.is_empty());
     assert!(ci.borrow().build.is_none());
     assert!(vcs.borrow().master.is_none());
@@ -636,19 +1091,95 @@ fn handle_merge_failed_notify_user_merge_next_commit() {
 }
 
 #[test]
-fn handle_merge_succeeded_notify_user_start_ci() {
+fn handle_merge_failed_merge_next_commit_by_priority() {
     let mut ui = MemoryUi::new();
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
+    }]).unwrap();
+    db.push_queue(PipelineId(0), QueueEntry{
+        commit: memory_commit_c(),
+        pr: memory_pr_b(),
+        message: "M!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
+    }).unwrap();
+    db.push_queue(PipelineId(0), QueueEntry{
+        commit: memory_commit_d(),
+        pr: memory_pr_c(),
+        message: "P!".to_owned(),
+        priority: 5,
+        approver: "Approver!".to_owned(),
     }).unwrap();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::VcsEvent(vcs::Event::FailedMergeToStaging(
+            PipelineId(0),
+            None,
+            memory_commit_a()
+        ))
+    );
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_d(),
+            pr: memory_pr_c(),
+            message: "P!".to_owned(),
+            priority: 5,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]);
+    assert_eq!(db.queue.len(), 1);
+    assert_eq!(db.queue[0].pr, memory_pr_b());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_d());
+}
+
+#[test]
+fn handle_merge_succeeded_notify_user_start_ci() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
         &mut ui,
@@ -657,18 +1188,27 @@ fn handle_merge_succeeded_notify_user_start_ci() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_a(),
             memory_commit_b()
         )),
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    });
+    }]);
     assert!(db.queue.is_empty());
     assert_eq!(ci.borrow().build.as_ref().unwrap(), &memory_commit_b());
     assert!(vcs.borrow().master.is_none());
@@ -688,14 +1228,22 @@ fn handle_ci_failed_notify_user() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
         &mut ui,
@@ -708,7 +1256,7 @@ fn handle_ci_failed_notify_user() {
             None,
         ))
     );
-    assert!(db.running.is_none());
+    assert!(db.running.is_empty());
     assert!(db.queue.is_empty());
     assert!(vcs.borrow().master.is_none());
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_b());
@@ -728,18 +1276,28 @@ fn handle_ci_failed_notify_user_next_commit() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     db.push_queue(PipelineId(0), QueueEntry{
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "M!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
     }).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
@@ -753,14 +1311,22 @@ fn handle_ci_failed_notify_user_next_commit() {
             None,
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_c(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_b(),
+            message: "M!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_b(),
-        message: "M!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    });
+    }]);
     assert!(db.queue.is_empty());
     assert!(vcs.borrow().master.is_none());
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
@@ -780,14 +1346,22 @@ fn handle_ci_started_notify_user() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
-        message: "MSG!".to_owned(),
         built: false,
-    }).unwrap();
+    }]).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
         &mut ui,
@@ -800,7 +1374,7 @@ fn handle_ci_started_notify_user() {
             Some("http://example.com/".into_url().expect("this to be valid")),
         ))
     );
-    assert!(db.running.is_some());
+    assert!(!db.running.is_empty());
     assert!(db.queue.is_empty());
     assert_eq!(
         ui.borrow().results,
@@ -818,14 +1392,22 @@ fn handle_ci_succeeded_move_to_master() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
-        message: "MSG!".to_owned(),
         built: false,
-    }).unwrap();
+    }]).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
         &mut ui,
@@ -838,7 +1420,7 @@ fn handle_ci_succeeded_move_to_master() {
             None,
         ))
     );
-    assert!(db.running.is_some());
+    assert!(!db.running.is_empty());
     assert!(db.queue.is_empty());
     assert_eq!(vcs.borrow().master.as_ref().unwrap(), &memory_commit_b());
     assert_eq!(
@@ -859,14 +1441,22 @@ fn handle_ci_double_succeeded_move_to_master() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
-        message: "MSG!".to_owned(),
         built: false,
-    }).unwrap();
+    }]).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
         &mut ui,
@@ -879,7 +1469,7 @@ fn handle_ci_double_succeeded_move_to_master() {
             None,
         ))
     );
-    assert!(db.running.is_some());
+    assert!(!db.running.is_empty());
     assert!(db.queue.is_empty());
     assert_eq!(vcs.borrow().master.as_ref().unwrap(), &memory_commit_b());
     assert_eq!(
@@ -903,7 +1493,7 @@ fn handle_ci_double_succeeded_move_to_master() {
             None,
         ))
     );
-    assert!(db.running.is_some());
+    assert!(!db.running.is_empty());
     assert!(db.queue.is_empty());
     assert_eq!(vcs.borrow().master.as_ref().unwrap(), &memory_commit_b());
     assert_eq!(
@@ -924,14 +1514,22 @@ fn handle_move_failed_notify_user() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
-        message: "MSG!".to_owned(),
         built: true,
-    }).unwrap();
+    }]).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
         &mut ui,
@@ -943,7 +1541,7 @@ fn handle_move_failed_notify_user() {
             memory_commit_b(),
         ))
     );
-    assert!(db.running.is_none());
+    assert!(db.running.is_empty());
     assert!(db.queue.is_empty());
     assert!(vcs.borrow().master.is_none());
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_b());
@@ -962,18 +1560,28 @@ fn handle_move_failed_notify_user_next_commit() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
-        message: "MSG!".to_owned(),
         built: true,
-    }).unwrap();
+    }]).unwrap();
     db.push_queue(PipelineId(0), QueueEntry{
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "M!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
     }).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
@@ -986,14 +1594,22 @@ fn handle_move_failed_notify_user_next_commit() {
             memory_commit_b(),
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_c(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_b(),
+            message: "M!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_b(),
-        message: "M!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    });
+    }]);
     assert!(db.queue.is_empty());
     assert!(vcs.borrow().master.is_none());
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
@@ -1012,14 +1628,22 @@ fn handle_move_succeeded_notify_user() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: true,
-    }).unwrap();
+    }]).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
         &mut ui,
@@ -1031,7 +1655,7 @@ fn handle_move_succeeded_notify_user() {
             memory_commit_b(),
         ))
     );
-    assert!(db.running.is_none());
+    assert!(db.running.is_empty());
     assert!(db.queue.is_empty());
     assert!(vcs.borrow().master.is_none());
     assert_eq!(
@@ -1049,18 +1673,28 @@ fn handle_move_succeeded_notify_user_next_commit() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: true,
-    }).unwrap();
+    }]).unwrap();
     db.push_queue(PipelineId(0), QueueEntry{
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "M!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
     }).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
@@ -1073,14 +1707,22 @@ fn handle_move_succeeded_notify_user_next_commit() {
             memory_commit_b(),
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_c(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_b(),
+            message: "M!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_b(),
-        message: "M!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    });
+    }]);
     assert!(db.queue.is_empty());
     assert!(vcs.borrow().master.is_none());
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
@@ -1101,14 +1743,22 @@ fn handle_ui_cancel() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     handle_event(
         &mut ui,
         &mut vcs,
@@ -1119,14 +1769,7 @@ fn handle_ui_cancel() {
             memory_pr_a()
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_a(),
-        merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        canceled: true,
-        built: false,
-        message: "MSG!".to_owned(),
-    });
+    assert!(db.running.is_empty());
 }
 
 #[test]
@@ -1135,14 +1778,22 @@ fn handle_ui_changed_cancel() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     handle_event(
         &mut ui,
         &mut vcs,
@@ -1156,14 +1807,7 @@ fn handle_ui_changed_cancel() {
             Url::parse("http://www.com/").unwrap(),
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_a(),
-        merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        canceled: true,
-        built: false,
-        message: "MSG!".to_owned(),
-    });
+    assert!(db.running.is_empty());
 }
 
 #[test]
@@ -1172,14 +1816,22 @@ fn handle_ui_changed_no_real_change() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     handle_event(
         &mut ui,
         &mut vcs,
@@ -1193,14 +1845,22 @@ fn handle_ui_changed_no_real_change() {
             Url::parse("http://www.com/").unwrap(),
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    });
+    }]);
 }
 
 #[test]
@@ -1209,18 +1869,28 @@ fn handle_ui_changed_cancel_queue() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     db.push_queue(PipelineId(0), QueueEntry{
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "MSG!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
     }).unwrap();
     handle_event(
         &mut ui,
@@ -1235,14 +1905,22 @@ fn handle_ui_changed_cancel_queue() {
             Url::parse("http://www.com/").unwrap(),
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    });
+    }]);
     assert!(db.queue.is_empty());
 }
 
@@ -1252,18 +1930,28 @@ fn handle_ui_changed_no_real_change_queue() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     db.push_queue(PipelineId(0), QueueEntry{
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "MSG!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
     }).unwrap();
     handle_event(
         &mut ui,
@@ -1278,18 +1966,28 @@ fn handle_ui_changed_no_real_change_queue() {
             Url::parse("http://www.com/").unwrap(),
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    });
+    }]);
     assert_eq!(db.queue[0], QueueEntry{
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "MSG!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
     });
 }
 
@@ -1299,14 +1997,22 @@ fn handle_ui_closed() {
     let mut vcs = MemoryVcs::new();
     let mut ci = MemoryCi::new();
     let mut db = MemoryDb::new();
-    db.put_running(PipelineId(0), RunningEntry{
-        pull_commit: memory_commit_a(),
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        message: "MSG!".to_owned(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-    }).unwrap();
+    }]).unwrap();
     handle_event(
         &mut ui,
         &mut vcs,
@@ -1317,14 +2023,86 @@ fn handle_ui_closed() {
             memory_pr_a()
         ))
     );
-    assert_eq!(db.running.unwrap(), RunningEntry{
-        pull_commit: memory_commit_a(),
-        merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
-        canceled: true,
-        built: false,
-        message: "MSG!".to_owned(),
-    });
+    assert!(db.running.is_empty());
+}
+
+#[test]
+fn handle_ui_changed_revokes_delegation() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.add_delegation(
+        PipelineId(0),
+        &memory_pr_a(),
+        "helper",
+        &memory_commit_a(),
+    ).unwrap();
+    // The same head, like when only the title was edited.
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Changed(
+            PipelineId(0),
+            memory_pr_a(),
+            memory_commit_a(),
+            "".to_owned(),
+            Url::parse("http://www.com/").unwrap(),
+        ))
+    );
+    assert!(db.is_delegated(PipelineId(0), &memory_pr_a(), "helper")
+        .unwrap());
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Changed(
+            PipelineId(0),
+            memory_pr_a(),
+            memory_commit_c(),
+            "".to_owned(),
+            Url::parse("http://www.com/").unwrap(),
+        ))
+    );
+    assert!(!db.is_delegated(PipelineId(0), &memory_pr_a(), "helper")
+        .unwrap());
+}
+
+#[test]
+fn handle_ui_closed_revokes_delegation() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.add_delegation(
+        PipelineId(0),
+        &memory_pr_a(),
+        "helper",
+        &memory_commit_a(),
+    ).unwrap();
+    db.add_delegation(
+        PipelineId(0),
+        &memory_pr_b(),
+        "helper",
+        &memory_commit_c(),
+    ).unwrap();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Closed(
+            PipelineId(0),
+            memory_pr_a()
+        ))
+    );
+    assert!(!db.is_delegated(PipelineId(0), &memory_pr_a(), "helper")
+        .unwrap());
+    assert!(db.is_delegated(PipelineId(0), &memory_pr_b(), "helper")
+        .unwrap());
 }
 
 #[test]
@@ -1343,6 +2121,8 @@ fn handle_runthrough() {
             memory_pr_a(),
             Some(memory_commit_a()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
     assert_eq!(vcs.borrow().staging, Some(memory_commit_a()));
@@ -1361,18 +2141,27 @@ fn handle_runthrough() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_a(),
             memory_commit_b(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "Message!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "Message!".to_owned(),
-    }));
+    }]);
     assert!(db.queue.is_empty());
     assert_eq!(vcs.borrow().staging, Some(memory_commit_b()));
     assert!(vcs.borrow().master.is_none());
@@ -1462,16 +2251,26 @@ fn handle_runthrough_next_commit() {
             memory_pr_a(),
             Some(memory_commit_a()),
             "MSG!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_a()));
     assert!(vcs.borrow().master.is_none());
     assert!(ci.borrow().build.is_none());
@@ -1492,16 +2291,26 @@ fn handle_runthrough_next_commit() {
             memory_pr_c(),
             Some(memory_commit_c()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(db.queue.len(), 1);
     // The first is now done merging. It should now be sent to the CI.
     vcs.borrow_mut().staging = Some(memory_commit_b());
@@ -1512,18 +2321,27 @@ fn handle_runthrough_next_commit() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_a(),
             memory_commit_b(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_b()));
     assert!(vcs.borrow().master.is_none());
     assert_eq!(ci.borrow().build, Some(memory_commit_b()));
@@ -1614,6 +2432,7 @@ fn handle_runthrough_next_commit() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_c(),
             memory_commit_d(),
         ))
@@ -1665,16 +2484,26 @@ fn handle_runthrough_2_ci() {
             memory_pr_a(),
             Some(memory_commit_a()),
             "MSG!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_a()));
     assert!(vcs.borrow().master.is_none());
     assert!(ci1.borrow().build.is_none());
@@ -1696,16 +2525,26 @@ fn handle_runthrough_2_ci() {
             memory_pr_c(),
             Some(memory_commit_c()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(db.queue.len(), 1);
     // The first is now done merging. It should now be sent to the CI.
     vcs.borrow_mut().staging = Some(memory_commit_b());
@@ -1717,18 +2556,27 @@ fn handle_runthrough_2_ci() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_a(),
             memory_commit_b(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_b()));
     assert!(vcs.borrow().master.is_none());
     assert_eq!(ci1.borrow().build, Some(memory_commit_b()));
@@ -1758,14 +2606,22 @@ fn handle_runthrough_2_ci() {
             None,
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_b()));
     assert!(vcs.borrow().master.is_none());
     assert_eq!(ui.borrow().results, vec![
@@ -1858,6 +2714,7 @@ fn handle_runthrough_2_ci() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_c(),
             memory_commit_d(),
         ))
@@ -1910,16 +2767,26 @@ fn handle_runthrough_2_ci_first_failed() {
             memory_pr_a(),
             Some(memory_commit_a()),
             "MSG!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_a()));
     assert!(vcs.borrow().master.is_none());
     assert!(ci1.borrow().build.is_none());
@@ -1941,16 +2808,26 @@ fn handle_runthrough_2_ci_first_failed() {
             memory_pr_c(),
             Some(memory_commit_c()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(db.queue.len(), 1);
     // The first is now done merging. It should now be sent to the CI.
     vcs.borrow_mut().staging = Some(memory_commit_b());
@@ -1962,18 +2839,27 @@ fn handle_runthrough_2_ci_first_failed() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_a(),
             memory_commit_b(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_b()));
     assert!(vcs.borrow().master.is_none());
     assert_eq!(ci1.borrow().build, Some(memory_commit_b()));
@@ -2003,14 +2889,22 @@ fn handle_runthrough_2_ci_first_failed() {
             None,
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_c(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_c(),
+            message: "Message!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_c(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "Message!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_c()));
     assert!(vcs.borrow().master.is_none());
     assert_eq!(ui.borrow().results, vec![
@@ -2072,18 +2966,27 @@ fn handle_runthrough_2_ci_first_failed() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_c(),
             memory_commit_d(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_c(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_c(),
+            message: "Message!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_d()),
-        pr: memory_pr_c(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "Message!".to_owned(),
-    }));
+    }]);
     assert_eq!(ci1.borrow().build, Some(memory_commit_d()));
     assert_eq!(ci2.borrow().build, Some(memory_commit_d()));
     assert_eq!(ui.borrow().results, vec![
@@ -2128,16 +3031,26 @@ fn handle_runthrough_2_ci_second_failed() {
             memory_pr_a(),
             Some(memory_commit_a()),
             "MSG!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_a()));
     assert!(vcs.borrow().master.is_none());
     assert!(ci1.borrow().build.is_none());
@@ -2159,16 +3072,26 @@ fn handle_runthrough_2_ci_second_failed() {
             memory_pr_c(),
             Some(memory_commit_c()),
             "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(db.queue.len(), 1);
     // The first is now done merging. It should now be sent to the CI.
     vcs.borrow_mut().staging = Some(memory_commit_b());
@@ -2180,18 +3103,27 @@ fn handle_runthrough_2_ci_second_failed() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_a(),
             memory_commit_b(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_b()));
     assert!(vcs.borrow().master.is_none());
     assert_eq!(ci1.borrow().build, Some(memory_commit_b()));
@@ -2221,14 +3153,22 @@ fn handle_runthrough_2_ci_second_failed() {
             None,
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_a(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_b()),
-        pr: memory_pr_a(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "MSG!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_b()));
     assert!(vcs.borrow().master.is_none());
     assert_eq!(ui.borrow().results, vec![
@@ -2256,14 +3196,22 @@ fn handle_runthrough_2_ci_second_failed() {
             None,
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_c(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_c(),
+            message: "Message!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: None,
-        pr: memory_pr_c(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "Message!".to_owned(),
-    }));
+    }]);
     assert_eq!(vcs.borrow().staging, Some(memory_commit_c()));
     assert!(vcs.borrow().master.is_none());
     assert_eq!(ui.borrow().results, vec![
@@ -2293,18 +3241,27 @@ fn handle_runthrough_2_ci_second_failed() {
         &mut db,
         Event::VcsEvent(vcs::Event::MergedToStaging(
             PipelineId(0),
+            None,
             memory_commit_c(),
             memory_commit_d(),
         ))
     );
-    assert_eq!(db.running, Some(RunningEntry{
-        pull_commit: memory_commit_c(),
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_c(),
+            message: "Message!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
         merge_commit: Some(memory_commit_d()),
-        pr: memory_pr_c(),
+        started_at: None,
+        retries: 0,
         canceled: false,
         built: false,
-        message: "Message!".to_owned(),
-    }));
+    }]);
     assert_eq!(ci1.borrow().build, Some(memory_commit_d()));
     assert_eq!(ci2.borrow().build, Some(memory_commit_d()));
     assert_eq!(ui.borrow().results, vec![
@@ -2328,4 +3285,1750 @@ fn handle_runthrough_2_ci_second_failed() {
             memory_commit_d(),
         )),
     ]);
-}
\ No newline at end of file
+}
+
+#[test]
+fn handle_batch_pop_from_queue() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: true,
+    }]).unwrap();
+    db.push_queue(PipelineId(0), QueueEntry{
+        commit: memory_commit_c(),
+        pr: memory_pr_b(),
+        message: "M!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
+    }).unwrap();
+    db.push_queue(PipelineId(0), QueueEntry{
+        commit: memory_commit_d(),
+        pr: memory_pr_c(),
+        message: "P!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
+    }).unwrap();
+    handle_event_batch(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        2,
+        Event::VcsEvent(vcs::Event::MovedToMaster(
+            PipelineId(0),
+            memory_commit_b(),
+        ))
+    );
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![
+            QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+            QueueEntry{
+                commit: memory_commit_d(),
+                pr: memory_pr_c(),
+                message: "P!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+        ],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]);
+    assert!(db.queue.is_empty());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
+    assert!(vcs.borrow().staging_base.is_none());
+}
+
+#[test]
+fn handle_batch_merge_next_onto_staging() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![
+            QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+            QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+        ],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    handle_event_batch(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        2,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            None,
+            memory_commit_a(),
+            memory_commit_b(),
+        ))
+    );
+    assert!(db.running[0].merge_commit.is_none());
+    assert!(ci.borrow().build.is_none());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
+    assert_eq!(
+        vcs.borrow().staging_base.as_ref().unwrap(),
+        &memory_commit_b()
+    );
+    assert!(ui.borrow().results.is_empty());
+}
+
+#[test]
+fn handle_batch_merge_last_start_ci() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![
+            QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+            QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+        ],
+        staging: 0,
+        base_commit: Some(memory_commit_b()),
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    handle_event_batch(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        2,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            Some(memory_commit_b()),
+            memory_commit_c(),
+            memory_commit_d(),
+        ))
+    );
+    assert_eq!(
+        db.running[0].merge_commit.unwrap(),
+        memory_commit_d()
+    );
+    assert_eq!(ci.borrow().build.as_ref().unwrap(), &memory_commit_d());
+    assert_eq!(
+        ui.borrow().results,
+        vec![
+            (memory_pr_a(), ui::Status::StartingBuild(
+                memory_commit_a(),
+                memory_commit_d(),
+            )),
+            (memory_pr_b(), ui::Status::StartingBuild(
+                memory_commit_c(),
+                memory_commit_d(),
+            )),
+        ]
+    );
+}
+
+#[test]
+fn handle_batch_merge_failed_retest_rest() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![
+            QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+            QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+        ],
+        staging: 0,
+        base_commit: Some(memory_commit_b()),
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    handle_event_batch(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        2,
+        Event::VcsEvent(vcs::Event::FailedMergeToStaging(
+            PipelineId(0),
+            Some(memory_commit_b()),
+            memory_commit_c(),
+        ))
+    );
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]);
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
+    assert!(vcs.borrow().staging_base.is_none());
+    assert_eq!(
+        ui.borrow().results,
+        vec![(memory_pr_b(), ui::Status::Unmergeable(memory_commit_c()))]
+    );
+}
+
+#[test]
+fn handle_batch_ci_succeeded_notify_all() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![
+            QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+            QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+        ],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_b());
+    handle_event_batch(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        2,
+        Event::CiEvent(ci::Event::BuildSucceeded(
+            CiId(1),
+            memory_commit_b(),
+            None,
+        ))
+    );
+    assert!(db.running[0].built);
+    assert_eq!(vcs.borrow().master.as_ref().unwrap(), &memory_commit_b());
+    assert_eq!(
+        ui.borrow().results,
+        vec![
+            (memory_pr_a(), ui::Status::Success(
+                memory_commit_a(),
+                memory_commit_b(),
+                None,
+            )),
+            (memory_pr_b(), ui::Status::Success(
+                memory_commit_c(),
+                memory_commit_b(),
+                None,
+            )),
+        ]
+    );
+}
+
+#[test]
+fn handle_batch_ci_failed_bisect() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![
+            QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+            QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+        ],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_b());
+    handle_event_batch(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        2,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+        ))
+    );
+    assert_eq!(db.running, vec![
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 0,
+            base_commit: None,
+            merge_commit: None,
+            started_at: None,
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 1,
+            base_commit: None,
+            merge_commit: None,
+            started_at: None,
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+    ]);
+    assert!(vcs.borrow().master.is_none());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
+    assert!(ui.borrow().results.is_empty());
+}
+
+#[test]
+fn handle_batch_ci_failed_run_other_half() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 0,
+            base_commit: None,
+            merge_commit: Some(memory_commit_b()),
+            started_at: None,
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 1,
+            base_commit: None,
+            merge_commit: None,
+            started_at: None,
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+    ]).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_b());
+    handle_event_batch(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        2,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+        ))
+    );
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_b(),
+            message: "M!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 1,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]);
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
+    assert_eq!(
+        ui.borrow().results,
+        vec![(memory_pr_a(), ui::Status::Failure(
+            memory_commit_a(),
+            memory_commit_b(),
+            None,
+        ))]
+    );
+}
+
+#[test]
+fn handle_batch_cancel_retest_rest() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![
+            QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+            QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            },
+        ],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    handle_event_batch(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        2,
+        Event::UiEvent(ui::Event::Canceled(
+            PipelineId(0),
+            memory_pr_a()
+        ))
+    );
+    assert!(!db.running[0].canceled);
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
+    // The merge that was already under way is stale, and gets ignored.
+    handle_event_batch(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        2,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            None,
+            memory_commit_a(),
+            memory_commit_b(),
+        ))
+    );
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_b(),
+            message: "M!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]);
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
+    assert!(ci.borrow().build.is_none());
+}
+
+#[test]
+fn handle_depth_stack_next_onto_head() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    handle_event_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        2,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_a(),
+            Some(memory_commit_a()),
+            "MSG!".to_owned(),
+            0,
+            "Approver!".to_owned(),
+        ))
+    );
+    handle_event_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        2,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_b(),
+            Some(memory_commit_c()),
+            "M!".to_owned(),
+            0,
+            "Approver!".to_owned(),
+        ))
+    );
+    // The second one can't be merged until the first one is.
+    assert_eq!(db.running.len(), 2);
+    assert_eq!(db.running[1].staging, 1);
+    assert!(db.queue.is_empty());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
+    handle_event_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        2,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            None,
+            memory_commit_a(),
+            memory_commit_b(),
+        ))
+    );
+    assert_eq!(ci.borrow().build.as_ref().unwrap(), &memory_commit_b());
+    assert_eq!(db.running[1].base_commit, Some(memory_commit_b()));
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
+    assert_eq!(
+        vcs.borrow().staging_base.as_ref().unwrap(),
+        &memory_commit_b()
+    );
+    assert_eq!(vcs.borrow().staging_slot, 1);
+}
+
+#[test]
+fn handle_depth_wait_for_head() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 0,
+            base_commit: None,
+            merge_commit: Some(memory_commit_b()),
+            started_at: None,
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 1,
+            base_commit: Some(memory_commit_b()),
+            merge_commit: Some(memory_commit_d()),
+            started_at: None,
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+    ]).unwrap();
+    // The one behind finishes first. It has to wait.
+    handle_event_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        2,
+        Event::CiEvent(ci::Event::BuildSucceeded(
+            CiId(1),
+            memory_commit_d(),
+            None,
+        ))
+    );
+    assert!(vcs.borrow().master.is_none());
+    assert!(ui.borrow().results.is_empty());
+    handle_event_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        2,
+        Event::CiEvent(ci::Event::BuildSucceeded(
+            CiId(1),
+            memory_commit_b(),
+            None,
+        ))
+    );
+    assert_eq!(vcs.borrow().master.as_ref().unwrap(), &memory_commit_b());
+    // Once the head lands, the one behind it already has its results.
+    handle_event_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        2,
+        Event::VcsEvent(vcs::Event::MovedToMaster(
+            PipelineId(0),
+            memory_commit_b(),
+        ))
+    );
+    assert_eq!(vcs.borrow().master.as_ref().unwrap(), &memory_commit_d());
+    assert_eq!(db.running.len(), 1);
+    assert!(db.running[0].built);
+    assert_eq!(
+        ui.borrow().results,
+        vec![
+            (memory_pr_a(), ui::Status::Success(
+                memory_commit_a(),
+                memory_commit_b(),
+                None,
+            )),
+            (memory_pr_a(), ui::Status::Completed(
+                memory_commit_a(),
+                memory_commit_b(),
+            )),
+            (memory_pr_b(), ui::Status::Success(
+                memory_commit_c(),
+                memory_commit_d(),
+                None,
+            )),
+        ]
+    );
+}
+
+#[test]
+fn handle_depth_head_failed_restack() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 0,
+            base_commit: None,
+            merge_commit: Some(memory_commit_b()),
+            started_at: None,
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 1,
+            base_commit: Some(memory_commit_b()),
+            merge_commit: None,
+            started_at: None,
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+    ]).unwrap();
+    handle_event_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        2,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+        ))
+    );
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_b(),
+            message: "M!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 1,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]);
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
+    assert!(vcs.borrow().staging_base.is_none());
+    // The merge on top of the failed commit is stale, and gets ignored.
+    handle_event_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        2,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            Some(memory_commit_b()),
+            memory_commit_c(),
+            memory_commit_d(),
+        ))
+    );
+    assert!(db.running[0].merge_commit.is_none());
+    assert!(ci.borrow().build.is_none());
+    assert_eq!(
+        ui.borrow().results,
+        vec![(memory_pr_a(), ui::Status::Failure(
+            memory_commit_a(),
+            memory_commit_b(),
+            None,
+        ))]
+    );
+}
+
+#[test]
+fn handle_merge_succeeded_start_timer() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut timer = MemoryTimer::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    handle_event_timeout(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut timer,
+        &mut db,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            None,
+            memory_commit_a(),
+            memory_commit_b(),
+        ))
+    );
+    assert!(db.running[0].started_at.is_some());
+    assert_eq!(ci.borrow().build.as_ref().unwrap(), &memory_commit_b());
+    assert_eq!(
+        timer.borrow().timers,
+        vec![(memory_commit_b(), Duration::from_secs(60))]
+    );
+}
+
+#[test]
+fn handle_ci_timed_out_notify_user_next_commit() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut timer = MemoryTimer::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: Some(UNIX_EPOCH),
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    db.push_queue(PipelineId(0), QueueEntry{
+        commit: memory_commit_c(),
+        pr: memory_pr_b(),
+        message: "M!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
+    }).unwrap();
+    handle_event_timeout(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut timer,
+        &mut db,
+        Event::TimerEvent(timer::Event::TimedOut(
+            PipelineId(0),
+            memory_commit_b(),
+        ))
+    );
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_c(),
+            pr: memory_pr_b(),
+            message: "M!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]);
+    assert!(vcs.borrow().master.is_none());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_c());
+    assert_eq!(
+        ui.borrow().results,
+        vec![(memory_pr_a(), ui::Status::TimedOut(
+            memory_commit_a(),
+            memory_commit_b(),
+        ))]
+    );
+}
+
+#[test]
+fn handle_ci_timed_out_after_built() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut timer = MemoryTimer::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: Some(UNIX_EPOCH),
+        retries: 0,
+        canceled: false,
+        built: true,
+    }]).unwrap();
+    handle_event_timeout(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut timer,
+        &mut db,
+        Event::TimerEvent(timer::Event::TimedOut(
+            PipelineId(0),
+            memory_commit_b(),
+        ))
+    );
+    assert!(db.running[0].built);
+    assert!(ui.borrow().results.is_empty());
+}
+
+#[test]
+fn handle_ci_timed_out_after_reported_behind_head() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut timer = MemoryTimer::new();
+    let mut db = MemoryDb::new();
+    let running = vec![
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_a(),
+                pr: memory_pr_a(),
+                message: "MSG!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 0,
+            base_commit: None,
+            merge_commit: Some(memory_commit_b()),
+            started_at: Some(UNIX_EPOCH),
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+        RunningEntry{
+            entries: vec![QueueEntry{
+                commit: memory_commit_c(),
+                pr: memory_pr_b(),
+                message: "M!".to_owned(),
+                priority: 0,
+                approver: "Approver!".to_owned(),
+            }],
+            staging: 1,
+            base_commit: Some(memory_commit_b()),
+            merge_commit: Some(memory_commit_d()),
+            started_at: Some(UNIX_EPOCH),
+            retries: 0,
+            canceled: false,
+            built: false,
+        },
+    ];
+    db.put_running(PipelineId(0), running.clone()).unwrap();
+    // The one behind the head passes, and waits for the head.
+    handle_event_timeout_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut timer,
+        &mut db,
+        2,
+        Event::CiEvent(ci::Event::BuildSucceeded(
+            CiId(1),
+            memory_commit_d(),
+            None,
+        ))
+    );
+    // Its timer going off while it waits must not throw it away.
+    handle_event_timeout_depth(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut timer,
+        &mut db,
+        2,
+        Event::TimerEvent(timer::Event::TimedOut(
+            PipelineId(0),
+            memory_commit_d(),
+        ))
+    );
+    assert_eq!(db.running, running);
+    assert!(db.failed.is_empty());
+    assert!(ui.borrow().results.is_empty());
+}
+
+#[test]
+fn handle_ci_failed_auto_retry() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    handle_event_auto_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+        ))
+    );
+    assert_eq!(db.running[0].retries, 1);
+    assert_eq!(
+        db.running[0].merge_commit.as_ref().unwrap(),
+        &memory_commit_b()
+    );
+    assert!(db.cis.is_empty());
+    assert!(db.failed.is_empty());
+    assert_eq!(ci.borrow().build.as_ref().unwrap(), &memory_commit_b());
+    assert_eq!(
+        ui.borrow().results,
+        vec![(memory_pr_a(), ui::Status::Retrying(
+            memory_commit_a(),
+            memory_commit_b(),
+            None,
+        ))]
+    );
+    handle_event_auto_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        1,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+        ))
+    );
+    assert!(db.running.is_empty());
+    assert_eq!(db.failed, vec![QueueEntry{
+        commit: memory_commit_a(),
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
+    }]);
+    assert_eq!(
+        ui.borrow().results[1],
+        (memory_pr_a(), ui::Status::Failure(
+            memory_commit_a(),
+            memory_commit_b(),
+            None,
+        ))
+    );
+}
+
+#[test]
+fn handle_retry_failed() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.add_failed(PipelineId(0), QueueEntry{
+        commit: memory_commit_a(),
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        priority: 1,
+        approver: "Approver!".to_owned(),
+    }).unwrap();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Retry(
+            PipelineId(0),
+            memory_pr_a(),
+        ))
+    );
+    assert!(db.failed.is_empty());
+    assert_eq!(db.running, vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 1,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]);
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
+    assert_eq!(
+        ui.borrow().results,
+        vec![(memory_pr_a(), ui::Status::Approved(memory_commit_a()))]
+    );
+}
+
+#[test]
+fn handle_retry_nothing_failed() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Retry(
+            PipelineId(0),
+            memory_pr_a(),
+        ))
+    );
+    assert!(db.running.is_empty());
+    assert!(vcs.borrow().staging.is_none());
+    assert_eq!(
+        ui.borrow().results,
+        vec![(memory_pr_a(), ui::Status::NothingToRetry)]
+    );
+}
+
+#[test]
+fn handle_retry_after_changed() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.add_failed(PipelineId(0), QueueEntry{
+        commit: memory_commit_a(),
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
+    }).unwrap();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Changed(
+            PipelineId(0),
+            memory_pr_a(),
+            memory_commit_c(),
+            "".to_owned(),
+            Url::parse("http://www.com/").unwrap(),
+        ))
+    );
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Retry(
+            PipelineId(0),
+            memory_pr_a(),
+        ))
+    );
+    assert!(db.running.is_empty());
+    assert!(db.failed.is_empty());
+    assert_eq!(
+        ui.borrow().results,
+        vec![(memory_pr_a(), ui::Status::Invalidated)]
+    );
+}
+
+#[test]
+fn handle_tree_closed_skip_low_priority() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.push_queue(PipelineId(0), QueueEntry{
+        commit: memory_commit_a(),
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
+    }).unwrap();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::TreeClosed(PipelineId(0), 1))
+    );
+    assert_eq!(db.tree_closed, Some(1));
+    assert!(db.running.is_empty());
+    assert_eq!(db.queue.len(), 1);
+    assert!(vcs.borrow().staging.is_none());
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_b(),
+            Some(memory_commit_b()),
+            "MSG!".to_owned(),
+            1,
+            "Approver!".to_owned(),
+        ))
+    );
+    assert_eq!(db.running.len(), 1);
+    assert_eq!(db.running[0].entries[0].pr, memory_pr_b());
+    assert_eq!(db.queue.len(), 1);
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_b());
+}
+
+#[test]
+fn handle_tree_opened_start_queued() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.tree_closed = Some(1);
+    db.push_queue(PipelineId(0), QueueEntry{
+        commit: memory_commit_a(),
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
+    }).unwrap();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::TreeOpened(PipelineId(0)))
+    );
+    assert_eq!(db.tree_closed, None);
+    assert!(db.queue.is_empty());
+    assert_eq!(db.running[0].entries[0].pr, memory_pr_a());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
+}
+
+#[test]
+fn handle_move_succeeded_add_history() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: Some(UNIX_EPOCH),
+        retries: 0,
+        canceled: false,
+        built: true,
+    }]).unwrap();
+    db.set_ci_state(
+        CiId(1),
+        CiState::Succeeded,
+        &memory_commit_b(),
+        Some(&Url::parse("http://example.com/").unwrap()),
+    ).unwrap();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::VcsEvent(vcs::Event::MovedToMaster(
+            PipelineId(0),
+            memory_commit_b(),
+        ))
+    );
+    assert_eq!(db.history.len(), 1);
+    let history = &db.history[0];
+    assert_eq!(history.pr, memory_pr_a());
+    assert_eq!(history.pull_commit, memory_commit_a());
+    assert_eq!(history.merge_commit, Some(memory_commit_b()));
+    assert_eq!(history.message, "MSG!");
+    assert_eq!(history.approver, "Approver!");
+    assert_eq!(history.status, HistoryStatus::Completed);
+    assert_eq!(
+        history.ci_urls,
+        vec![Url::parse("http://example.com/").unwrap()]
+    );
+    assert_eq!(history.started_at, Some(UNIX_EPOCH));
+    assert!(db.cis.is_empty());
+}
+
+#[test]
+fn handle_canceled_add_history() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    db.push_queue(PipelineId(0), QueueEntry{
+        commit: memory_commit_c(),
+        pr: memory_pr_b(),
+        message: "M!".to_owned(),
+        priority: 0,
+        approver: "Approver!".to_owned(),
+    }).unwrap();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Canceled(
+            PipelineId(0),
+            memory_pr_b(),
+        ))
+    );
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Canceled(
+            PipelineId(0),
+            memory_pr_a(),
+        ))
+    );
+    assert!(db.running.is_empty());
+    assert!(db.queue.is_empty());
+    let history: Vec<_> = db.history.iter()
+        .map(|h| (h.pr.clone(), h.pull_commit.clone(), h.status))
+        .collect();
+    assert_eq!(history, vec![
+        (memory_pr_b(), memory_commit_c(), HistoryStatus::Canceled),
+        (memory_pr_a(), memory_commit_a(), HistoryStatus::Canceled),
+    ]);
+}
+
+#[test]
+fn recover_merge_to_staging() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    recover(&mut ui, &mut vcs, &mut ci, &mut db);
+    assert_eq!(vcs.borrow().staging, Some(memory_commit_a()));
+    assert_eq!(vcs.borrow().master, None);
+    assert_eq!(ci.borrow().build, None);
+    assert_eq!(db.running[0].merge_commit, None);
+}
+
+#[test]
+fn recover_start_build() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    recover(&mut ui, &mut vcs, &mut ci, &mut db);
+    assert_eq!(vcs.borrow().staging, None);
+    assert_eq!(ci.borrow().build, Some(memory_commit_b()));
+    assert_eq!(db.running[0].merge_commit, Some(memory_commit_b()));
+}
+
+#[test]
+fn recover_start_build_only_unreported() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci1 = MemoryCi::new();
+    let mut ci2 = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    db.set_ci_state(
+        CiId(1),
+        CiState::Succeeded,
+        &memory_commit_b(),
+        None,
+    ).unwrap();
+    recover_2_ci(&mut ui, &mut vcs, &mut ci1, &mut ci2, &mut db);
+    assert_eq!(ci1.borrow().build, None);
+    assert_eq!(ci2.borrow().build, Some(memory_commit_b()));
+    assert_eq!(
+        db.get_ci_state(CiId(1), &memory_commit_b()).unwrap(),
+        Some((CiState::Succeeded, None))
+    );
+    assert_eq!(db.running[0].merge_commit, Some(memory_commit_b()));
+}
+
+#[test]
+fn recover_move_staging_to_master() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: true,
+    }]).unwrap();
+    recover(&mut ui, &mut vcs, &mut ci, &mut db);
+    assert_eq!(vcs.borrow().master, Some(memory_commit_b()));
+    assert_eq!(ci.borrow().build, None);
+    assert!(db.running[0].built);
+}
+
+#[test]
+fn recover_built_without_merge_commit() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: None,
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: true,
+    }]).unwrap();
+    recover(&mut ui, &mut vcs, &mut ci, &mut db);
+    assert_eq!(vcs.borrow().staging, Some(memory_commit_a()));
+    assert_eq!(vcs.borrow().master, None);
+    assert!(!db.running[0].built);
+}
+
+#[test]
+fn handle_approved_queue_position() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    for &(ref pr, ref commit) in &[
+        (memory_pr_a(), memory_commit_a()),
+        (memory_pr_b(), memory_commit_b()),
+        (memory_pr_c(), memory_commit_c()),
+    ] {
+        handle_event(
+            &mut ui,
+            &mut vcs,
+            &mut ci,
+            &mut db,
+            Event::UiEvent(ui::Event::Approved(
+                PipelineId(0),
+                pr.clone(),
+                Some(commit.clone()),
+                "Message!".to_owned(),
+                0,
+                "Approver!".to_owned(),
+            ))
+        );
+    }
+    // The first one went straight from the queue to staging.
+    assert_eq!(
+        ui.borrow().details,
+        vec![
+            (memory_pr_a(), ui::Details{
+                queue_position: Some(1),
+                ci_urls: vec![],
+            }),
+            (memory_pr_b(), ui::Details{
+                queue_position: Some(1),
+                ci_urls: vec![],
+            }),
+            (memory_pr_c(), ui::Details{
+                queue_position: Some(2),
+                ci_urls: vec![],
+            }),
+        ]
+    );
+}
+
+#[test]
+fn handle_ci_failed_details() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), vec![RunningEntry{
+        entries: vec![QueueEntry{
+            commit: memory_commit_a(),
+            pr: memory_pr_a(),
+            message: "MSG!".to_owned(),
+            priority: 0,
+            approver: "Approver!".to_owned(),
+        }],
+        staging: 0,
+        base_commit: None,
+        merge_commit: Some(memory_commit_b()),
+        started_at: None,
+        retries: 0,
+        canceled: false,
+        built: false,
+    }]).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_b());
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            Some(Url::parse("http://example.com/").unwrap()),
+        ))
+    );
+    assert!(db.cis.is_empty());
+    assert_eq!(
+        ui.borrow().details,
+        vec![(memory_pr_a(), ui::Details{
+            queue_position: None,
+            ci_urls: vec![
+                (CiId(1), Url::parse("http://example.com/").unwrap()),
+            ],
+        })]
+    );
+}
+#[test]
+fn handle_statuses_go_to_outbox() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    let outbox = Outbox::new(
+        vec![
+            Url::parse("http://a.example.com/").unwrap(),
+            Url::parse("http://b.example.com/").unwrap(),
+        ],
+        Box::new(MemoryHooksPipelines),
+    );
+    handle_event_outbox(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        &outbox,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_a(),
+            Some(memory_commit_a()),
+            "Message!".to_owned(),
+            0,
+            "Approver!".to_owned(),
+        )),
+    );
+    handle_event_outbox(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        &outbox,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            None,
+            memory_commit_a(),
+            memory_commit_b(),
+        )),
+    );
+    // Every status the UI got is queued once for each URL, in order.
+    assert_eq!(ui.borrow().results.len(), 2);
+    let states: Vec<(String, String)> = db.outbox.iter()
+        .map(|&(_, ref entry)| {
+            let body: Value = serde_json::from_str(&entry.body).unwrap();
+            (
+                entry.url.as_str().to_owned(),
+                body.find("state").and_then(Value::as_str).unwrap().to_owned(),
+            )
+        })
+        .collect();
+    assert_eq!(states, vec![
+        ("http://a.example.com/".to_owned(), "approved".to_owned()),
+        ("http://b.example.com/".to_owned(), "approved".to_owned()),
+        ("http://a.example.com/".to_owned(), "starting_build".to_owned()),
+        ("http://b.example.com/".to_owned(), "starting_build".to_owned()),
+    ]);
+    assert!(db.outbox.iter().all(|&(_, ref entry)| entry.attempts == 0));
+}
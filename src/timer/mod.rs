@@ -0,0 +1,76 @@
+// This file is released under the same terms as Rust itself.
+
+/*! Timeouts for builds that the CI never reports back on.
+ */
+
+use config::PipelinesConfig;
+use pipeline::{self, GetPipelineId, PipelineId};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use vcs::Commit;
+
+#[derive(Clone, Debug)]
+pub enum Message {
+    /// Report back if the merge commit is still around after the duration.
+    StartTimer(PipelineId, Commit, Duration),
+}
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    TimedOut(PipelineId, Commit),
+}
+
+impl GetPipelineId for Event {
+    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, _: &C) -> PipelineId {
+        match *self {
+            Event::TimedOut(i, _) => i,
+        }
+    }
+}
+
+pub struct Worker;
+
+impl Worker {
+    pub fn new() -> Self {
+        Worker
+    }
+}
+
+impl pipeline::Worker<Event, Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<Message>,
+        send_event: Sender<Event>,
+    ) {
+        let mut timers: Vec<(Instant, PipelineId, Commit)> = Vec::new();
+        loop {
+            let now = Instant::now();
+            let (expired, pending): (Vec<_>, Vec<_>) = timers.into_iter()
+                .partition(|&(deadline, _, _)| deadline <= now);
+            timers = pending;
+            for (_, pipeline_id, commit) in expired {
+                send_event.send(Event::TimedOut(pipeline_id, commit))
+                    .expect("Pipeline gone timeout");
+            }
+            let next = timers.iter().map(|&(deadline, _, _)| deadline).min();
+            let msg = if let Some(next) = next {
+                match recv_msg.recv_timeout(next - now) {
+                    Ok(msg) => msg,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            } else {
+                match recv_msg.recv() {
+                    Ok(msg) => msg,
+                    Err(_) => return,
+                }
+            };
+            match msg {
+                Message::StartTimer(pipeline_id, commit, timeout) => {
+                    let deadline = Instant::now() + timeout;
+                    timers.push((deadline, pipeline_id, commit));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,595 @@
+// This file is released under the same terms as Rust itself.
+
+/*! A front-end for scripts and internal tools, instead of a code host.
+
+    Every command is a signed JSON `POST` to one of `/open`, `/change`,
+    `/close`, `/approve` and `/cancel`. Pull requests are named by whatever
+    id the caller likes, but `vcs::git` fetches that id as-is, so with
+    plain git hosting it should be the ref the change is on, like
+    `refs/heads/my-topic`. Statuses are posted back to the callback URL,
+    if there is one, signed with the same secret.
+ */
+
+use crossbeam;
+use hyper;
+use hyper::Url;
+use hyper::buffer::BufReader;
+use hyper::client::Client;
+use hyper::header::{ContentType, Headers, UserAgent};
+use hyper::method::Method;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use pipeline::{self, PipelineId};
+use serde_json::{self, from_slice as json_from_slice};
+use std::io::BufWriter;
+use std::iter;
+use std::sync::mpsc::{Sender, Receiver};
+use ui::{self, Pr};
+use util::USER_AGENT;
+use util::api_headers;
+use vcs::Commit;
+
+/// The pull request id is the ref to fetch.
+pub const REMOTE_FORMAT: ui::RemoteFormat = ui::RemoteFormat("{}");
+
+#[derive(Clone, Debug)]
+pub struct RepoPipelines {
+    pub pipeline_id: PipelineId,
+    pub try_pipeline_id: Option<PipelineId>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PipelineType {
+    Stage,
+    Try,
+}
+
+pub trait ProjectsConfig: Send + Sync + 'static {
+    fn pipelines_by_project(&self, &str) -> Option<RepoPipelines>;
+    fn project_by_pipeline(&self, PipelineId)
+        -> Option<(String, PipelineType)>;
+}
+
+pub struct Worker {
+    listen: String,
+    projects: Box<ProjectsConfig>,
+    secrets: Vec<String>,
+    callback: Option<Url>,
+    client: Client,
+}
+
+impl Worker {
+    pub fn new(
+        listen: String,
+        secrets: Vec<String>,
+        callback: Option<Url>,
+        projects: Box<ProjectsConfig>,
+    ) -> Worker {
+        Worker {
+            listen: listen,
+            projects: projects,
+            secrets: secrets,
+            callback: callback,
+            client: Client::new(),
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct OpenDesc {
+    project: String,
+    pr: String,
+    commit: String,
+    title: String,
+    url: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CloseDesc {
+    project: String,
+    pr: String,
+}
+#[derive(Deserialize, Serialize)]
+struct ApproveDesc {
+    project: String,
+    pr: String,
+    commit: Option<String>,
+    message: Option<String>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default, rename="try")]
+    is_try: bool,
+    #[serde(default)]
+    approver: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CancelDesc {
+    project: String,
+    pr: String,
+    #[serde(default, rename="try")]
+    is_try: bool,
+}
+#[derive(Deserialize, Serialize)]
+struct StatusDesc {
+    project: String,
+    #[serde(rename="try")]
+    is_try: bool,
+    pr: String,
+    status: String,
+    pull_commit: Option<String>,
+    merge_commit: Option<String>,
+    url: Option<String>,
+    queue_position: Option<usize>,
+}
+
+impl pipeline::Worker<ui::Event, ui::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<ui::Message>,
+        mut send_event: Sender<ui::Event>
+    ) {
+        crossbeam::scope(|scope| {
+            let s2 = &*self;
+            let send_event_2 = send_event.clone();
+            scope.spawn(move || {
+                s2.run_webhook(send_event_2);
+            });
+            loop {
+                s2.handle_message(
+                    recv_msg.recv().expect("Pipeline went away"),
+                    &mut send_event,
+                );
+            }
+        })
+    }
+}
+
+impl Worker {
+    fn run_webhook(
+        &self,
+        send_event: Sender<ui::Event>,
+    ) {
+        let mut listener = HttpListener::new(&self.listen[..])
+            .expect("webhook");
+        while let Ok(mut stream) = listener.accept() {
+            let addr = stream.peer_addr()
+                .expect("webhook client address");
+            let mut stream_clone = stream.clone();
+            let mut buf_read = BufReader::new(
+                &mut stream_clone as &mut NetworkStream
+            );
+            let mut buf_write = BufWriter::new(&mut stream);
+            let req = match Request::new(&mut buf_read, addr) {
+                Ok(req) => req,
+                Err(e) => {
+                    warn!("Invalid webhook HTTP: {:?}", e);
+                    continue;
+                }
+            };
+            let mut head = Headers::new();
+            let res = Response::new(&mut buf_write, &mut head);
+            self.handle_webhook(req, res, &send_event);
+        }
+    }
+
+    fn handle_webhook(
+        &self,
+        mut req: Request,
+        mut res: Response,
+        send_event: &Sender<ui::Event>
+    ) {
+        let path = match req.uri {
+            RequestUri::AbsolutePath(ref path) => path.clone(),
+            _ => String::new(),
+        };
+        let status = if req.method != Method::Post {
+            StatusCode::MethodNotAllowed
+        } else {
+            match api_headers::parse(&mut req, &self.secrets) {
+                Some(body) => self.handle_command(send_event, &path, &body),
+                None => StatusCode::Forbidden,
+            }
+        };
+        *res.status_mut() = status;
+        if let Err(e) = res.send(&[]) {
+            warn!("Failed to send response to command {}: {:?}", path, e);
+        }
+    }
+
+    fn handle_command(
+        &self,
+        send_event: &Sender<ui::Event>,
+        path: &str,
+        body: &[u8],
+    ) -> StatusCode {
+        let events = match path {
+            "/open" | "/change" => {
+                let desc: OpenDesc = match json_from_slice(body) {
+                    Ok(desc) => desc,
+                    Err(e) => {
+                        warn!("Got invalid {} command: {:?}", path, e);
+                        return StatusCode::BadRequest;
+                    }
+                };
+                let url = match Url::parse(&desc.url) {
+                    Ok(url) => url,
+                    Err(e) => {
+                        warn!("Got bad PR URL {}: {:?}", desc.url, e);
+                        return StatusCode::BadRequest;
+                    }
+                };
+                let repo_pipelines =
+                    match self.projects.pipelines_by_project(&desc.project) {
+                        Some(repo_pipelines) => repo_pipelines,
+                        None => return StatusCode::NotFound,
+                    };
+                let pr = Pr::from(desc.pr);
+                let commit = Commit::from(desc.commit);
+                let title = desc.title;
+                iter::once(repo_pipelines.pipeline_id)
+                    .chain(repo_pipelines.try_pipeline_id)
+                    .map(|pipeline_id| if path == "/open" {
+                        ui::Event::Opened(
+                            pipeline_id,
+                            pr.clone(),
+                            commit.clone(),
+                            title.clone(),
+                            url.clone(),
+                        )
+                    } else {
+                        ui::Event::Changed(
+                            pipeline_id,
+                            pr.clone(),
+                            commit.clone(),
+                            title.clone(),
+                            url.clone(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            }
+            "/close" => {
+                let desc: CloseDesc = match json_from_slice(body) {
+                    Ok(desc) => desc,
+                    Err(e) => {
+                        warn!("Got invalid close command: {:?}", e);
+                        return StatusCode::BadRequest;
+                    }
+                };
+                let repo_pipelines =
+                    match self.projects.pipelines_by_project(&desc.project) {
+                        Some(repo_pipelines) => repo_pipelines,
+                        None => return StatusCode::NotFound,
+                    };
+                let pr = Pr::from(desc.pr);
+                iter::once(repo_pipelines.pipeline_id)
+                    .chain(repo_pipelines.try_pipeline_id)
+                    .map(|pipeline_id| ui::Event::Closed(
+                        pipeline_id,
+                        pr.clone(),
+                    ))
+                    .collect::<Vec<_>>()
+            }
+            "/approve" => {
+                let desc: ApproveDesc = match json_from_slice(body) {
+                    Ok(desc) => desc,
+                    Err(e) => {
+                        warn!("Got invalid approve command: {:?}", e);
+                        return StatusCode::BadRequest;
+                    }
+                };
+                let pipeline_id = match self.pipeline_id(
+                    &desc.project,
+                    desc.is_try,
+                ) {
+                    Some(pipeline_id) => pipeline_id,
+                    None => return StatusCode::NotFound,
+                };
+                let pr = desc.pr;
+                let message = desc.message
+                    .unwrap_or_else(|| format!("Merge {}", pr));
+                vec![ui::Event::Approved(
+                    pipeline_id,
+                    Pr::from(pr),
+                    desc.commit.map(Commit::from),
+                    message,
+                    if desc.is_try { 0 } else { desc.priority },
+                    desc.approver,
+                )]
+            }
+            "/cancel" => {
+                let desc: CancelDesc = match json_from_slice(body) {
+                    Ok(desc) => desc,
+                    Err(e) => {
+                        warn!("Got invalid cancel command: {:?}", e);
+                        return StatusCode::BadRequest;
+                    }
+                };
+                let pipeline_id = match self.pipeline_id(
+                    &desc.project,
+                    desc.is_try,
+                ) {
+                    Some(pipeline_id) => pipeline_id,
+                    None => return StatusCode::NotFound,
+                };
+                vec![ui::Event::Canceled(pipeline_id, Pr::from(desc.pr))]
+            }
+            _ => {
+                warn!("Got unknown command {}", path);
+                return StatusCode::NotFound;
+            }
+        };
+        info!("Got {} command", path);
+        for event in events {
+            send_event.send(event).expect("Pipeline to be there");
+        }
+        StatusCode::NoContent
+    }
+
+    fn pipeline_id(&self, project: &str, is_try: bool) -> Option<PipelineId> {
+        self.projects.pipelines_by_project(project)
+            .and_then(|repo_pipelines| if is_try {
+                repo_pipelines.try_pipeline_id
+            } else {
+                Some(repo_pipelines.pipeline_id)
+            })
+    }
+
+    fn handle_message(
+        &self,
+        msg: ui::Message,
+        _: &mut Sender<ui::Event>,
+    ) {
+        match msg {
+            ui::Message::SendResult(pipeline_id, pr, status, details) => {
+                let result = self.send_result_to_callback(
+                    pipeline_id,
+                    &pr,
+                    &status,
+                    &details,
+                );
+                if let Err(e) = result {
+                    warn!("Failed to send {:?} to PR {}: {:?}", status, pr, e)
+                }
+            }
+        }
+    }
+
+    fn send_result_to_callback(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: &ui::Status,
+        details: &ui::Details,
+    ) -> Result<(), ApiCallbackError> {
+        let callback = match self.callback {
+            Some(ref callback) => callback,
+            None => return Ok(()),
+        };
+        let (project, pipeline_type) =
+            match self.projects.project_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => return Err(ApiCallbackError::Pipeline(pipeline_id)),
+            };
+        let (name, pull_commit, merge_commit, url) = status_parts(status);
+        let body = try!(serde_json::to_string(&StatusDesc {
+            project: project,
+            is_try: match pipeline_type {
+                PipelineType::Stage => false,
+                PipelineType::Try => true,
+            },
+            pr: pr.to_string(),
+            status: name.to_owned(),
+            pull_commit: pull_commit.map(ToString::to_string),
+            merge_commit: merge_commit.map(ToString::to_string),
+            url: url.map(ToString::to_string),
+            queue_position: details.queue_position,
+        }));
+        let mut headers = Headers::new();
+        headers.set(ContentType::json());
+        headers.set(UserAgent(USER_AGENT.to_owned()));
+        // The last secret is the newest one.
+        if let Some(secret) = self.secrets.last() {
+            api_headers::sign(&mut headers, secret, body.as_bytes());
+        }
+        let resp = try!(
+            self.client.post(callback.clone())
+                .headers(headers)
+                .body(&body[..])
+                .send()
+        );
+        if !resp.status.is_success() {
+            return Err(ApiCallbackError::HttpStatus(resp.status));
+        }
+        Ok(())
+    }
+}
+
+/// The name a status goes by in callbacks and outbound webhooks,
+/// along with the commits and build it is about.
+pub fn status_parts(
+    status: &ui::Status,
+) -> (&'static str, Option<&Commit>, Option<&Commit>, Option<&Url>) {
+    match *status {
+        ui::Status::Approved(ref pull) =>
+            ("approved", Some(pull), None, None),
+        ui::Status::Invalidated =>
+            ("invalidated", None, None, None),
+        ui::Status::NoCommit =>
+            ("no_commit", None, None, None),
+        ui::Status::NothingToRetry =>
+            ("nothing_to_retry", None, None, None),
+        ui::Status::Unmergeable(ref pull) =>
+            ("unmergeable", Some(pull), None, None),
+        ui::Status::StartingBuild(ref pull, ref merge) =>
+            ("starting_build", Some(pull), Some(merge), None),
+        ui::Status::Testing(ref pull, ref merge, ref url) =>
+            ("testing", Some(pull), Some(merge), url.as_ref()),
+        ui::Status::Success(ref pull, ref merge, ref url) =>
+            ("success", Some(pull), Some(merge), url.as_ref()),
+        ui::Status::Failure(ref pull, ref merge, ref url) =>
+            ("failure", Some(pull), Some(merge), url.as_ref()),
+        ui::Status::Retrying(ref pull, ref merge, ref url) =>
+            ("retrying", Some(pull), Some(merge), url.as_ref()),
+        ui::Status::TimedOut(ref pull, ref merge) =>
+            ("timed_out", Some(pull), Some(merge), None),
+        ui::Status::Unmoveable(ref pull, ref merge) =>
+            ("unmoveable", Some(pull), Some(merge), None),
+        ui::Status::Completed(ref pull, ref merge) =>
+            ("completed", Some(pull), Some(merge), None),
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ApiCallbackError {
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// JSON error
+        Json(err: serde_json::error::Error) {
+            cause(err)
+            from()
+        }
+        /// Project not found for pipeline
+        Pipeline(pipeline_id: PipelineId) {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::status::StatusCode;
+    use pipeline::PipelineId;
+    use std::sync::mpsc::channel;
+    use super::{PipelineType, ProjectsConfig, RepoPipelines, Worker};
+    use super::status_parts;
+    use ui::{Event, Pr, Status};
+    use vcs::Commit;
+
+    struct OneProject;
+
+    impl ProjectsConfig for OneProject {
+        fn pipelines_by_project(&self, project: &str)
+                -> Option<RepoPipelines> {
+            if project == "testp" {
+                Some(RepoPipelines {
+                    pipeline_id: PipelineId(0),
+                    try_pipeline_id: Some(PipelineId(1)),
+                })
+            } else {
+                None
+            }
+        }
+        fn project_by_pipeline(&self, _: PipelineId)
+                -> Option<(String, PipelineType)> {
+            None
+        }
+    }
+
+    fn worker() -> Worker {
+        Worker::new(
+            "localhost:0".to_owned(),
+            vec!["secret".to_owned()],
+            None,
+            Box::new(OneProject),
+        )
+    }
+
+    #[test]
+    fn test_open_goes_to_both_pipelines() {
+        let (send_event, recv_event) = channel();
+        let status = worker().handle_command(
+            &send_event,
+            "/open",
+            br#"{"project":"testp","pr":"refs/heads/topic","commit":"a",
+                 "title":"T","url":"http://example.com/topic"}"#,
+        );
+        assert_eq!(status, StatusCode::NoContent);
+        for expected in &[PipelineId(0), PipelineId(1)] {
+            match recv_event.try_recv() {
+                Ok(Event::Opened(pipeline_id, pr, commit, title, _)) => {
+                    assert_eq!(pipeline_id, *expected);
+                    assert_eq!(pr, Pr::from("refs/heads/topic".to_owned()));
+                    assert_eq!(commit, Commit::from("a".to_owned()));
+                    assert_eq!(title, "T");
+                }
+                e => panic!("Unexpected event {:?}", e),
+            }
+        }
+        assert!(recv_event.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_try_approve() {
+        let (send_event, recv_event) = channel();
+        let status = worker().handle_command(
+            &send_event,
+            "/approve",
+            br#"{"project":"testp","pr":"1","try":true,"priority":5,
+                 "approver":"luser"}"#,
+        );
+        assert_eq!(status, StatusCode::NoContent);
+        match recv_event.try_recv() {
+            Ok(Event::Approved(
+                pipeline_id,
+                pr,
+                None,
+                message,
+                0,
+                approver,
+            )) => {
+                assert_eq!(pipeline_id, PipelineId(1));
+                assert_eq!(pr, Pr::from("1".to_owned()));
+                assert_eq!(message, "Merge 1");
+                assert_eq!(approver, "luser");
+            }
+            e => panic!("Unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_bad_commands() {
+        let (send_event, recv_event) = channel();
+        let worker = worker();
+        assert_eq!(
+            worker.handle_command(
+                &send_event,
+                "/close",
+                br#"{"project":"nope","pr":"1"}"#,
+            ),
+            StatusCode::NotFound
+        );
+        assert_eq!(
+            worker.handle_command(&send_event, "/close", br#"{"pr":"1"}"#),
+            StatusCode::BadRequest
+        );
+        assert_eq!(
+            worker.handle_command(&send_event, "/merge", b"{}"),
+            StatusCode::NotFound
+        );
+        assert!(recv_event.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_status_parts() {
+        let pull = Commit::from("a".to_owned());
+        let merge = Commit::from("b".to_owned());
+        assert_eq!(
+            status_parts(&Status::Approved(pull.clone())),
+            ("approved", Some(&pull), None, None)
+        );
+        assert_eq!(
+            status_parts(&Status::TimedOut(pull.clone(), merge.clone())),
+            ("timed_out", Some(&pull), Some(&merge), None)
+        );
+        assert_eq!(
+            status_parts(&Status::Invalidated),
+            ("invalidated", None, None, None)
+        );
+    }
+}
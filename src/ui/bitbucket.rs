@@ -0,0 +1,609 @@
+// This file is released under the same terms as Rust itself.
+
+/*! The Bitbucket Server front-end.
+
+    Repositories are named by project key and slug, instead of by owner
+    and name, but this front-end still calls them the owner and the repo.
+    Only users with write access to the repository may give commands.
+ */
+
+use crossbeam;
+use hyper::Url;
+use hyper::buffer::BufReader;
+use hyper::header::Headers;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use serde_json::{from_reader as json_from_reader};
+use serde_json::{from_slice as json_from_slice};
+use std::io::BufWriter;
+use std::iter;
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, Receiver};
+use ui::{self, comments, github, Pr};
+use util::bitbucket_api::{self, BitbucketRequestError, Client};
+use util::bitbucket_headers;
+use vcs::Commit;
+
+/// Bitbucket Server keeps the head of every pull request in its own ref.
+pub const REMOTE_FORMAT: ui::RemoteFormat =
+    ui::RemoteFormat("refs/pull-requests/{}/from");
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    /// The project key, like `PROJ`.
+    pub owner: String,
+    /// The repository slug.
+    pub repo: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct RepoPipelines {
+    pub pipeline_id: PipelineId,
+    pub try_pipeline_id: Option<PipelineId>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PipelineType {
+    Stage,
+    Try,
+}
+
+pub trait ProjectsConfig: Send + Sync + 'static {
+    /// The pipelines for pull requests into the given base branch.
+    fn pipelines_by_repo(&self, &Repo, &str) -> Option<RepoPipelines>;
+    fn repo_by_pipeline(&self, PipelineId) -> Option<(Repo, PipelineType)>;
+}
+
+pub struct Worker {
+    listen: String,
+    projects: Box<ProjectsConfig>,
+    client: Arc<Client>,
+    user_ident: String,
+    secrets: Vec<String>,
+}
+
+impl Worker {
+    pub fn new(
+        listen: String,
+        client: Arc<Client>,
+        user: String,
+        secrets: Vec<String>,
+        projects: Box<ProjectsConfig>,
+    ) -> Worker {
+        Worker {
+            listen: listen,
+            projects: projects,
+            client: client,
+            user_ident: format!("@{}", user),
+            secrets: secrets,
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct ProjectDesc {
+    key: String,
+}
+#[derive(Deserialize, Serialize)]
+struct RepositoryDesc {
+    slug: String,
+    project: ProjectDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct UserDesc {
+    name: String,
+}
+#[derive(Deserialize, Serialize)]
+struct ParticipantDesc {
+    user: UserDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct FromRefDesc {
+    #[serde(rename="latestCommit")]
+    latest_commit: String,
+}
+#[derive(Deserialize, Serialize)]
+struct ToRefDesc {
+    #[serde(rename="displayId")]
+    display_id: String,
+    repository: RepositoryDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct LinkDesc {
+    href: String,
+}
+#[derive(Deserialize, Serialize)]
+struct LinksDesc {
+    #[serde(rename="self")]
+    self_links: Vec<LinkDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct PrDesc {
+    id: u32,
+    title: String,
+    description: Option<String>,
+    state: String,
+    author: ParticipantDesc,
+    #[serde(rename="fromRef")]
+    from_ref: FromRefDesc,
+    #[serde(rename="toRef")]
+    to_ref: ToRefDesc,
+    links: LinksDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct PullRequestDesc {
+    #[serde(rename="pullRequest")]
+    pull_request: PrDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct CommentCommentDesc {
+    text: String,
+    author: UserDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct CommentDesc {
+    #[serde(rename="pullRequest")]
+    pull_request: PrDesc,
+    comment: CommentCommentDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct UsersDesc {
+    values: Vec<UserDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct PostCommentComment {
+    text: String,
+}
+#[derive(Deserialize, Serialize)]
+struct BuildStatusDesc {
+    state: String,
+    key: String,
+    name: String,
+    url: String,
+    description: String,
+}
+
+impl pipeline::Worker<ui::Event, ui::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<ui::Message>,
+        mut send_event: Sender<ui::Event>
+    ) {
+        crossbeam::scope(|scope| {
+            let s2 = &*self;
+            let send_event_2 = send_event.clone();
+            scope.spawn(move || {
+                s2.run_webhook(send_event_2);
+            });
+            loop {
+                s2.handle_message(
+                    recv_msg.recv().expect("Pipeline went away"),
+                    &mut send_event,
+                );
+            }
+        })
+    }
+}
+
+impl Worker {
+    fn run_webhook(
+        &self,
+        send_event: Sender<ui::Event>,
+    ) {
+        let mut listener = HttpListener::new(&self.listen[..])
+            .expect("webhook");
+        while let Ok(mut stream) = listener.accept() {
+            let addr = stream.peer_addr()
+                .expect("webhook client address");
+            let mut stream_clone = stream.clone();
+            let mut buf_read = BufReader::new(
+                &mut stream_clone as &mut NetworkStream
+            );
+            let mut buf_write = BufWriter::new(&mut stream);
+            let req = match Request::new(&mut buf_read, addr) {
+                Ok(req) => req,
+                Err(e) => {
+                    warn!("Invalid webhook HTTP: {:?}", e);
+                    continue;
+                }
+            };
+            let mut head = Headers::new();
+            let res = Response::new(&mut buf_write, &mut head);
+            self.handle_webhook(req, res, &send_event);
+        }
+    }
+
+    fn handle_webhook(
+        &self,
+        mut req: Request,
+        mut res: Response,
+        send_event: &Sender<ui::Event>
+    ) {
+        let head = bitbucket_headers::parse(&mut req, &self.secrets);
+        let (x_event_key, body) = match head {
+            Some(head) => head,
+            None => return,
+        };
+        match &x_event_key[..] {
+            b"diagnostics:ping" => {
+                *res.status_mut() = StatusCode::NoContent;
+                if let Err(e) = res.send(&[]) {
+                    warn!("Failed to send response to ping: {:?}", e);
+                }
+            }
+            b"pr:opened" |
+            b"pr:from_ref_updated" |
+            b"pr:merged" |
+            b"pr:declined" |
+            b"pr:deleted" => {
+                if let Ok(desc) = json_from_slice::<PullRequestDesc>(&body) {
+                    let action = String::from_utf8_lossy(&x_event_key);
+                    info!(
+                        "Got PR message for #{}: {}",
+                        desc.pull_request.id,
+                        action,
+                    );
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to Bitbucket PR: {:?}",
+                            e,
+                        );
+                    }
+                    self.handle_pr(send_event, &action, desc);
+                } else {
+                    warn!("Got invalid PR message");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!("Failed to send response to bad PR: {:?}", e);
+                    }
+                }
+            }
+            b"pr:comment:added" => {
+                if let Ok(desc) = json_from_slice::<CommentDesc>(&body) {
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to Bitbucket comment: \
+                             {:?}",
+                            e,
+                        );
+                    }
+                    if !desc.comment.text.contains(&self.user_ident) {
+                        info!("Comment does not mention me; do nothing");
+                    } else if desc.pull_request.state != "OPEN" {
+                        info!("Comment is for closed PR; do nothing");
+                    } else {
+                        info!("Got pull request comment");
+                        self.handle_pr_comment(send_event, desc);
+                    }
+                } else {
+                    warn!("Got invalid comment");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad comment: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
+            e => {
+                *res.status_mut() = StatusCode::BadRequest;
+                if let Err(e) = res.send(&[]) {
+                    warn!(
+                        "Failed to send response to Bitbucket unknown: {:?}",
+                        e,
+                    );
+                }
+                warn!(
+                    "Got Unknown Event {}",
+                    String::from_utf8_lossy(&e)
+                );
+            }
+        }
+    }
+
+    fn handle_pr(
+        &self,
+        send_event: &Sender<ui::Event>,
+        action: &str,
+        desc: PullRequestDesc,
+    ) {
+        let pr_desc = desc.pull_request;
+        let repo = repo_of(&pr_desc);
+        let repo_pipelines = match self.projects.pipelines_by_repo(
+            &repo,
+            &pr_desc.to_ref.display_id,
+        ) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                warn!(
+                    "Got bad repo {:?} branch {}",
+                    repo,
+                    pr_desc.to_ref.display_id,
+                );
+                return;
+            }
+        };
+        let pr = Pr::from(pr_desc.id.to_string());
+        let commit = Commit::from(pr_desc.from_ref.latest_commit.clone());
+        let url = match self.pr_url(&pr_desc) {
+            Some(url) => url,
+            None => return,
+        };
+        let pipeline_ids = iter::once(repo_pipelines.pipeline_id)
+            .chain(repo_pipelines.try_pipeline_id);
+        for pipeline_id in pipeline_ids {
+            let event = match action {
+                "pr:merged" | "pr:declined" | "pr:deleted" =>
+                    Some(ui::Event::Closed(
+                        pipeline_id,
+                        pr.clone(),
+                    )),
+                "pr:opened" => Some(ui::Event::Opened(
+                    pipeline_id,
+                    pr.clone(),
+                    commit.clone(),
+                    pr_desc.title.clone(),
+                    url.clone(),
+                )),
+                "pr:from_ref_updated" => Some(ui::Event::Changed(
+                    pipeline_id,
+                    pr.clone(),
+                    commit.clone(),
+                    pr_desc.title.clone(),
+                    url.clone(),
+                )),
+                _ => None,
+            };
+            if let Some(event) = event {
+                send_event.send(event).expect("Pipeline to be there");
+            }
+        }
+    }
+
+    fn handle_pr_comment(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: CommentDesc,
+    ) {
+        let repo = repo_of(&desc.pull_request);
+        let user = &desc.comment.author.name;
+        let command = match comments::parse(&desc.comment.text, user) {
+            Some(command) => command,
+            None => {
+                info!("Pull request comment is not a command");
+                return;
+            }
+        };
+        let allowed = self.user_has_write(user, &repo)
+            .unwrap_or_else(|e| {
+                warn!("Failed to check if {} has permission: {:?}", user, e);
+                false
+            });
+        if !allowed {
+            info!("Got mentioned by not-permitted user");
+            return;
+        }
+        let pr_desc = &desc.pull_request;
+        let repo_pipelines = match self.projects.pipelines_by_repo(
+            &repo,
+            &pr_desc.to_ref.display_id,
+        ) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                warn!(
+                    "Got bad repo {:?} branch {}",
+                    repo,
+                    pr_desc.to_ref.display_id,
+                );
+                return;
+            }
+        };
+        let pr = Pr::from(pr_desc.id.to_string());
+        let (pipeline_id, user, commit, priority) = match command {
+            comments::Command::Approved(user, commit, priority) =>
+                (repo_pipelines.pipeline_id, user, commit, priority),
+            comments::Command::TryApproved(user, commit) => {
+                match repo_pipelines.try_pipeline_id {
+                    Some(try_pipeline_id) => (try_pipeline_id, user, commit, 0),
+                    None => return,
+                }
+            }
+            comments::Command::Canceled => {
+                send_event.send(ui::Event::Canceled(
+                    repo_pipelines.pipeline_id,
+                    pr,
+                )).expect("PR Canceled: Pipeline error");
+                return;
+            }
+            comments::Command::TryCanceled => {
+                if let Some(try_pipeline_id) = repo_pipelines.try_pipeline_id {
+                    send_event.send(ui::Event::Canceled(
+                        try_pipeline_id,
+                        pr,
+                    )).expect("PR Canceled: Pipeline error");
+                }
+                return;
+            }
+            comments::Command::Retry => {
+                send_event.send(ui::Event::Retry(
+                    repo_pipelines.pipeline_id,
+                    pr,
+                )).expect("PR Retry: Pipeline error");
+                return;
+            }
+            comments::Command::TreeClosed(priority) => {
+                send_event.send(ui::Event::TreeClosed(
+                    repo_pipelines.pipeline_id,
+                    priority,
+                )).expect("Tree closed: Pipeline error");
+                return;
+            }
+            comments::Command::TreeOpened => {
+                send_event.send(ui::Event::TreeOpened(
+                    repo_pipelines.pipeline_id,
+                )).expect("Tree opened: Pipeline error");
+                return;
+            }
+            comments::Command::Delegated(_) |
+            comments::Command::Undelegated => {
+                info!("Delegation is not supported on Bitbucket");
+                return;
+            }
+        };
+        let message = format!(
+            "{}\n\nMerge #{} a=@{} r=@{}\n{}\n\n{}",
+            pr_desc.title,
+            pr,
+            pr_desc.author.user.name,
+            user,
+            iter::repeat('_').take(72).collect::<String>(),
+            pr_desc.description.as_ref().map(|b| &b[..]).unwrap_or(""),
+        );
+        send_event.send(ui::Event::Approved(
+            pipeline_id,
+            pr,
+            commit,
+            message,
+            priority,
+            user.to_owned(),
+        )).expect("PR Approved: Pipeline error");
+    }
+
+    fn handle_message(
+        &self,
+        msg: ui::Message,
+        _: &mut Sender<ui::Event>,
+    ) {
+        match msg {
+            ui::Message::SendResult(pipeline_id, pr, status, _) => {
+                let result = self.send_result_to_pr(pipeline_id, &pr, &status);
+                if let Err(e) = result {
+                    warn!("Failed to send {:?} to PR {}: {:?}", status, pr, e)
+                }
+            }
+        }
+    }
+
+    fn send_result_to_pr(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: &ui::Status,
+    ) -> Result<(), BitbucketRequestError> {
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(BitbucketRequestError::Pipeline(pipeline_id));
+                }
+            };
+        let context = match pipeline_type {
+            PipelineType::Stage => "continuous-integration/aelita",
+            PipelineType::Try => "continuous-integration/aelita/try",
+        }.to_owned();
+        if let Some(comment_body) = github::comment_body(status) {
+            let url = format!(
+                "/rest/api/1.0/projects/{}/repos/{}/pull-requests/{}/comments",
+                repo.owner,
+                repo.repo,
+                pr,
+            );
+            try!(self.client.post(&url, &PostCommentComment {
+                text: comment_body.into_owned(),
+            }));
+        }
+        if let Some((pull_commit, merge_commit, status_body)) =
+                github::commit_status(status, context) {
+            // Bitbucket requires every build status to link somewhere.
+            let url = status_body.target_url.unwrap_or_else(|| format!(
+                "{}/projects/{}/repos/{}/pull-requests/{}",
+                self.client.host(),
+                repo.owner,
+                repo.repo,
+                pr,
+            ));
+            let body = BuildStatusDesc {
+                state: match &status_body.state[..] {
+                    "pending" => "INPROGRESS",
+                    "success" => "SUCCESSFUL",
+                    _ => "FAILED",
+                }.to_owned(),
+                name: status_body.context.clone(),
+                key: status_body.context,
+                url: url,
+                description: status_body.description,
+            };
+            let commits = iter::once(pull_commit).chain(merge_commit);
+            for commit in commits {
+                let url = format!("/rest/build-status/1.0/commits/{}", commit);
+                try!(self.client.post(&url, &body));
+            }
+        }
+        Ok(())
+    }
+
+    fn user_has_write(
+        &self,
+        user: &str,
+        repo: &Repo,
+    ) -> Result<bool, BitbucketRequestError> {
+        let url = format!(
+            "/rest/api/1.0/users?filter={}\
+             &permission.1=REPO_WRITE\
+             &permission.1.projectKey={}\
+             &permission.1.repositorySlug={}",
+            bitbucket_api::query_value(user),
+            bitbucket_api::query_value(&repo.owner),
+            bitbucket_api::query_value(&repo.repo),
+        );
+        let desc: UsersDesc =
+            try!(json_from_reader(try!(self.client.get(&url))));
+        // The filter matches on prefixes, so check for the exact name.
+        Ok(desc.values.iter().any(|u| u.name == user))
+    }
+
+    fn pr_url(&self, pr_desc: &PrDesc) -> Option<Url> {
+        let href = match pr_desc.links.self_links.get(0) {
+            Some(link) => &link.href,
+            None => {
+                warn!("Got PR #{} without a link", pr_desc.id);
+                return None;
+            }
+        };
+        match Url::parse(href) {
+            Ok(url) => Some(url),
+            Err(e) => {
+                warn!("Got bad PR URL {}: {:?}", href, e);
+                None
+            }
+        }
+    }
+}
+
+fn repo_of(pr_desc: &PrDesc) -> Repo {
+    Repo {
+        owner: pr_desc.to_ref.repository.project.key.clone(),
+        repo: pr_desc.to_ref.repository.slug.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::REMOTE_FORMAT;
+    use ui::Pr;
+    use vcs::Remote;
+
+    #[test]
+    fn test_remote() {
+        assert_eq!(
+            Pr::from("12".to_owned()).remote(REMOTE_FORMAT),
+            Remote::from("refs/pull-requests/12/from".to_owned())
+        );
+    }
+}
@@ -20,6 +20,22 @@ lazy_static!{
         .expect("try- is a valid regex");
     static ref CANCEL_SELF: Regex = Regex::new(r#"\br-(\W|$)"#)
         .expect("r- is a valid regex");
+    static ref RETRY: Regex = Regex::new(r#"\bretry(\W|$)"#)
+        .expect("retry is a valid regex");
+    static ref TREE_CLOSED: Regex =
+        Regex::new(r#"\btreeclosed=(-?\d+)\b"#)
+        .expect("treeclosed= is a valid regex");
+    static ref TREE_OPENED: Regex = Regex::new(r#"\btreeclosed-(\W|$)"#)
+        .expect("treeclosed- is a valid regex");
+    static ref PRIORITY: Regex = Regex::new(r#"\bp=(-?\d+)\b"#)
+        .expect("p= is a valid regex");
+    static ref DELEGATE_BEHALF: Regex =
+        Regex::new(r#"\bdelegate=(@?\w+)\b"#)
+        .expect("delegate= is a valid regex");
+    static ref DELEGATE_AUTHOR: Regex = Regex::new(r#"\bdelegate\+(\W|$)"#)
+        .expect("delegate+ is a valid regex");
+    static ref DELEGATE_CANCEL: Regex = Regex::new(r#"\bdelegate-(\W|$)"#)
+        .expect("delegate- is a valid regex");
 }
 
 fn parse_approved_behalf(body: &str) -> Option<&str> {
@@ -62,6 +78,50 @@ fn parse_try_canceled(body: &str) -> bool {
     TRY_CANCEL_SELF.is_match(body)
 }
 
+fn parse_retry(body: &str) -> bool {
+    RETRY.is_match(body)
+}
+
+fn parse_tree_closed(body: &str) -> Option<i32> {
+    TREE_CLOSED.captures(body)
+        .and_then(|capture| capture.at(1))
+        .and_then(|priority| priority.parse().ok())
+}
+
+fn parse_tree_opened(body: &str) -> bool {
+    TREE_OPENED.is_match(body)
+}
+
+/// `None` if there is a priority, but it doesn't fit in an `i32`.
+fn parse_priority(body: &str) -> Option<i32> {
+    match PRIORITY.captures(body).and_then(|capture| capture.at(1)) {
+        Some(priority) => priority.parse().ok(),
+        None => Some(0),
+    }
+}
+
+/// `Some(None)` is `delegate+`, to the PR's author.
+fn parse_delegated(body: &str) -> Option<Option<&str>> {
+    let behalf = DELEGATE_BEHALF.captures(body)
+        .and_then(|capture| capture.at(1))
+        .map(|username| {
+            if username.as_bytes()[0] == b'@' {
+                &username[1..]
+            } else {
+                username
+            }
+        });
+    match (behalf, DELEGATE_AUTHOR.is_match(body)) {
+        (Some(user), false) => Some(Some(user)),
+        (None, true) => Some(None),
+        _ => None,
+    }
+}
+
+fn parse_undelegated(body: &str) -> bool {
+    DELEGATE_CANCEL.is_match(body)
+}
+
 fn parse_specific_commit(body: &str) -> Option<Commit> {
     SPECIFIC_COMMIT.captures(body)
         .and_then(|capture| capture.at(1))
@@ -70,10 +130,17 @@ fn parse_specific_commit(body: &str) -> Option<Commit> {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Command<'a> {
-    Approved(&'a str, Option<Commit>),
+    Approved(&'a str, Option<Commit>, i32),
     Canceled,
+    Retry,
     TryApproved(&'a str, Option<Commit>),
     TryCanceled,
+    TreeClosed(i32),
+    TreeOpened,
+    /// Let the user (or the PR's author, if there isn't one) `r+` and `r-`
+    /// this PR, until it gets new commits.
+    Delegated(Option<&'a str>),
+    Undelegated,
 }
 
 pub fn parse<'a>(body: &'a str, def_user: &'a str) -> Option<Command<'a>> {
@@ -81,9 +148,28 @@ pub fn parse<'a>(body: &'a str, def_user: &'a str) -> Option<Command<'a>> {
     let approved_default = parse_approved_default(body);
     let canceled = parse_canceled(body);
     let commit = parse_specific_commit(body);
+    let priority = match parse_priority(body) {
+        Some(priority) => priority,
+        None => return None,
+    };
+    let retry = parse_retry(body);
     let try_behalf = parse_try_approved_behalf(body);
     let try_approved_default = parse_try_approved_default(body);
     let try_canceled = parse_try_canceled(body);
+    let tree_closed = parse_tree_closed(body);
+    let tree_opened = parse_tree_opened(body);
+    let delegated = parse_delegated(body);
+    let undelegated = parse_undelegated(body);
+    if delegated.is_some() || undelegated {
+        let others = approved_behalf.is_some() || approved_default ||
+            canceled || try_behalf.is_some() || try_approved_default ||
+            try_canceled || retry || tree_closed.is_some() || tree_opened;
+        return match (others, delegated, undelegated) {
+            (false, Some(user), false) => Some(Command::Delegated(user)),
+            (false, None, true) => Some(Command::Undelegated),
+            _ => None,
+        };
+    }
     match (
         approved_behalf,
         approved_default,
@@ -91,19 +177,28 @@ pub fn parse<'a>(body: &'a str, def_user: &'a str) -> Option<Command<'a>> {
         try_behalf,
         try_approved_default,
         try_canceled,
+        retry,
+        tree_closed,
+        tree_opened,
     ) {
-        (Some(user), false, false, None,       false, false) =>
-            Some(Command::Approved(user, commit)),
-        (None,       true, false, None,        false, false) =>
-            Some(Command::Approved(def_user, commit)),
-        (None,       false, true, None,        false, false) =>
+        (Some(user), false, false, None, false, false, false, None, false) =>
+            Some(Command::Approved(user, commit, priority)),
+        (None, true, false, None, false, false, false, None, false) =>
+            Some(Command::Approved(def_user, commit, priority)),
+        (None, false, true, None, false, false, false, None, false) =>
             Some(Command::Canceled),
-        (None,       false, false, Some(user), false, false) =>
+        (None, false, false, Some(user), false, false, false, None, false) =>
             Some(Command::TryApproved(user, commit)),
-        (None,       false, false, None,       true, false) =>
+        (None, false, false, None, true, false, false, None, false) =>
             Some(Command::TryApproved(def_user, commit)),
-        (None,       false, false, None,       false, true) =>
+        (None, false, false, None, false, true, false, None, false) =>
             Some(Command::TryCanceled),
+        (None, false, false, None, false, false, true, None, false) =>
+            Some(Command::Retry),
+        (None, false, false, None, false, false, false, Some(p), false) =>
+            Some(Command::TreeClosed(p)),
+        (None, false, false, None, false, false, false, None, true) =>
+            Some(Command::TreeOpened),
         _ => None,
     }
 }
@@ -125,13 +220,13 @@ mod test {
     #[test] fn test_comment_body_approved_def() {
         assert_eq!(
             parse("r+", "luser"),
-            Some(Command::Approved("luser", None))
+            Some(Command::Approved("luser", None, 0))
         );
     }
     #[test] fn test_comment_body_on_behalf() {
         assert_eq!(
             parse("r=genius", "luser"),
-            Some(Command::Approved("genius", None))
+            Some(Command::Approved("genius", None, 0))
         );
     }
     #[test] fn test_comment_cancel() {
@@ -155,13 +250,13 @@ mod test {
     #[test] fn test_comment_approved_space_back() {
         assert_eq!(
             parse("r+ is not a license to kill", "luser"),
-            Some(Command::Approved("luser", None))
+            Some(Command::Approved("luser", None, 0))
         );
     }
     #[test] fn test_comment_approved_behalf_word_boundary_back() {
         assert_eq!(
             parse("r=genius, thanks!", "luser"),
-            Some(Command::Approved("genius", None))
+            Some(Command::Approved("genius", None, 0))
         );
     }
     #[test] fn test_comment_approved_behalf_empty() {
@@ -170,19 +265,19 @@ mod test {
     #[test] fn test_comment_approved_behalf_at() {
         assert_eq!(
             parse("r=@genius", "luser"),
-            Some(Command::Approved("genius", None))
+            Some(Command::Approved("genius", None, 0))
         );
     }
     #[test] fn test_comment_approved_word_boundary_back() {
         assert_eq!(
             parse("r+!", "luser"),
-            Some(Command::Approved("luser", None))
+            Some(Command::Approved("luser", None, 0))
         );
     }
     #[test] fn test_comment_approved_word_boundary_front() {
         assert_eq!(
             parse("!r+", "luser"),
-            Some(Command::Approved("luser", None))
+            Some(Command::Approved("luser", None, 0))
         );
     }
     #[test] fn test_comment_approved_specific_commit() {
@@ -192,10 +287,42 @@ mod test {
                 "luser",
                 Some(Commit::from(
                     "a4068472538866d0b603793539875dac1f962c2e".to_owned()
-                ))
+                )),
+                0
             ))
         );
     }
+    #[test] fn test_comment_approved_priority() {
+        assert_eq!(
+            parse("r+ p=5", "luser"),
+            Some(Command::Approved("luser", None, 5))
+        );
+    }
+    #[test] fn test_comment_approved_behalf_priority() {
+        assert_eq!(
+            parse("p=10 r=genius", "luser"),
+            Some(Command::Approved("genius", None, 10))
+        );
+    }
+    #[test] fn test_comment_approved_negative_priority() {
+        assert_eq!(
+            parse("r+ p=-1", "luser"),
+            Some(Command::Approved("luser", None, -1))
+        );
+    }
+    #[test] fn test_comment_priority_alone() {
+        assert_eq!(parse("p=5", "luser"), None);
+    }
+    #[test] fn test_comment_priority_overflow() {
+        assert_eq!(parse("r+ p=99999999999", "luser"), None);
+        assert_eq!(parse("r+ p=-99999999999", "luser"), None);
+    }
+    #[test] fn test_comment_priority_not_substr() {
+        assert_eq!(
+            parse("r+ up=5", "luser"),
+            Some(Command::Approved("luser", None, 0))
+        );
+    }
     #[test] fn test_try() {
         assert_eq!(
             parse("try+", "luser"),
@@ -208,4 +335,55 @@ mod test {
             Some(Command::TryCanceled)
         );
     }
+    #[test] fn test_retry() {
+        assert_eq!(parse("retry", "luser"), Some(Command::Retry));
+    }
+    #[test] fn test_retry_not_try() {
+        assert_eq!(parse("retry+", "luser"), Some(Command::Retry));
+    }
+    #[test] fn test_retry_not_substr() {
+        assert_eq!(parse("retrying", "luser"), None);
+    }
+    #[test] fn test_retry_approved() {
+        assert_eq!(parse("r+ retry", "luser"), None);
+    }
+    #[test] fn test_tree_closed() {
+        assert_eq!(
+            parse("treeclosed=5", "luser"),
+            Some(Command::TreeClosed(5))
+        );
+    }
+    #[test] fn test_tree_closed_negative() {
+        assert_eq!(
+            parse("treeclosed=-1", "luser"),
+            Some(Command::TreeClosed(-1))
+        );
+    }
+    #[test] fn test_tree_closed_approved() {
+        assert_eq!(parse("r+ treeclosed=5", "luser"), None);
+    }
+    #[test] fn test_tree_opened() {
+        assert_eq!(
+            parse("treeclosed-", "luser"),
+            Some(Command::TreeOpened)
+        );
+    }
+    #[test] fn test_tree_closed_empty() {
+        assert_eq!(parse("treeclosed=", "luser"), None);
+    }
+    #[test] fn test_delegate_author() {
+        assert_eq!(parse("delegate+", "luser"), Some(Command::Delegated(None)));
+    }
+    #[test] fn test_delegate_behalf() {
+        assert_eq!(
+            parse("delegate=@genius", "luser"),
+            Some(Command::Delegated(Some("genius")))
+        );
+    }
+    #[test] fn test_undelegate() {
+        assert_eq!(parse("delegate-", "luser"), Some(Command::Undelegated));
+    }
+    #[test] fn test_delegate_approved() {
+        assert_eq!(parse("r+ delegate+", "luser"), None);
+    }
 }
@@ -0,0 +1,561 @@
+// This file is released under the same terms as Rust itself.
+
+/*! The Gitea (and Forgejo) front-end.
+
+    Gitea's webhooks and API are close copies of GitHub's, so the comments
+    and commit statuses are the same ones the GitHub front-end sends.
+    Only collaborators with write access may give commands.
+ */
+
+use crossbeam;
+use hyper::Url;
+use hyper::buffer::BufReader;
+use hyper::header::Headers;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use serde_json::{from_reader as json_from_reader};
+use serde_json::{from_slice as json_from_slice};
+use std::io::BufWriter;
+use std::iter;
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, Receiver};
+use ui::{self, comments, github, Pr};
+use util::gitea_api::{Client, GiteaRequestError};
+use util::gitea_headers;
+use vcs::Commit;
+
+/// Gitea keeps the head of every pull request where GitHub does.
+pub const REMOTE_FORMAT: ui::RemoteFormat = ui::RemoteFormat("pull/{}/head");
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    pub owner: String,
+    pub repo: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct RepoPipelines {
+    pub pipeline_id: PipelineId,
+    pub try_pipeline_id: Option<PipelineId>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PipelineType {
+    Stage,
+    Try,
+}
+
+pub trait ProjectsConfig: Send + Sync + 'static {
+    /// The pipelines for pull requests into the given base branch.
+    fn pipelines_by_repo(&self, &Repo, &str) -> Option<RepoPipelines>;
+    fn repo_by_pipeline(&self, PipelineId) -> Option<(Repo, PipelineType)>;
+}
+
+pub struct Worker {
+    listen: String,
+    projects: Box<ProjectsConfig>,
+    client: Arc<Client>,
+    user_ident: String,
+    secrets: Vec<String>,
+}
+
+impl Worker {
+    pub fn new(
+        listen: String,
+        client: Arc<Client>,
+        user: String,
+        secrets: Vec<String>,
+        projects: Box<ProjectsConfig>,
+    ) -> Worker {
+        Worker {
+            listen: listen,
+            projects: projects,
+            client: client,
+            user_ident: format!("@{}", user),
+            secrets: secrets,
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct RepositoryDesc {
+    name: String,
+    owner: UserDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct UserDesc {
+    login: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PrBranchDesc {
+    sha: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PrBaseDesc {
+    // ref is a reserved word.
+    #[serde(rename="ref")]
+    ref_name: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PrDesc {
+    number: u32,
+    head: PrBranchDesc,
+    base: PrBaseDesc,
+    html_url: String,
+    title: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PullRequestDesc {
+    action: String,
+    pull_request: PrDesc,
+    repository: RepositoryDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct IssueCommentIssue {
+    number: u32,
+    title: String,
+    body: Option<String>,
+    state: String,
+    user: UserDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct IssueCommentComment {
+    user: UserDesc,
+    body: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CommentDesc {
+    action: String,
+    issue: IssueCommentIssue,
+    comment: IssueCommentComment,
+    repository: RepositoryDesc,
+    #[serde(default)]
+    is_pull: bool,
+}
+#[derive(Deserialize, Serialize)]
+struct PrBaseOnlyDesc {
+    base: PrBaseDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct PermissionDesc {
+    permission: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PostCommentComment {
+    body: String,
+}
+
+impl pipeline::Worker<ui::Event, ui::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<ui::Message>,
+        mut send_event: Sender<ui::Event>
+    ) {
+        crossbeam::scope(|scope| {
+            let s2 = &*self;
+            let send_event_2 = send_event.clone();
+            scope.spawn(move || {
+                s2.run_webhook(send_event_2);
+            });
+            loop {
+                s2.handle_message(
+                    recv_msg.recv().expect("Pipeline went away"),
+                    &mut send_event,
+                );
+            }
+        })
+    }
+}
+
+impl Worker {
+    fn run_webhook(
+        &self,
+        send_event: Sender<ui::Event>,
+    ) {
+        let mut listener = HttpListener::new(&self.listen[..])
+            .expect("webhook");
+        while let Ok(mut stream) = listener.accept() {
+            let addr = stream.peer_addr()
+                .expect("webhook client address");
+            let mut stream_clone = stream.clone();
+            let mut buf_read = BufReader::new(
+                &mut stream_clone as &mut NetworkStream
+            );
+            let mut buf_write = BufWriter::new(&mut stream);
+            let req = match Request::new(&mut buf_read, addr) {
+                Ok(req) => req,
+                Err(e) => {
+                    warn!("Invalid webhook HTTP: {:?}", e);
+                    continue;
+                }
+            };
+            let mut head = Headers::new();
+            let res = Response::new(&mut buf_write, &mut head);
+            self.handle_webhook(req, res, &send_event);
+        }
+    }
+
+    fn handle_webhook(
+        &self,
+        mut req: Request,
+        mut res: Response,
+        send_event: &Sender<ui::Event>
+    ) {
+        let head = gitea_headers::parse(&mut req, &self.secrets);
+        let (x_gitea_event, body) = match head {
+            Some(head) => head,
+            None => return,
+        };
+        match &x_gitea_event[..] {
+            b"pull_request" => {
+                if let Ok(desc) = json_from_slice::<PullRequestDesc>(&body) {
+                    info!(
+                        "Got PR message for #{}: {}",
+                        desc.pull_request.number,
+                        desc.action,
+                    );
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!("Failed to send response to Gitea PR: {:?}", e);
+                    }
+                    self.handle_pr(send_event, desc);
+                } else {
+                    warn!("Got invalid PR message");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!("Failed to send response to bad PR: {:?}", e);
+                    }
+                }
+            }
+            // Gitea sends comments on pull requests as issue comments,
+            // the same as GitHub.
+            b"issue_comment" => {
+                if let Ok(desc) = json_from_slice::<CommentDesc>(&body) {
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to Gitea comment: {:?}",
+                            e,
+                        );
+                    }
+                    if !desc.comment.body.contains(&self.user_ident) {
+                        info!("Comment does not mention me; do nothing");
+                    } else if desc.action != "created" {
+                        info!("Comment was not just made; do nothing");
+                    } else if !desc.is_pull {
+                        info!("Got comment on issue; do nothing");
+                    } else if desc.issue.state != "open" {
+                        info!("Comment is for closed PR; do nothing");
+                    } else {
+                        info!("Got pull request comment");
+                        self.handle_pr_comment(send_event, desc);
+                    }
+                } else {
+                    warn!("Got invalid comment");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad comment: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
+            e => {
+                *res.status_mut() = StatusCode::BadRequest;
+                if let Err(e) = res.send(&[]) {
+                    warn!(
+                        "Failed to send response to Gitea unknown: {:?}",
+                        e,
+                    );
+                }
+                warn!(
+                    "Got Unknown Event {}",
+                    String::from_utf8_lossy(&e)
+                );
+            }
+        }
+    }
+
+    fn handle_pr(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: PullRequestDesc,
+    ) {
+        let repo = Repo {
+            owner: desc.repository.owner.login,
+            repo: desc.repository.name,
+        };
+        let pr_desc = desc.pull_request;
+        let repo_pipelines = match self.projects.pipelines_by_repo(
+            &repo,
+            &pr_desc.base.ref_name,
+        ) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                warn!(
+                    "Got bad repo {:?} branch {}",
+                    repo,
+                    pr_desc.base.ref_name,
+                );
+                return;
+            }
+        };
+        let pr = Pr::from(pr_desc.number.to_string());
+        let commit = Commit::from(pr_desc.head.sha);
+        let url = match Url::parse(&pr_desc.html_url) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Got bad PR URL {}: {:?}", pr_desc.html_url, e);
+                return;
+            }
+        };
+        let pipeline_ids = iter::once(repo_pipelines.pipeline_id)
+            .chain(repo_pipelines.try_pipeline_id);
+        for pipeline_id in pipeline_ids {
+            let event = match &desc.action[..] {
+                "closed" => Some(ui::Event::Closed(
+                    pipeline_id,
+                    pr.clone(),
+                )),
+                "opened" | "reopened" => Some(ui::Event::Opened(
+                    pipeline_id,
+                    pr.clone(),
+                    commit.clone(),
+                    pr_desc.title.clone(),
+                    url.clone(),
+                )),
+                "synchronized" | "edited" => Some(ui::Event::Changed(
+                    pipeline_id,
+                    pr.clone(),
+                    commit.clone(),
+                    pr_desc.title.clone(),
+                    url.clone(),
+                )),
+                _ => None,
+            };
+            if let Some(event) = event {
+                send_event.send(event).expect("Pipeline to be there");
+            }
+        }
+    }
+
+    fn handle_pr_comment(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: CommentDesc,
+    ) {
+        let repo = Repo {
+            owner: desc.repository.owner.login,
+            repo: desc.repository.name,
+        };
+        let user = &desc.comment.user.login;
+        let command = match comments::parse(&desc.comment.body, user) {
+            Some(command) => command,
+            None => {
+                info!("Pull request comment is not a command");
+                return;
+            }
+        };
+        let allowed = self.user_has_write(user, &repo)
+            .unwrap_or_else(|e| {
+                warn!("Failed to check if {} has permission: {:?}", user, e);
+                false
+            });
+        if !allowed {
+            info!("Got mentioned by not-permitted user");
+            return;
+        }
+        // The comment does not say which branch the PR is into, so ask.
+        let base = match self.get_pr_base(&repo, desc.issue.number) {
+            Ok(base) => base,
+            Err(e) => {
+                warn!("Failed to get #{}: {:?}", desc.issue.number, e);
+                return;
+            }
+        };
+        let repo_pipelines = match self.projects.pipelines_by_repo(
+            &repo,
+            &base,
+        ) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                warn!("Got bad repo {:?} branch {}", repo, base);
+                return;
+            }
+        };
+        let pr = Pr::from(desc.issue.number.to_string());
+        let (pipeline_id, user, commit, priority) = match command {
+            comments::Command::Approved(user, commit, priority) =>
+                (repo_pipelines.pipeline_id, user, commit, priority),
+            comments::Command::TryApproved(user, commit) => {
+                match repo_pipelines.try_pipeline_id {
+                    Some(try_pipeline_id) => (try_pipeline_id, user, commit, 0),
+                    None => return,
+                }
+            }
+            comments::Command::Canceled => {
+                send_event.send(ui::Event::Canceled(
+                    repo_pipelines.pipeline_id,
+                    pr,
+                )).expect("PR Canceled: Pipeline error");
+                return;
+            }
+            comments::Command::TryCanceled => {
+                if let Some(try_pipeline_id) = repo_pipelines.try_pipeline_id {
+                    send_event.send(ui::Event::Canceled(
+                        try_pipeline_id,
+                        pr,
+                    )).expect("PR Canceled: Pipeline error");
+                }
+                return;
+            }
+            comments::Command::Retry => {
+                send_event.send(ui::Event::Retry(
+                    repo_pipelines.pipeline_id,
+                    pr,
+                )).expect("PR Retry: Pipeline error");
+                return;
+            }
+            comments::Command::TreeClosed(priority) => {
+                send_event.send(ui::Event::TreeClosed(
+                    repo_pipelines.pipeline_id,
+                    priority,
+                )).expect("Tree closed: Pipeline error");
+                return;
+            }
+            comments::Command::TreeOpened => {
+                send_event.send(ui::Event::TreeOpened(
+                    repo_pipelines.pipeline_id,
+                )).expect("Tree opened: Pipeline error");
+                return;
+            }
+            comments::Command::Delegated(_) |
+            comments::Command::Undelegated => {
+                info!("Delegation is not supported on Gitea");
+                return;
+            }
+        };
+        let message = format!(
+            "{}\n\nMerge #{} a=@{} r=@{}\n{}\n\n{}",
+            desc.issue.title,
+            pr,
+            desc.issue.user.login,
+            user,
+            iter::repeat('_').take(72).collect::<String>(),
+            desc.issue.body.as_ref().map(|b| &b[..]).unwrap_or(""),
+        );
+        send_event.send(ui::Event::Approved(
+            pipeline_id,
+            pr,
+            commit,
+            message,
+            priority,
+            user.to_owned(),
+        )).expect("PR Approved: Pipeline error");
+    }
+
+    fn handle_message(
+        &self,
+        msg: ui::Message,
+        _: &mut Sender<ui::Event>,
+    ) {
+        match msg {
+            ui::Message::SendResult(pipeline_id, pr, status, _) => {
+                let result = self.send_result_to_pr(pipeline_id, &pr, &status);
+                if let Err(e) = result {
+                    warn!("Failed to send {:?} to PR {}: {:?}", status, pr, e)
+                }
+            }
+        }
+    }
+
+    fn send_result_to_pr(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: &ui::Status,
+    ) -> Result<(), GiteaRequestError> {
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(GiteaRequestError::Pipeline(pipeline_id));
+                }
+            };
+        let context = match pipeline_type {
+            PipelineType::Stage => "continuous-integration/aelita",
+            PipelineType::Try => "continuous-integration/aelita/try",
+        }.to_owned();
+        if let Some(comment_body) = github::comment_body(status) {
+            let url = format!(
+                "/repos/{}/{}/issues/{}/comments",
+                repo.owner,
+                repo.repo,
+                pr,
+            );
+            try!(self.client.post(&url, &PostCommentComment {
+                body: comment_body.into_owned(),
+            }));
+        }
+        if let Some((pull_commit, merge_commit, status_body)) =
+                github::commit_status(status, context) {
+            let commits = iter::once(pull_commit).chain(merge_commit);
+            for commit in commits {
+                let url = format!(
+                    "/repos/{}/{}/statuses/{}",
+                    repo.owner,
+                    repo.repo,
+                    commit,
+                );
+                try!(self.client.post(&url, &status_body));
+            }
+        }
+        Ok(())
+    }
+
+    fn user_has_write(
+        &self,
+        user: &str,
+        repo: &Repo,
+    ) -> Result<bool, GiteaRequestError> {
+        let url = format!(
+            "/repos/{}/{}/collaborators/{}/permission",
+            repo.owner,
+            repo.repo,
+            user,
+        );
+        match self.client.get(&url) {
+            Ok(resp) => {
+                let desc: PermissionDesc = try!(json_from_reader(resp));
+                Ok(match &desc.permission[..] {
+                    "write" | "admin" | "owner" => true,
+                    _ => false,
+                })
+            }
+            Err(GiteaRequestError::HttpStatus(StatusCode::NotFound)) |
+            Err(GiteaRequestError::HttpStatus(StatusCode::Forbidden)) =>
+                Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_pr_base(
+        &self,
+        repo: &Repo,
+        number: u32,
+    ) -> Result<String, GiteaRequestError> {
+        let url = format!(
+            "/repos/{}/{}/pulls/{}",
+            repo.owner,
+            repo.repo,
+            number,
+        );
+        let desc: PrBaseOnlyDesc =
+            try!(json_from_reader(try!(self.client.get(&url))));
+        Ok(desc.base.ref_name)
+    }
+}
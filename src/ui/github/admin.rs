@@ -0,0 +1,145 @@
+// This file is released under the same terms as Rust itself.
+
+/*! Telling a running instance what to do, from the command line.
+
+    Everything is posted to the admin listener, which only listens on
+    loopback, so it cannot be sent (or sent again) from anywhere else.
+    It is signed with the webhook secret the same way GitHub does it, too,
+    so other users on the same machine cannot do it either.
+ */
+
+use hex::ToHex;
+use hyper;
+use hyper::client::Client as HyperClient;
+use hyper::header::Headers;
+use hyper::status::StatusCode;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use ui::github::replay::{self, ReplayError, Selection};
+use util::crypto::generate_sha256_hmac;
+
+/// Where the admin listener takes requests to flush the permission cache.
+pub const FLUSH_PERMISSIONS_PATH: &'static str = "/admin/flush-permissions";
+
+/// Where the admin listener takes webhooks to send again.
+pub const REPLAY_PATH: &'static str = "/admin/replay";
+
+/// Whether everything the address resolves to is on this machine.
+pub fn is_loopback(listen: &str) -> bool {
+    match listen.to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs: Vec<SocketAddr> = addrs.collect();
+            !addrs.is_empty() && addrs.iter().all(|addr| match *addr {
+                SocketAddr::V4(ref addr) => addr.ip().is_loopback(),
+                SocketAddr::V6(ref addr) => addr.ip().is_loopback(),
+            })
+        }
+        Err(_) => false,
+    }
+}
+
+/// Everything needed to talk to a running listener.
+pub struct Admin {
+    pub admin_listen: Option<String>,
+    pub secret: String,
+    pub replay_log: Option<PathBuf>,
+}
+
+impl Admin {
+    /// Send the selected deliveries again, oldest first,
+    /// and return how many were sent.
+    pub fn replay(&self, selection: &Selection) -> Result<usize, AdminError> {
+        let log = match self.replay_log {
+            Some(ref log) => log,
+            None => return Err(AdminError::NoReplayLog),
+        };
+        let entries = try!(replay::entries(log, selection));
+        for entry in &entries {
+            info!("Replaying {:?} ({})", entry.delivery, entry.event);
+            let mut headers = Headers::new();
+            headers.set_raw(
+                "X-Github-Event",
+                vec![entry.event.clone().into_bytes()],
+            );
+            if let Some(ref delivery) = entry.delivery {
+                headers.set_raw(
+                    "X-GitHub-Delivery",
+                    vec![delivery.clone().into_bytes()],
+                );
+            }
+            try!(self.post(REPLAY_PATH, headers, &entry.body));
+        }
+        Ok(entries.len())
+    }
+
+    /// Make the listener forget who can write to which repos.
+    pub fn flush_permissions(&self) -> Result<(), AdminError> {
+        self.post(FLUSH_PERMISSIONS_PATH, Headers::new(), "")
+    }
+
+    fn post(
+        &self,
+        path: &str,
+        mut headers: Headers,
+        body: &str,
+    ) -> Result<(), AdminError> {
+        let admin_listen = match self.admin_listen {
+            Some(ref admin_listen) => admin_listen,
+            None => return Err(AdminError::NoAdminListen),
+        };
+        let url = format!("http://{}{}", admin_listen, path);
+        let signature =
+            generate_sha256_hmac(self.secret.as_bytes(), body.as_bytes());
+        headers.set_raw(
+            "X-Hub-Signature-256",
+            vec![format!("sha256={}", signature.to_hex()).into_bytes()],
+        );
+        headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+        let resp = try!(
+            HyperClient::new().post(&url)
+                .headers(headers)
+                .body(body)
+                .send()
+        );
+        if !resp.status.is_success() {
+            return Err(AdminError::HttpStatus(resp.status));
+        }
+        Ok(())
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum AdminError {
+        /// The replay log could not be read
+        Replay(err: ReplayError) {
+            cause(err)
+            from()
+        }
+        /// There is no replay log configured
+        NoReplayLog {}
+        /// There is no admin listener configured
+        NoAdminListen {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_loopback;
+
+    #[test]
+    fn loopback_listen() {
+        assert!(is_loopback("127.0.0.1:9002"));
+        assert!(is_loopback("[::1]:9002"));
+        assert!(!is_loopback("0.0.0.0:9002"));
+        assert!(!is_loopback("10.0.0.1:9002"));
+        assert!(!is_loopback("not an address"));
+    }
+}
@@ -7,6 +7,7 @@ use postgres::params::{ConnectParams, IntoConnectParams};
 use std::convert::AsRef;
 use std::error::Error;
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use ui::github::TeamId;
 use pipeline::PipelineId;
 
@@ -59,6 +60,61 @@ impl Cache {
             Cache::Postgres(ref mut c) => c.set_is_org(pipeline_id, is_org),
         }
     }
+    /// Record a webhook delivery, returning false if we already had it.
+    /// Deliveries older than the TTL are forgotten.
+    pub fn add_delivery(&mut self, delivery: &str, ttl: Duration) -> bool {
+        let now = unix_now();
+        let expired = now - ttl.as_secs() as i64;
+        match *self {
+            Cache::Sqlite(ref mut c) => c.add_delivery(delivery, now, expired),
+            Cache::Postgres(ref mut c) =>
+                c.add_delivery(delivery, now, expired),
+        }
+    }
+    /// Whether the user could write to the pipeline's repo,
+    /// if we checked it within the TTL.
+    pub fn user_has_write(
+        &mut self,
+        pipeline_id: PipelineId,
+        user: &str,
+        ttl: Duration,
+    ) -> Option<bool> {
+        let expired = unix_now() - ttl.as_secs() as i64;
+        match *self {
+            Cache::Sqlite(ref mut c) =>
+                c.user_has_write(pipeline_id, user, expired),
+            Cache::Postgres(ref mut c) =>
+                c.user_has_write(pipeline_id, user, expired),
+        }
+    }
+    pub fn set_user_has_write(
+        &mut self,
+        pipeline_id: PipelineId,
+        user: &str,
+        has_write: bool,
+    ) {
+        let now = unix_now();
+        match *self {
+            Cache::Sqlite(ref mut c) =>
+                c.set_user_has_write(pipeline_id, user, has_write, now),
+            Cache::Postgres(ref mut c) =>
+                c.set_user_has_write(pipeline_id, user, has_write, now),
+        }
+    }
+    /// Forget everything we know about who can write to what,
+    /// so that it all gets asked for again.
+    pub fn flush_permissions(&mut self) {
+        match *self {
+            Cache::Sqlite(ref mut c) => c.flush_permissions(),
+            Cache::Postgres(ref mut c) => c.flush_permissions(),
+        }
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .expect("the clock to be past 1970")
+        .as_secs() as i64
 }
 
 pub struct Sqlite {
@@ -77,6 +133,17 @@ impl Sqlite {
             CREATE TABLE IF NOT EXISTS github_is_org (
                 pipeline_id INTEGER PRIMARY KEY,
                 is_org BOOLEAN
+            );
+            CREATE TABLE IF NOT EXISTS github_deliveries (
+                delivery TEXT PRIMARY KEY,
+                received_at BIGINT
+            );
+            CREATE TABLE IF NOT EXISTS github_user_permissions (
+                pipeline_id INTEGER,
+                user_login TEXT,
+                has_write BOOLEAN,
+                checked_at BIGINT,
+                UNIQUE (pipeline_id, user_login)
             )
         "###));
         Ok(Sqlite{
@@ -154,6 +221,75 @@ impl Sqlite {
             &pipeline_id.0, &is_org
         ]).expect("to set is_org");
     }
+    fn add_delivery(
+        &mut self,
+        delivery: &str,
+        now: i64,
+        expired: i64,
+    ) -> bool {
+        let trans = self.conn
+            .transaction()
+            .expect("Start add-delivery transaction");
+        let sql = r###"
+            DELETE FROM github_deliveries
+            WHERE received_at < ?
+        "###;
+        trans.execute(sql, &[
+            &expired,
+        ]).expect("to forget old deliveries");
+        let sql = r###"
+            INSERT OR IGNORE INTO github_deliveries (delivery, received_at)
+            VALUES (?, ?)
+        "###;
+        let added = trans.execute(sql, &[
+            &delivery, &now,
+        ]).expect("to add a delivery");
+        trans.commit().expect("Commit add-delivery transaction");
+        added != 0
+    }
+    fn user_has_write(
+        &mut self,
+        pipeline_id: PipelineId,
+        user: &str,
+        expired: i64,
+    ) -> Option<bool> {
+        let sql = r###"
+            SELECT has_write
+            FROM github_user_permissions
+            WHERE pipeline_id = ? AND user_login = ? AND checked_at >= ?
+        "###;
+        let mut stmt = self.conn.prepare(&sql)
+            .expect("Prepare user-has-write query");
+        let mut rows = stmt
+            .query_map(&[&pipeline_id.0, &user, &expired], |row| {
+                row.get::<_, bool>(0)
+            })
+            .expect("Get user-has-write");
+        rows.next().map(|row| row.expect("SQLite to work"))
+    }
+    fn set_user_has_write(
+        &mut self,
+        pipeline_id: PipelineId,
+        user: &str,
+        has_write: bool,
+        now: i64,
+    ) {
+        let sql = r###"
+            REPLACE INTO github_user_permissions
+                (pipeline_id, user_login, has_write, checked_at)
+            VALUES (?, ?, ?, ?)
+        "###;
+        self.conn.execute(sql, &[
+            &pipeline_id.0, &user, &has_write, &now,
+        ]).expect("to set user-has-write");
+    }
+    fn flush_permissions(&mut self) {
+        self.conn.execute_batch(r###"
+            DELETE FROM github_user_permissions;
+            DELETE FROM github_teams_with_write;
+            DELETE FROM github_is_org
+        "###).expect("to flush permissions");
+    }
 }
 
 pub struct Postgres {
@@ -176,6 +312,17 @@ impl Postgres {
             CREATE TABLE IF NOT EXISTS github_is_org (
                 pipeline_id INTEGER PRIMARY KEY,
                 is_org BOOLEAN
+            );
+            CREATE TABLE IF NOT EXISTS github_deliveries (
+                delivery TEXT PRIMARY KEY,
+                received_at BIGINT
+            );
+            CREATE TABLE IF NOT EXISTS github_user_permissions (
+                pipeline_id INTEGER,
+                user_login TEXT,
+                has_write BOOLEAN,
+                checked_at BIGINT,
+                UNIQUE (pipeline_id, user_login)
             )
         "###));
         Ok(result)
@@ -262,4 +409,152 @@ impl Postgres {
             ]));
         }}
     }
-}
\ No newline at end of file
+    fn add_delivery(
+        &mut self,
+        delivery: &str,
+        now: i64,
+        expired: i64,
+    ) -> bool {
+        retry!{{
+            let conn = retry_unwrap!(self.conn());
+            let trans = retry_unwrap!(conn.transaction());
+            let sql = r###"
+                DELETE FROM github_deliveries
+                WHERE received_at < $1
+            "###;
+            retry_unwrap!(trans.execute(sql, &[
+                &expired,
+            ]));
+            let sql = r###"
+                INSERT INTO github_deliveries (delivery, received_at)
+                VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+            "###;
+            let added = retry_unwrap!(trans.execute(sql, &[
+                &delivery, &now,
+            ]));
+            retry_unwrap!(trans.commit());
+            added != 0
+        }}
+    }
+    fn user_has_write(
+        &mut self,
+        pipeline_id: PipelineId,
+        user: &str,
+        expired: i64,
+    ) -> Option<bool> {
+        retry!{{
+            let sql = r###"
+                SELECT has_write
+                FROM github_user_permissions
+                WHERE pipeline_id = $1 AND user_login = $2
+                    AND checked_at >= $3
+            "###;
+            let conn = retry_unwrap!(self.conn());
+            let stmt = retry_unwrap!(conn.prepare(&sql));
+            let rows = retry_unwrap!(stmt.query(&[
+                &pipeline_id.0, &user, &expired,
+            ]));
+            let rows = rows.iter();
+            let mut rows = rows.map(|row| row.get::<_, bool>(0));
+            rows.next()
+        }}
+    }
+    fn set_user_has_write(
+        &mut self,
+        pipeline_id: PipelineId,
+        user: &str,
+        has_write: bool,
+        now: i64,
+    ) {
+        retry!{{
+            let sql = r###"
+                INSERT INTO github_user_permissions
+                    (pipeline_id, user_login, has_write, checked_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (pipeline_id, user_login)
+                DO UPDATE SET has_write = $3, checked_at = $4
+            "###;
+            let conn = retry_unwrap!(self.conn());
+            retry_unwrap!(conn.execute(sql, &[
+                &pipeline_id.0, &user, &has_write, &now,
+            ]));
+        }}
+    }
+    fn flush_permissions(&mut self) {
+        retry!{{
+            let conn = retry_unwrap!(self.conn());
+            retry_unwrap!(conn.batch_execute(r###"
+                DELETE FROM github_user_permissions;
+                DELETE FROM github_teams_with_write;
+                DELETE FROM github_is_org
+            "###));
+        }}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pipeline::PipelineId;
+    use std::time::Duration;
+    use super::{Cache, Sqlite};
+    use ui::github::TeamId;
+
+    fn sqlite() -> Sqlite {
+        Sqlite::open(":memory:").unwrap()
+    }
+
+    #[test]
+    fn test_user_has_write_ttl() {
+        let mut cache = sqlite();
+        cache.set_user_has_write(PipelineId(0), "testu", true, 100);
+        cache.set_user_has_write(PipelineId(0), "nobody", false, 100);
+        // Checked at 100, so it is good until something older expires.
+        assert_eq!(
+            cache.user_has_write(PipelineId(0), "testu", 40),
+            Some(true)
+        );
+        assert_eq!(
+            cache.user_has_write(PipelineId(0), "nobody", 100),
+            Some(false)
+        );
+        assert_eq!(cache.user_has_write(PipelineId(0), "testu", 101), None);
+        assert_eq!(cache.user_has_write(PipelineId(1), "testu", 40), None);
+        // Checking again starts the TTL over.
+        cache.set_user_has_write(PipelineId(0), "testu", false, 200);
+        assert_eq!(
+            cache.user_has_write(PipelineId(0), "testu", 101),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_user_has_write_within_ttl() {
+        let mut cache = Cache::Sqlite(sqlite());
+        let ttl = Duration::from_secs(60);
+        assert_eq!(cache.user_has_write(PipelineId(0), "testu", ttl), None);
+        cache.set_user_has_write(PipelineId(0), "testu", true);
+        assert_eq!(
+            cache.user_has_write(PipelineId(0), "testu", ttl),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_flush_permissions() {
+        let mut cache = Cache::Sqlite(sqlite());
+        let ttl = Duration::from_secs(60);
+        cache.set_user_has_write(PipelineId(0), "testu", true);
+        cache.set_user_has_write(PipelineId(1), "other", false);
+        cache.set_teams_with_write(PipelineId(0), vec![TeamId(1)].into_iter());
+        cache.set_is_org(PipelineId(0), true);
+        assert!(cache.add_delivery("delivery", ttl));
+        cache.flush_permissions();
+        assert_eq!(cache.user_has_write(PipelineId(0), "testu", ttl), None);
+        assert_eq!(cache.user_has_write(PipelineId(1), "other", ttl), None);
+        assert!(cache.teams_with_write(PipelineId(0)).is_empty());
+        assert_eq!(cache.is_org(PipelineId(0)), None);
+        // Deliveries have nothing to do with permissions.
+        assert!(!cache.add_delivery("delivery", ttl));
+    }
+}
@@ -1,9 +1,11 @@
 // This file is released under the same terms as Rust itself.
 
+pub mod admin;
 mod cache;
+pub mod replay;
 
 use crossbeam;
-use db;
+use db::{self, Db};
 use hyper;
 use hyper::Url;
 use hyper::buffer::BufReader;
@@ -11,25 +13,34 @@ use hyper::header::{self, qitem, Accept, Headers};
 use hyper::net::{HttpListener, NetworkListener, NetworkStream};
 use hyper::server::{Request, Response};
 use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
 use pipeline::{self, PipelineId};
-use rest::{authorization, Authorization, Client, Mime};
+use rest::Mime;
+use serde::Deserialize;
 use serde_json::{
     self,
     from_slice as json_from_slice,
 };
 use std;
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::BufWriter;
 use std::iter;
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{Sender, Receiver};
+use std::thread;
+use std::time::Duration;
 use ui::{self, comments, Pr};
-use util::USER_AGENT;
+use util::github_auth::{AuthError, Credentials, GithubClient};
+use util::github_api;
 use util::github_headers;
 use vcs::Commit;
 use vcs::git::ToShortString;
 
+/// GitHub keeps the head of every pull request under `refs/pull`.
+pub const REMOTE_FORMAT: ui::RemoteFormat = ui::RemoteFormat("pull/{}/head");
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct TeamId(pub u32);
 
@@ -43,6 +54,20 @@ pub struct Repo {
 pub struct RepoPipelines {
     pub pipeline_id: PipelineId,
     pub try_pipeline_id: Option<PipelineId>,
+    /// Take every approving review as `r+`,
+    /// not just the ones that mention us.
+    pub review_approves: bool,
+    /// Who may `r+`, instead of everyone who can push.
+    pub reviewers: Option<AccessList>,
+    /// Who may `try+`, as well as whoever may `r+`.
+    pub try_users: Option<AccessList>,
+}
+
+/// Users, and teams of the repo owner's org by slug.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AccessList {
+    pub users: Vec<String>,
+    pub teams: Vec<String>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -51,44 +76,72 @@ pub enum PipelineType {
     Try,
 }
 
+/// GitHub may redeliver a webhook well after the first time,
+/// if someone asks it to, so remember them for a while.
+const DELIVERY_TTL_SEC: u64 = 7*24*60*60;
+
 pub trait ProjectsConfig: Send + Sync + 'static {
-    fn pipelines_by_repo(&self, &Repo) -> Option<RepoPipelines>;
+    /// The pipelines for PRs against the given base branch.
+    fn pipelines_by_repo(&self, &Repo, &str) -> Option<RepoPipelines>;
+    /// The pipelines for every base branch the repo has them for.
+    fn all_pipelines_by_repo(&self, &Repo) -> Vec<RepoPipelines>;
     fn repo_by_pipeline(&self, PipelineId) -> Option<(Repo, PipelineType)>;
+    fn repos(&self) -> Vec<Repo>;
 }
 
 pub struct Worker {
     listen: String,
     projects: Box<ProjectsConfig>,
-    client: Client<Authorization<authorization::Token>>,
+    credentials: Arc<Credentials>,
     user_ident: String,
-    secret: String,
+    secrets: Vec<String>,
     cache: Mutex<cache::Cache>,
+    /// The pipelines' database, which keeps the delegations.
+    db: Mutex<db::DbBox>,
+    sync_interval: Option<Duration>,
+    checks: bool,
+    check_runs: Mutex<HashMap<(Repo, Commit, String), u64>>,
+    /// The base branch of every open PR we have been told about.
+    pr_bases: Mutex<HashMap<(Repo, u32), String>>,
+    replay_log: Option<replay::Log>,
+    /// Where to take requests from `admin`, which must be loopback.
+    admin_listen: Option<String>,
+    permission_ttl: Duration,
 }
 
 impl Worker {
     pub fn new(
         listen: String,
-        host: String,
-        token: String,
+        credentials: Arc<Credentials>,
         user: String,
-        secret: String,
+        secrets: Vec<String>,
         projects: Box<ProjectsConfig>,
         cache_builder: db::Builder,
+        db_builder: db::Builder,
+        sync_interval: Option<Duration>,
+        checks: bool,
+        replay_log: Option<PathBuf>,
+        admin_listen: Option<String>,
+        permission_ttl: Duration,
     ) -> Worker {
         let user_ident = format!("@{}", user);
         Worker {
             listen: listen,
             projects: projects,
             user_ident: user_ident,
-            client: Client::new(USER_AGENT.to_owned())
-                .base(&host)
-                .authorization(Authorization(authorization::Token{
-                    token: token,
-                })),
-            secret: secret,
+            credentials: credentials,
+            secrets: secrets,
             cache: Mutex::new(
                 cache::from_builder(&cache_builder).expect("to get a cache")
             ),
+            db: Mutex::new(db_builder.open().expect("to open the db")),
+            sync_interval: sync_interval,
+            checks: checks,
+            check_runs: Mutex::new(HashMap::new()),
+            pr_bases: Mutex::new(HashMap::new()),
+            replay_log: replay_log.map(replay::Log::new),
+            admin_listen: admin_listen,
+            permission_ttl: permission_ttl,
         }
     }
 }
@@ -151,6 +204,10 @@ struct TeamDesc {
     id: u32,
 }
 #[derive(Deserialize, Serialize)]
+struct MembershipDesc {
+    state: String,
+}
+#[derive(Deserialize, Serialize)]
 struct TeamRepoDesc {
     permissions: Option<TeamRepoPermissions>,
 }
@@ -165,10 +222,17 @@ struct PrBranchDesc {
     sha: String,
 }
 #[derive(Deserialize, Serialize)]
+struct PrBaseDesc {
+    // ref is a reserved word.
+    #[serde(rename="ref")]
+    ref_name: String,
+}
+#[derive(Deserialize, Serialize)]
 struct PrDesc {
     state: String,
     number: u32,
     head: PrBranchDesc,
+    base: PrBaseDesc,
     html_url: String,
     title: String,
 }
@@ -179,13 +243,95 @@ struct PullRequestDesc {
     repository: RepositoryDesc,
 }
 #[derive(Deserialize, Serialize)]
-struct StatusDesc {
+struct ReviewDesc {
+    user: UserDesc,
+    body: Option<String>,
+    commit_id: String,
+    state: String,
+}
+#[derive(Deserialize, Serialize)]
+struct ReviewPrDesc {
+    number: u32,
+    title: String,
+    body: Option<String>,
+    user: UserDesc,
+    base: PrBaseDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct PullRequestReviewDesc {
+    action: String,
+    review: ReviewDesc,
+    pull_request: ReviewPrDesc,
+    repository: RepositoryDesc,
+}
+/// A commit status, as GitHub (and the forges that copy its API) take it.
+#[derive(Deserialize, Serialize)]
+pub struct StatusDesc {
+    pub state: String,
+    pub target_url: Option<String>,
+    pub description: String,
+    pub context: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CombinedStatusDesc {
+    statuses: Vec<CommitStatusDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct CommitStatusDesc {
     state: String,
-    target_url: Option<String>,
-    description: String,
+    description: Option<String>,
     context: String,
 }
 #[derive(Deserialize, Serialize)]
+struct CheckRunDesc {
+    name: String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    head_sha: Option<String>,
+    status: String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    conclusion: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    details_url: Option<String>,
+    output: CheckRunOutputDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct CheckRunOutputDesc {
+    title: String,
+    summary: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CheckRunRefDesc {
+    id: u64,
+    status: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CheckRunsDesc {
+    check_runs: Vec<CheckRunRefDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct InstallationAccountDesc {
+    id: u64,
+    account: OwnerDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct InstallationRepoDesc {
+    name: String,
+}
+#[derive(Deserialize, Serialize)]
+struct InstallationDesc {
+    action: String,
+    installation: InstallationAccountDesc,
+    #[serde(default)]
+    repositories: Vec<InstallationRepoDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct InstallationRepositoriesDesc {
+    action: String,
+    installation: InstallationAccountDesc,
+    #[serde(default)]
+    repositories_added: Vec<InstallationRepoDesc>,
+}
+#[derive(Deserialize, Serialize)]
 struct TeamAddDesc {
     repository: RepositoryDesc,
 }
@@ -200,8 +346,20 @@ impl pipeline::Worker<ui::Event, ui::Message> for Worker {
             let s2 = &*self;
             let send_event_2 = send_event.clone();
             scope.spawn(move || {
-                s2.run_webhook(send_event_2);
+                s2.run_webhook(&s2.listen, false, send_event_2);
             });
+            if let Some(ref admin_listen) = s2.admin_listen {
+                let send_event_4 = send_event.clone();
+                scope.spawn(move || {
+                    s2.run_webhook(admin_listen, true, send_event_4);
+                });
+            }
+            if let Some(interval) = s2.sync_interval {
+                let send_event_3 = send_event.clone();
+                scope.spawn(move || {
+                    s2.run_sync(send_event_3, interval);
+                });
+            }
             loop {
                 s2.handle_message(
                     recv_msg.recv().expect("Pipeline went away"),
@@ -213,12 +371,15 @@ impl pipeline::Worker<ui::Event, ui::Message> for Worker {
 }
 
 impl Worker {
+    /// Take webhooks from GitHub, or, on the admin listener,
+    /// requests from `admin`.
     fn run_webhook(
         &self,
+        listen: &str,
+        admin: bool,
         send_event: Sender<ui::Event>,
     ) {
-        let mut listener = HttpListener::new(&self.listen[..])
-            .expect("webhook");
+        let mut listener = HttpListener::new(listen).expect("webhook");
         while let Ok(mut stream) = listener.accept() {
             let addr = stream.peer_addr()
                 .expect("webhook client address");
@@ -236,21 +397,161 @@ impl Worker {
             };
             let mut head = Headers::new();
             let res = Response::new(&mut buf_write, &mut head);
-            self.handle_webhook(req, res, &send_event);
+            self.handle_webhook(req, res, admin, &send_event);
+        }
+    }
+
+    /// Webhooks that got lost while we were down (or just lost)
+    /// leave the pending PRs out of date, so check them against GitHub.
+    fn run_sync(
+        &self,
+        send_event: Sender<ui::Event>,
+        interval: Duration,
+    ) {
+        let mut open_prs: HashMap<Repo, HashMap<u32, PrDesc>> =
+            HashMap::new();
+        loop {
+            for repo in self.projects.repos() {
+                let known = open_prs.remove(&repo);
+                match self.sync_repo(&repo, known, &send_event) {
+                    Ok(open) => {
+                        open_prs.insert(repo, open);
+                    }
+                    Err(e) => {
+                        warn!("Failed to sync PRs of {:?}: {:?}", repo, e);
+                    }
+                }
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    /// Send events for every PR that changed since the last sync,
+    /// and return the ones that are open now.
+    ///
+    /// Without a last sync, we cannot tell what the pipelines know,
+    /// so every open PR is sent as changed,
+    /// and every recently closed one (the first page of them) as closed.
+    fn sync_repo(
+        &self,
+        repo: &Repo,
+        mut known: Option<HashMap<u32, PrDesc>>,
+        send_event: &Sender<ui::Event>,
+    ) -> Result<HashMap<u32, PrDesc>, GithubRequestError> {
+        let send = |action: &str, desc: &PrDesc| {
+            self.set_pr_base(repo, action, desc);
+            let base = &desc.base.ref_name;
+            match self.projects.pipelines_by_repo(repo, base) {
+                Some(repo_pipelines) => self.handle_pr_desc(
+                    action,
+                    send_event,
+                    &repo_pipelines,
+                    desc,
+                ),
+                None => info!("PR #{} is against {}; skip", desc.number, base),
+            }
+        };
+        let open = try!(self.get_prs(repo, "state=open"));
+        let mut open_prs = HashMap::new();
+        for desc in open {
+            match known.as_mut().map(|k| k.remove(&desc.number)) {
+                None => send("synchronize", &desc),
+                Some(None) => send("opened", &desc),
+                Some(Some(ref old))
+                        if old.base.ref_name != desc.base.ref_name => {
+                    // It belongs to another branch's pipelines now.
+                    send("closed", old);
+                    send("opened", &desc);
+                }
+                Some(Some(ref old)) if old.head.sha != desc.head.sha ||
+                        old.title != desc.title => {
+                    send("synchronize", &desc);
+                }
+                Some(Some(_)) => {}
+            }
+            open_prs.insert(desc.number, desc);
+        }
+        // Whatever is left of the known PRs is not open any more.
+        let closed = match known {
+            Some(known) => known.into_iter().map(|(_, desc)| desc).collect(),
+            None => try!(self.get_prs_page(
+                repo,
+                "state=closed&sort=updated&direction=desc",
+            )),
+        };
+        for desc in closed {
+            send("closed", &desc);
         }
+        Ok(open_prs)
     }
 
     fn handle_webhook(
         &self,
         mut req: Request,
         mut res: Response,
+        admin: bool,
         send_event: &Sender<ui::Event>
     ) {
-        let head = github_headers::parse(&mut req, self.secret.as_bytes());
+        let delivery = req.headers.get_raw("X-GitHub-Delivery")
+            .and_then(|raw| raw.get(0))
+            .map(|raw| String::from_utf8_lossy(raw).into_owned());
+        let (is_replay, is_flush) = {
+            let is_admin_path = |path: &str| {
+                admin && req.uri == RequestUri::AbsolutePath(path.to_owned())
+            };
+            (
+                is_admin_path(admin::REPLAY_PATH),
+                is_admin_path(admin::FLUSH_PERMISSIONS_PATH),
+            )
+        };
+        if admin && !is_replay && !is_flush {
+            warn!("Got unknown admin request {:?}", req.uri);
+            *res.status_mut() = StatusCode::NotFound;
+            if let Err(e) = res.send(&[]) {
+                warn!("Failed to send response to admin: {:?}", e);
+            }
+            return;
+        }
+        let head = github_headers::parse(&mut req, &self.secrets);
         let (x_github_event, body) = match head {
             Some(head) => head,
             None => return,
         };
+        if is_flush {
+            info!("Got admin request to flush permissions");
+            self.cache.lock().unwrap().flush_permissions();
+            *res.status_mut() = StatusCode::NoContent;
+            if let Err(e) = res.send(&[]) {
+                warn!("Failed to send response to flush: {:?}", e);
+            }
+            return;
+        }
+        if is_replay {
+            info!("Got replayed delivery {:?}", delivery);
+        } else {
+            if let Some(ref delivery) = delivery {
+                let ttl = Duration::from_secs(DELIVERY_TTL_SEC);
+                if !self.cache.lock().unwrap().add_delivery(delivery, ttl) {
+                    info!("Got duplicate delivery {}; do nothing", delivery);
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to duplicate: {:?}",
+                            e,
+                        );
+                    }
+                    return;
+                }
+            }
+            if let Some(ref replay_log) = self.replay_log {
+                let delivery = delivery.as_ref().map(|d| &d[..]);
+                let result =
+                    replay_log.append(delivery, &x_github_event, &body);
+                if let Err(e) = result {
+                    warn!("Failed to write to the replay log: {:?}", e);
+                }
+            }
+        }
         match &x_github_event[..] {
             b"issue_comment" => {
                 if let Ok(desc) = json_from_slice::<CommentDesc>(&body) {
@@ -297,40 +598,26 @@ impl Worker {
                         owner: desc.repository.owner.login,
                         repo: desc.repository.name,
                     };
-                    let pr = Pr::from(desc.pull_request.number.to_string());
-                    let repo_pipelines =
-                        match self.projects.pipelines_by_repo(&repo) {
-                            Some(repo_pipelines) => repo_pipelines,
-                            None => {
-                                warn!(
-                                    "Got bad repo {:?}",
-                                    repo
-                                );
-                                return;
-                            }
-                        };
-                    let commit = Commit::from(
-                        desc.pull_request.head.sha
-                    );
-                    if let Some(pipeline_id) = repo_pipelines.try_pipeline_id {
-                        self.handle_pr_update(
-                            &desc.action[..],
-                            send_event,
-                            pipeline_id,
-                            commit.clone(),
-                            pr.clone(),
-                            desc.pull_request.title.clone(),
-                            desc.pull_request.html_url.clone(),
-                        );
-                    }
-                    self.handle_pr_update(
+                    self.set_pr_base(&repo, &desc.action, &desc.pull_request);
+                    let repo_pipelines = match self.projects.pipelines_by_repo(
+                        &repo,
+                        &desc.pull_request.base.ref_name,
+                    ) {
+                        Some(repo_pipelines) => repo_pipelines,
+                        None => {
+                            warn!(
+                                "Got bad repo {:?} branch {}",
+                                repo,
+                                desc.pull_request.base.ref_name,
+                            );
+                            return;
+                        }
+                    };
+                    self.handle_pr_desc(
                         &desc.action[..],
                         send_event,
-                        repo_pipelines.pipeline_id,
-                        commit,
-                        pr,
-                        desc.pull_request.title,
-                        desc.pull_request.html_url,
+                        &repo_pipelines,
+                        &desc.pull_request,
                     );
                 } else {
                     warn!("Got invalid PR message");
@@ -340,6 +627,33 @@ impl Worker {
                     }
                 }
             }
+            b"pull_request_review" => {
+                let desc = json_from_slice::<PullRequestReviewDesc>(&body);
+                if let Ok(desc) = desc {
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to Github review: {:?}",
+                            e,
+                        );
+                    }
+                    if desc.action == "submitted" {
+                        info!("Got pull request review");
+                        self.handle_pr_review(send_event, desc);
+                    } else {
+                        info!("Got pull request review {}", desc.action);
+                    }
+                } else {
+                    warn!("Got invalid review");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad review: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
             b"ping" => {
                 if let Ok(desc) = json_from_slice::<PingDesc>(&body) {
                     info!("Got Ping: {}", desc.zen);
@@ -366,14 +680,12 @@ impl Worker {
                         owner: desc.repository.owner.login,
                         repo: desc.repository.name,
                     };
-                    let repo_pipelines =
-                        match self.projects.pipelines_by_repo(&repo) {
-                            Some(repo_pipelines) => repo_pipelines,
-                            None => {
-                                warn!("team add event for nonexistant repo");
-                                return;
-                            }
-                        };
+                    let all_pipelines =
+                        self.projects.all_pipelines_by_repo(&repo);
+                    if all_pipelines.is_empty() {
+                        warn!("team add event for nonexistant repo");
+                        return;
+                    }
                     let mut cache = self.cache.lock().unwrap();
                     let teams = match self.get_all_teams_with_write(&repo) {
                         Ok(t) => t,
@@ -382,10 +694,12 @@ impl Worker {
                             return;
                         }
                     };
-                    cache.set_teams_with_write(
-                        repo_pipelines.pipeline_id,
-                        teams.iter().cloned(),
-                    );
+                    for repo_pipelines in all_pipelines {
+                        cache.set_teams_with_write(
+                            repo_pipelines.pipeline_id,
+                            teams.iter().cloned(),
+                        );
+                    }
                 } else {
                     warn!("Got invalid team add event");
                     *res.status_mut() = StatusCode::BadRequest;
@@ -397,6 +711,85 @@ impl Worker {
                     }
                 }
             }
+            b"member" | b"membership" | b"team" => {
+                info!(
+                    "Got {} event; flush permissions",
+                    String::from_utf8_lossy(&x_github_event),
+                );
+                *res.status_mut() = StatusCode::NoContent;
+                if let Err(e) = res.send(&[]) {
+                    warn!("Failed to send response to Github team: {:?}", e);
+                }
+                self.cache.lock().unwrap().flush_permissions();
+            }
+            b"installation" => {
+                if let Ok(desc) = json_from_slice::<InstallationDesc>(&body) {
+                    info!("Got installation event: {}", desc.action);
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to installation: {:?}",
+                            e,
+                        );
+                    }
+                    let owner = &desc.installation.account.login;
+                    if desc.action == "deleted" {
+                        self.credentials.remove_installation(owner);
+                    } else {
+                        self.credentials.add_installation(
+                            owner,
+                            desc.installation.id,
+                        );
+                        self.handle_installed_repos(
+                            owner,
+                            &desc.repositories,
+                            send_event,
+                        );
+                    }
+                } else {
+                    warn!("Got invalid installation event");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad installation: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
+            b"installation_repositories" => {
+                let desc =
+                    json_from_slice::<InstallationRepositoriesDesc>(&body);
+                if let Ok(desc) = desc {
+                    info!("Got installation repositories: {}", desc.action);
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to installation: {:?}",
+                            e,
+                        );
+                    }
+                    let owner = &desc.installation.account.login;
+                    self.credentials.add_installation(
+                        owner,
+                        desc.installation.id,
+                    );
+                    self.handle_installed_repos(
+                        owner,
+                        &desc.repositories_added,
+                        send_event,
+                    );
+                } else {
+                    warn!("Got invalid installation repositories event");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad installation: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
             e => {
                 *res.status_mut() = StatusCode::BadRequest;
                 if let Err(e) = res.send(&[]) {
@@ -413,6 +806,60 @@ impl Worker {
         }
     }
 
+    /// The app can see repos it was just given access to,
+    /// so pick up the PRs that were opened before then.
+    fn handle_installed_repos(
+        &self,
+        owner: &str,
+        repos: &[InstallationRepoDesc],
+        send_event: &Sender<ui::Event>,
+    ) {
+        for repo in repos {
+            let repo = Repo{
+                owner: owner.to_owned(),
+                repo: repo.name.clone(),
+            };
+            if self.projects.all_pipelines_by_repo(&repo).is_empty() {
+                info!("Installed on {:?}, which has no pipelines", repo);
+                continue;
+            }
+            if let Err(e) = self.sync_repo(&repo, None, send_event) {
+                warn!("Failed to sync PRs of {:?}: {:?}", repo, e);
+            }
+        }
+    }
+
+    fn handle_pr_desc(
+        &self,
+        action: &str,
+        send_event: &Sender<ui::Event>,
+        repo_pipelines: &RepoPipelines,
+        desc: &PrDesc,
+    ) {
+        let pr = Pr::from(desc.number.to_string());
+        let commit = Commit::from(desc.head.sha.clone());
+        if let Some(pipeline_id) = repo_pipelines.try_pipeline_id {
+            self.handle_pr_update(
+                action,
+                send_event,
+                pipeline_id,
+                commit.clone(),
+                pr.clone(),
+                desc.title.clone(),
+                desc.html_url.clone(),
+            );
+        }
+        self.handle_pr_update(
+            action,
+            send_event,
+            repo_pipelines.pipeline_id,
+            commit,
+            pr,
+            desc.title.clone(),
+            desc.html_url.clone(),
+        );
+    }
+
     fn handle_pr_update(
         &self,
         action: &str,
@@ -459,36 +906,125 @@ impl Worker {
             repo: desc.repository.name,
         };
         let pr = Pr::from(desc.issue.number.to_string());
-        let repo_pipelines = match self.projects.pipelines_by_repo(&repo) {
-            Some(repo_pipelines) => repo_pipelines,
+        let repo_pipelines =
+            match self.pipelines_by_pr(&repo, desc.issue.number) {
+                Ok(Some(repo_pipelines)) => repo_pipelines,
+                Ok(None) => {
+                    warn!(
+                        "Got bad repo {:?}",
+                        repo
+                    );
+                    return;
+                }
+                Err(e) => {
+                    warn!("Failed to get the branch of {}: {:?}", pr, e);
+                    return;
+                }
+            };
+        let user = &desc.comment.user.login;
+        let body = &desc.comment.body;
+        let command = match comments::parse(&body, user) {
+            Some(command) => command,
             None => {
-                warn!(
-                    "Got bad repo {:?}",
-                    repo
-                );
+                info!("Pull request comment is not a command");
                 return;
             }
         };
-        let user = &desc.comment.user.login;
-        let body = &desc.comment.body;
-        let pipeline_id = repo_pipelines.pipeline_id;
-        let allowed = self.user_has_write(user, &repo, pipeline_id)
+        let is_try = match command {
+            comments::Command::TryApproved(_, _) |
+            comments::Command::TryCanceled => true,
+            _ => false,
+        };
+        let delegated = match command {
+            comments::Command::Approved(_, _, _) |
+            comments::Command::Canceled =>
+                self.is_delegated(repo_pipelines.pipeline_id, &pr, user),
+            _ => false,
+        };
+        let allowed = delegated ||
+            self.user_may(user, &repo, &repo_pipelines, is_try)
             .unwrap_or_else(|e| {
                 warn!("Failed to check if {} has permission: {:?}", user, e);
                 false
             });
         if !allowed {
             info!("Got mentioned by not-permitted user");
-        } else if let Some(command) = comments::parse(&body, user) {
+        } else {
             self.handle_comment_command(
                 send_event,
                 command,
+                &repo,
                 &desc.issue,
                 &repo_pipelines,
                 pr,
             );
+        }
+    }
+
+    fn handle_pr_review(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: PullRequestReviewDesc,
+    ) {
+        let repo = Repo{
+            owner: desc.repository.owner.login,
+            repo: desc.repository.name,
+        };
+        let pr = Pr::from(desc.pull_request.number.to_string());
+        let repo_pipelines = match self.projects.pipelines_by_repo(
+            &repo,
+            &desc.pull_request.base.ref_name,
+        ) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                warn!(
+                    "Got bad repo {:?} branch {}",
+                    repo,
+                    desc.pull_request.base.ref_name,
+                );
+                return;
+            }
+        };
+        let review = desc.review;
+        let approved = match &review.state.to_lowercase()[..] {
+            "approved" => true,
+            "changes_requested" => false,
+            _ => {
+                info!("Review does not approve or reject; do nothing");
+                return;
+            }
+        };
+        let mentioned = review.body.as_ref()
+            .map(|body| body.contains(&self.user_ident))
+            .unwrap_or(false);
+        if !repo_pipelines.review_approves && !mentioned {
+            info!("Review does not mention me; do nothing");
+            return;
+        }
+        let user = &review.user.login;
+        let pipeline_id = repo_pipelines.pipeline_id;
+        let allowed = self.is_delegated(pipeline_id, &pr, user) ||
+            self.user_may(user, &repo, &repo_pipelines, false)
+            .unwrap_or_else(|e| {
+                warn!("Failed to check if {} has permission: {:?}", user, e);
+                false
+            });
+        if !allowed {
+            info!("Got review by not-permitted user");
+        } else if approved {
+            self.handle_approved_pr(
+                pipeline_id,
+                send_event,
+                &desc.pull_request.title,
+                &desc.pull_request.user.login,
+                desc.pull_request.body.as_ref().map(|x| &x[..]),
+                pr,
+                user,
+                Some(Commit::from(review.commit_id.clone())),
+                0,
+            );
         } else {
-            info!("Pull request comment is not a command");
+            self.handle_canceled_pr(pipeline_id, send_event, pr);
         }
     }
 
@@ -496,19 +1032,23 @@ impl Worker {
         &self,
         send_event: &Sender<ui::Event>,
         command: comments::Command,
+        repo: &Repo,
         issue: &IssueCommentIssue,
         repo_pipelines: &RepoPipelines,
         pr: Pr,
     ) {
         match command {
-            comments::Command::Approved(user, commit) => {
+            comments::Command::Approved(user, commit, priority) => {
                 self.handle_approved_pr(
                     repo_pipelines.pipeline_id,
                     send_event,
-                    issue,
+                    &issue.title,
+                    &issue.user.login,
+                    issue.body.as_ref().map(|x| &x[..]),
                     pr,
                     user,
                     commit,
+                    priority,
                 );
             }
             comments::Command::Canceled => {
@@ -518,15 +1058,35 @@ impl Worker {
                     pr,
                 );
             }
+            comments::Command::Retry => {
+                send_event.send(ui::Event::Retry(
+                    repo_pipelines.pipeline_id,
+                    pr,
+                )).expect("PR Retry: Pipeline error");
+            }
+            comments::Command::TreeClosed(priority) => {
+                send_event.send(ui::Event::TreeClosed(
+                    repo_pipelines.pipeline_id,
+                    priority,
+                )).expect("Tree closed: Pipeline error");
+            }
+            comments::Command::TreeOpened => {
+                send_event.send(ui::Event::TreeOpened(
+                    repo_pipelines.pipeline_id,
+                )).expect("Tree opened: Pipeline error");
+            }
             comments::Command::TryApproved(user, commit) => {
                 if let Some(try_pipeline_id) = repo_pipelines.try_pipeline_id {
                     self.handle_approved_pr(
                         try_pipeline_id,
                         send_event,
-                        issue,
+                        &issue.title,
+                        &issue.user.login,
+                        issue.body.as_ref().map(|x| &x[..]),
                         pr,
                         user,
                         commit,
+                        0,
                     );
                 }
             }
@@ -539,6 +1099,69 @@ impl Worker {
                     );
                 }
             }
+            comments::Command::Delegated(user) => {
+                self.handle_delegated_pr(
+                    repo,
+                    repo_pipelines.pipeline_id,
+                    issue,
+                    &pr,
+                    user.unwrap_or(&issue.user.login),
+                );
+            }
+            comments::Command::Undelegated => {
+                if let Err(e) = self.db.lock().unwrap().remove_delegations(
+                    repo_pipelines.pipeline_id,
+                    &pr,
+                    None,
+                ) {
+                    warn!("Failed to undelegate {}: {:?}", pr, e);
+                }
+            }
+        }
+    }
+
+    /// The delegation is tied to the PR's head commit,
+    /// so that the pipeline takes it back when more commits are pushed.
+    fn handle_delegated_pr(
+        &self,
+        repo: &Repo,
+        pipeline_id: PipelineId,
+        issue: &IssueCommentIssue,
+        pr: &Pr,
+        user: &str,
+    ) {
+        let desc = match self.get_pr(repo, issue.number) {
+            Ok(desc) => desc,
+            Err(e) => {
+                warn!("Failed to get the head of {}: {:?}", pr, e);
+                return;
+            }
+        };
+        info!("Delegating {} to {}", pr, user);
+        if let Err(e) = self.db.lock().unwrap().add_delegation(
+            pipeline_id,
+            pr,
+            &user.to_lowercase(),
+            &Commit::from(desc.head.sha),
+        ) {
+            warn!("Failed to delegate {}: {:?}", pr, e);
+        }
+    }
+
+    fn is_delegated(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        user: &str,
+    ) -> bool {
+        let delegated = self.db.lock().unwrap()
+            .is_delegated(pipeline_id, pr, &user.to_lowercase());
+        match delegated {
+            Ok(delegated) => delegated,
+            Err(e) => {
+                warn!("Failed to check delegations on {}: {:?}", pr, e);
+                false
+            }
         }
     }
 
@@ -546,25 +1169,30 @@ impl Worker {
         &self,
         pipeline_id: PipelineId,
         send_event: &Sender<ui::Event>,
-        issue: &IssueCommentIssue,
+        title: &str,
+        author: &str,
+        body: Option<&str>,
         pr: Pr,
         user: &str,
         commit: Option<Commit>,
+        priority: i32,
     ) {
         let message = format!(
             "{}\n\nMerge #{} a=@{} r=@{}\n{}\n\n{}",
-            issue.title,
+            title,
             pr,
-            issue.user.login,
+            author,
             user,
             iter::repeat('_').take(72).collect::<String>(),
-            issue.body.as_ref().map(|x| &x[..]).unwrap_or(""),
+            body.unwrap_or(""),
         );
         send_event.send(ui::Event::Approved(
             pipeline_id,
             pr,
             commit,
             message,
+            priority,
+            user.to_owned(),
         )).expect("PR Approved: Pipeline error");
     }
 
@@ -586,8 +1214,13 @@ impl Worker {
         _: &mut Sender<ui::Event>,
     ) {
         match msg {
-            ui::Message::SendResult(pipeline_id, pr, status) => {
-                let result = self.send_result_to_pr(pipeline_id, &pr, &status);
+            ui::Message::SendResult(pipeline_id, pr, status, details) => {
+                let result = self.send_result_to_pr(
+                    pipeline_id,
+                    &pr,
+                    &status,
+                    &details,
+                );
                 if let Err(e) = result {
                     warn!("Failed to send {:?} to pr {}: {:?}", status, pr, e)
                 }
@@ -595,11 +1228,73 @@ impl Worker {
         }
     }
 
+    /// Whether the user may `r+`, or `try+` if `is_try`, on the repo.
+    ///
+    /// Projects can list who may, and otherwise it's whoever can push.
+    /// Anyone who may `r+` may also `try+`.
+    fn user_may(
+        &self,
+        user: &str,
+        repo: &Repo,
+        repo_pipelines: &RepoPipelines,
+        is_try: bool,
+    ) -> Result<bool, GithubRequestError> {
+        if is_try {
+            if let Some(ref try_users) = repo_pipelines.try_users {
+                if try!(self.user_is_listed(user, repo, try_users)) {
+                    return Ok(true);
+                }
+            }
+        }
+        match repo_pipelines.reviewers {
+            Some(ref reviewers) => self.user_is_listed(user, repo, reviewers),
+            None => self.user_has_write(user, repo, repo_pipelines.pipeline_id),
+        }
+    }
+
+    fn user_is_listed(
+        &self,
+        user: &str,
+        repo: &Repo,
+        list: &AccessList,
+    ) -> Result<bool, GithubRequestError> {
+        // GitHub logins are not case-sensitive.
+        let user_lower = user.to_lowercase();
+        if list.users.iter().any(|u| u.to_lowercase() == user_lower) {
+            return Ok(true);
+        }
+        for team in &list.teams {
+            if try!(self.user_is_member_of_slug(user, repo, team)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
     fn user_has_write(
         &self,
         user: &str,
         repo: &Repo,
         pipeline_id: PipelineId,
+    ) -> Result<bool, GithubRequestError> {
+        let cached = self.cache.lock().unwrap()
+            .user_has_write(pipeline_id, user, self.permission_ttl);
+        if let Some(has_write) = cached {
+            info!("Using cached permission check");
+            return Ok(has_write);
+        }
+        let has_write =
+            try!(self.check_user_has_write(user, repo, pipeline_id));
+        self.cache.lock().unwrap()
+            .set_user_has_write(pipeline_id, user, has_write);
+        Ok(has_write)
+    }
+
+    fn check_user_has_write(
+        &self,
+        user: &str,
+        repo: &Repo,
+        pipeline_id: PipelineId,
     ) -> Result<bool, GithubRequestError> {
         let mut cache = self.cache.lock().unwrap();
         match cache.is_org(pipeline_id) {
@@ -608,7 +1303,7 @@ impl Worker {
                 let teams = cache.teams_with_write(pipeline_id);
                 let mut allowed = false;
                 for team in teams {
-                    if try!(self.user_is_member_of(user, team)) {
+                    if try!(self.user_is_member_of(user, repo, team)) {
                         allowed = true;
                         break;
                     }
@@ -632,7 +1327,7 @@ impl Worker {
                     );
                 }
                 drop(cache);
-                self.user_has_write(user, repo, pipeline_id)
+                self.check_user_has_write(user, repo, pipeline_id)
             }
         }
     }
@@ -642,6 +1337,7 @@ impl Worker {
         pipeline_id: PipelineId,
         pr: &Pr,
         status: &ui::Status,
+        details: &ui::Details,
     ) -> Result<(), GithubRequestError> {
         let (repo, pipeline_type) =
             match self.projects.repo_by_pipeline(pipeline_id) {
@@ -650,147 +1346,21 @@ impl Worker {
                     return Err(GithubRequestError::Pipeline(pipeline_id));
                 }
             };
-        let comment_body = match *status {
-            ui::Status::Approved(_) => None,
-            ui::Status::StartingBuild(_, _) => None,
-            ui::Status::Testing(_, _, _) => None,
-            ui::Status::Success(_, _, ref url) => Some({
-                if let Some(ref url) = *url {
-                    Cow::Owned(format!(":+1: [Build succeeded]({})", url))
-                } else {
-                    Cow::Borrowed(":+1: Build succeeded")
-                }
-            }),
-            ui::Status::Failure(_, _, ref url) => Some({
-                if let Some(ref url) = *url {
-                    Cow::Owned(format!(":-1: [Build failed]({})", url))
-                } else {
-                    Cow::Borrowed(":-1: Build failed")
-                }
-            }),
-            ui::Status::Unmergeable(_) => Some(Cow::Borrowed(
-                ":x: Merge conflict!"
-            )),
-            ui::Status::Unmoveable(_, _) => Some(Cow::Borrowed(
-                ":scream: Internal error while fast-forward master"
-            )),
-            ui::Status::Invalidated => Some(Cow::Borrowed(
-                ":no_good: New commits added"
-            )),
-            ui::Status::NoCommit => Some(Cow::Borrowed(
-                ":scream: Internal error: no commit found for PR"
-            )),
-            ui::Status::Completed(_, _) => None,
-        };
+        let client = try!(self.client(&repo));
+        let comment_body = comment_body(status);
         let context = match pipeline_type {
             PipelineType::Stage => "continuous-integration/aelita",
             PipelineType::Try => "continuous-integration/aelita/try",
         }.to_owned();
-        let status = match *status {
-            ui::Status::Approved(ref pull_commit) => Some((
-                pull_commit,
-                None,
-                StatusDesc {
-                    state: "pending".to_owned(),
-                    target_url: None,
-                    description: format!(
-                        "Approved {}",
-                        pull_commit,
-                    ),
-                    context: context,
-                }
-            )),
-            ui::Status::StartingBuild(
-                ref pull_commit,
-                ref merge_commit,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "pending".to_owned(),
-                    target_url: None,
-                    description: format!(
-                        "Testing {} with merge commit {}",
-                        &pull_commit.to_short_string()[..],
-                        &merge_commit.to_short_string()[..],
-                    ),
-                    context: context,
-                }
-            )),
-            ui::Status::Testing(
-                ref pull_commit,
-                ref merge_commit,
-                ref url,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "pending".to_owned(),
-                    target_url: url.as_ref().map(ToString::to_string),
-                    description: format!(
-                        "Testing {} with merge commit {}",
-                        &pull_commit.to_short_string()[..],
-                        &merge_commit.to_short_string()[..],
-                    ),
-                    context: context,
-                }
-            )),
-            ui::Status::Success(
-                ref pull_commit,
-                ref merge_commit,
-                ref url,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "success".to_owned(),
-                    target_url: url.as_ref().map(ToString::to_string),
-                    description: "Tests passed".to_owned(),
-                    context: context,
-                }
-            )),
-            ui::Status::Failure(
-                ref pull_commit,
-                ref merge_commit, 
-                ref url,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "failure".to_owned(),
-                    target_url: url.as_ref().map(ToString::to_string),
-                    description: "Tests failed".to_owned(),
-                    context: context,
-                }
-            )),
-            ui::Status::Unmergeable(
-                ref pull_commit,
-            ) => Some((
-                pull_commit,
-                None,
-                StatusDesc {
-                    state: "failure".to_owned(),
-                    target_url: None,
-                    description: "Merge failed".to_owned(),
-                    context: context,
-                }
-            )),
-            ui::Status::Unmoveable(
-                ref pull_commit,
-                ref merge_commit,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "error".to_owned(),
-                    target_url: None,
-                    description: "Merge failed".to_owned(),
-                    context: context,
-                }
-            )),
-            ui::Status::Invalidated | ui::Status::NoCommit => None,
-            ui::Status::Completed(_, _) => None,
+        let check_run = if self.checks {
+            Some((
+                check_run_state(status),
+                self.check_run_summary(&repo, status, details),
+            ))
+        } else {
+            None
         };
+        let status = commit_status(status, context);
         if let Some(comment_body) = comment_body {
             let url = format!(
                 "/repos/{}/{}/issues/{}/comments",
@@ -801,10 +1371,9 @@ impl Worker {
             let comment = PostCommentComment{
                 body: comment_body.into_owned(),
             };
-            let resp = try!(
-                try!(self.client.post(&url).expect("url").json(&comment))
+            let resp = github_send!(
+                try!(client.post(&url).expect("url").json(&comment))
                     .header(Self::accept(AcceptType::Regular))
-                    .send()
             );
             if !resp.is_success() {
                 return Err(GithubRequestError::HttpStatus(resp.http.status))
@@ -812,16 +1381,34 @@ impl Worker {
         }
         if let Some(status) = status {
             let (pull_commit, merge_commit, status_body) = status;
+            if let Some((state, ref summary)) = check_run {
+                try!(self.send_check_run(
+                    &repo,
+                    pull_commit,
+                    state,
+                    &status_body,
+                    summary,
+                ));
+                if let Some(merge_commit) = merge_commit {
+                    try!(self.send_check_run(
+                        &repo,
+                        merge_commit,
+                        state,
+                        &status_body,
+                        summary,
+                    ));
+                }
+                return Ok(());
+            }
             let url = format!(
                 "/repos/{}/{}/statuses/{}",
                 repo.owner,
                 repo.repo,
                 pull_commit
             );
-            let resp = try!(
-                try!(self.client.post(&url).expect("url").json(&status_body))
+            let resp = github_send!(
+                try!(client.post(&url).expect("url").json(&status_body))
                     .header(Self::accept(AcceptType::Regular))
-                    .send()
             );
             if !resp.is_success() {
                 return Err(GithubRequestError::HttpStatus(resp.http.status))
@@ -833,13 +1420,12 @@ impl Worker {
                     repo.repo,
                     merge_commit
                 );
-                let resp = try!(
+                let resp = github_send!(
                     try!(
-                        self.client.post(&url).expect("url")
+                        client.post(&url).expect("url")
                             .json(&status_body)
                     )
                         .header(Self::accept(AcceptType::Regular))
-                        .send()
                 );
                 if !resp.is_success() {
                     return Err(GithubRequestError::HttpStatus(
@@ -851,20 +1437,220 @@ impl Worker {
         Ok(())
     }
 
+    /// Create the check run for a commit, or update it if it has not
+    /// completed yet, so that a PR has one check run that moves along
+    /// the pipeline instead of a new status at every step.
+    fn send_check_run(
+        &self,
+        repo: &Repo,
+        commit: &Commit,
+        state: (&'static str, Option<&'static str>),
+        status_body: &StatusDesc,
+        summary: &str,
+    ) -> Result<(), GithubRequestError> {
+        let client = try!(self.client(repo));
+        let key = (repo.clone(), commit.clone(), status_body.context.clone());
+        let id = self.check_runs.lock().unwrap().get(&key).cloned();
+        let id = match id {
+            Some(id) => Some(id),
+            None => try!(self.find_check_run(
+                repo,
+                commit,
+                &status_body.context,
+            )),
+        };
+        let (status, conclusion) = state;
+        let check_run = CheckRunDesc {
+            name: status_body.context.clone(),
+            head_sha: match id {
+                Some(_) => None,
+                None => Some(commit.to_string()),
+            },
+            status: status.to_owned(),
+            conclusion: conclusion.map(ToOwned::to_owned),
+            details_url: status_body.target_url.clone(),
+            output: CheckRunOutputDesc {
+                title: status_body.description.clone(),
+                // GitHub will not take an empty summary.
+                summary: if summary.is_empty() {
+                    status_body.description.clone()
+                } else {
+                    summary.to_owned()
+                },
+            },
+        };
+        let resp = if let Some(id) = id {
+            let url = format!(
+                "/repos/{}/{}/check-runs/{}",
+                repo.owner,
+                repo.repo,
+                id
+            );
+            github_send!(
+                try!(client.patch(&url).expect("url").json(&check_run))
+                    .header(Self::accept(AcceptType::Checks))
+            )
+        } else {
+            let url = format!(
+                "/repos/{}/{}/check-runs",
+                repo.owner,
+                repo.repo
+            );
+            github_send!(
+                try!(client.post(&url).expect("url").json(&check_run))
+                    .header(Self::accept(AcceptType::Checks))
+            )
+        };
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+        let check_run: CheckRunRefDesc = try!(resp.json());
+        let mut check_runs = self.check_runs.lock().unwrap();
+        if conclusion.is_some() {
+            // A retry gets a check run of its own.
+            check_runs.remove(&key);
+        } else {
+            check_runs.insert(key, check_run.id);
+        }
+        Ok(())
+    }
+
+    /// Look for an unfinished check run we made before a restart.
+    fn find_check_run(
+        &self,
+        repo: &Repo,
+        commit: &Commit,
+        name: &str,
+    ) -> Result<Option<u64>, GithubRequestError> {
+        let client = try!(self.client(repo));
+        let url = format!(
+            "/repos/{}/{}/commits/{}/check-runs?check_name={}",
+            repo.owner,
+            repo.repo,
+            commit,
+            name,
+        );
+        let resp = github_send!(
+            client.get(&url).expect("valid url")
+                .header(Self::accept(AcceptType::Checks))
+        );
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+        let check_runs: CheckRunsDesc = try!(resp.json());
+        Ok(check_runs.check_runs.into_iter()
+            .find(|check_run| check_run.status != "completed")
+            .map(|check_run| check_run.id))
+    }
+
+    /// The markdown summary of a check run: where the PR is in the queue,
+    /// what is being tested, and what the CI had to say about it.
+    fn check_run_summary(
+        &self,
+        repo: &Repo,
+        status: &ui::Status,
+        details: &ui::Details,
+    ) -> String {
+        let mut summary = String::new();
+        if let Some(queue_position) = details.queue_position {
+            summary.push_str(&format!(
+                "**Queue position:** {}\n\n",
+                queue_position,
+            ));
+        }
+        let merge_commit = match *status {
+            ui::Status::StartingBuild(_, ref merge_commit) |
+            ui::Status::Testing(_, ref merge_commit, _) |
+            ui::Status::Success(_, ref merge_commit, _) |
+            ui::Status::Failure(_, ref merge_commit, _) |
+            ui::Status::Retrying(_, ref merge_commit, _) |
+            ui::Status::TimedOut(_, ref merge_commit) |
+            ui::Status::Unmoveable(_, ref merge_commit) => Some(merge_commit),
+            _ => None,
+        };
+        if let Some(merge_commit) = merge_commit {
+            summary.push_str(&format!(
+                "**Merge commit:** {}\n\n",
+                merge_commit,
+            ));
+        }
+        if !details.ci_urls.is_empty() {
+            summary.push_str("**Builds:**\n\n");
+            for &(ci_id, ref url) in &details.ci_urls {
+                summary.push_str(&format!(
+                    "* [Build {}]({})\n",
+                    ci_id.0,
+                    url,
+                ));
+            }
+            summary.push_str("\n");
+        }
+        if let ui::Status::Failure(_, ref merge_commit, _) = *status {
+            match self.failure_excerpt(repo, merge_commit) {
+                Ok(ref excerpt) if !excerpt.is_empty() => {
+                    summary.push_str("**Failures:**\n\n```\n");
+                    summary.push_str(excerpt);
+                    summary.push_str("\n```\n");
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("Failed to get statuses of {}: {:?}", merge_commit, e)
+                }
+            }
+        }
+        summary
+    }
+
+    /// The failing statuses on a merge commit, one per line.
+    fn failure_excerpt(
+        &self,
+        repo: &Repo,
+        merge_commit: &Commit,
+    ) -> Result<String, GithubRequestError> {
+        let client = try!(self.client(repo));
+        let url = format!(
+            "/repos/{}/{}/commits/{}/status",
+            repo.owner,
+            repo.repo,
+            merge_commit,
+        );
+        let resp = github_send!(
+            client.get(&url).expect("valid url")
+                .header(Self::accept(AcceptType::Regular))
+        );
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+        let combined: CombinedStatusDesc = try!(resp.json());
+        let lines: Vec<String> = combined.statuses.into_iter()
+            .filter(|status| {
+                (status.state == "failure" || status.state == "error") &&
+                    !status.context.starts_with("continuous-integration/aelita")
+            })
+            .map(|status| match status.description {
+                Some(description) =>
+                    format!("{}: {}", status.context, description),
+                None => status.context,
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
     fn user_is_member_of(
         &self,
         user: &str,
+        repo: &Repo,
         team: TeamId,
     ) -> Result<bool, GithubRequestError> {
+        let client = try!(self.client(repo));
         let url = format!(
             "/teams/{}/members/{}",
             team.0,
             user,
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = github_send!(
+            client.get(&url).expect("valid url")
                 .header(Self::accept(AcceptType::Regular))
-                .send()
         );
         if resp.http.status == StatusCode::NotFound {
             Ok(false)
@@ -875,21 +1661,48 @@ impl Worker {
         }
     }
 
+    fn user_is_member_of_slug(
+        &self,
+        user: &str,
+        repo: &Repo,
+        team: &str,
+    ) -> Result<bool, GithubRequestError> {
+        let client = try!(self.client(repo));
+        let url = format!(
+            "/orgs/{}/teams/{}/memberships/{}",
+            repo.owner,
+            team,
+            user,
+        );
+        let resp = github_send!(
+            client.get(&url).expect("valid url")
+                .header(Self::accept(AcceptType::Regular))
+        );
+        if resp.http.status == StatusCode::NotFound {
+            Ok(false)
+        } else if resp.is_success() {
+            let membership: MembershipDesc = try!(resp.json());
+            Ok(membership.state == "active")
+        } else {
+            Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+    }
+
     fn user_is_collaborator_for(
         &self,
         user: &str,
         repo: &Repo,
     ) -> Result<bool, GithubRequestError> {
+        let client = try!(self.client(repo));
         let url = format!(
             "/repos/{}/{}/collaborators/{}",
             repo.owner,
             repo.repo,
             user,
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = github_send!(
+            client.get(&url).expect("valid url")
                 .header(Self::accept(AcceptType::Regular))
-                .send()
         );
         if resp.http.status == StatusCode::NotFound {
             Ok(false)
@@ -904,15 +1717,15 @@ impl Worker {
         &self,
         repo: &Repo,
     ) -> Result<bool, GithubRequestError> {
+        let client = try!(self.client(repo));
         let url = format!(
             "/repos/{}/{}",
             repo.owner,
             repo.repo,
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = github_send!(
+            client.get(&url).expect("valid url")
                 .header(Self::accept(AcceptType::Regular))
-                .send()
         );
         if resp.is_success() {
             let repo_desc: RepositoryDesc = try!(resp.json());
@@ -932,59 +1745,402 @@ impl Worker {
         }
     }
 
-    fn get_all_teams_with_write(
+    /// Comments do not say which branch their PR is against,
+    /// so use the one the PR's own events said,
+    /// or ask GitHub if we have not seen any.
+    fn pipelines_by_pr(
         &self,
         repo: &Repo,
-    ) -> Result<HashSet<TeamId>, GithubRequestError> {
+        number: u32,
+    ) -> Result<Option<RepoPipelines>, GithubRequestError> {
+        let base = self.pr_bases.lock().unwrap()
+            .get(&(repo.clone(), number))
+            .cloned();
+        let base = match base {
+            Some(base) => base,
+            None => {
+                let desc = try!(self.get_pr(repo, number));
+                self.set_pr_base(repo, &desc.state, &desc);
+                desc.base.ref_name
+            }
+        };
+        Ok(self.projects.pipelines_by_repo(repo, &base))
+    }
+
+    /// Remember which branch a PR is against, until it is closed.
+    fn set_pr_base(&self, repo: &Repo, action: &str, desc: &PrDesc) {
+        let key = (repo.clone(), desc.number);
+        let mut pr_bases = self.pr_bases.lock().unwrap();
+        if action == "closed" {
+            pr_bases.remove(&key);
+        } else {
+            pr_bases.insert(key, desc.base.ref_name.clone());
+        }
+    }
+
+    fn get_pr(
+        &self,
+        repo: &Repo,
+        number: u32,
+    ) -> Result<PrDesc, GithubRequestError> {
+        let client = try!(self.client(repo));
         let url = format!(
-            "/orgs/{}/teams",
+            "/repos/{}/{}/pulls/{}",
             repo.owner,
+            repo.repo,
+            number,
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = github_send!(
+            client.get(&url).expect("valid url")
                 .header(Self::accept(AcceptType::Regular))
-                .send()
         );
         if resp.is_success() {
-            let all_teams: Vec<TeamDesc> = try!(resp.json());
-            let mut writing_teams = HashSet::new();
-            for team in all_teams {
-                let url = format!(
-                    "/teams/{}/repos/{}/{}",
-                    team.id,
-                    repo.owner,
-                    repo.repo
-                );
-                let resp = try!(
-                    self.client.get(&url).expect("valid url")
-                        .header(Self::accept(AcceptType::Repository))
-                        .send()
-                );
-                let team_repo: TeamRepoDesc = try!(resp.json());
-                if let Some(ref permissions) = team_repo.permissions {
-                    if permissions.admin || permissions.push {
-                        writing_teams.insert(TeamId(team.id));
-                    }
-                }
-            }
-            Ok(writing_teams)
+            Ok(try!(resp.json()))
         } else {
             Err(GithubRequestError::HttpStatus(resp.http.status))
         }
     }
+
+    fn get_prs(
+        &self,
+        repo: &Repo,
+        filter: &str,
+    ) -> Result<Vec<PrDesc>, GithubRequestError> {
+        let client = try!(self.client(repo));
+        let url = format!(
+            "/repos/{}/{}/pulls?{}&per_page=100",
+            repo.owner,
+            repo.repo,
+            filter,
+        );
+        self.get_all_pages(&client, url, AcceptType::Regular)
+    }
+
+    /// Just the first page, for lists that go back to the start of the repo.
+    fn get_prs_page(
+        &self,
+        repo: &Repo,
+        filter: &str,
+    ) -> Result<Vec<PrDesc>, GithubRequestError> {
+        let client = try!(self.client(repo));
+        let url = format!(
+            "/repos/{}/{}/pulls?{}&per_page=100",
+            repo.owner,
+            repo.repo,
+            filter,
+        );
+        let resp = github_send!(
+            client.get(&url).expect("valid url")
+                .header(Self::accept(AcceptType::Regular))
+        );
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status));
+        }
+        Ok(try!(resp.json()))
+    }
+
+    fn get_all_teams_with_write(
+        &self,
+        repo: &Repo,
+    ) -> Result<HashSet<TeamId>, GithubRequestError> {
+        let client = try!(self.client(repo));
+        let url = format!(
+            "/orgs/{}/teams",
+            repo.owner,
+        );
+        let all_teams: Vec<TeamDesc> =
+            try!(self.get_all_pages(&client, url, AcceptType::Regular));
+        let mut writing_teams = HashSet::new();
+        for team in all_teams {
+            let url = format!(
+                "/teams/{}/repos/{}/{}",
+                team.id,
+                repo.owner,
+                repo.repo
+            );
+            let resp = github_send!(
+                client.get(&url).expect("valid url")
+                    .header(Self::accept(AcceptType::Repository))
+            );
+            let team_repo: TeamRepoDesc = try!(resp.json());
+            if let Some(ref permissions) = team_repo.permissions {
+                if permissions.admin || permissions.push {
+                    writing_teams.insert(TeamId(team.id));
+                }
+            }
+        }
+        Ok(writing_teams)
+    }
+
+    /// Get every page of a listing, not just the first,
+    /// by following the `Link` header.
+    fn get_all_pages<T: Deserialize>(
+        &self,
+        client: &GithubClient,
+        url: String,
+        accept_type: AcceptType,
+    ) -> Result<Vec<T>, GithubRequestError> {
+        let mut all = Vec::new();
+        let mut url = Some(url);
+        while let Some(page_url) = url.take() {
+            let resp = github_send!(
+                client.get(&page_url).expect("valid url")
+                    .header(Self::accept(accept_type))
+            );
+            if !resp.is_success() {
+                return Err(GithubRequestError::HttpStatus(resp.http.status));
+            }
+            url = github_api::next_page(&resp.http.headers);
+            let page: Vec<T> = try!(resp.json());
+            all.extend(page);
+        }
+        Ok(all)
+    }
+    /// A client that can act on the repo, which is a different one for
+    /// every owner when we are a GitHub App.
+    fn client(
+        &self,
+        repo: &Repo,
+    ) -> Result<Arc<GithubClient>, GithubRequestError> {
+        Ok(try!(self.credentials.client(&repo.owner)))
+    }
+
     fn accept(accept_type: AcceptType) -> Accept {
         let mime: Mime = match accept_type {
             AcceptType::Regular => "application/vnd.github.v3+json",
             AcceptType::Repository =>
                 "application/vnd.github.v3.repository+json",
+            AcceptType::Checks =>
+                "application/vnd.github.antiope-preview+json",
         }.parse().expect("hard-coded mimes to be valid");
         header::Accept(vec![qitem(mime)])
     }
 }
 
+#[derive(Clone, Copy)]
 enum AcceptType {
     Regular,
     Repository,
+    Checks,
+}
+
+/// The comment to leave on a PR about a result, if it is worth one.
+pub fn comment_body(status: &ui::Status) -> Option<Cow<'static, str>> {
+    match *status {
+        ui::Status::Approved(_) => None,
+        ui::Status::StartingBuild(_, _) => None,
+        ui::Status::Testing(_, _, _) => None,
+        ui::Status::Success(_, _, ref url) => Some({
+            if let Some(ref url) = *url {
+                Cow::Owned(format!(":+1: [Build succeeded]({})", url))
+            } else {
+                Cow::Borrowed(":+1: Build succeeded")
+            }
+        }),
+        ui::Status::Failure(_, _, ref url) => Some({
+            if let Some(ref url) = *url {
+                Cow::Owned(format!(":-1: [Build failed]({})", url))
+            } else {
+                Cow::Borrowed(":-1: Build failed")
+            }
+        }),
+        ui::Status::Retrying(_, _, ref url) => Some({
+            if let Some(ref url) = *url {
+                Cow::Owned(format!(
+                    ":repeat: [Build failed]({}), trying again",
+                    url,
+                ))
+            } else {
+                Cow::Borrowed(":repeat: Build failed, trying again")
+            }
+        }),
+        ui::Status::TimedOut(_, _) => Some(Cow::Borrowed(
+            ":hourglass: Build timed out"
+        )),
+        ui::Status::Unmergeable(_) => Some(Cow::Borrowed(
+            ":x: Merge conflict!"
+        )),
+        ui::Status::Unmoveable(_, _) => Some(Cow::Borrowed(
+            ":scream: Internal error while fast-forward master"
+        )),
+        ui::Status::Invalidated => Some(Cow::Borrowed(
+            ":no_good: New commits added"
+        )),
+        ui::Status::NoCommit => Some(Cow::Borrowed(
+            ":scream: Internal error: no commit found for PR"
+        )),
+        ui::Status::NothingToRetry => Some(Cow::Borrowed(
+            ":grey_question: No failed build to retry"
+        )),
+        ui::Status::Completed(_, _) => None,
+    }
+}
+
+/// The commit status to set for a result, with the pull commit
+/// and the merge commit (if there is one yet) that it goes on.
+pub fn commit_status(
+    status: &ui::Status,
+    context: String,
+) -> Option<(&Commit, Option<&Commit>, StatusDesc)> {
+    match *status {
+        ui::Status::Approved(ref pull_commit) => Some((
+            pull_commit,
+            None,
+            StatusDesc {
+                state: "pending".to_owned(),
+                target_url: None,
+                description: format!(
+                    "Approved {}",
+                    pull_commit,
+                ),
+                context: context,
+            }
+        )),
+        ui::Status::StartingBuild(
+            ref pull_commit,
+            ref merge_commit,
+        ) => Some((
+            pull_commit,
+            Some(merge_commit),
+            StatusDesc {
+                state: "pending".to_owned(),
+                target_url: None,
+                description: format!(
+                    "Testing {} with merge commit {}",
+                    &pull_commit.to_short_string()[..],
+                    &merge_commit.to_short_string()[..],
+                ),
+                context: context,
+            }
+        )),
+        ui::Status::Testing(
+            ref pull_commit,
+            ref merge_commit,
+            ref url,
+        ) => Some((
+            pull_commit,
+            Some(merge_commit),
+            StatusDesc {
+                state: "pending".to_owned(),
+                target_url: url.as_ref().map(ToString::to_string),
+                description: format!(
+                    "Testing {} with merge commit {}",
+                    &pull_commit.to_short_string()[..],
+                    &merge_commit.to_short_string()[..],
+                ),
+                context: context,
+            }
+        )),
+        ui::Status::Success(
+            ref pull_commit,
+            ref merge_commit,
+            ref url,
+        ) => Some((
+            pull_commit,
+            Some(merge_commit),
+            StatusDesc {
+                state: "success".to_owned(),
+                target_url: url.as_ref().map(ToString::to_string),
+                description: "Tests passed".to_owned(),
+                context: context,
+            }
+        )),
+        ui::Status::Failure(
+            ref pull_commit,
+            ref merge_commit, 
+            ref url,
+        ) => Some((
+            pull_commit,
+            Some(merge_commit),
+            StatusDesc {
+                state: "failure".to_owned(),
+                target_url: url.as_ref().map(ToString::to_string),
+                description: "Tests failed".to_owned(),
+                context: context,
+            }
+        )),
+        ui::Status::Retrying(
+            ref pull_commit,
+            ref merge_commit,
+            ref url,
+        ) => Some((
+            pull_commit,
+            Some(merge_commit),
+            StatusDesc {
+                state: "pending".to_owned(),
+                target_url: url.as_ref().map(ToString::to_string),
+                description: format!(
+                    "Retrying {} with merge commit {}",
+                    &pull_commit.to_short_string()[..],
+                    &merge_commit.to_short_string()[..],
+                ),
+                context: context,
+            }
+        )),
+        ui::Status::TimedOut(
+            ref pull_commit,
+            ref merge_commit,
+        ) => Some((
+            pull_commit,
+            Some(merge_commit),
+            StatusDesc {
+                state: "failure".to_owned(),
+                target_url: None,
+                description: "Tests timed out".to_owned(),
+                context: context,
+            }
+        )),
+        ui::Status::Unmergeable(
+            ref pull_commit,
+        ) => Some((
+            pull_commit,
+            None,
+            StatusDesc {
+                state: "failure".to_owned(),
+                target_url: None,
+                description: "Merge failed".to_owned(),
+                context: context,
+            }
+        )),
+        ui::Status::Unmoveable(
+            ref pull_commit,
+            ref merge_commit,
+        ) => Some((
+            pull_commit,
+            Some(merge_commit),
+            StatusDesc {
+                state: "error".to_owned(),
+                target_url: None,
+                description: "Merge failed".to_owned(),
+                context: context,
+            }
+        )),
+        ui::Status::Invalidated |
+        ui::Status::NoCommit |
+        ui::Status::NothingToRetry => None,
+        ui::Status::Completed(_, _) => None,
+    }
+}
+
+/// The check run status and conclusion that go with a result.
+fn check_run_state(
+    status: &ui::Status,
+) -> (&'static str, Option<&'static str>) {
+    match *status {
+        ui::Status::Approved(_) => ("queued", None),
+        ui::Status::StartingBuild(_, _) |
+        ui::Status::Testing(_, _, _) |
+        ui::Status::Retrying(_, _, _) => ("in_progress", None),
+        ui::Status::Success(_, _, _) |
+        ui::Status::Completed(_, _) => ("completed", Some("success")),
+        ui::Status::TimedOut(_, _) => ("completed", Some("timed_out")),
+        ui::Status::Failure(_, _, _) |
+        ui::Status::Unmergeable(_) |
+        ui::Status::Unmoveable(_, _) => ("completed", Some("failure")),
+        ui::Status::Invalidated |
+        ui::Status::NoCommit |
+        ui::Status::NothingToRetry => ("completed", Some("cancelled")),
+    }
 }
 
 quick_error! {
@@ -1009,5 +2165,12 @@ quick_error! {
         }
         /// Repo not found for pipeline
         Pipeline(pipeline_id: PipelineId) {}
+        /// No token to act on the repo
+        Auth(err: AuthError) {
+            cause(err)
+            from()
+        }
     }
 }
+
+#[cfg(test)] mod test;
@@ -0,0 +1,179 @@
+// This file is released under the same terms as Rust itself.
+
+/*! A log of the webhooks we accepted, so they can be sent again.
+
+    Each accepted delivery is one line of JSON. Once the log grows past
+    `MAX_LOG_BYTES`, it is moved aside to `<path>.1`, replacing the one
+    that was there, so no more than twice that is ever kept on disk.
+
+    Replaying posts the payloads back to the admin listener (see `admin`),
+    so they go through the same webhook handler as everything else.
+    Only what comes in there is let through as a replay,
+    instead of being taken as a duplicate.
+ */
+
+use serde_json;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_LOG_BYTES: u64 = 16*1024*1024;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub delivery: Option<String>,
+    pub event: String,
+    pub received_at: u64,
+    pub body: String,
+}
+
+pub struct Log {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Log {
+    pub fn new(path: PathBuf) -> Log {
+        Log {
+            path: path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn append(
+        &self,
+        delivery: Option<&str>,
+        event: &[u8],
+        body: &[u8],
+    ) -> Result<(), ReplayError> {
+        let received_at = SystemTime::now().duration_since(UNIX_EPOCH)
+            .expect("the clock to be past 1970")
+            .as_secs();
+        let entry = Entry {
+            delivery: delivery.map(ToOwned::to_owned),
+            event: String::from_utf8_lossy(event).into_owned(),
+            received_at: received_at,
+            body: String::from_utf8_lossy(body).into_owned(),
+        };
+        let line = try!(serde_json::to_string(&entry));
+        let _lock = self.lock.lock().unwrap();
+        let len = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if len > MAX_LOG_BYTES {
+            try!(fs::rename(&self.path, old_path(&self.path)));
+        }
+        let mut file = try!(
+            OpenOptions::new().create(true).append(true).open(&self.path)
+        );
+        try!(writeln!(file, "{}", line));
+        Ok(())
+    }
+}
+
+/// Which of the logged deliveries to replay.
+#[derive(Clone, Debug)]
+pub enum Selection {
+    Delivery(String),
+    /// Everything received in this range of UNIX times, inclusive.
+    Between(u64, u64),
+}
+
+impl Selection {
+    fn matches(&self, entry: &Entry) -> bool {
+        match *self {
+            Selection::Delivery(ref delivery) =>
+                entry.delivery.as_ref() == Some(delivery),
+            Selection::Between(since, until) =>
+                entry.received_at >= since && entry.received_at <= until,
+        }
+    }
+}
+
+/// The selected deliveries from the log, oldest first.
+pub fn entries(
+    log: &Path,
+    selection: &Selection,
+) -> Result<Vec<Entry>, ReplayError> {
+    let mut entries = Vec::new();
+    for path in &[old_path(log), log.to_owned()] {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for line in BufReader::new(file).lines() {
+            let entry: Entry = try!(serde_json::from_str(&try!(line)));
+            if selection.matches(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn old_path(path: &Path) -> PathBuf {
+    let mut old = OsString::from(path.as_os_str());
+    old.push(".1");
+    PathBuf::from(old)
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ReplayError {
+        /// The log could not be read or written
+        Io(err: io::Error) {
+            cause(err)
+            from()
+        }
+        /// The log has something in it that is not an entry
+        Json(err: serde_json::error::Error) {
+            cause(err)
+            from()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use super::{old_path, Entry, Selection};
+
+    fn entry(delivery: &str, received_at: u64) -> Entry {
+        Entry {
+            delivery: Some(delivery.to_owned()),
+            event: "issue_comment".to_owned(),
+            received_at: received_at,
+            body: "{}".to_owned(),
+        }
+    }
+
+    #[test]
+    fn select_delivery() {
+        let selection = Selection::Delivery("abc".to_owned());
+        assert!(selection.matches(&entry("abc", 10)));
+        assert!(!selection.matches(&entry("abd", 10)));
+    }
+
+    #[test]
+    fn select_between() {
+        let selection = Selection::Between(10, 20);
+        assert!(!selection.matches(&entry("a", 9)));
+        assert!(selection.matches(&entry("a", 10)));
+        assert!(selection.matches(&entry("a", 20)));
+        assert!(!selection.matches(&entry("a", 21)));
+    }
+
+    #[test]
+    fn rotated_log_path() {
+        assert_eq!(
+            old_path(Path::new("logs/github-replay.log")),
+            PathBuf::from("logs/github-replay.log.1")
+        );
+    }
+}
@@ -0,0 +1,491 @@
+// This file is released under the same terms as Rust itself.
+
+use db::{self, Db};
+use hex::ToHex;
+use hyper::buffer::BufReader;
+use hyper::header::Headers;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::uri::RequestUri;
+use pipeline::PipelineId;
+use serde_json;
+use std::collections::HashMap;
+use std::io::{BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use super::{AccessList, PipelineType, ProjectsConfig, Repo, RepoPipelines};
+use super::{PrBaseDesc, PrBranchDesc, PrDesc, Worker};
+use ui::{Event, Pr};
+use util::crypto::generate_sha256_hmac;
+use util::github_auth::Credentials;
+use vcs::Commit;
+
+struct OneProject(RepoPipelines);
+
+impl ProjectsConfig for OneProject {
+    fn pipelines_by_repo(&self, repo: &Repo, branch: &str)
+            -> Option<RepoPipelines> {
+        if repo == &testp() && branch == "master" {
+            Some(self.0.clone())
+        } else {
+            None
+        }
+    }
+    fn all_pipelines_by_repo(&self, repo: &Repo) -> Vec<RepoPipelines> {
+        self.pipelines_by_repo(repo, "master").into_iter().collect()
+    }
+    fn repo_by_pipeline(&self, _: PipelineId)
+            -> Option<(Repo, PipelineType)> {
+        None
+    }
+    fn repos(&self) -> Vec<Repo> {
+        vec![testp()]
+    }
+}
+
+fn testp() -> Repo {
+    Repo {
+        owner: "AelitaBot".to_owned(),
+        repo: "testp".to_owned(),
+    }
+}
+
+/// Pipelines that only `testu` may `r+` on, so that checking does not
+/// need to ask GitHub.
+fn listed_pipelines(review_approves: bool) -> RepoPipelines {
+    RepoPipelines {
+        pipeline_id: PipelineId(0),
+        try_pipeline_id: None,
+        review_approves: review_approves,
+        reviewers: Some(AccessList {
+            users: vec!["testu".to_owned()],
+            teams: vec![],
+        }),
+        try_users: None,
+    }
+}
+
+fn worker(host: &str, repo_pipelines: RepoPipelines) -> Worker {
+    Worker::new(
+        "localhost:0".to_owned(),
+        Arc::new(Credentials::token(host.to_owned(), "token".to_owned())),
+        "AelitaBot".to_owned(),
+        vec!["secret".to_owned()],
+        Box::new(OneProject(repo_pipelines)),
+        db::Builder::from_str(":memory:").unwrap(),
+        db::Builder::from_str(":memory:").unwrap(),
+        None,
+        false,
+        None,
+        None,
+        Duration::from_secs(60),
+    )
+}
+
+/// Send a review of PR #1 through the worker,
+/// and return whatever it sent to the pipelines.
+fn review(
+    worker: &Worker,
+    user: &str,
+    state: &str,
+    body: &str,
+) -> Receiver<Event> {
+    let (send_event, recv_event) = channel();
+    let desc = serde_json::from_str(&format!(r#"{{
+        "action": "submitted",
+        "review": {{
+            "user": {{"login": "{}", "type": "User"}},
+            "body": "{}",
+            "commit_id": "a",
+            "state": "{}"
+        }},
+        "pull_request": {{
+            "number": 1,
+            "title": "T",
+            "body": null,
+            "user": {{"login": "author", "type": "User"}},
+            "base": {{"ref": "master"}}
+        }},
+        "repository": {{
+            "name": "testp",
+            "owner": {{"login": "AelitaBot", "type": "User"}}
+        }}
+    }}"#, user, body, state)).unwrap();
+    worker.handle_pr_review(&send_event, desc);
+    recv_event
+}
+
+/// Send a comment on PR #1 through the worker,
+/// and return whatever it sent to the pipelines.
+fn comment(worker: &Worker, user: &str, body: &str) -> Receiver<Event> {
+    // Say which branch it is against, so that the worker doesn't ask.
+    worker.pr_bases.lock().unwrap()
+        .insert((testp(), 1), "master".to_owned());
+    let (send_event, recv_event) = channel();
+    let desc = serde_json::from_str(&format!(r#"{{
+        "issue": {{
+            "number": 1,
+            "title": "T",
+            "body": null,
+            "pull_request": {{"html_url": "http://example.com/pr/1"}},
+            "state": "open",
+            "user": {{"login": "author", "type": "User"}}
+        }},
+        "comment": {{
+            "user": {{"login": "{}", "type": "User"}},
+            "body": "{}"
+        }},
+        "repository": {{
+            "name": "testp",
+            "owner": {{"login": "AelitaBot", "type": "User"}}
+        }}
+    }}"#, user, body)).unwrap();
+    worker.handle_pr_comment(&send_event, desc);
+    recv_event
+}
+
+/// Send a signed webhook through the worker, like GitHub would,
+/// and return whatever it sent to the pipelines.
+fn webhook(worker: &Worker, event: &str, body: &str) -> Receiver<Event> {
+    let mut listener = HttpListener::new("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let signature = generate_sha256_hmac(b"secret", body.as_bytes());
+    let request = format!(
+        "POST / HTTP/1.1\r\n\
+         Host: {}\r\n\
+         X-GitHub-Event: {}\r\n\
+         X-Hub-Signature-256: sha256={}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        addr,
+        event,
+        signature.to_hex(),
+        body.len(),
+        body,
+    );
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    });
+    let (send_event, recv_event) = channel();
+    {
+        let mut stream = listener.accept().unwrap();
+        let addr = stream.peer_addr().unwrap();
+        let mut stream_clone = stream.clone();
+        let mut buf_read = BufReader::new(
+            &mut stream_clone as &mut NetworkStream
+        );
+        let mut buf_write = BufWriter::new(&mut stream);
+        let req = Request::new(&mut buf_read, addr).unwrap();
+        let mut head = Headers::new();
+        let res = Response::new(&mut buf_write, &mut head);
+        worker.handle_webhook(req, res, false, &send_event);
+    }
+    let response = client.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+    recv_event
+}
+
+#[test]
+fn test_review_approves_without_mention() {
+    let worker = worker("http://localhost:0", listed_pipelines(true));
+    let recv_event = review(&worker, "testu", "approved", "LGTM");
+    match recv_event.try_recv() {
+        Ok(Event::Approved(pipeline_id, pr, commit, message, 0, approver)) => {
+            assert_eq!(pipeline_id, PipelineId(0));
+            assert_eq!(pr, Pr::from("1".to_owned()));
+            assert_eq!(commit, Some(Commit::from("a".to_owned())));
+            assert!(message.contains("r=@testu"));
+            assert_eq!(approver, "testu");
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+    assert!(recv_event.try_recv().is_err());
+}
+
+#[test]
+fn test_review_needs_mention() {
+    let worker = worker("http://localhost:0", listed_pipelines(false));
+    let recv_event = review(&worker, "testu", "approved", "LGTM");
+    assert!(recv_event.try_recv().is_err());
+    let recv_event = review(&worker, "testu", "APPROVED", "@AelitaBot LGTM");
+    match recv_event.try_recv() {
+        Ok(Event::Approved(PipelineId(0), _, Some(_), _, 0, _)) => {}
+        e => panic!("Unexpected event {:?}", e),
+    }
+}
+
+#[test]
+fn test_review_changes_requested() {
+    let worker = worker("http://localhost:0", listed_pipelines(true));
+    let recv_event = review(&worker, "testu", "changes_requested", "No");
+    match recv_event.try_recv() {
+        Ok(Event::Canceled(pipeline_id, pr)) => {
+            assert_eq!(pipeline_id, PipelineId(0));
+            assert_eq!(pr, Pr::from("1".to_owned()));
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+}
+
+#[test]
+fn test_review_neither() {
+    let worker = worker("http://localhost:0", listed_pipelines(true));
+    for state in &["commented", "dismissed"] {
+        let recv_event = review(&worker, "testu", state, "@AelitaBot hm");
+        assert!(recv_event.try_recv().is_err());
+    }
+}
+
+#[test]
+fn test_review_not_permitted() {
+    let worker = worker("http://localhost:0", listed_pipelines(true));
+    let recv_event = review(&worker, "someone", "approved", "@AelitaBot");
+    assert!(recv_event.try_recv().is_err());
+}
+
+#[test]
+fn test_comment_delegated_approves() {
+    let worker = worker("http://localhost:0", listed_pipelines(true));
+    worker.db.lock().unwrap().add_delegation(
+        PipelineId(0),
+        &Pr::from("1".to_owned()),
+        "helper",
+        &Commit::from("a".to_owned()),
+    ).unwrap();
+    let recv_event = comment(&worker, "Helper", "r+");
+    match recv_event.try_recv() {
+        Ok(Event::Approved(pipeline_id, pr, None, message, 0, approver)) => {
+            assert_eq!(pipeline_id, PipelineId(0));
+            assert_eq!(pr, Pr::from("1".to_owned()));
+            assert!(message.contains("r=@Helper"));
+            assert_eq!(approver, "Helper");
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+}
+
+#[test]
+fn test_comment_not_delegated() {
+    let worker = worker("http://localhost:0", listed_pipelines(true));
+    worker.db.lock().unwrap().add_delegation(
+        PipelineId(0),
+        &Pr::from("2".to_owned()),
+        "helper",
+        &Commit::from("a".to_owned()),
+    ).unwrap();
+    // Delegated on some other PR, which does not count for this one.
+    let recv_event = comment(&worker, "helper", "r+");
+    assert!(recv_event.try_recv().is_err());
+    let recv_event = comment(&worker, "someone", "r+");
+    assert!(recv_event.try_recv().is_err());
+}
+
+/// Answer the given number of requests like GitHub would,
+/// and return its address, and the paths it was asked for.
+fn fake_github<F>(requests: usize, answer: F)
+        -> (String, JoinHandle<Vec<String>>)
+    where F: Fn(&str) -> (Option<String>, String) + Send + 'static
+{
+    let mut listener = HttpListener::new("127.0.0.1:0").unwrap();
+    let host = format!("http://{}", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        let mut paths = Vec::new();
+        for _ in 0..requests {
+            let mut stream = listener.accept().unwrap();
+            let addr = stream.peer_addr().unwrap();
+            let mut stream_clone = stream.clone();
+            let mut buf_read = BufReader::new(
+                &mut stream_clone as &mut NetworkStream
+            );
+            let mut buf_write = BufWriter::new(&mut stream);
+            let req = Request::new(&mut buf_read, addr).unwrap();
+            let path = match req.uri {
+                RequestUri::AbsolutePath(ref path) => path.clone(),
+                ref uri => panic!("Unexpected request for {:?}", uri),
+            };
+            let (link, body) = answer(&path);
+            let mut head = Headers::new();
+            let mut res = Response::new(&mut buf_write, &mut head);
+            res.headers_mut().set_raw("Connection", vec![b"close".to_vec()]);
+            if let Some(link) = link {
+                res.headers_mut().set_raw("Link", vec![link.into_bytes()]);
+            }
+            res.send(body.as_bytes()).unwrap();
+            paths.push(path);
+        }
+        paths
+    });
+    (host, handle)
+}
+
+fn pr_json(number: u32, state: &str, sha: &str) -> String {
+    format!(r#"{{
+        "state": "{}",
+        "number": {},
+        "head": {{"sha": "{}"}},
+        "base": {{"ref": "master"}},
+        "html_url": "https://github.com/AelitaBot/testp/pull/{}",
+        "title": "T"
+    }}"#, state, number, sha, number)
+}
+
+#[test]
+fn test_sync_without_last_sync() {
+    // There are more closed PRs, but only the first page is recent enough
+    // to matter, so it must not ask for the next one.
+    let (host, server) = fake_github(2, |path| {
+        if path.contains("state=open") {
+            (None, format!("[{}]", pr_json(1, "open", "a")))
+        } else {
+            assert!(path.contains("state=closed"));
+            (
+                Some(concat!(
+                    r#"<http://127.0.0.1:1/repos/AelitaBot/testp/pulls"#,
+                    r#"?state=closed&page=2>; rel="next""#,
+                ).to_owned()),
+                format!("[{}]", pr_json(2, "closed", "b")),
+            )
+        }
+    });
+    let worker = worker(&host, listed_pipelines(false));
+    let (send_event, recv_event) = channel();
+    let open = worker.sync_repo(&testp(), None, &send_event).unwrap();
+    assert_eq!(open.len(), 1);
+    assert!(open.contains_key(&1));
+    match recv_event.try_recv() {
+        Ok(Event::Changed(PipelineId(0), pr, commit, _, _)) => {
+            assert_eq!(pr, Pr::from("1".to_owned()));
+            assert_eq!(commit, Commit::from("a".to_owned()));
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+    match recv_event.try_recv() {
+        Ok(Event::Closed(PipelineId(0), pr)) => {
+            assert_eq!(pr, Pr::from("2".to_owned()));
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+    assert!(recv_event.try_recv().is_err());
+    assert_eq!(server.join().unwrap().len(), 2);
+}
+
+#[test]
+fn test_sync_since_last_sync() {
+    let (host, server) = fake_github(1, |path| {
+        assert!(path.contains("state=open"));
+        (None, format!(
+            "[{}, {}]",
+            pr_json(1, "open", "a"),
+            pr_json(3, "open", "c"),
+        ))
+    });
+    let worker = worker(&host, listed_pipelines(false));
+    let known_pr = |number: u32, sha: &str| PrDesc {
+        state: "open".to_owned(),
+        number: number,
+        head: PrBranchDesc { sha: sha.to_owned() },
+        base: PrBaseDesc { ref_name: "master".to_owned() },
+        html_url: format!("https://github.com/AelitaBot/testp/pull/{}", number),
+        title: "T".to_owned(),
+    };
+    let mut known = HashMap::new();
+    known.insert(1, known_pr(1, "a"));
+    known.insert(2, known_pr(2, "b"));
+    let (send_event, recv_event) = channel();
+    let open = worker.sync_repo(&testp(), Some(known), &send_event).unwrap();
+    assert_eq!(open.len(), 2);
+    // #1 did not change, #3 is new, and #2 is gone, without asking GitHub.
+    match recv_event.try_recv() {
+        Ok(Event::Opened(PipelineId(0), pr, _, _, _)) => {
+            assert_eq!(pr, Pr::from("3".to_owned()));
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+    match recv_event.try_recv() {
+        Ok(Event::Closed(PipelineId(0), pr)) => {
+            assert_eq!(pr, Pr::from("2".to_owned()));
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+    assert!(recv_event.try_recv().is_err());
+    assert_eq!(server.join().unwrap().len(), 1);
+}
+
+fn access_pipelines(reviewers: AccessList, try_users: AccessList)
+        -> RepoPipelines {
+    RepoPipelines {
+        pipeline_id: PipelineId(0),
+        try_pipeline_id: Some(PipelineId(1)),
+        review_approves: false,
+        reviewers: Some(reviewers),
+        try_users: Some(try_users),
+    }
+}
+
+#[test]
+fn test_user_may_listed() {
+    let repo_pipelines = access_pipelines(
+        AccessList { users: vec!["testu".to_owned()], teams: vec![] },
+        AccessList { users: vec!["tryu".to_owned()], teams: vec![] },
+    );
+    // Nothing here needs to ask GitHub.
+    let worker = worker("http://localhost:0", repo_pipelines.clone());
+    let may = |user: &str, is_try: bool| {
+        worker.user_may(user, &testp(), &repo_pipelines, is_try).unwrap()
+    };
+    assert!(may("testu", false));
+    assert!(may("TestU", false));
+    assert!(may("testu", true));
+    assert!(!may("tryu", false));
+    assert!(may("tryu", true));
+    assert!(!may("someone", false));
+    assert!(!may("someone", true));
+}
+
+#[test]
+fn test_user_may_team() {
+    let (host, server) = fake_github(2, |path| {
+        let state = match path {
+            "/orgs/AelitaBot/teams/core/memberships/member" => "active",
+            "/orgs/AelitaBot/teams/core/memberships/invited" => "pending",
+            _ => panic!("Unexpected request for {}", path),
+        };
+        (None, format!(r#"{{"state": "{}"}}"#, state))
+    });
+    let repo_pipelines = access_pipelines(
+        AccessList { users: vec![], teams: vec!["core".to_owned()] },
+        AccessList::default(),
+    );
+    let worker = worker(&host, repo_pipelines.clone());
+    assert!(worker.user_may("member", &testp(), &repo_pipelines, false)
+        .unwrap());
+    assert!(!worker.user_may("invited", &testp(), &repo_pipelines, false)
+        .unwrap());
+    assert_eq!(server.join().unwrap().len(), 2);
+}
+
+#[test]
+fn test_membership_webhook_flushes_permissions() {
+    let worker = worker("http://localhost:0", listed_pipelines(false));
+    let ttl = Duration::from_secs(60);
+    let has_write = |user: &str| {
+        worker.cache.lock().unwrap().user_has_write(PipelineId(0), user, ttl)
+    };
+    for event in &["member", "membership", "team"] {
+        worker.cache.lock().unwrap()
+            .set_user_has_write(PipelineId(0), "testu", true);
+        assert_eq!(has_write("testu"), Some(true));
+        let recv_event = webhook(&worker, event, r#"{"action": "removed"}"#);
+        assert!(recv_event.try_recv().is_err());
+        assert_eq!(has_write("testu"), None);
+    }
+}
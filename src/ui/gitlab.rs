@@ -0,0 +1,676 @@
+// This file is released under the same terms as Rust itself.
+
+/*! The GitLab front-end: merge requests and the notes on them.
+
+    Merge requests are taken in by their webhooks, like GitHub's PRs,
+    and results are sent back as notes and commit statuses.
+    Only developers of the project (or better) may give commands.
+ */
+
+use crossbeam;
+use hyper::Url;
+use hyper::buffer::BufReader;
+use hyper::header::Headers;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use serde_json::{from_reader as json_from_reader};
+use serde_json::{from_slice as json_from_slice};
+use std::borrow::Cow;
+use std::io::BufWriter;
+use std::iter;
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, Receiver};
+use ui::{self, comments, github, Pr};
+use util::gitlab_api::{self, Client, GitlabRequestError};
+use util::gitlab_headers;
+use vcs::Commit;
+use vcs::git::ToShortString;
+use vcs::gitlab as gitlab_git;
+
+/// GitLab keeps the head of every merge request under
+/// `refs/merge-requests`.
+pub const REMOTE_FORMAT: ui::RemoteFormat =
+    ui::RemoteFormat("merge-requests/{}/head");
+
+/// Developers are the least who can push to a project.
+const DEVELOPER_ACCESS: u32 = 30;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    /// The group or user the project is in.
+    pub owner: String,
+    pub repo: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct RepoPipelines {
+    pub pipeline_id: PipelineId,
+    pub try_pipeline_id: Option<PipelineId>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PipelineType {
+    Stage,
+    Try,
+}
+
+pub trait ProjectsConfig: Send + Sync + 'static {
+    /// The pipelines for merge requests into the given target branch.
+    fn pipelines_by_repo(&self, &Repo, &str) -> Option<RepoPipelines>;
+    fn repo_by_pipeline(&self, PipelineId) -> Option<(Repo, PipelineType)>;
+}
+
+pub struct Worker {
+    listen: String,
+    projects: Box<ProjectsConfig>,
+    client: Arc<Client>,
+    user_ident: String,
+    secrets: Vec<String>,
+}
+
+impl Worker {
+    pub fn new(
+        listen: String,
+        client: Arc<Client>,
+        user: String,
+        secrets: Vec<String>,
+        projects: Box<ProjectsConfig>,
+    ) -> Worker {
+        Worker {
+            listen: listen,
+            projects: projects,
+            client: client,
+            user_ident: format!("@{}", user),
+            secrets: secrets,
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct ProjectDesc {
+    path_with_namespace: String,
+}
+#[derive(Deserialize, Serialize)]
+struct UserDesc {
+    #[serde(default)]
+    id: u32,
+    username: String,
+}
+#[derive(Deserialize, Serialize)]
+struct LastCommitDesc {
+    id: String,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestAttributesDesc {
+    iid: u32,
+    title: String,
+    url: String,
+    state: String,
+    action: Option<String>,
+    #[serde(default)]
+    source_branch: String,
+    target_branch: String,
+    last_commit: LastCommitDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestHookDesc {
+    project: ProjectDesc,
+    object_attributes: MergeRequestAttributesDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct NoteAttributesDesc {
+    note: String,
+    noteable_type: String,
+}
+#[derive(Deserialize, Serialize)]
+struct NoteMergeRequestDesc {
+    iid: u32,
+    state: String,
+    target_branch: String,
+}
+#[derive(Deserialize, Serialize)]
+struct NoteHookDesc {
+    user: UserDesc,
+    project: ProjectDesc,
+    object_attributes: NoteAttributesDesc,
+    merge_request: Option<NoteMergeRequestDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestDesc {
+    title: String,
+    description: Option<String>,
+    author: UserDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct MemberDesc {
+    access_level: u32,
+}
+#[derive(Deserialize, Serialize)]
+struct NoteDesc {
+    body: String,
+}
+#[derive(Deserialize, Serialize)]
+struct StatusDesc {
+    state: String,
+    name: String,
+    target_url: Option<String>,
+    description: String,
+}
+
+impl pipeline::Worker<ui::Event, ui::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<ui::Message>,
+        mut send_event: Sender<ui::Event>
+    ) {
+        crossbeam::scope(|scope| {
+            let s2 = &*self;
+            let send_event_2 = send_event.clone();
+            scope.spawn(move || {
+                s2.run_webhook(send_event_2);
+            });
+            loop {
+                s2.handle_message(
+                    recv_msg.recv().expect("Pipeline went away"),
+                    &mut send_event,
+                );
+            }
+        })
+    }
+}
+
+impl Worker {
+    fn run_webhook(
+        &self,
+        send_event: Sender<ui::Event>,
+    ) {
+        let mut listener = HttpListener::new(&self.listen[..])
+            .expect("webhook");
+        while let Ok(mut stream) = listener.accept() {
+            let addr = stream.peer_addr()
+                .expect("webhook client address");
+            let mut stream_clone = stream.clone();
+            let mut buf_read = BufReader::new(
+                &mut stream_clone as &mut NetworkStream
+            );
+            let mut buf_write = BufWriter::new(&mut stream);
+            let req = match Request::new(&mut buf_read, addr) {
+                Ok(req) => req,
+                Err(e) => {
+                    warn!("Invalid webhook HTTP: {:?}", e);
+                    continue;
+                }
+            };
+            let mut head = Headers::new();
+            let res = Response::new(&mut buf_write, &mut head);
+            self.handle_webhook(req, res, &send_event);
+        }
+    }
+
+    fn handle_webhook(
+        &self,
+        mut req: Request,
+        mut res: Response,
+        send_event: &Sender<ui::Event>
+    ) {
+        let head = gitlab_headers::parse(&mut req, &self.secrets);
+        let (x_gitlab_event, body) = match head {
+            Some(head) => head,
+            None => return,
+        };
+        match &x_gitlab_event[..] {
+            b"Merge Request Hook" => {
+                let desc = json_from_slice::<MergeRequestHookDesc>(&body);
+                if let Ok(desc) = desc {
+                    info!(
+                        "Got MR message for !{}: {:?}",
+                        desc.object_attributes.iid,
+                        desc.object_attributes.action,
+                    );
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!("Failed to send response to GitLab MR: {:?}", e);
+                    }
+                    self.handle_mr(send_event, desc);
+                } else {
+                    warn!("Got invalid MR message");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!("Failed to send response to bad MR: {:?}", e);
+                    }
+                }
+            }
+            b"Note Hook" => {
+                if let Ok(desc) = json_from_slice::<NoteHookDesc>(&body) {
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to GitLab note: {:?}",
+                            e,
+                        );
+                    }
+                    let is_open = desc.merge_request.as_ref()
+                        .map(|mr| mr.state == "opened");
+                    if !desc.object_attributes.note.contains(&self.user_ident) {
+                        info!("Note does not mention me; do nothing");
+                    } else if desc.object_attributes.noteable_type !=
+                            "MergeRequest" {
+                        info!("Got note on something else; do nothing");
+                    } else if is_open != Some(true) {
+                        info!("Note is for closed MR; do nothing");
+                    } else {
+                        info!("Got merge request note");
+                        self.handle_mr_note(send_event, desc);
+                    }
+                } else {
+                    warn!("Got invalid note");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!("Failed to send response to bad note: {:?}", e);
+                    }
+                }
+            }
+            e => {
+                *res.status_mut() = StatusCode::BadRequest;
+                if let Err(e) = res.send(&[]) {
+                    warn!(
+                        "Failed to send response to GitLab unknown: {:?}",
+                        e,
+                    );
+                }
+                warn!(
+                    "Got Unknown Event {}",
+                    String::from_utf8_lossy(&e)
+                );
+            }
+        }
+    }
+
+    fn repo(&self, project: &ProjectDesc) -> Option<Repo> {
+        let path = &project.path_with_namespace;
+        match gitlab_api::split_project_path(path) {
+            Some((owner, repo)) => Some(Repo {
+                owner: owner.to_owned(),
+                repo: repo.to_owned(),
+            }),
+            None => {
+                warn!("Got bad project path {}", path);
+                None
+            }
+        }
+    }
+
+    fn handle_mr(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: MergeRequestHookDesc,
+    ) {
+        let repo = match self.repo(&desc.project) {
+            Some(repo) => repo,
+            None => return,
+        };
+        let attrs = desc.object_attributes;
+        if attrs.source_branch.ends_with(gitlab_git::TEMP_SUFFIX) {
+            info!("Got MR !{} of our own; do nothing", attrs.iid);
+            return;
+        }
+        let repo_pipelines = match self.projects.pipelines_by_repo(
+            &repo,
+            &attrs.target_branch,
+        ) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                warn!(
+                    "Got bad repo {:?} branch {}",
+                    repo,
+                    attrs.target_branch,
+                );
+                return;
+            }
+        };
+        let pr = Pr::from(attrs.iid.to_string());
+        let commit = Commit::from(attrs.last_commit.id);
+        let url = match Url::parse(&attrs.url) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Got bad MR URL {}: {:?}", attrs.url, e);
+                return;
+            }
+        };
+        let action = attrs.action.as_ref().map(|a| &a[..]).unwrap_or("");
+        let pipeline_ids = iter::once(repo_pipelines.pipeline_id)
+            .chain(repo_pipelines.try_pipeline_id);
+        for pipeline_id in pipeline_ids {
+            let event = match action {
+                "close" | "merge" => Some(ui::Event::Closed(
+                    pipeline_id,
+                    pr.clone(),
+                )),
+                "open" | "reopen" => Some(ui::Event::Opened(
+                    pipeline_id,
+                    pr.clone(),
+                    commit.clone(),
+                    attrs.title.clone(),
+                    url.clone(),
+                )),
+                "update" => Some(ui::Event::Changed(
+                    pipeline_id,
+                    pr.clone(),
+                    commit.clone(),
+                    attrs.title.clone(),
+                    url.clone(),
+                )),
+                _ => None,
+            };
+            if let Some(event) = event {
+                send_event.send(event).expect("Pipeline to be there");
+            }
+        }
+    }
+
+    fn handle_mr_note(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: NoteHookDesc,
+    ) {
+        let repo = match self.repo(&desc.project) {
+            Some(repo) => repo,
+            None => return,
+        };
+        let mr = desc.merge_request.expect("MR notes to have an MR");
+        let repo_pipelines = match self.projects.pipelines_by_repo(
+            &repo,
+            &mr.target_branch,
+        ) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                warn!("Got bad repo {:?} branch {}", repo, mr.target_branch);
+                return;
+            }
+        };
+        let user = &desc.user.username;
+        let note = &desc.object_attributes.note;
+        let command = match comments::parse(note, user) {
+            Some(command) => command,
+            None => {
+                info!("Merge request note is not a command");
+                return;
+            }
+        };
+        let allowed = self.user_is_developer(desc.user.id, &repo)
+            .unwrap_or_else(|e| {
+                warn!("Failed to check if {} has permission: {:?}", user, e);
+                false
+            });
+        if !allowed {
+            info!("Got mentioned by not-permitted user");
+            return;
+        }
+        let pr = Pr::from(mr.iid.to_string());
+        let (pipeline_id, user, commit, priority) = match command {
+            comments::Command::Approved(user, commit, priority) =>
+                (repo_pipelines.pipeline_id, user, commit, priority),
+            comments::Command::TryApproved(user, commit) => {
+                match repo_pipelines.try_pipeline_id {
+                    Some(try_pipeline_id) => (try_pipeline_id, user, commit, 0),
+                    None => return,
+                }
+            }
+            comments::Command::Canceled => {
+                send_event.send(ui::Event::Canceled(
+                    repo_pipelines.pipeline_id,
+                    pr,
+                )).expect("MR Canceled: Pipeline error");
+                return;
+            }
+            comments::Command::TryCanceled => {
+                if let Some(try_pipeline_id) = repo_pipelines.try_pipeline_id {
+                    send_event.send(ui::Event::Canceled(
+                        try_pipeline_id,
+                        pr,
+                    )).expect("MR Canceled: Pipeline error");
+                }
+                return;
+            }
+            comments::Command::Retry => {
+                send_event.send(ui::Event::Retry(
+                    repo_pipelines.pipeline_id,
+                    pr,
+                )).expect("MR Retry: Pipeline error");
+                return;
+            }
+            comments::Command::TreeClosed(priority) => {
+                send_event.send(ui::Event::TreeClosed(
+                    repo_pipelines.pipeline_id,
+                    priority,
+                )).expect("Tree closed: Pipeline error");
+                return;
+            }
+            comments::Command::TreeOpened => {
+                send_event.send(ui::Event::TreeOpened(
+                    repo_pipelines.pipeline_id,
+                )).expect("Tree opened: Pipeline error");
+                return;
+            }
+            comments::Command::Delegated(_) |
+            comments::Command::Undelegated => {
+                info!("Delegation is not supported on GitLab");
+                return;
+            }
+        };
+        // The note does not say who wrote the MR, so ask.
+        let desc = match self.get_mr(&repo, mr.iid) {
+            Ok(desc) => desc,
+            Err(e) => {
+                warn!("Failed to get {}: {:?}", pr, e);
+                return;
+            }
+        };
+        let message = format!(
+            "{}\n\nMerge !{} a=@{} r=@{}\n{}\n\n{}",
+            desc.title,
+            pr,
+            desc.author.username,
+            user,
+            iter::repeat('_').take(72).collect::<String>(),
+            desc.description.as_ref().map(|d| &d[..]).unwrap_or(""),
+        );
+        send_event.send(ui::Event::Approved(
+            pipeline_id,
+            pr,
+            commit,
+            message,
+            priority,
+            user.to_owned(),
+        )).expect("MR Approved: Pipeline error");
+    }
+
+    fn handle_message(
+        &self,
+        msg: ui::Message,
+        _: &mut Sender<ui::Event>,
+    ) {
+        match msg {
+            ui::Message::SendResult(pipeline_id, pr, status, _) => {
+                let result = self.send_result_to_mr(pipeline_id, &pr, &status);
+                if let Err(e) = result {
+                    warn!("Failed to send {:?} to MR {}: {:?}", status, pr, e)
+                }
+            }
+        }
+    }
+
+    fn send_result_to_mr(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: &ui::Status,
+    ) -> Result<(), GitlabRequestError> {
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(GitlabRequestError::Pipeline(pipeline_id));
+                }
+            };
+        let project = gitlab_api::project_path(&repo.owner, &repo.repo);
+        // The same notes as on GitHub, but GitLab calls them MRs.
+        let note_body = match *status {
+            ui::Status::NoCommit => Some(Cow::Borrowed(
+                ":scream: Internal error: no commit found for MR"
+            )),
+            ref status => github::comment_body(status),
+        };
+        let name = match pipeline_type {
+            PipelineType::Stage => "continuous-integration/aelita",
+            PipelineType::Try => "continuous-integration/aelita/try",
+        };
+        let testing = |pull_commit: &Commit, merge_commit: &Commit| format!(
+            "Testing {} with merge commit {}",
+            &pull_commit.to_short_string()[..],
+            &merge_commit.to_short_string()[..],
+        );
+        // GitLab's commit states are pending, running, success, failed
+        // and canceled.
+        let status = match *status {
+            ui::Status::Approved(ref pull_commit) => Some((
+                pull_commit,
+                None,
+                "pending",
+                None,
+                format!("Approved {}", pull_commit),
+            )),
+            ui::Status::StartingBuild(ref pull_commit, ref merge_commit) =>
+                Some((
+                    pull_commit,
+                    Some(merge_commit),
+                    "running",
+                    None,
+                    testing(pull_commit, merge_commit),
+                )),
+            ui::Status::Testing(ref pull_commit, ref merge_commit, ref url) =>
+                Some((
+                    pull_commit,
+                    Some(merge_commit),
+                    "running",
+                    url.as_ref(),
+                    testing(pull_commit, merge_commit),
+                )),
+            ui::Status::Success(ref pull_commit, ref merge_commit, ref url) =>
+                Some((
+                    pull_commit,
+                    Some(merge_commit),
+                    "success",
+                    url.as_ref(),
+                    "Tests passed".to_owned(),
+                )),
+            ui::Status::Failure(ref pull_commit, ref merge_commit, ref url) =>
+                Some((
+                    pull_commit,
+                    Some(merge_commit),
+                    "failed",
+                    url.as_ref(),
+                    "Tests failed".to_owned(),
+                )),
+            ui::Status::Retrying(ref pull_commit, ref merge_commit, ref url) =>
+                Some((
+                    pull_commit,
+                    Some(merge_commit),
+                    "running",
+                    url.as_ref(),
+                    format!(
+                        "Retrying {} with merge commit {}",
+                        &pull_commit.to_short_string()[..],
+                        &merge_commit.to_short_string()[..],
+                    ),
+                )),
+            ui::Status::TimedOut(ref pull_commit, ref merge_commit) => Some((
+                pull_commit,
+                Some(merge_commit),
+                "failed",
+                None,
+                "Tests timed out".to_owned(),
+            )),
+            ui::Status::Unmergeable(ref pull_commit) => Some((
+                pull_commit,
+                None,
+                "failed",
+                None,
+                "Merge failed".to_owned(),
+            )),
+            ui::Status::Unmoveable(ref pull_commit, ref merge_commit) => Some((
+                pull_commit,
+                Some(merge_commit),
+                "failed",
+                None,
+                "Merge failed".to_owned(),
+            )),
+            ui::Status::Invalidated |
+            ui::Status::NoCommit |
+            ui::Status::NothingToRetry => None,
+            ui::Status::Completed(_, _) => None,
+        };
+        if let Some(note_body) = note_body {
+            let url = format!(
+                "/projects/{}/merge_requests/{}/notes",
+                project,
+                pr,
+            );
+            try!(self.client.post(&url, &NoteDesc {
+                body: note_body.into_owned(),
+            }));
+        }
+        if let Some((pull_commit, merge_commit, state, url, description)) =
+                status {
+            let status_body = StatusDesc {
+                state: state.to_owned(),
+                name: name.to_owned(),
+                target_url: url.map(ToString::to_string),
+                description: description,
+            };
+            let commits = iter::once(pull_commit).chain(merge_commit);
+            for commit in commits {
+                let url = format!("/projects/{}/statuses/{}", project, commit);
+                try!(self.client.post(&url, &status_body));
+            }
+        }
+        Ok(())
+    }
+
+    fn user_is_developer(
+        &self,
+        user_id: u32,
+        repo: &Repo,
+    ) -> Result<bool, GitlabRequestError> {
+        let url = format!(
+            "/projects/{}/members/all/{}",
+            gitlab_api::project_path(&repo.owner, &repo.repo),
+            user_id,
+        );
+        match self.client.get(&url) {
+            Ok(resp) => {
+                let member: MemberDesc = try!(json_from_reader(resp));
+                Ok(member.access_level >= DEVELOPER_ACCESS)
+            }
+            Err(GitlabRequestError::HttpStatus(StatusCode::NotFound)) =>
+                Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_mr(
+        &self,
+        repo: &Repo,
+        iid: u32,
+    ) -> Result<MergeRequestDesc, GitlabRequestError> {
+        let url = format!(
+            "/projects/{}/merge_requests/{}",
+            gitlab_api::project_path(&repo.owner, &repo.repo),
+            iid,
+        );
+        Ok(try!(json_from_reader(try!(self.client.get(&url)))))
+    }
+}
@@ -5,9 +5,14 @@
     control.
  */
 
+pub mod api;
+pub mod bitbucket;
+pub mod gitea;
 pub mod github;
+pub mod gitlab;
 mod comments;
 
+use ci::CiId;
 use config::PipelinesConfig;
 use hyper::Url;
 use pipeline::{GetPipelineId, PipelineId};
@@ -16,13 +21,20 @@ use vcs::{Commit, Remote};
 
 #[derive(Clone, Debug)]
 pub enum Message {
-    SendResult(PipelineId, Pr, Status)
+    SendResult(PipelineId, Pr, Status, Details)
 }
 
 #[derive(Clone, Debug)]
 pub enum Event {
-    Approved(PipelineId, Pr, Option<Commit>, String),
+    /// Queue a PR with the given merge message and priority,
+    /// on behalf of the named user.
+    Approved(PipelineId, Pr, Option<Commit>, String, i32, String),
     Canceled(PipelineId, Pr),
+    /// Queue the last entry of the PR that failed to build again.
+    Retry(PipelineId, Pr),
+    /// Stop merging queue entries below the given priority.
+    TreeClosed(PipelineId, i32),
+    TreeOpened(PipelineId),
     Opened(PipelineId, Pr, Commit, String, Url),
     Changed(PipelineId, Pr, Commit, String, Url),
     Closed(PipelineId, Pr),
@@ -33,15 +45,28 @@ pub enum Status {
     Approved(Commit),
     Invalidated,
     NoCommit,
+    NothingToRetry,
     Unmergeable(Commit),
     StartingBuild(Commit, Commit),
     Testing(Commit, Commit, Option<Url>),
     Success(Commit, Commit, Option<Url>),
     Failure(Commit, Commit, Option<Url>),
+    Retrying(Commit, Commit, Option<Url>),
+    TimedOut(Commit, Commit),
     Unmoveable(Commit, Commit),
     Completed(Commit, Commit),
 }
 
+/// Extra information about a status, for front-ends that can show more
+/// than a one-line description of it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Details {
+    /// Where the pull request is in the queue, starting at 1.
+    pub queue_position: Option<usize>,
+    /// The builds of the merge commit that have reported back so far.
+    pub ci_urls: Vec<(CiId, Url)>,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Pr(String);
 
@@ -63,9 +88,14 @@ impl Into<String> for Pr {
     }
 }
 
+/// Where a front-end keeps the head of each pull request, for `vcs::git`
+/// to fetch it from. The pull request number goes where the `{}` is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RemoteFormat(pub &'static str);
+
 impl Pr {
-    pub fn remote(&self) -> Remote {
-        Remote::from(format!("pull/{}/head", self.0))
+    pub fn remote(&self, format: RemoteFormat) -> Remote {
+        Remote::from(format.0.replace("{}", &self.0))
     }
 
     pub fn as_str(&self) -> &str {
@@ -76,8 +106,11 @@ impl Pr {
 impl GetPipelineId for Event {
     fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, _: &C) -> PipelineId {
         match *self {
-            Event::Approved(i, _, _, _) => i,
+            Event::Approved(i, _, _, _, _, _) => i,
             Event::Canceled(i, _) => i,
+            Event::Retry(i, _) => i,
+            Event::TreeClosed(i, _) => i,
+            Event::TreeOpened(i) => i,
             Event::Opened(i, _, _, _, _) => i,
             Event::Changed(i, _, _, _, _) => i,
             Event::Closed(i, _) => i,
@@ -0,0 +1,126 @@
+// This file is released under the same terms as Rust itself.
+
+use hex::ToHex;
+use hyper::header::Headers;
+use hyper::server::Request;
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+use util::crypto::generate_sha256_hmac;
+use util::github_headers::{read_body, verify_sha256_header};
+
+/// How far off from our clock a command's timestamp may be, in seconds.
+pub const MAX_SKEW_SEC: u64 = 5 * 60;
+
+/// Read a command, if it was recently signed with any of the secrets.
+///
+/// Commands carry the time they were sent, in seconds since the epoch,
+/// in the `X-Aelita-Timestamp` header, and a SHA-256 HMAC of that
+/// timestamp, a `.`, and the body in the `X-Aelita-Signature` header.
+/// Commands that are more than `MAX_SKEW_SEC` old are refused, so one
+/// that was overheard can't be replayed later.
+pub fn parse(req: &mut Request, secrets: &[String]) -> Option<Vec<u8>> {
+    let body = match read_body(req) {
+        Some(body) => body,
+        None => return None,
+    };
+    let timestamp = match parse_timestamp(&req.headers) {
+        Some(timestamp) => timestamp,
+        None => {
+            warn!("Got missing or invalid X-Aelita-Timestamp");
+            return None;
+        }
+    };
+    if !is_fresh(timestamp, now()) {
+        warn!("Got stale X-Aelita-Timestamp {}", timestamp);
+        return None;
+    }
+    let payload = signed_payload(timestamp, &body);
+    if !verify_sha256_header(
+        &req.headers,
+        "X-Aelita-Signature",
+        secrets,
+        &payload,
+    ) {
+        return None;
+    }
+    Some(body)
+}
+
+/// Sign a callback the same way that commands are signed.
+pub fn sign(headers: &mut Headers, secret: &str, body: &[u8]) {
+    sign_at(headers, secret, body, now())
+}
+
+fn sign_at(headers: &mut Headers, secret: &str, body: &[u8], timestamp: u64) {
+    let signature = generate_sha256_hmac(
+        secret.as_bytes(),
+        &signed_payload(timestamp, body),
+    );
+    headers.set_raw("X-Aelita-Timestamp", vec![
+        timestamp.to_string().into_bytes()
+    ]);
+    headers.set_raw("X-Aelita-Signature", vec![
+        format!("sha256={}", signature.to_hex()).into_bytes()
+    ]);
+}
+
+fn signed_payload(timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{}.", timestamp).into_bytes();
+    payload.extend_from_slice(body);
+    payload
+}
+
+fn parse_timestamp(headers: &Headers) -> Option<u64> {
+    headers.get_raw("X-Aelita-Timestamp")
+        .and_then(|raw| raw.get(0))
+        .and_then(|raw| str::from_utf8(raw).ok())
+        .and_then(|raw| raw.parse().ok())
+}
+
+fn is_fresh(timestamp: u64, now: u64) -> bool {
+    now.saturating_sub(timestamp) <= MAX_SKEW_SEC &&
+        timestamp.saturating_sub(now) <= MAX_SKEW_SEC
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .expect("the clock to be past 1970")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::header::Headers;
+    use super::{MAX_SKEW_SEC, is_fresh, parse_timestamp, sign_at};
+    use super::signed_payload;
+    use util::github_headers::verify_sha256_header;
+
+    #[test]
+    fn test_signature_round_trip() {
+        let mut headers = Headers::new();
+        sign_at(&mut headers, "secret", b"body", 1500000000);
+        assert_eq!(parse_timestamp(&headers), Some(1500000000));
+        let secrets = vec!["secret".to_owned()];
+        let verify = |timestamp: u64, body: &[u8]| verify_sha256_header(
+            &headers,
+            "X-Aelita-Signature",
+            &secrets,
+            &signed_payload(timestamp, body),
+        );
+        assert!(verify(1500000000, b"body"));
+        assert!(!verify(1500000001, b"body"));
+        assert!(!verify(1500000000, b"other body"));
+    }
+
+    #[test]
+    fn test_stale_timestamp() {
+        let now = 1500000000;
+        assert!(is_fresh(now, now));
+        assert!(is_fresh(now - MAX_SKEW_SEC, now));
+        assert!(is_fresh(now + MAX_SKEW_SEC, now));
+        assert!(!is_fresh(now - MAX_SKEW_SEC - 1, now));
+        assert!(!is_fresh(now + MAX_SKEW_SEC + 1, now));
+        assert!(!is_fresh(0, now));
+        assert!(!is_fresh(u64::max_value(), now));
+    }
+}
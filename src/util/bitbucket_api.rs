@@ -0,0 +1,118 @@
+// This file is released under the same terms as Rust itself.
+
+/*! What every Bitbucket Server API call needs: the host and the token.
+
+    The token is an HTTP access token, which Bitbucket Server takes
+    as a bearer token. Paths are given in full, from `/rest` on,
+    since build statuses are kept by a different API than the rest.
+ */
+
+use hyper;
+use hyper::client::Client as HyperClient;
+use hyper::client::Response;
+use hyper::header::{Authorization, ContentType, Headers, UserAgent};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use pipeline::PipelineId;
+use serde::Serialize;
+use serde_json;
+use url::percent_encoding::{utf8_percent_encode, QUERY_ENCODE_SET};
+use util::USER_AGENT;
+
+pub struct Client {
+    host: String,
+    token: String,
+    http: HyperClient,
+}
+
+impl Client {
+    /// The host is where Bitbucket Server itself is,
+    /// like `https://bitbucket.example.com`.
+    pub fn new(host: String, token: String) -> Client {
+        Client {
+            host: host,
+            token: token,
+            http: HyperClient::new(),
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn get(&self, path: &str) -> Result<Response, BitbucketRequestError> {
+        self.send(Method::Get, path, None)
+    }
+
+    pub fn post<T: Serialize>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<Response, BitbucketRequestError> {
+        let body = try!(serde_json::to_string(body));
+        self.send(Method::Post, path, Some(body))
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<String>,
+    ) -> Result<Response, BitbucketRequestError> {
+        let url = format!("{}{}", self.host, path);
+        let mut headers = Headers::new();
+        headers.set(Authorization(format!("Bearer {}", self.token)));
+        headers.set(UserAgent(USER_AGENT.to_owned()));
+        let request = self.http.request(method, &url[..]);
+        let resp = try!(match body {
+            Some(ref body) => {
+                headers.set(ContentType::json());
+                request.headers(headers).body(&body[..]).send()
+            }
+            None => request.headers(headers).send(),
+        });
+        if !resp.status.is_success() {
+            return Err(BitbucketRequestError::HttpStatus(resp.status));
+        }
+        Ok(resp)
+    }
+}
+
+/// User names are free to have spaces and such in them.
+pub fn query_value(value: &str) -> String {
+    utf8_percent_encode(value, QUERY_ENCODE_SET)
+        .to_string()
+        .replace("&", "%26")
+        .replace("+", "%2B")
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum BitbucketRequestError {
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// JSON error
+        Json(err: serde_json::error::Error) {
+            cause(err)
+            from()
+        }
+        /// Repo not found for pipeline
+        Pipeline(pipeline_id: PipelineId) {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::query_value;
+
+    #[test]
+    fn test_query_value() {
+        assert_eq!(query_value("testu"), "testu");
+        assert_eq!(query_value("a b&c+d"), "a%20b%26c%2Bd");
+    }
+}
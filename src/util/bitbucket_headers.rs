@@ -0,0 +1,26 @@
+// This file is released under the same terms as Rust itself.
+
+use hyper::server::Request;
+use util::github_headers::{read_body, verify_sha256_header};
+
+/// Read a webhook, if it was signed with any of the secrets.
+///
+/// Bitbucket Server names the event in `X-Event-Key`, and signs the body
+/// the way GitHub does, but with SHA-256 in the `X-Hub-Signature` header.
+pub fn parse(
+    req: &mut Request,
+    secrets: &[String],
+) -> Option<(Vec<u8>, Vec<u8>)> {
+    let x_event_key = req.headers.get_raw("X-Event-Key")
+        .and_then(|raw| raw.get(0))
+        .cloned()
+        .unwrap_or_else(Vec::new);
+    let body = match read_body(req) {
+        Some(body) => body,
+        None => return None,
+    };
+    if !verify_sha256_header(&req.headers, "X-Hub-Signature", secrets, &body) {
+        return None;
+    }
+    Some((x_event_key, body))
+}
@@ -1,10 +1,13 @@
 // This file is released under the same terms as Rust itself.
 
-use openssl::crypto::hash::Type;
+use openssl::crypto::hash::{hash, Type};
 use openssl::crypto::hmac::hmac;
 use openssl::crypto::memcmp::eq as secure_eq;
+use openssl::crypto::pkey::PKey;
+use openssl::ssl::error::SslError;
 
 pub const SHA1_LEN: usize = 40;
+pub const SHA256_LEN: usize = 64;
 
 pub fn generate_sha1_hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
 	hmac(Type::SHA1, key, data)
@@ -13,4 +16,20 @@ pub fn generate_sha1_hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
 pub fn verify_sha1_hmac(key: &[u8], data: &[u8], signature: &[u8]) -> bool {
 	let expected_signature = hmac(Type::SHA1, key, data);
 	secure_eq(&expected_signature, signature)
+}
+
+pub fn generate_sha256_hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+	hmac(Type::SHA256, key, data)
+}
+
+pub fn verify_sha256_hmac(key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+	let expected_signature = hmac(Type::SHA256, key, data);
+	secure_eq(&expected_signature, signature)
+}
+
+/// An RSASSA-PKCS1-v1_5 signature using SHA-256, which is what JWT calls RS256.
+pub fn sign_sha256_rsa(pem: &[u8], data: &[u8]) -> Result<Vec<u8>, SslError> {
+	let key = try!(PKey::private_key_from_pem(&mut &pem[..]));
+	let digest = hash(Type::SHA256, data);
+	Ok(key.sign_with_hash(&digest, Type::SHA256))
 }
\ No newline at end of file
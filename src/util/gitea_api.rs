@@ -0,0 +1,111 @@
+// This file is released under the same terms as Rust itself.
+
+/*! What every Gitea (or Forgejo) API call needs: the host and the token.
+
+    The API is shaped like GitHub's, but lives under `/api/v1`
+    on the same host as the web UI.
+ */
+
+use hyper;
+use hyper::client::Client as HyperClient;
+use hyper::client::Response;
+use hyper::header::{Authorization, ContentType, Headers, UserAgent};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use pipeline::PipelineId;
+use serde::Serialize;
+use serde_json;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use util::USER_AGENT;
+
+pub struct Client {
+    host: String,
+    token: String,
+    http: HyperClient,
+}
+
+impl Client {
+    /// The host is where Gitea itself is, like `https://codeberg.org`.
+    pub fn new(host: String, token: String) -> Client {
+        Client {
+            host: host,
+            token: token,
+            http: HyperClient::new(),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Result<Response, GiteaRequestError> {
+        self.send(Method::Get, path, None)
+    }
+
+    pub fn delete(&self, path: &str) -> Result<Response, GiteaRequestError> {
+        self.send(Method::Delete, path, None)
+    }
+
+    pub fn post<T: Serialize>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<Response, GiteaRequestError> {
+        let body = try!(serde_json::to_string(body));
+        self.send(Method::Post, path, Some(body))
+    }
+
+    pub fn patch<T: Serialize>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<Response, GiteaRequestError> {
+        let body = try!(serde_json::to_string(body));
+        self.send(Method::Patch, path, Some(body))
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<String>,
+    ) -> Result<Response, GiteaRequestError> {
+        let url = format!("{}/api/v1{}", self.host, path);
+        let mut headers = Headers::new();
+        headers.set(Authorization(format!("token {}", self.token)));
+        headers.set(UserAgent(USER_AGENT.to_owned()));
+        let request = self.http.request(method, &url[..]);
+        let resp = try!(match body {
+            Some(ref body) => {
+                headers.set(ContentType::json());
+                request.headers(headers).body(&body[..]).send()
+            }
+            None => request.headers(headers).send(),
+        });
+        if !resp.status.is_success() {
+            return Err(GiteaRequestError::HttpStatus(resp.status));
+        }
+        Ok(resp)
+    }
+}
+
+/// Branch names may have slashes in them.
+pub fn path_segment(segment: &str) -> String {
+    utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET).to_string()
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum GiteaRequestError {
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// JSON error
+        Json(err: serde_json::error::Error) {
+            cause(err)
+            from()
+        }
+        /// Repo not found for pipeline
+        Pipeline(pipeline_id: PipelineId) {}
+    }
+}
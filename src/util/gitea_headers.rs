@@ -0,0 +1,47 @@
+// This file is released under the same terms as Rust itself.
+
+use hyper::header::Headers;
+use hyper::server::Request;
+use util::github_headers::{read_body, verify_sha256_hex};
+
+/// Read a webhook, if it was signed with any of the secrets.
+///
+/// Gitea signs the body with a SHA-256 HMAC, in bare hex.
+/// Forgejo sends the same headers under its own name as well as Gitea's.
+pub fn parse(
+    req: &mut Request,
+    secrets: &[String],
+) -> Option<(Vec<u8>, Vec<u8>)> {
+    let x_gitea_event = first_header(
+        &req.headers,
+        &["X-Forgejo-Event", "X-Gitea-Event"],
+    ).unwrap_or_else(Vec::new);
+    let signature = first_header(
+        &req.headers,
+        &["X-Forgejo-Signature", "X-Gitea-Signature"],
+    );
+    let body = match read_body(req) {
+        Some(body) => body,
+        None => return None,
+    };
+    let signature = match signature {
+        Some(signature) => signature,
+        None => {
+            warn!("Got no X-Gitea-Signature");
+            return None;
+        }
+    };
+    if !verify_sha256_hex(&signature, secrets, &body) {
+        warn!("Got invalid or incorrect X-Gitea-Signature");
+        return None;
+    }
+    Some((x_gitea_event, body))
+}
+
+fn first_header(headers: &Headers, names: &[&str]) -> Option<Vec<u8>> {
+    names.iter()
+        .filter_map(|name| headers.get_raw(name))
+        .filter_map(|raw| raw.get(0))
+        .next()
+        .cloned()
+}
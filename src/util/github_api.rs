@@ -0,0 +1,158 @@
+// This file is released under the same terms as Rust itself.
+
+//! What every GitHub API call needs to do: follow pages and obey the
+//! rate limit. Requests are sent through the `github_send!` macro.
+
+use hyper::header::Headers;
+use hyper::status::StatusCode;
+use std::cmp;
+use std::str;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// If GitHub said that we were going too fast, how long to wait
+/// before sending the request again.
+pub fn backoff(status: &StatusCode, headers: &Headers) -> Option<Duration> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+        .expect("the clock to be past 1970")
+        .as_secs();
+    backoff_at(status, headers, now)
+}
+
+/// If GitHub said that we were going too fast, wait for as long as it
+/// asked. Returns whether the request should be sent again.
+pub fn wait_for_backoff(status: &StatusCode, headers: &Headers) -> bool {
+    match backoff(status, headers) {
+        Some(wait) => {
+            warn!("Hit the GitHub rate limit; waiting {:?}", wait);
+            thread::sleep(wait);
+            true
+        }
+        None => false,
+    }
+}
+
+fn backoff_at(
+    status: &StatusCode,
+    headers: &Headers,
+    now: u64,
+) -> Option<Duration> {
+    // A 403 is also what GitHub sends when we're really not allowed to,
+    // and those are not worth waiting for.
+    if *status != StatusCode::Forbidden &&
+            *status != StatusCode::TooManyRequests {
+        return None;
+    }
+    // The abuse limit tells us how long to wait.
+    if let Some(secs) = header_u64(headers, "Retry-After") {
+        return Some(Duration::from_secs(cmp::max(secs, 1)));
+    }
+    // The regular limit tells us when it starts over.
+    if header_u64(headers, "X-RateLimit-Remaining") == Some(0) {
+        let reset = header_u64(headers, "X-RateLimit-Reset")
+            .unwrap_or(now + 60);
+        let secs = if reset > now { reset - now } else { 1 };
+        return Some(Duration::from_secs(secs));
+    }
+    None
+}
+
+/// The URL of the next page of a listing, if there is one.
+pub fn next_page(headers: &Headers) -> Option<String> {
+    let link = match headers.get_raw("Link").and_then(|raw| raw.get(0)) {
+        Some(link) => link,
+        None => return None,
+    };
+    let link = match str::from_utf8(link) {
+        Ok(link) => link,
+        Err(_) => return None,
+    };
+    // <https://api.github.com/...&page=2>; rel="next", <...>; rel="last"
+    for part in link.split(',') {
+        let mut params = part.split(';').map(str::trim);
+        let url = match params.next() {
+            Some(url) if url.starts_with('<') && url.ends_with('>') =>
+                &url[1..url.len()-1],
+            _ => continue,
+        };
+        if params.any(|param| param == r#"rel="next""#) {
+            return Some(url.to_owned());
+        }
+    }
+    None
+}
+
+fn header_u64(headers: &Headers, name: &str) -> Option<u64> {
+    headers.get_raw(name)
+        .and_then(|raw| raw.get(0))
+        .and_then(|value| str::from_utf8(value).ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::header::Headers;
+    use hyper::status::StatusCode;
+    use std::time::Duration;
+    use super::{backoff_at, next_page};
+
+    fn headers(values: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for &(name, value) in values {
+            headers.set_raw(name.to_owned(), vec![value.as_bytes().to_vec()]);
+        }
+        headers
+    }
+
+    #[test]
+    fn next_page_link() {
+        let headers = headers(&[(
+            "Link",
+            concat!(
+                r#"<https://api.github.com/orgs/o/teams?page=2>; rel="next", "#,
+                r#"<https://api.github.com/orgs/o/teams?page=5>; rel="last""#,
+            ),
+        )]);
+        assert_eq!(
+            next_page(&headers),
+            Some("https://api.github.com/orgs/o/teams?page=2".to_owned())
+        );
+    }
+
+    #[test]
+    fn last_page_link() {
+        let headers = headers(&[(
+            "Link",
+            concat!(
+                r#"<https://api.github.com/orgs/o/t?page=1>; rel="first", "#,
+                r#"<https://api.github.com/orgs/o/t?page=4>; rel="prev""#,
+            ),
+        )]);
+        assert_eq!(next_page(&headers), None);
+        assert_eq!(next_page(&Headers::new()), None);
+    }
+
+    #[test]
+    fn backoff_rate_limit() {
+        let limited = headers(&[
+            ("X-RateLimit-Remaining", "0"),
+            ("X-RateLimit-Reset", "1030"),
+        ]);
+        assert_eq!(
+            backoff_at(&StatusCode::Forbidden, &limited, 1000),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(backoff_at(&StatusCode::Ok, &limited, 1000), None);
+        let retry_after = headers(&[("Retry-After", "5")]);
+        assert_eq!(
+            backoff_at(&StatusCode::TooManyRequests, &retry_after, 1000),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn backoff_forbidden() {
+        let allowed = headers(&[("X-RateLimit-Remaining", "4999")]);
+        assert_eq!(backoff_at(&StatusCode::Forbidden, &allowed, 1000), None);
+    }
+}
@@ -0,0 +1,343 @@
+// This file is released under the same terms as Rust itself.
+
+/*! Credentials for the GitHub API.
+
+    Either one personal access token is used for everything,
+    or we act as a GitHub App, which needs a separate token
+    for every account that it is installed on.
+ */
+
+use hyper;
+use hyper::client::Client as HyperClient;
+use hyper::client::Response as HyperResponse;
+use hyper::header::{qitem, Accept, Authorization, Bearer, UserAgent};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use openssl::ssl::error::SslError;
+use rest::{self, authorization, Client, Mime};
+use serde_json::{self, from_reader as json_from_reader};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use util::USER_AGENT;
+use util::github_api;
+use util::crypto::sign_sha256_rsa;
+
+pub type GithubClient = Client<rest::Authorization<authorization::Token>>;
+
+/// Installation tokens last for an hour.
+/// Get a new one well before then, so that none expire mid-request.
+const TOKEN_LIFETIME_SEC: u64 = 50*60;
+
+/// App JWTs may last for ten minutes, but ours only need to last long
+/// enough to get an installation token.
+const JWT_LIFETIME_SEC: u64 = 5*60;
+
+pub struct Credentials {
+    host: String,
+    auth: Auth,
+}
+
+enum Auth {
+    Token(Arc<GithubClient>),
+    App(App),
+}
+
+struct App {
+    id: u64,
+    private_key: Vec<u8>,
+    /// The installation tokens we have, by account, and when we got them.
+    clients: Mutex<HashMap<String, (Instant, Arc<GithubClient>)>>,
+    /// The installations we know of, by account.
+    installations: Mutex<HashMap<String, u64>>,
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct AccountDesc {
+    login: String,
+}
+#[derive(Deserialize, Serialize)]
+struct InstallationDesc {
+    id: u64,
+    account: AccountDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct AccessTokenDesc {
+    token: String,
+}
+
+impl Credentials {
+    pub fn token(host: String, token: String) -> Credentials {
+        let client = Client::new(USER_AGENT.to_owned())
+            .base(&host)
+            .authorization(rest::Authorization(authorization::Token{
+                token: token,
+            }));
+        Credentials {
+            host: host,
+            auth: Auth::Token(Arc::new(client)),
+        }
+    }
+
+    /// Act as a GitHub App, given its ID and PEM-encoded private key.
+    pub fn app(host: String, id: u64, private_key: Vec<u8>) -> Credentials {
+        Credentials {
+            host: host,
+            auth: Auth::App(App {
+                id: id,
+                private_key: private_key,
+                clients: Mutex::new(HashMap::new()),
+                installations: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// A client that can act on the repos of the given user or org.
+    pub fn client(&self, owner: &str) -> Result<Arc<GithubClient>, AuthError> {
+        let app = match self.auth {
+            Auth::Token(ref client) => return Ok(client.clone()),
+            Auth::App(ref app) => app,
+        };
+        // Hold on to the lock while getting a new token,
+        // so that the other threads wait for it instead of getting their own.
+        let mut clients = app.clients.lock().unwrap();
+        if let Some(client) = fresh_client(&clients, owner, Instant::now()) {
+            return Ok(client);
+        }
+        let installation = try!(self.installation(app, owner));
+        let url = format!(
+            "{}/app/installations/{}/access_tokens",
+            self.host,
+            installation,
+        );
+        let resp = try!(self.app_request(app, Method::Post, &url));
+        let token: AccessTokenDesc = try!(json_from_reader(resp));
+        info!("Got a new installation token for {}", owner);
+        let client = Arc::new(
+            Client::new(USER_AGENT.to_owned())
+                .base(&self.host)
+                .authorization(rest::Authorization(authorization::Token{
+                    token: token.token,
+                }))
+        );
+        clients.insert(owner.to_owned(), (Instant::now(), client.clone()));
+        Ok(client)
+    }
+
+    /// The app was installed on a user or org.
+    pub fn add_installation(&self, owner: &str, id: u64) {
+        if let Auth::App(ref app) = self.auth {
+            app.installations.lock().unwrap().insert(owner.to_owned(), id);
+        }
+    }
+
+    /// The app was uninstalled from a user or org,
+    /// so its token is no good any more.
+    pub fn remove_installation(&self, owner: &str) {
+        if let Auth::App(ref app) = self.auth {
+            app.installations.lock().unwrap().remove(owner);
+            app.clients.lock().unwrap().remove(owner);
+        }
+    }
+
+    /// Find the installation for an account, asking GitHub if the
+    /// installation webhook has not told us about it.
+    fn installation(&self, app: &App, owner: &str) -> Result<u64, AuthError> {
+        if let Some(&id) = app.installations.lock().unwrap().get(owner) {
+            return Ok(id);
+        }
+        let mut all: Vec<InstallationDesc> = Vec::new();
+        let mut url = Some(
+            format!("{}/app/installations?per_page=100", self.host)
+        );
+        while let Some(page_url) = url.take() {
+            let resp = try!(self.app_request(app, Method::Get, &page_url));
+            url = github_api::next_page(&resp.headers);
+            let page: Vec<InstallationDesc> = try!(json_from_reader(resp));
+            all.extend(page);
+        }
+        let mut installations = app.installations.lock().unwrap();
+        for installation in all {
+            installations.insert(installation.account.login, installation.id);
+        }
+        match installations.get(owner) {
+            Some(&id) => Ok(id),
+            None => Err(AuthError::NotInstalled(owner.to_owned())),
+        }
+    }
+
+    /// Make a request as the app itself, rather than an installation.
+    fn app_request(
+        &self,
+        app: &App,
+        method: Method,
+        url: &str,
+    ) -> Result<HyperResponse, AuthError> {
+        let mime: Mime = "application/vnd.github.machine-man-preview+json"
+            .parse().expect("hard-coded mimes to be valid");
+        let resp = github_send!(hyper
+            HyperClient::new().request(method.clone(), url)
+                .header(Authorization(Bearer{ token: try!(app.jwt()) }))
+                .header(UserAgent(USER_AGENT.to_owned()))
+                .header(Accept(vec![qitem(mime.clone())]))
+        );
+        if !resp.status.is_success() {
+            return Err(AuthError::HttpStatus(resp.status));
+        }
+        Ok(resp)
+    }
+}
+
+impl App {
+    fn jwt(&self) -> Result<String, SslError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .expect("the clock to be past 1970")
+            .as_secs();
+        let claims = jwt_claims(self.id, now);
+        let message = format!(
+            "{}.{}",
+            base64_url(br#"{"alg":"RS256","typ":"JWT"}"#),
+            base64_url(claims.as_bytes()),
+        );
+        let signature = try!(sign_sha256_rsa(
+            &self.private_key,
+            message.as_bytes(),
+        ));
+        Ok(format!("{}.{}", message, base64_url(&signature)))
+    }
+}
+
+/// The claims of an app JWT issued at the given UNIX time.
+fn jwt_claims(id: u64, now: u64) -> String {
+    // Backdate it a bit, in case our clock is ahead of GitHub's.
+    format!(
+        r#"{{"iat":{},"exp":{},"iss":{}}}"#,
+        now - 60,
+        now + JWT_LIFETIME_SEC,
+        id,
+    )
+}
+
+/// The installation token we have for an account,
+/// unless it is too close to expiring to use.
+fn fresh_client(
+    clients: &HashMap<String, (Instant, Arc<GithubClient>)>,
+    owner: &str,
+    now: Instant,
+) -> Option<Arc<GithubClient>> {
+    match clients.get(owner) {
+        Some(&(created, ref client)) if
+                now.duration_since(created) <
+                    Duration::from_secs(TOKEN_LIFETIME_SEC) =>
+            Some(client.clone()),
+        _ => None,
+    }
+}
+
+/// Unpadded URL-safe base64, as used in JWTs.
+fn base64_url(data: &[u8]) -> String {
+    const ALPHABET: &'static [u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut result = String::with_capacity((data.len() * 4 + 2) / 3);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - i * 8));
+        for i in 0..(chunk.len() + 1) {
+            let index = (bits >> (18 - i * 6)) & 0x3F;
+            result.push(ALPHABET[index as usize] as char);
+        }
+    }
+    result
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum AuthError {
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// JSON error
+        Json(err: serde_json::error::Error) {
+            cause(err)
+            from()
+        }
+        /// The private key could not sign a JWT
+        Key(err: SslError) {
+            cause(err)
+            from()
+        }
+        /// The app is not installed for the user or org
+        NotInstalled(owner: String) {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rest::{self, authorization, Client};
+    use serde_json::{self, Value};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use super::{JWT_LIFETIME_SEC, TOKEN_LIFETIME_SEC};
+    use super::{base64_url, fresh_client, jwt_claims};
+    use util::USER_AGENT;
+
+    #[test]
+    fn jwt_claims_window() {
+        let claims: Value = serde_json::from_str(
+            &jwt_claims(42, 1500000000)
+        ).unwrap();
+        let claim = |name| claims.find(name).and_then(Value::as_u64);
+        let (iat, exp) = (claim("iat").unwrap(), claim("exp").unwrap());
+        assert_eq!(claim("iss"), Some(42));
+        assert!(iat <= 1500000000);
+        assert!(exp > 1500000000);
+        assert_eq!(exp, 1500000000 + JWT_LIFETIME_SEC);
+        // GitHub refuses JWTs that last longer than ten minutes.
+        assert!(exp - iat <= 10*60);
+    }
+
+    #[test]
+    fn installation_token_reuse() {
+        let client = Arc::new(
+            Client::new(USER_AGENT.to_owned())
+                .base("http://localhost:0")
+                .authorization(rest::Authorization(authorization::Token{
+                    token: "token".to_owned(),
+                }))
+        );
+        let created = Instant::now();
+        let mut clients = HashMap::new();
+        clients.insert("testu".to_owned(), (created, client));
+        let at = |secs| created + Duration::from_secs(secs);
+        assert!(fresh_client(&clients, "testu", created).is_some());
+        assert!(
+            fresh_client(&clients, "testu", at(TOKEN_LIFETIME_SEC - 1))
+                .is_some()
+        );
+        assert!(
+            fresh_client(&clients, "testu", at(TOKEN_LIFETIME_SEC)).is_none()
+        );
+        assert!(fresh_client(&clients, "otheru", created).is_none());
+    }
+
+    #[test]
+    fn base64_url_padding() {
+        assert_eq!(base64_url(b""), "");
+        assert_eq!(base64_url(b"f"), "Zg");
+        assert_eq!(base64_url(b"fo"), "Zm8");
+        assert_eq!(base64_url(b"foo"), "Zm9v");
+        assert_eq!(base64_url(b"foob"), "Zm9vYg");
+    }
+
+    #[test]
+    fn base64_url_alphabet() {
+        assert_eq!(base64_url(&[0xfb, 0xff]), "-_8");
+    }
+}
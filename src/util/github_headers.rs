@@ -4,9 +4,15 @@ use hex::FromHex;
 use hyper::header::Headers;
 use hyper::server::Request;
 use std::io::Read;
-use util::crypto::{SHA1_LEN, verify_sha1_hmac};
+use util::crypto::{SHA1_LEN, SHA256_LEN};
+use util::crypto::{verify_sha1_hmac, verify_sha256_hmac};
 
-pub fn parse(req: &mut Request, secret: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+/// Read a webhook, if it was signed with any of the secrets.
+/// More than one secret may be accepted while one is being rotated out.
+pub fn parse(
+    req: &mut Request,
+    secrets: &[String],
+) -> Option<(Vec<u8>, Vec<u8>)> {
     let x_github_event = {
         if let Some(xges) = req.headers.get_raw("X-Github-Event") {
             if let Some(xge) = xges.get(0) {
@@ -18,15 +24,10 @@ pub fn parse(req: &mut Request, secret: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
             vec![]
         }
     };
-    let body = {
-        let mut body = Vec::new();
-        let result = req.read_to_end(&mut body);
-        if let Err(e) = result {
-            warn!("Failed to read body: {:?}", e);
-            return None;
-        } else {
-            body
-        }
+    let body = if let Some(body) = read_body(req) {
+        body
+    } else {
+        return None;
     };
     let signature = parse_signature(&req.headers);
     let signature = if let Some(signature) = signature {
@@ -34,16 +35,97 @@ pub fn parse(req: &mut Request, secret: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
     } else {
         return None;
     };
-    if !verify_sha1_hmac(secret, &body, &signature) {
+    if !verify_signature(&signature, secrets, &body) {
         warn!("Got incorrect signature");
         return None;
     }
     Some((x_github_event, body))
 }
 
-fn parse_signature(headers: &Headers) -> Option<Vec<u8>> {
+/// Read the whole body of a webhook, so that its signature can be checked.
+pub fn read_body(req: &mut Request) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    if let Err(e) = req.read_to_end(&mut body) {
+        warn!("Failed to read body: {:?}", e);
+        return None;
+    }
+    Some(body)
+}
+
+/// Check that `data` was signed with any of the secrets,
+/// in a `sha256=hex` header like GitHub's `X-Hub-Signature-256`.
+pub fn verify_sha256_header(
+    headers: &Headers,
+    name: &str,
+    secrets: &[String],
+    data: &[u8],
+) -> bool {
+    let signature =
+        parse_signature_header(headers, name, "sha256=", SHA256_LEN);
+    let signature = if let Some(signature) = signature {
+        Signature::Sha256(signature)
+    } else {
+        return false;
+    };
+    if !verify_signature(&signature, secrets, data) {
+        warn!("Got incorrect {}", name);
+        return false;
+    }
+    true
+}
+
+/// Check that `data` was signed with any of the secrets,
+/// in bare hex with no prefix, the way Gitea signs its webhooks.
+pub fn verify_sha256_hex(
+    signature: &[u8],
+    secrets: &[String],
+    data: &[u8],
+) -> bool {
+    if signature.len() != SHA256_LEN {
+        return false;
+    }
+    let signature = match Vec::from_hex(signature) {
+        Ok(signature) => Signature::Sha256(signature),
+        Err(_) => return false,
+    };
+    verify_signature(&signature, secrets, data)
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Signature {
+    Sha1(Vec<u8>),
+    Sha256(Vec<u8>),
+}
+
+/// GitHub sends both signatures; the SHA-256 one is used when it's there.
+fn parse_signature(headers: &Headers) -> Option<Signature> {
+    if headers.get_raw("X-Hub-Signature-256").is_some() {
+        parse_signature_header(
+            headers,
+            "X-Hub-Signature-256",
+            "sha256=",
+            SHA256_LEN,
+        ).map(Signature::Sha256)
+    } else {
+        parse_signature_header(
+            headers,
+            "X-Hub-Signature",
+            "sha1=",
+            SHA1_LEN,
+        ).map(Signature::Sha1)
+    }
+}
+
+/// One `prefix=hex` signature header, as GitHub (and those who copy it)
+/// sends them.
+pub fn parse_signature_header(
+    headers: &Headers,
+    name: &str,
+    prefix: &str,
+    len: usize,
+) -> Option<Vec<u8>> {
     let x_hub_signature = {
-        if let Some(xges) = headers.get_raw("X-Hub-Signature") {
+        if let Some(xges) = headers.get_raw(name) {
             if let Some(xge) = xges.get(0) {
                 xge.clone()
             } else {
@@ -53,25 +135,49 @@ fn parse_signature(headers: &Headers) -> Option<Vec<u8>> {
             vec![]
         }
     };
-    if x_hub_signature.len() != SHA1_LEN+5 {
-        warn!("Got wrong length X-Hub-Signature");
+    if x_hub_signature.len() != len + prefix.len() {
+        warn!("Got wrong length {}", name);
+        return None;
+    }
+    if !x_hub_signature.starts_with(prefix.as_bytes()) {
+        warn!("Got wrong prefix in {}", name);
         return None;
     }
-    let signature = &x_hub_signature[5..]; // sha1=
+    let signature = &x_hub_signature[prefix.len()..];
     let signature = Vec::from_hex(&signature);
     let signature = if let Ok(signature) = signature {
         signature
     } else {
-        warn!("Got invalid hex in X-Hub-Signature");
+        warn!("Got invalid hex in {}", name);
         return None;
     };
     Some(signature)
 }
 
+fn verify_signature(
+    signature: &Signature,
+    secrets: &[String],
+    body: &[u8],
+) -> bool {
+    secrets.iter().any(|secret| {
+        match *signature {
+            Signature::Sha1(ref signature) =>
+                verify_sha1_hmac(secret.as_bytes(), body, signature),
+            Signature::Sha256(ref signature) =>
+                verify_sha256_hmac(secret.as_bytes(), body, signature),
+        }
+    })
+}
+
 #[cfg(test)]
 mod test {
+    use hex::ToHex;
     use hyper::header::Headers;
-    use super::parse_signature;
+    use openssl::crypto::hash::Type;
+    use openssl::crypto::hmac::hmac;
+    use super::{parse_signature, parse_signature_header, verify_signature};
+    use super::{Signature, verify_sha256_header, verify_sha256_hex};
+    use util::crypto::SHA256_LEN;
     #[test]
     fn test_empty_signature() {
         let mut headers = Headers::new();
@@ -81,4 +187,78 @@ mod test {
         headers.set_raw("X-Hub-Signature", vec![ vec![ b' ' ] ]);
         assert!(parse_signature(&headers).is_none());
     }
+    #[test]
+    fn test_prefer_sha256_signature() {
+        let sha1 = hmac(Type::SHA1, b"secret", b"body");
+        let sha256 = hmac(Type::SHA256, b"secret", b"body");
+        let mut headers = Headers::new();
+        headers.set_raw(
+            "X-Hub-Signature",
+            vec![ format!("sha1={}", sha1.to_hex()).into_bytes() ],
+        );
+        assert_eq!(parse_signature(&headers), Some(Signature::Sha1(sha1)));
+        headers.set_raw(
+            "X-Hub-Signature-256",
+            vec![ format!("sha256={}", sha256.to_hex()).into_bytes() ],
+        );
+        assert_eq!(parse_signature(&headers), Some(Signature::Sha256(sha256)));
+    }
+    #[test]
+    fn test_signature_header() {
+        let signature = hmac(Type::SHA256, b"secret", b"body");
+        let parse = |value: String| {
+            let mut headers = Headers::new();
+            headers.set_raw("X-Hub-Signature", vec![ value.into_bytes() ]);
+            parse_signature_header(
+                &headers,
+                "X-Hub-Signature",
+                "sha256=",
+                SHA256_LEN,
+            )
+        };
+        assert_eq!(
+            parse(format!("sha256={}", signature.to_hex())),
+            Some(signature.clone())
+        );
+        assert_eq!(parse(signature.to_hex()), None);
+        assert_eq!(parse(format!("sha1={}", signature.to_hex())), None);
+    }
+    #[test]
+    fn test_verify_sha256_header() {
+        let signature = hmac(Type::SHA256, b"new", b"body");
+        let mut headers = Headers::new();
+        headers.set_raw(
+            "X-Hub-Signature",
+            vec![ format!("sha256={}", signature.to_hex()).into_bytes() ],
+        );
+        let secrets = vec!["old".to_owned(), "new".to_owned()];
+        let verify = |headers: &Headers, data: &[u8]| {
+            verify_sha256_header(headers, "X-Hub-Signature", &secrets, data)
+        };
+        assert!(verify(&headers, b"body"));
+        assert!(!verify(&headers, b"other body"));
+        assert!(!verify(&Headers::new(), b"body"));
+    }
+    #[test]
+    fn test_any_secret() {
+        let signature = Signature::Sha256(
+            hmac(Type::SHA256, b"new", b"body")
+        );
+        let secrets = vec!["old".to_owned(), "new".to_owned()];
+        assert!(verify_signature(&signature, &secrets, b"body"));
+        assert!(!verify_signature(&signature, &secrets[..1], b"body"));
+        assert!(!verify_signature(&signature, &secrets, b"other body"));
+    }
+    #[test]
+    fn test_verify_sha256_hex() {
+        let signature = hmac(Type::SHA256, b"new", b"body");
+        let hex = signature.to_hex().into_bytes();
+        let prefixed = format!("sha256={}", signature.to_hex()).into_bytes();
+        let secrets = vec!["old".to_owned(), "new".to_owned()];
+        assert!(verify_sha256_hex(&hex, &secrets, b"body"));
+        assert!(!verify_sha256_hex(&hex, &secrets[..1], b"body"));
+        assert!(!verify_sha256_hex(&hex, &secrets, b"other body"));
+        assert!(!verify_sha256_hex(&prefixed, &secrets, b"body"));
+        assert!(!verify_sha256_hex(b"", &secrets, b"body"));
+    }
 }
\ No newline at end of file
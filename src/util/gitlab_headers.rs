@@ -0,0 +1,59 @@
+// This file is released under the same terms as Rust itself.
+
+use hyper::server::Request;
+use openssl::crypto::memcmp::eq as secure_eq;
+use util::github_headers::read_body;
+
+/// Read a webhook, if it came with any of the secret tokens.
+///
+/// GitLab does not sign its webhooks; it sends the secret itself
+/// in `X-Gitlab-Token`, so that's what is checked.
+pub fn parse(
+    req: &mut Request,
+    secrets: &[String],
+) -> Option<(Vec<u8>, Vec<u8>)> {
+    let x_gitlab_event = req.headers.get_raw("X-Gitlab-Event")
+        .and_then(|raw| raw.get(0))
+        .cloned()
+        .unwrap_or_else(Vec::new);
+    let token = req.headers.get_raw("X-Gitlab-Token")
+        .and_then(|raw| raw.get(0))
+        .cloned();
+    let body = match read_body(req) {
+        Some(body) => body,
+        None => return None,
+    };
+    let token = match token {
+        Some(token) => token,
+        None => {
+            warn!("Got no X-Gitlab-Token");
+            return None;
+        }
+    };
+    if !verify_token(&token, secrets) {
+        warn!("Got incorrect X-Gitlab-Token");
+        return None;
+    }
+    Some((x_gitlab_event, body))
+}
+
+fn verify_token(token: &[u8], secrets: &[String]) -> bool {
+    secrets.iter().any(|secret| {
+        let secret = secret.as_bytes();
+        secret.len() == token.len() && secure_eq(secret, token)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::verify_token;
+    #[test]
+    fn test_any_token() {
+        let secrets = vec!["old".to_owned(), "new".to_owned()];
+        assert!(verify_token(b"new", &secrets));
+        assert!(verify_token(b"old", &secrets));
+        assert!(!verify_token(b"new", &secrets[..1]));
+        assert!(!verify_token(b"ne", &secrets));
+        assert!(!verify_token(b"", &secrets));
+    }
+}
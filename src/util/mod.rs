@@ -1,8 +1,5 @@
 // This file is released under the same terms as Rust itself.
 
-pub mod crypto;
-pub mod github_headers;
-
 pub const USER_AGENT: &'static str =
     "aelita/0.1 (https://github.com/AelitaBot/aelita)";
 
@@ -35,4 +32,48 @@ macro_rules! retry_unwrap {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Send a request to the GitHub API, and if we have hit the rate limit,
+/// wait for as long as GitHub asks and send it again.
+///
+/// Requests built with `rest` are sent with `github_send!(REQUEST)`,
+/// and ones built with plain hyper with `github_send!(hyper REQUEST)`.
+/// Either way, the request expression is evaluated again for every attempt.
+macro_rules! github_send {
+    (hyper $request: expr) => {{
+        use util::github_api::wait_for_backoff;
+        let mut resp;
+        loop {
+            resp = try!($request.send());
+            if !wait_for_backoff(&resp.status, &resp.headers) {
+                break;
+            }
+        }
+        resp
+    }};
+    ($request: expr) => {{
+        use util::github_api::wait_for_backoff;
+        let mut resp;
+        loop {
+            resp = try!($request.send());
+            if !wait_for_backoff(&resp.http.status, &resp.http.headers) {
+                break;
+            }
+        }
+        resp
+    }};
+}
+
+// These come after the macros, so that they can use them.
+pub mod api_headers;
+pub mod bitbucket_api;
+pub mod bitbucket_headers;
+pub mod crypto;
+pub mod gitea_api;
+pub mod gitea_headers;
+pub mod github_api;
+pub mod github_auth;
+pub mod github_headers;
+pub mod gitlab_api;
+pub mod gitlab_headers;
@@ -85,7 +85,7 @@ impl Worker {
     ) {
         match msg {
             vcs::Message::MergeToStaging(
-                pipeline_id, pull_commit, message, remote
+                pipeline_id, staging, base_commit, pull_commit, message, remote
             ) => {
                 let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
                     Some(repo) => repo,
@@ -96,7 +96,12 @@ impl Worker {
                 };
                 info!("Merging {} ...", pull_commit);
                 match self.merge_to_staging(
-                    &repo, &pull_commit, &message, &remote.0
+                    &repo,
+                    staging,
+                    base_commit.as_ref(),
+                    &pull_commit,
+                    &message,
+                    &remote.0,
                 ) {
                     Err(e) => {
                         warn!(
@@ -106,6 +111,7 @@ impl Worker {
                         );
                         send_event.send(vcs::Event::FailedMergeToStaging(
                             pipeline_id,
+                            base_commit,
                             pull_commit,
                         )).expect("Pipeline gone merge to staging error");
                     }
@@ -113,6 +119,7 @@ impl Worker {
                         info!("Merged {} to {}", pull_commit, merge_commit);
                         send_event.send(vcs::Event::MergedToStaging(
                             pipeline_id,
+                            base_commit,
                             pull_commit,
                             merge_commit,
                         )).expect("Pipeline gone merge to staging");
@@ -154,10 +161,13 @@ impl Worker {
     fn merge_to_staging(
         &self,
         repo: &Repo,
+        staging: usize,
+        base_commit: Option<&Commit>,
         pull_commit: &Commit,
         message: &str,
         remote: &str,
     ) -> Result<Commit, GitError> {
+        let staging_branch = vcs::staging_branch(&repo.staging_branch, staging);
         try!(self.setup_dir(repo));
         try_cmd!(Command::new(&self.executable), cmd,
         cmd.current_dir(&repo.path)
@@ -169,20 +179,24 @@ impl Worker {
             .arg("origin")
             .arg(&repo.master_branch)
             .arg(remote));
+        let base = match base_commit {
+            Some(base_commit) => base_commit.to_string(),
+            None => format!("origin/{}", repo.master_branch),
+        };
         try_cmd!(Command::new(&self.executable), cmd,
         cmd.current_dir(&repo.path)
             .arg("checkout")
-            .arg(format!("origin/{}", repo.master_branch)));
+            .arg(&base));
         try_cmd!(Command::new(&self.executable), cmd,
         cmd.current_dir(&repo.path)
             .arg("branch")
             .arg("-f")
-            .arg(&repo.staging_branch)
-            .arg(format!("origin/{}", repo.master_branch)));
+            .arg(&staging_branch)
+            .arg(&base));
         try_cmd!(Command::new(&self.executable), cmd,
         cmd.current_dir(&repo.path)
             .arg("checkout")
-            .arg(&repo.staging_branch));
+            .arg(&staging_branch));
         try_cmd!(Command::new(&self.executable), cmd,
         cmd.current_dir(&repo.path)
             .arg("merge")
@@ -195,12 +209,12 @@ impl Worker {
             .arg("push")
             .arg("-f")
             .arg("origin")
-            .arg(&repo.staging_branch));
+            .arg(&staging_branch));
         let mut commit_string = String::new();
         try!(try!(File::open(
             Path::new(&repo.path)
                 .join(".git/refs/heads/")
-                .join(&repo.staging_branch)
+                .join(&staging_branch)
         )).read_to_string(&mut commit_string));
         commit_string = commit_string.replace("\n", "").replace("\r", "");
         Ok(Commit::from(commit_string))
@@ -0,0 +1,339 @@
+// This file is released under the same terms as Rust itself.
+
+/*! Merging through the Gitea (or Forgejo) API.
+
+    Like GitLab, Gitea cannot merge two commits or move a branch
+    through its API, so the pull commit is pushed to a temporary branch
+    and merged with a pull request of our own, and staging is reset by
+    deleting and recreating it. Master is moved with a fast-forward-only
+    merge, which needs Gitea 1.22 or newer.
+ */
+
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use serde_json::from_reader as json_from_reader;
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, Receiver};
+use util::gitea_api::{self, Client, GiteaRequestError};
+use vcs::{self, Commit};
+
+pub trait PipelinesConfig: Send + Sync + 'static {
+    fn repo_by_pipeline(&self, PipelineId) -> Option<Repo>;
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    pub owner: String,
+    pub repo: String,
+    pub master_branch: String,
+    pub staging_branch: String,
+}
+
+pub struct Worker {
+    pipelines: Box<PipelinesConfig>,
+    client: Arc<Client>,
+}
+
+impl Worker {
+    pub fn new(
+        client: Arc<Client>,
+        pipelines: Box<PipelinesConfig>
+    ) -> Worker {
+        Worker{
+            pipelines: pipelines,
+            client: client,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum MergeStyle {
+    Merge,
+    FastForwardOnly,
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct BranchCommitDesc {
+    id: String,
+}
+#[derive(Deserialize, Serialize)]
+struct BranchDesc {
+    commit: BranchCommitDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct BranchCreateDesc {
+    new_branch_name: String,
+    old_ref_name: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PullRequestCreateDesc {
+    head: String,
+    base: String,
+    title: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PullRequestDesc {
+    number: u32,
+    merge_commit_sha: Option<String>,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeDesc {
+    #[serde(rename="Do")]
+    merge_style: String,
+    #[serde(rename="MergeTitleField")]
+    title: String,
+    #[serde(rename="MergeMessageField")]
+    message: String,
+    head_commit_id: String,
+    delete_branch_after_merge: bool,
+}
+#[derive(Deserialize, Serialize)]
+struct PullRequestUpdateDesc {
+    state: String,
+}
+
+impl pipeline::Worker<vcs::Event, vcs::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<vcs::Message>,
+        mut send_event: Sender<vcs::Event>
+    ) {
+        loop {
+            self.handle_message(
+                recv_msg.recv().expect("Pipeline went away"),
+                &mut send_event,
+            );
+        }
+    }
+}
+
+impl Worker {
+    fn handle_message(
+        &self,
+        msg: vcs::Message,
+        send_event: &mut Sender<vcs::Event>,
+    ) {
+        match msg {
+            vcs::Message::MergeToStaging(
+                pipeline_id, staging, base_commit, pull_commit, message, _
+            ) => {
+                match self.merge_to_staging(
+                    pipeline_id,
+                    staging,
+                    base_commit.as_ref(),
+                    &pull_commit,
+                    message,
+                ) {
+                    Ok(merge_commit) => {
+                        send_event.send(vcs::Event::MergedToStaging(
+                            pipeline_id,
+                            base_commit,
+                            pull_commit,
+                            merge_commit,
+                        )).expect("Pipeline gone merge to staging");
+                    },
+                    Err(e) => {
+                        warn!("Failed to merge to staging: {:?}", e);
+                        send_event.send(vcs::Event::FailedMergeToStaging(
+                            pipeline_id,
+                            base_commit,
+                            pull_commit,
+                        )).expect("Pipeline gone merge to staging error");
+                    }
+                }
+            }
+            vcs::Message::MoveStagingToMaster(pipeline_id, merge_commit) => {
+                match self.move_to_master(pipeline_id, &merge_commit) {
+                    Ok(()) => {
+                        send_event.send(vcs::Event::MovedToMaster(
+                            pipeline_id,
+                            merge_commit,
+                        )).expect("Pipeline gone move to master");
+                    },
+                    Err(e) => {
+                        warn!("Failed to move to master: {:?}", e);
+                        send_event.send(vcs::Event::FailedMoveToMaster(
+                            pipeline_id,
+                            merge_commit,
+                        )).expect("Pipeline gone move to master error");
+                    }
+                }
+            }
+        }
+    }
+    fn move_to_master(
+        &self,
+        pipeline_id: PipelineId,
+        merge_commit: &Commit,
+    ) -> Result<(), GiteaRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GiteaRequestError::Pipeline(pipeline_id)),
+        };
+        // Gitea refuses the merge if master has moved since staging
+        // was built, so there is no need to check it first.
+        let temp_branch = format!("{}.aelita", repo.master_branch);
+        try!(self.reset_branch(&repo, &temp_branch, merge_commit.as_str()));
+        try!(self.merge_branch(
+            &repo,
+            &temp_branch,
+            &repo.master_branch,
+            merge_commit,
+            format!("Move {} to {}", merge_commit, repo.master_branch),
+            MergeStyle::FastForwardOnly,
+        ));
+        Ok(())
+    }
+    fn merge_to_staging(
+        &self,
+        pipeline_id: PipelineId,
+        staging: usize,
+        base_commit: Option<&Commit>,
+        pull_commit: &Commit,
+        message: String,
+    ) -> Result<Commit, GiteaRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GiteaRequestError::Pipeline(pipeline_id)),
+        };
+        let staging_branch = vcs::staging_branch(&repo.staging_branch, staging);
+        // Step 1: get the contents of master (or the given base).
+        let master_sha = if let Some(base_commit) = base_commit {
+            base_commit.to_string()
+        } else {
+            match try!(self.branch_sha(&repo, &repo.master_branch)) {
+                Some(master_sha) => master_sha,
+                None => {
+                    return Err(GiteaRequestError::HttpStatus(
+                        StatusCode::NotFound
+                    ));
+                }
+            }
+        };
+        // Step 2: reset staging to the contents of master.
+        try!(self.reset_branch(&repo, &staging_branch, &master_sha));
+        // Step 3: merge the pull request into staging.
+        let temp_branch = format!("{}.aelita", staging_branch);
+        try!(self.reset_branch(&repo, &temp_branch, pull_commit.as_str()));
+        self.merge_branch(
+            &repo,
+            &temp_branch,
+            &staging_branch,
+            pull_commit,
+            message,
+            MergeStyle::Merge,
+        )
+    }
+    /// The commit a branch is on, or `None` if there is no such branch.
+    fn branch_sha(
+        &self,
+        repo: &Repo,
+        branch: &str,
+    ) -> Result<Option<String>, GiteaRequestError> {
+        let url = format!(
+            "/repos/{}/{}/branches/{}",
+            repo.owner,
+            repo.repo,
+            gitea_api::path_segment(branch),
+        );
+        debug!("Get branch SHA: {}", url);
+        match self.client.get(&url) {
+            Ok(resp) => {
+                let branch_desc: BranchDesc = try!(json_from_reader(resp));
+                Ok(Some(branch_desc.commit.id))
+            }
+            Err(GiteaRequestError::HttpStatus(StatusCode::NotFound)) =>
+                Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// Point a branch at a commit, creating it if it isn't there.
+    fn reset_branch(
+        &self,
+        repo: &Repo,
+        branch: &str,
+        sha: &str,
+    ) -> Result<(), GiteaRequestError> {
+        let branch_sha = try!(self.branch_sha(repo, branch));
+        if branch_sha.as_ref().map(|s| &s[..]) == Some(sha) {
+            return Ok(());
+        }
+        if branch_sha.is_some() {
+            let url = format!(
+                "/repos/{}/{}/branches/{}",
+                repo.owner,
+                repo.repo,
+                gitea_api::path_segment(branch),
+            );
+            debug!("Delete branch: {}", url);
+            try!(self.client.delete(&url));
+        }
+        let url = format!("/repos/{}/{}/branches", repo.owner, repo.repo);
+        debug!("Create branch {} at {}", branch, sha);
+        try!(self.client.post(&url, &BranchCreateDesc {
+            new_branch_name: branch.to_owned(),
+            old_ref_name: sha.to_owned(),
+        }));
+        Ok(())
+    }
+    /// Merge one branch into another with a throwaway pull request,
+    /// returning the resulting commit.
+    fn merge_branch(
+        &self,
+        repo: &Repo,
+        head_branch: &str,
+        base_branch: &str,
+        head: &Commit,
+        message: String,
+        style: MergeStyle,
+    ) -> Result<Commit, GiteaRequestError> {
+        let url = format!("/repos/{}/{}/pulls", repo.owner, repo.repo);
+        debug!("Open pull request {} -> {}", head_branch, base_branch);
+        let title = message.lines().next().unwrap_or("").to_owned();
+        let pr_desc: PullRequestDesc = try!(json_from_reader(try!(
+            self.client.post(&url, &PullRequestCreateDesc {
+                head: head_branch.to_owned(),
+                base: base_branch.to_owned(),
+                title: title.clone(),
+            })
+        )));
+        let pr_url = format!(
+            "/repos/{}/{}/pulls/{}",
+            repo.owner,
+            repo.repo,
+            pr_desc.number,
+        );
+        let url = format!("{}/merge", pr_url);
+        debug!("Merge pull request: {}", url);
+        // The title is the first line of the message; the rest is the body.
+        let body = message.splitn(2, '\n').nth(1).unwrap_or("");
+        let result = self.client.post(&url, &MergeDesc {
+            merge_style: match style {
+                MergeStyle::Merge => "merge",
+                MergeStyle::FastForwardOnly => "fast-forward-only",
+            }.to_owned(),
+            title: title,
+            message: body.trim_left().to_owned(),
+            head_commit_id: head.to_string(),
+            delete_branch_after_merge: true,
+        });
+        if let Err(e) = result {
+            // Don't leave it lying around for a human to find.
+            let close = self.client.patch(&pr_url, &PullRequestUpdateDesc {
+                state: "closed".to_owned(),
+            });
+            if let Err(e) = close {
+                warn!("Failed to close pull request: {:?}", e);
+            }
+            return Err(e);
+        }
+        // The merge itself answers with nothing, so look the commit up.
+        let result_desc: PullRequestDesc =
+            try!(json_from_reader(try!(self.client.get(&pr_url))));
+        match result_desc.merge_commit_sha {
+            Some(merge_commit_sha) => Ok(Commit::from(merge_commit_sha)),
+            None => Err(GiteaRequestError::HttpStatus(StatusCode::Conflict)),
+        }
+    }
+}
@@ -4,12 +4,13 @@ use hyper;
 use hyper::header::{self, qitem, Accept};
 use hyper::status::StatusCode;
 use pipeline::{self, PipelineId};
-use rest::{authorization, Authorization, Client, Mime};
+use rest::Mime;
 use serde_json;
 use std;
 use std::convert::From;
+use std::sync::Arc;
 use std::sync::mpsc::{Sender, Receiver};
-use util::USER_AGENT;
+use util::github_auth::{AuthError, Credentials};
 use vcs::{self, Commit};
 
 pub trait PipelinesConfig: Send + Sync + 'static {
@@ -27,22 +28,17 @@ pub struct Repo {
 
 pub struct Worker {
     pipelines: Box<PipelinesConfig>,
-    client: Client<Authorization<authorization::Token>>,
+    credentials: Arc<Credentials>,
 }
 
 impl Worker {
     pub fn new(
-        host: String,
-        token: String,
+        credentials: Arc<Credentials>,
         pipelines: Box<PipelinesConfig>
     ) -> Worker {
         Worker{
             pipelines: pipelines,
-            client: Client::new(USER_AGENT.to_owned())
-                .base(&host)
-                .authorization(Authorization(authorization::Token{
-                    token: token,
-                })),
+            credentials: credentials,
         }
     }
 }
@@ -70,14 +66,19 @@ impl Worker {
     ) {
         match msg {
             vcs::Message::MergeToStaging(
-                pipeline_id, pull_commit, message, _
+                pipeline_id, staging, base_commit, pull_commit, message, _
             ) => {
                 match self.merge_to_staging(
-                    pipeline_id, &pull_commit, message
+                    pipeline_id,
+                    staging,
+                    base_commit.as_ref(),
+                    &pull_commit,
+                    message,
                 ) {
                     Ok(merge_commit) => {
                         send_event.send(vcs::Event::MergedToStaging(
                             pipeline_id,
+                            base_commit,
                             pull_commit,
                             merge_commit,
                         )).expect("Pipeline gone merge to staging");
@@ -86,6 +87,7 @@ impl Worker {
                         warn!("Failed to merge to staging: {:?}", e);
                         send_event.send(vcs::Event::FailedMergeToStaging(
                             pipeline_id,
+                            base_commit,
                             pull_commit,
                         )).expect("Pipeline gone merge to staging error");
                     }
@@ -119,6 +121,7 @@ impl Worker {
             Some(repo) => repo,
             None => return Err(GithubRequestError::Pipeline(pipeline_id)),
         };
+        let client = try!(self.credentials.client(&repo.owner));
 
         // If GitHub's UI is also being used, it will also do this. But
         // protected branches rely on a strict happens-before relationship.
@@ -142,10 +145,9 @@ impl Worker {
             description: "Tests passed".to_owned(),
             context: "continuous-integration/aelita".to_owned(),
         };
-        let resp = try!(
-            try!(self.client.post(&url).expect("url").json(&status_body))
+        let resp = github_send!(
+            try!(client.post(&url).expect("url").json(&status_body))
                 .header(Self::accept())
-                .send()
         );
         if !resp.is_success() {
             return Err(GithubRequestError::HttpStatus(resp.http.status))
@@ -167,13 +169,12 @@ impl Worker {
             force: false,
             sha: merge_commit.to_string(),
         };
-        let resp = try!(
+        let resp = github_send!(
             try!(
-                self.client.patch(&url).expect("valid url")
+                client.patch(&url).expect("valid url")
                     .json(&update_desc)
             )
                 .header(Self::accept())
-                .send()
         );
         if !resp.is_success() {
             return Err(GithubRequestError::HttpStatus(resp.http.status));
@@ -183,6 +184,8 @@ impl Worker {
     fn merge_to_staging(
         &self,
         pipeline_id: PipelineId,
+        staging: usize,
+        base_commit: Option<&Commit>,
         pull_commit: &Commit,
         message: String,
     ) -> Result<Commit, GithubRequestError> {
@@ -190,14 +193,8 @@ impl Worker {
             Some(repo) => repo,
             None => return Err(GithubRequestError::Pipeline(pipeline_id)),
         };
-        // Step 1: get the contents of master.
-        let url = format!(
-            "/repos/{}/{}/git/refs/heads/{}",
-            repo.owner,
-            repo.repo,
-            repo.master_branch
-        );
-        debug!("Get master SHA: {}", url);
+        let client = try!(self.credentials.client(&repo.owner));
+        let staging_branch = vcs::staging_branch(&repo.staging_branch, staging);
         #[derive(Deserialize, Serialize)]
         struct ObjectDesc {
             sha: String,
@@ -206,16 +203,27 @@ impl Worker {
         struct RefDesc {
             object: ObjectDesc,
         }
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
-                .header(Self::accept())
-                .send()
-        );
-        if !resp.is_success() {
-            return Err(GithubRequestError::HttpStatus(resp.http.status));
-        }
-        let resp_desc: RefDesc = try!(resp.json());
-        let master_sha = resp_desc.object.sha;
+        // Step 1: get the contents of master (or the given base).
+        let master_sha = if let Some(base_commit) = base_commit {
+            base_commit.to_string()
+        } else {
+            let url = format!(
+                "/repos/{}/{}/git/refs/heads/{}",
+                repo.owner,
+                repo.repo,
+                repo.master_branch
+            );
+            debug!("Get master SHA: {}", url);
+            let resp = github_send!(
+                client.get(&url).expect("valid url")
+                    .header(Self::accept())
+            );
+            if !resp.is_success() {
+                return Err(GithubRequestError::HttpStatus(resp.http.status));
+            }
+            let resp_desc: RefDesc = try!(resp.json());
+            resp_desc.object.sha
+        };
         // Step 2: reset staging to the contents of master.
         // Do it in a single step if no rewinding is needed, but we may
         // need to rewind.
@@ -223,12 +231,11 @@ impl Worker {
             "/repos/{}/{}/git/refs/heads/{}",
             repo.owner,
             repo.repo,
-            repo.staging_branch
+            staging_branch
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = github_send!(
+            client.get(&url).expect("valid url")
                 .header(Self::accept())
-                .send()
         );
         let staging_up_to_date = if resp.is_success() {
             let resp_desc: RefDesc = try!(resp.json());
@@ -250,13 +257,12 @@ impl Worker {
                     force: true,
                     sha: master_sha,
                 };
-                let resp = try!(
+                let resp = github_send!(
                     try!(
-                        self.client.patch(&url).expect("valid url")
+                        client.patch(&url).expect("valid url")
                             .json(&update_desc)
                     )
                         .header(Self::accept())
-                        .send()
                 );
                 if !resp.is_success() {
                     return Err(GithubRequestError::HttpStatus(
@@ -278,16 +284,15 @@ impl Worker {
                     sha: String,
                 }
                 let create_desc = RefCreateDesc{
-                    git_ref: format!("refs/heads/{}", repo.staging_branch),
+                    git_ref: format!("refs/heads/{}", staging_branch),
                     sha: master_sha,
                 };
-                let resp = try!(
+                let resp = github_send!(
                     try!(
-                        self.client.post(&url).expect("valid url")
+                        client.post(&url).expect("valid url")
                             .json(&create_desc)
                     )
                         .header(Self::accept())
-                        .send()
                 );
                 if !resp.is_success() {
                     return Err(GithubRequestError::HttpStatus(
@@ -314,14 +319,13 @@ impl Worker {
             sha: String,
         }
         let merge_desc = MergeDesc {
-            base: repo.staging_branch.clone(),
+            base: staging_branch,
             head: pull_commit.to_string(),
             commit_message: message,
         };
-        let resp = try!(
-            try!(self.client.post(&url).expect("valid url").json(&merge_desc))
+        let resp = github_send!(
+            try!(client.post(&url).expect("valid url").json(&merge_desc))
                 .header(Self::accept())
-                .send()
         );
         if !resp.is_success() {
             return Err(GithubRequestError::HttpStatus(resp.http.status));
@@ -358,5 +362,10 @@ quick_error! {
         }
         /// Repo not found for pipeline
         Pipeline(pipeline_id: PipelineId) {}
+        /// No token to act on the repo
+        Auth(err: AuthError) {
+            cause(err)
+            from()
+        }
     }
 }
\ No newline at end of file
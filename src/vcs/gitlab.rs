@@ -0,0 +1,423 @@
+// This file is released under the same terms as Rust itself.
+
+/*! Merging through the GitLab API.
+
+    GitLab has no API to merge two commits directly, nor one to move a
+    branch, so the pull commit is pushed to a temporary branch and merged
+    with a merge request of our own. Staging is reset by deleting and
+    recreating it. That needs the project to allow merge commits.
+
+    Master is moved with git instead, by pushing the tested commit to it
+    without forcing, so that it is a fast-forward or nothing at all.
+    A merge request would make a merge commit of its own, which nobody
+    has tested, and set off our own merge request webhook. GitLab only
+    fast-forwards merge requests when the whole project is set to, and
+    then the merge requests into staging could not make merge commits.
+ */
+
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use serde_json::from_reader as json_from_reader;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, Receiver};
+use util::gitlab_api::{self, Client, GitlabRequestError};
+use vcs::{self, Commit};
+
+/// Added to the branch names of our own merge requests.
+pub const TEMP_SUFFIX: &'static str = ".aelita";
+
+pub trait PipelinesConfig: Send + Sync + 'static {
+    fn repo_by_pipeline(&self, PipelineId) -> Option<Repo>;
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    pub owner: String,
+    pub repo: String,
+    pub master_branch: String,
+    pub staging_branch: String,
+}
+
+pub struct Worker {
+    pipelines: Box<PipelinesConfig>,
+    client: Arc<Client>,
+    executable: String,
+    /// Where to keep a bare repo of every project, to push master from.
+    path: PathBuf,
+}
+
+impl Worker {
+    pub fn new(
+        client: Arc<Client>,
+        pipelines: Box<PipelinesConfig>,
+        executable: String,
+        path: PathBuf,
+    ) -> Worker {
+        Worker{
+            pipelines: pipelines,
+            client: client,
+            executable: executable,
+            path: path,
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct BranchCommitDesc {
+    id: String,
+}
+#[derive(Deserialize, Serialize)]
+struct BranchDesc {
+    commit: BranchCommitDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct BranchCreateDesc {
+    branch: String,
+    #[serde(rename="ref")]
+    git_ref: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CommitDesc {
+    parent_ids: Vec<String>,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestCreateDesc {
+    source_branch: String,
+    target_branch: String,
+    title: String,
+    remove_source_branch: bool,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestDesc {
+    iid: u32,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeDesc {
+    merge_commit_message: String,
+    sha: String,
+    should_remove_source_branch: bool,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeResultDesc {
+    sha: String,
+    merge_commit_sha: Option<String>,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestUpdateDesc {
+    state_event: String,
+}
+
+impl pipeline::Worker<vcs::Event, vcs::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<vcs::Message>,
+        mut send_event: Sender<vcs::Event>
+    ) {
+        loop {
+            self.handle_message(
+                recv_msg.recv().expect("Pipeline went away"),
+                &mut send_event,
+            );
+        }
+    }
+}
+
+impl Worker {
+    fn handle_message(
+        &self,
+        msg: vcs::Message,
+        send_event: &mut Sender<vcs::Event>,
+    ) {
+        match msg {
+            vcs::Message::MergeToStaging(
+                pipeline_id, staging, base_commit, pull_commit, message, _
+            ) => {
+                match self.merge_to_staging(
+                    pipeline_id,
+                    staging,
+                    base_commit.as_ref(),
+                    &pull_commit,
+                    message,
+                ) {
+                    Ok(merge_commit) => {
+                        send_event.send(vcs::Event::MergedToStaging(
+                            pipeline_id,
+                            base_commit,
+                            pull_commit,
+                            merge_commit,
+                        )).expect("Pipeline gone merge to staging");
+                    },
+                    Err(e) => {
+                        warn!("Failed to merge to staging: {:?}", e);
+                        send_event.send(vcs::Event::FailedMergeToStaging(
+                            pipeline_id,
+                            base_commit,
+                            pull_commit,
+                        )).expect("Pipeline gone merge to staging error");
+                    }
+                }
+            }
+            vcs::Message::MoveStagingToMaster(pipeline_id, merge_commit) => {
+                match self.move_to_master(pipeline_id, &merge_commit) {
+                    Ok(()) => {
+                        send_event.send(vcs::Event::MovedToMaster(
+                            pipeline_id,
+                            merge_commit,
+                        )).expect("Pipeline gone move to master");
+                    },
+                    Err(e) => {
+                        warn!("Failed to move to master: {:?}", e);
+                        send_event.send(vcs::Event::FailedMoveToMaster(
+                            pipeline_id,
+                            merge_commit,
+                        )).expect("Pipeline gone move to master error");
+                    }
+                }
+            }
+        }
+    }
+    fn move_to_master(
+        &self,
+        pipeline_id: PipelineId,
+        merge_commit: &Commit,
+    ) -> Result<(), GitlabRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GitlabRequestError::Pipeline(pipeline_id)),
+        };
+        let project = gitlab_api::project_path(&repo.owner, &repo.repo);
+        // Step 1: make sure master has not moved since staging was built.
+        let url = format!(
+            "/projects/{}/repository/commits/{}",
+            project,
+            merge_commit,
+        );
+        debug!("Get merge commit parents: {}", url);
+        let commit_desc: CommitDesc =
+            try!(json_from_reader(try!(self.client.get(&url))));
+        let master_sha = try!(self.branch_sha(&project, &repo.master_branch));
+        if commit_desc.parent_ids.get(0) != master_sha.as_ref() {
+            return Err(GitlabRequestError::NotFastForward);
+        }
+        // Step 2: get the tested commit from the staging branch it is on,
+        // and push it to master. Git refuses if that would not be
+        // a fast-forward, in case master moved in the meantime.
+        let staging_branch =
+            try!(self.staging_branch_at(&project, &repo, merge_commit));
+        let dir = try!(self.setup_dir(&repo));
+        let url = self.client.git_url(&repo.owner, &repo.repo);
+        let refspec = format!(
+            "+refs/heads/{}:refs/heads/{}",
+            staging_branch,
+            staging_branch,
+        );
+        try!(self.git(&dir, &["fetch", "--quiet", &url[..], &refspec[..]]));
+        let refspec = format!(
+            "{}:refs/heads/{}",
+            merge_commit,
+            repo.master_branch,
+        );
+        try!(self.git(&dir, &["push", "--quiet", &url[..], &refspec[..]]));
+        Ok(())
+    }
+    /// The staging branch that is on the given commit.
+    /// They are made in order, so stop at the first one that isn't there.
+    fn staging_branch_at(
+        &self,
+        project: &str,
+        repo: &Repo,
+        commit: &Commit,
+    ) -> Result<String, GitlabRequestError> {
+        let mut staging = 0;
+        loop {
+            let branch = vcs::staging_branch(&repo.staging_branch, staging);
+            match try!(self.branch_sha(project, &branch)) {
+                Some(ref sha) if sha == commit.as_str() => return Ok(branch),
+                Some(_) => staging += 1,
+                None => return Err(GitlabRequestError::HttpStatus(
+                    StatusCode::NotFound
+                )),
+            }
+        }
+    }
+    /// Git needs the commit itself before it can push it,
+    /// so keep a bare repo of the project to fetch it into.
+    fn setup_dir(&self, repo: &Repo) -> Result<PathBuf, GitlabRequestError> {
+        let dir = self.path
+            .join(&repo.owner)
+            .join(format!("{}.git", repo.repo));
+        if !dir.exists() {
+            try!(fs::create_dir_all(&dir));
+            try!(self.git(&dir, &["init", "--bare", "--quiet"]));
+        }
+        Ok(dir)
+    }
+    fn git(&self, dir: &Path, args: &[&str]) -> Result<(), GitlabRequestError> {
+        // The URL has the token in it, so leave the arguments out.
+        info!("Run git {} in {:?}", args[0], dir);
+        let out = try!(
+            Command::new(&self.executable)
+                .arg("-C")
+                .arg(dir)
+                .args(args)
+                .output()
+        );
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            return Err(GitlabRequestError::Git(
+                out.status,
+                self.client.redact(&stderr),
+            ));
+        }
+        Ok(())
+    }
+    fn merge_to_staging(
+        &self,
+        pipeline_id: PipelineId,
+        staging: usize,
+        base_commit: Option<&Commit>,
+        pull_commit: &Commit,
+        message: String,
+    ) -> Result<Commit, GitlabRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GitlabRequestError::Pipeline(pipeline_id)),
+        };
+        let project = gitlab_api::project_path(&repo.owner, &repo.repo);
+        let staging_branch = vcs::staging_branch(&repo.staging_branch, staging);
+        // Step 1: get the contents of master (or the given base).
+        let master_sha = if let Some(base_commit) = base_commit {
+            base_commit.to_string()
+        } else {
+            match try!(self.branch_sha(&project, &repo.master_branch)) {
+                Some(master_sha) => master_sha,
+                None => {
+                    return Err(GitlabRequestError::HttpStatus(
+                        StatusCode::NotFound
+                    ));
+                }
+            }
+        };
+        // Step 2: reset staging to the contents of master.
+        try!(self.reset_branch(&project, &staging_branch, &master_sha));
+        // Step 3: merge the pull request into staging.
+        let temp_branch = format!("{}{}", staging_branch, TEMP_SUFFIX);
+        try!(self.reset_branch(&project, &temp_branch, pull_commit.as_str()));
+        self.merge_branch(
+            &project,
+            &temp_branch,
+            &staging_branch,
+            pull_commit,
+            message,
+        )
+    }
+    /// The commit a branch is on, or `None` if there is no such branch.
+    fn branch_sha(
+        &self,
+        project: &str,
+        branch: &str,
+    ) -> Result<Option<String>, GitlabRequestError> {
+        let url = format!(
+            "/projects/{}/repository/branches/{}",
+            project,
+            gitlab_api::path_segment(branch),
+        );
+        debug!("Get branch SHA: {}", url);
+        match self.client.get(&url) {
+            Ok(resp) => {
+                let branch_desc: BranchDesc = try!(json_from_reader(resp));
+                Ok(Some(branch_desc.commit.id))
+            }
+            Err(GitlabRequestError::HttpStatus(StatusCode::NotFound)) =>
+                Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// Point a branch at a commit, creating it if it isn't there.
+    fn reset_branch(
+        &self,
+        project: &str,
+        branch: &str,
+        sha: &str,
+    ) -> Result<(), GitlabRequestError> {
+        let branch_sha = try!(self.branch_sha(project, branch));
+        if branch_sha.as_ref().map(|s| &s[..]) == Some(sha) {
+            return Ok(());
+        }
+        if branch_sha.is_some() {
+            let url = format!(
+                "/projects/{}/repository/branches/{}",
+                project,
+                gitlab_api::path_segment(branch),
+            );
+            debug!("Delete branch: {}", url);
+            try!(self.client.delete(&url));
+        }
+        let url = format!("/projects/{}/repository/branches", project);
+        debug!("Create branch {} at {}", branch, sha);
+        try!(self.client.post(&url, &BranchCreateDesc {
+            branch: branch.to_owned(),
+            git_ref: sha.to_owned(),
+        }));
+        Ok(())
+    }
+    /// Merge one branch into another with a throwaway merge request,
+    /// returning the resulting commit.
+    fn merge_branch(
+        &self,
+        project: &str,
+        source_branch: &str,
+        target_branch: &str,
+        head: &Commit,
+        message: String,
+    ) -> Result<Commit, GitlabRequestError> {
+        let url = format!("/projects/{}/merge_requests", project);
+        debug!("Open merge request {} -> {}", source_branch, target_branch);
+        let mr_desc: MergeRequestDesc = try!(json_from_reader(try!(
+            self.client.post(&url, &MergeRequestCreateDesc {
+                source_branch: source_branch.to_owned(),
+                target_branch: target_branch.to_owned(),
+                title: message.lines().next().unwrap_or("").to_owned(),
+                remove_source_branch: true,
+            })
+        )));
+        let url = format!(
+            "/projects/{}/merge_requests/{}/merge",
+            project,
+            mr_desc.iid,
+        );
+        debug!("Merge merge request: {}", url);
+        let result = self.client.put(&url, &MergeDesc {
+            merge_commit_message: message,
+            sha: head.to_string(),
+            should_remove_source_branch: true,
+        });
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(e) => {
+                // Don't leave it lying around for a human to find.
+                let url = format!(
+                    "/projects/{}/merge_requests/{}",
+                    project,
+                    mr_desc.iid,
+                );
+                let close = self.client.put(&url, &MergeRequestUpdateDesc {
+                    state_event: "close".to_owned(),
+                });
+                if let Err(e) = close {
+                    warn!("Failed to close merge request: {:?}", e);
+                }
+                return Err(e);
+            }
+        };
+        let result_desc: MergeResultDesc = try!(json_from_reader(resp));
+        // A fast-forward merge makes no merge commit of its own.
+        Ok(Commit::from(
+            result_desc.merge_commit_sha.unwrap_or(result_desc.sha)
+        ))
+    }
+}
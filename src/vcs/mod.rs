@@ -1,7 +1,9 @@
 // This file is released under the same terms as Rust itself.
 
 pub mod git;
+pub mod gitea;
 pub mod github;
+pub mod gitlab;
 
 use config::PipelinesConfig;
 use pipeline::{GetPipelineId, PipelineId};
@@ -9,19 +11,25 @@ use std::fmt::{self, Display};
 
 #[derive(Clone, Debug)]
 pub enum Message {
-    MergeToStaging(PipelineId, Commit, String, Remote),
+    /// Merge a pull commit into one of the staging branches.
+    /// Staging is reset to master first, unless a base commit is given,
+    /// in which case the merge is built on top of it.
+    /// Batches are merged by chaining these together.
+    MergeToStaging(PipelineId, usize, Option<Commit>, Commit, String, Remote),
     MoveStagingToMaster(PipelineId, Commit),
 }
 
 #[derive(Clone, Debug)]
 pub enum Event {
-    MergedToStaging(PipelineId, Commit, Commit),
-    FailedMergeToStaging(PipelineId, Commit),
+    /// The base commit, the pull commit, and the resulting merge commit.
+    MergedToStaging(PipelineId, Option<Commit>, Commit, Commit),
+    /// The base commit and the pull commit.
+    FailedMergeToStaging(PipelineId, Option<Commit>, Commit),
     MovedToMaster(PipelineId, Commit),
     FailedMoveToMaster(PipelineId, Commit),
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Commit(String);
 
 impl Display for Commit {
@@ -69,11 +77,21 @@ impl Display for Remote {
     }
 }
 
+/// The name of the branch that a staging slot is built in.
+/// The first slot uses the configured staging branch itself.
+pub fn staging_branch(staging_branch: &str, staging: usize) -> String {
+    if staging == 0 {
+        staging_branch.to_owned()
+    } else {
+        format!("{}-{}", staging_branch, staging)
+    }
+}
+
 impl GetPipelineId for Event {
     fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, _: &C) -> PipelineId {
         match *self {
-        	Event::MergedToStaging(i, _, _) => i,
-    		Event::FailedMergeToStaging(i, _) => i,
+        	Event::MergedToStaging(i, _, _, _) => i,
+    		Event::FailedMergeToStaging(i, _, _) => i,
     		Event::MovedToMaster(i, _) => i,
     		Event::FailedMoveToMaster(i, _) => i,
         }
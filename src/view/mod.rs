@@ -3,7 +3,8 @@
 mod auth;
 
 use crossbeam;
-use db::{self, Db, DbBox, PendingEntry, QueueEntry, RunningEntry, Transaction};
+use db::{self, Db, DbBox, HistoryEntry, HistoryStatus, PendingEntry};
+use db::{QueueEntry, RunningEntry, Transaction};
 use horrorshow::prelude::*;
 use hyper::buffer::BufReader;
 use hyper::header::{ContentType, Headers};
@@ -20,6 +21,8 @@ use std::error::Error;
 use std::fmt::{self, Formatter};
 use std::io::{BufWriter, Write};
 use std::sync::mpsc::{Receiver, Sender};
+use std::time::{SystemTime, UNIX_EPOCH};
+use vcs::git::ToShortString;
 use view::auth::AuthManager;
 
 pub trait PipelinesConfig: Send + Sync + 'static {
@@ -31,6 +34,9 @@ pub use view::auth::{Auth, AuthRef};
 
 const THREAD_COUNT: usize = 3;
 
+/// Appended to a pipeline's page to get its history page.
+const HISTORY_SUFFIX: &'static str = "/history";
+
 pub struct Worker {
     listen: String,
     db_build: db::Builder,
@@ -150,10 +156,14 @@ impl<'a> Thread<'a> {
                 if path.as_bytes()[0] == b'/' {
                     path = &path[1..];
                 }
+                let history = path.ends_with(HISTORY_SUFFIX);
+                if history {
+                    path = &path[..path.len() - HISTORY_SUFFIX.len()];
+                }
                 match self.pipelines.pipeline_by_name(path) {
                     Some(pipeline_id) => {
                         *res.status_mut() = StatusCode::Ok;
-                        Some((path.to_owned(), pipeline_id))
+                        Some((path.to_owned(), pipeline_id, history))
                     }
                     None if path == "style.css" => {
                         res.headers_mut().set(ContentType(mime!(Text/Css)));
@@ -174,10 +184,12 @@ impl<'a> Thread<'a> {
         res.headers_mut().set(ContentType::html());
         let mut res = try!(res.start());
         try!(res.write_all(br##"<!DOCTYPE html>"##));
-        if let Some((name, pipeline_id)) = pipeline {
-            self.handle_pipeline_req(&name, pipeline_id, req, res)
-        } else {
-            self.handle_home_req(req, res)
+        match pipeline {
+            Some((name, pipeline_id, true)) =>
+                self.handle_history_req(&name, pipeline_id, req, res),
+            Some((name, pipeline_id, false)) =>
+                self.handle_pipeline_req(&name, pipeline_id, req, res),
+            None => self.handle_home_req(req, res),
         }
     }
     fn handle_pipeline_req(
@@ -187,25 +199,30 @@ impl<'a> Thread<'a> {
         _req: Request,
         mut res: Response<::hyper::net::Streaming>,
     ) -> Result<(), Box<Error>> {
-        let (pending_entries, queued_entries, running_entry) =
+        let (pending_entries, queued_entries, running, tree_closed) =
             try!(self.db.transaction(InfoTransaction{
                 pipeline_id: pipeline_id
             }).wc());
         let is_empty = pending_entries.is_empty();
-        let mut running = None;
+        let running_entries: Vec<QueueEntry> = running.into_iter()
+            .flat_map(|r| r.entries.into_iter())
+            .collect();
+        let mut running = Vec::new();
         let mut queued = Vec::new();
         let pending: Vec<_> = pending_entries.into_iter().filter_map(|entry| {
-            if Some(&entry.pr) == running_entry.as_ref().map(|x| &x.pr) {
-                running = Some(entry);
-            } else if queued_entries.iter()
-                    .filter(|q| q.pr == entry.pr)
-                    .next().is_some() {
-                queued.push(entry);
+            if let Some(i) = running_entries.iter()
+                    .position(|r| r.pr == entry.pr) {
+                running.push((i, entry));
+            } else if let Some(i) = queued_entries.iter()
+                    .position(|q| q.pr == entry.pr) {
+                queued.push((i, entry));
             } else {
                 return Some(entry);
             }
             None
         }).collect();
+        sort_by(&mut running, &|a, b| a.0.cmp(&b.0));
+        sort_by(&mut queued, &|a, b| a.0.cmp(&b.0));
         let html = html!{
             html {
                 head {
@@ -216,26 +233,62 @@ impl<'a> Thread<'a> {
                 }
                 body {
                     h1 { : name }
+                    p {
+                        a(href=format!("{}{}", name, HISTORY_SUFFIX)) {
+                            : "History"
+                        }
+                    }
+                    |t| {
+                        if let Some(priority) = tree_closed {
+                            t << html!{
+                                p(class="tree-closed") {
+                                    : format!(
+                                        "The tree is closed to pull requests \
+                                         with a priority below {}.",
+                                        priority,
+                                    )
+                                }
+                            }
+                        }
+                    }
                     table {
                         thead {
                             th { : "Status" }
                             th { : "PR#" }
                             th { : "Title" }
+                            th { : "Priority" }
                         }
                         tbody {
                             |t| {
-                                for entry in running {
-                                    render_entry(State::Running, entry, t);
+                                for (i, entry) in running {
+                                    let priority = running_entries[i].priority;
+                                    render_entry(
+                                        State::Running,
+                                        entry,
+                                        Some(priority),
+                                        t,
+                                    );
                                 }
-                                for entry in queued {
-                                    render_entry(State::Queued, entry, t);
+                                for (i, entry) in queued {
+                                    let priority = queued_entries[i].priority;
+                                    render_entry(
+                                        State::Queued,
+                                        entry,
+                                        Some(priority),
+                                        t,
+                                    );
                                 }
                                 for entry in pending {
-                                    render_entry(State::Pending, entry, t);
+                                    render_entry(
+                                        State::Pending,
+                                        entry,
+                                        None,
+                                        t,
+                                    );
                                 }
                                 if is_empty {
                                     t << html!{
-                                        td(colspan=3) {
+                                        td(colspan=4) {
                                             : "No opened pull requests"
                                         }
                                     }
@@ -250,6 +303,61 @@ impl<'a> Thread<'a> {
         try!(res.end());
         Ok(())
     }
+    fn handle_history_req(
+        &mut self,
+        name: &str,
+        pipeline_id: PipelineId,
+        _req: Request,
+        mut res: Response<::hyper::net::Streaming>,
+    ) -> Result<(), Box<Error>> {
+        let history = try!(self.db.transaction(HistoryTransaction{
+            pipeline_id: pipeline_id
+        }).wc());
+        let html = html!{
+            html {
+                head {
+                    title { : format!("{} history", name) }
+                    link(rel="stylesheet", href="https://cdnjs.cloudflare.com/ajax/libs/normalize/4.1.1/normalize.min.css");
+                    link(rel="stylesheet", href="/style.css");
+                    meta(name="viewport", content="width=device-width");
+                }
+                body {
+                    h1 { : format!("{} history", name) }
+                    p {
+                        a(href=format!("/{}", name)) { : "Queue" }
+                    }
+                    table {
+                        thead {
+                            th { : "Finished" }
+                            th { : "PR#" }
+                            th { : "Status" }
+                            th { : "Approved by" }
+                            th { : "Commits" }
+                            th { : "Builds" }
+                            th { : "Message" }
+                        }
+                        tbody {
+                            |t| {
+                                for entry in &history {
+                                    render_history_entry(entry, t);
+                                }
+                                if history.is_empty() {
+                                    t << html!{
+                                        td(colspan=7) {
+                                            : "Nothing has finished yet"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        try!(html.write_to_io(&mut res));
+        try!(res.end());
+        Ok(())
+    }
     fn handle_home_req(
         &mut self,
         _req: Request,
@@ -278,14 +386,15 @@ impl<'a> Thread<'a> {
                         tbody {
                             @ for &(ref n, pid) in &pipelines { |t| {
                                 let n = &**n;
-                                let (opened, queue, running) = 
+                                let (opened, queue, running, _) =
                                     self.db.transaction(InfoTransaction{
                                         pipeline_id: pid
-                                    }).unwrap_or((vec![], vec![], None));
+                                    })
+                                    .unwrap_or((vec![], vec![], vec![], None));
                                 let opened = opened.len();
+                                let running = running.iter()
+                                    .fold(0, |n, r| n + r.entries.len());
                                 let queue = queue.len();
-                                let running = running.is_some();
-                                let running = if running { 1 } else { 0 };
                                 let review = opened - queue - running;
                                 t << html!{
                                     tr {
@@ -315,6 +424,23 @@ impl<'a> Thread<'a> {
                         dd { : "Add the pull request as \"username.\"" }
                         dt { : Raw("<code>r-</code>") }
                         dd { : "Cancel the pull request." }
+                        dt { : Raw("<code>r+ p=1</code>") }
+                        dd {
+                            : "Add the pull request with a priority. \
+                               Higher priorities are merged first."
+                        }
+                        dt { : Raw("<code>retry</code>") }
+                        dd {
+                            : "Add the pull request to the merge queue again \
+                               after its build failed."
+                        }
+                        dt { : Raw("<code>treeclosed=5</code>") }
+                        dd {
+                            : "Stop merging pull requests with a priority \
+                               below 5, without canceling them."
+                        }
+                        dt { : Raw("<code>treeclosed-</code>") }
+                        dd { : "Start merging everything again." }
                     }
                 }
             }
@@ -333,7 +459,8 @@ impl Transaction for InfoTransaction {
     type Return = (
         Vec<PendingEntry>,
         Vec<QueueEntry>,
-        Option<RunningEntry>,
+        Vec<RunningEntry>,
+        Option<i32>,
     );
     fn run<D: Db>(
         self,
@@ -346,10 +473,29 @@ impl Transaction for InfoTransaction {
             let queued_entries = retry_unwrap!(
                 db.list_queue(self.pipeline_id)
             );
-            let running_entry = retry_unwrap!(
+            let running = retry_unwrap!(
                 db.peek_running(self.pipeline_id)
             );
-            Ok((pending_entries, queued_entries, running_entry))
+            let tree_closed = retry_unwrap!(
+                db.get_tree_closed(self.pipeline_id)
+            );
+            Ok((pending_entries, queued_entries, running, tree_closed))
+        }}
+    }
+}
+
+struct HistoryTransaction {
+    pipeline_id: PipelineId,
+}
+
+impl Transaction for HistoryTransaction {
+    type Return = Vec<HistoryEntry>;
+    fn run<D: Db>(
+        self,
+        db: &mut D
+    ) -> Result<Self::Return, Box<Error + Send + Sync>> {
+        retry!{{
+            Ok(retry_unwrap!(db.list_history(self.pipeline_id)))
         }}
     }
 }
@@ -395,6 +541,7 @@ enum State {
 fn render_entry(
     state: State,
     entry: PendingEntry,
+    priority: Option<i32>,
     t: &mut TemplateBuffer,
 ) {
     t << html!{
@@ -410,6 +557,86 @@ fn render_entry(
                 a(href=entry.url.to_string()) { : entry.pr.to_string() }
             }
             td { : &entry.title }
+            td { : priority.map(|p| p.to_string()).unwrap_or_default() }
         }
     };
 }
+
+fn render_history_entry(entry: &HistoryEntry, t: &mut TemplateBuffer) {
+    let title = entry.message.lines().next().unwrap_or("");
+    t << html!{
+        tr {
+            td { : format_time(entry.finished_at) }
+            td { : entry.pr.to_string() }
+            td {
+                : match entry.status {
+                    HistoryStatus::Completed => "Merged",
+                    HistoryStatus::Failed => "Failed",
+                    HistoryStatus::TimedOut => "Timed out",
+                    HistoryStatus::Unmergeable => "Merge conflict",
+                    HistoryStatus::Unmoveable => "Could not move to master",
+                    HistoryStatus::Canceled => "Canceled",
+                }
+            }
+            td { : &entry.approver }
+            td {
+                code { : entry.pull_commit.to_short_string() }
+                |t| {
+                    if let Some(ref merge_commit) = entry.merge_commit {
+                        t << html!{
+                            : " into ";
+                            code { : merge_commit.to_short_string() }
+                        }
+                    }
+                }
+            }
+            td {
+                @ for url in &entry.ci_urls {
+                    a(href=url.to_string()) { : "Build" }
+                    : " "
+                }
+                |t| {
+                    if let Some(started_at) = entry.started_at {
+                        t << html!{
+                            : format!("(started {})", format_time(started_at))
+                        }
+                    }
+                }
+            }
+            td {
+                details {
+                    summary { : title }
+                    pre { : &entry.message }
+                }
+            }
+        }
+    };
+}
+
+/// Format a timestamp as UTC, like "2016-11-01 12:30:00".
+fn format_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // Convert days since the epoch to a date in the proleptic
+    // Gregorian calendar, using eras of 400 years.
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+    )
+}
@@ -0,0 +1,3 @@
+#!/bin/sh
+# Stands in for git in the GitLab round trip.
+echo "$@" >> fake-git.log
@@ -0,0 +1,286 @@
+// This file is released under the same terms as Rust itself.
+
+//! Test the Bitbucket Server front-end front-to-back,
+//! against a fake Bitbucket API, a fake Jenkins, and a local git origin.
+
+#![feature(proc_macro)]
+
+extern crate env_logger;
+extern crate hex;
+extern crate hyper;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
+extern crate openssl;
+extern crate serde;
+extern crate serde_json;
+#[macro_use] extern crate serde_derive;
+
+use hex::ToHex;
+use hyper::buffer::BufReader;
+use hyper::client::Client;
+use hyper::header::{Authorization, Basic, Headers};
+use hyper::method::Method;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Mutex, Once, ONCE_INIT};
+use std::thread;
+use std::time;
+
+const EXECUTABLE: &'static str = "target/debug/aelita";
+
+const PULL_COMMIT: &'static str = "55016813274e906e4cbfed97be83e19e6cd93d91";
+const MASTER_COMMIT: &'static str = "e16d1eca074ae29ac1812e14316e96f3117d0675";
+
+lazy_static!{
+    static ref ONE_AT_A_TIME: Mutex<()> = Mutex::new(());
+}
+
+static START: Once = ONCE_INIT;
+
+fn single_request<T, H>(listener: &mut HttpListener, h: H) -> T
+    where H: FnOnce(Request, Response) -> T
+{
+    let mut stream = listener.accept().unwrap();
+    let addr = stream.peer_addr()
+        .expect("webhook client address");
+    let mut stream_clone = stream.clone();
+    let mut buf_read = BufReader::new(
+        &mut stream_clone as &mut NetworkStream
+    );
+    let mut buf_write = BufWriter::new(&mut stream);
+    let req = Request::new(&mut buf_read, addr)
+        .expect("webhook Request");
+    let mut head = Headers::new();
+    let res = Response::new(&mut buf_write, &mut head);
+    h(req, res)
+}
+
+fn send_webhook(url: &str, event: &str, body: &str) {
+    let http_client = Client::new();
+    let mut http_headers = Headers::new();
+    http_headers.set_raw("X-Event-Key", vec![event.as_bytes().to_vec()]);
+    http_headers.set_raw("X-Hub-Signature", vec![
+        format!("sha256={}", openssl::crypto::hmac::hmac(
+            openssl::crypto::hash::Type::SHA256,
+            "ME_SECRET_LOL".as_bytes(),
+            body.as_bytes(),
+        ).to_hex()).into_bytes()
+    ]);
+    http_client.post(url)
+        .body(body)
+        .headers(http_headers)
+        .send()
+        .unwrap();
+}
+
+fn assert_api_request(req: &Request, method: Method, path: &str) {
+    assert_eq!(req.method, method);
+    assert_eq!(req.uri, RequestUri::AbsolutePath(path.to_owned()));
+    assert_eq!(
+        &req.headers.get_raw("Authorization").unwrap()[0][..],
+        b"Bearer MY_ACCESS_TOKEN"
+    );
+}
+
+fn read_ref(path: &str) -> String {
+    let mut commit_string = String::new();
+    File::open(Path::new(path))
+        .unwrap()
+        .read_to_string(&mut commit_string)
+        .unwrap();
+    commit_string.replace("\n", "").replace("\r", "")
+}
+
+/// The pull request as Bitbucket Server describes it in webhooks.
+const PULL_REQUEST: &'static str = concat!(
+    r#" { "#,
+    r#" "id":1, "#,
+    r#" "title":"HA!", "#,
+    r#" "description":"Test", "#,
+    r#" "state":"OPEN", "#,
+    r#" "author":{"user":{"name":"testa"}}, "#,
+    r#" "fromRef":{ "#,
+        r#" "latestCommit":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+    r#" }, "#,
+    r#" "toRef":{ "#,
+        r#" "displayId":"master", "#,
+        r#" "repository":{"slug":"testp","project":{"key":"AelitaBot"}} "#,
+    r#" }, "#,
+    r#" "links":{"self":[{ "#,
+        r#" "href":"http://bitbucket.com/projects/AelitaBot/repos/testp/pull-requests/1" "#,
+    r#" }]} "#,
+    r#" } "#,
+);
+
+#[test]
+fn one_item_bitbucket_round_trip() {
+    let _lock = ONE_AT_A_TIME.lock();
+    START.call_once(|| env_logger::init().unwrap());
+
+    if !Path::new(EXECUTABLE).exists() {
+        panic!("Integration tests require the executable to be built.");
+    }
+
+    let mut bitbucket_server = HttpListener::new(&"localhost:9041").unwrap();
+    let mut jenkins_server = HttpListener::new(&"localhost:9042").unwrap();
+
+    Command::new("/bin/tar")
+        .current_dir("./tests/")
+        .arg("-xvf")
+        .arg("cache.tar.gz")
+        .output()
+        .unwrap();
+
+    info!("Bitbucket keeps the pull request under its own ref.");
+    fs::create_dir_all("tests/cache/origin/.git/refs/pull-requests/1")
+        .unwrap();
+    File::create("tests/cache/origin/.git/refs/pull-requests/1/from")
+        .unwrap()
+        .write_all(format!("{}\n", PULL_COMMIT).as_bytes())
+        .unwrap();
+
+    Command::new("/bin/rm")
+        .current_dir("./tests/")
+        .arg("db.sqlite")
+        .output()
+        .unwrap();
+
+    let executable = Path::new(EXECUTABLE).canonicalize().unwrap();
+    let mut aelita = Command::new(executable)
+        .current_dir("./tests/")
+        .arg("test-bitbucket-round-trip.toml")
+        .spawn()
+        .unwrap();
+
+    info!("Wait a sec for it to finish starting.");
+    thread::sleep(time::Duration::new(2, 0));
+
+    info!("Pull request comes into existance.");
+    send_webhook(
+        "http://localhost:9007",
+        "pr:opened",
+        &format!(r#"{{"pullRequest":{}}}"#, PULL_REQUEST),
+    );
+
+    info!("User posts comment to mark pull request reviewed.");
+    send_webhook(
+        "http://localhost:9007",
+        "pr:comment:added",
+        &format!(
+            r#"{{"pullRequest":{},"comment":{}}}"#,
+            PULL_REQUEST,
+            r#"{"text":"@AelitaBot r+","author":{"name":"testu"}}"#,
+        ),
+    );
+
+    info!("Aelita checks if user has write access to the repository.");
+    single_request(&mut bitbucket_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Get,
+            concat!(
+                "/rest/api/1.0/users?filter=testu",
+                "&permission.1=REPO_WRITE",
+                "&permission.1.projectKey=AelitaBot",
+                "&permission.1.repositorySlug=testp",
+            ),
+        );
+        *res.status_mut() = StatusCode::Ok;
+        res.send(br#"{"values":[{"name":"testu"}]}"#).unwrap();
+    });
+
+    info!("Aelita marks the pull request as approved.");
+    single_request(&mut bitbucket_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Post,
+            &format!("/rest/build-status/1.0/commits/{}", PULL_COMMIT),
+        );
+        #[derive(Deserialize)]
+        struct BuildStatusDesc {
+            state: String,
+            key: String,
+            url: String,
+        }
+        let desc: BuildStatusDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::NoContent;
+        res.send(&[]).unwrap();
+        assert_eq!("INPROGRESS", &desc.state[..]);
+        assert_eq!("continuous-integration/aelita", &desc.key[..]);
+        assert_eq!(
+            concat!(
+                "http://localhost:9041",
+                "/projects/AelitaBot/repos/testp/pull-requests/1",
+            ),
+            &desc.url[..]
+        );
+    });
+
+    info!("Aelita sends build trigger.");
+    single_request(&mut jenkins_server, |req, mut res| {
+        let path = "/job/testp/build?token=MY_BUILD_TOKEN".to_owned();
+        assert_eq!(
+            req.uri,
+            RequestUri::AbsolutePath(path)
+        );
+        assert_eq!(
+            req.headers.get::<Authorization<Basic>>().unwrap().0,
+            Basic{
+                username: "AelitaBot".to_owned(),
+                password: Some("MY_JENKINS_API_TOKEN".to_owned()),
+            }
+        );
+        *res.status_mut() = StatusCode::NoContent;
+        res.send(&[]).unwrap();
+    });
+
+    info!("Aelita merged the pull request from its Bitbucket ref.");
+    let commit_string = read_ref("tests/cache/origin/.git/refs/heads/staging");
+    let output = Command::new("git")
+        .current_dir("./tests/cache/origin/")
+        .arg("rev-list")
+        .arg("--parents")
+        .arg("-n1")
+        .arg(&commit_string)
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        format!("{} {} {}", commit_string, MASTER_COMMIT, PULL_COMMIT)
+    );
+
+    info!("Jenkins sends start notification to Aelita.");
+    let mut tcp_client = TcpStream::connect("localhost:9008").unwrap();
+    tcp_client.write(
+        r#"{"name":"testp","build":{"phase":"STARTED","full_url":"http://jenkins.com/job/1/","scm":{"commit":"CMMT"}}}"#
+            .replace("CMMT", &commit_string)
+            .as_bytes()
+    ).unwrap();
+    drop(tcp_client);
+
+    info!("Jenkins sends finished notification to Aelita.");
+    let mut tcp_client = TcpStream::connect("localhost:9008").unwrap();
+    tcp_client.write(
+        r#"{"name":"testp","build":{"phase":"COMPLETED","status":"SUCCESS","full_url":"http://jenkins.com/job/1/","scm":{"commit":"CMMT"}}}"#
+            .replace("CMMT", &commit_string)
+            .as_bytes()
+    ).unwrap();
+    drop(tcp_client);
+
+    info!("Wait a sec for it to finish pushing.");
+    thread::sleep(time::Duration::new(2, 0));
+
+    info!("Aelita fast-forwards master.");
+    let master_string = read_ref("tests/cache/origin/.git/refs/heads/master");
+    assert_eq!(commit_string, master_string);
+
+    aelita.kill().unwrap();
+}
@@ -0,0 +1,355 @@
+// This file is released under the same terms as Rust itself.
+
+//! Test the Gitea front-end and merges front-to-back,
+//! against a fake Gitea API and a fake Jenkins.
+
+#![feature(proc_macro)]
+
+extern crate env_logger;
+extern crate hex;
+extern crate hyper;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
+extern crate openssl;
+extern crate serde;
+extern crate serde_json;
+#[macro_use] extern crate serde_derive;
+
+use hex::ToHex;
+use hyper::buffer::BufReader;
+use hyper::client::Client;
+use hyper::header::{Authorization, Basic, Headers};
+use hyper::method::Method;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use std::io::{BufWriter, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Mutex, Once, ONCE_INIT};
+use std::thread;
+use std::time;
+
+const EXECUTABLE: &'static str = "target/debug/aelita";
+
+lazy_static!{
+    static ref ONE_AT_A_TIME: Mutex<()> = Mutex::new(());
+}
+
+static START: Once = ONCE_INIT;
+
+fn single_request<T, H>(listener: &mut HttpListener, h: H) -> T
+    where H: FnOnce(Request, Response) -> T
+{
+    let mut stream = listener.accept().unwrap();
+    let addr = stream.peer_addr()
+        .expect("webhook client address");
+    let mut stream_clone = stream.clone();
+    let mut buf_read = BufReader::new(
+        &mut stream_clone as &mut NetworkStream
+    );
+    let mut buf_write = BufWriter::new(&mut stream);
+    let req = Request::new(&mut buf_read, addr)
+        .expect("webhook Request");
+    let mut head = Headers::new();
+    let res = Response::new(&mut buf_write, &mut head);
+    h(req, res)
+}
+
+fn send_webhook(url: &str, event: &str, body: &str) {
+    let http_client = Client::new();
+    let mut http_headers = Headers::new();
+    http_headers.set_raw("X-Gitea-Event", vec![event.as_bytes().to_vec()]);
+    http_headers.set_raw("X-Gitea-Signature", vec![
+        openssl::crypto::hmac::hmac(
+            openssl::crypto::hash::Type::SHA256,
+            "ME_SECRET_LOL".as_bytes(),
+            body.as_bytes(),
+        ).to_hex().into_bytes()
+    ]);
+    http_client.post(url)
+        .body(body)
+        .headers(http_headers)
+        .send()
+        .unwrap();
+}
+
+fn assert_api_request(req: &Request, method: Method, path: &str) {
+    assert_eq!(req.method, method);
+    assert_eq!(
+        req.uri,
+        RequestUri::AbsolutePath(
+            format!("/api/v1/repos/AelitaBot/testp{}", path)
+        )
+    );
+    assert_eq!(
+        &req.headers.get_raw("Authorization").unwrap()[0][..],
+        b"token MY_PERSONAL_ACCESS_TOKEN"
+    );
+}
+
+#[test]
+fn one_item_gitea_round_trip() {
+    let _lock = ONE_AT_A_TIME.lock();
+    START.call_once(|| env_logger::init().unwrap());
+
+    if !Path::new(EXECUTABLE).exists() {
+        panic!("Integration tests require the executable to be built.");
+    }
+
+    Command::new("/bin/rm")
+        .current_dir("./tests/")
+        .arg("db.sqlite")
+        .output()
+        .unwrap();
+
+    let mut gitea_server = HttpListener::new(&"localhost:9031").unwrap();
+    let mut jenkins_server = HttpListener::new(&"localhost:9032").unwrap();
+    let mut gitea_git_server = HttpListener::new(&"localhost:9033").unwrap();
+
+    let executable = Path::new(EXECUTABLE).canonicalize().unwrap();
+    let mut aelita = Command::new(executable)
+        .current_dir("./tests/")
+        .arg("test-gitea-round-trip.toml")
+        .spawn()
+        .unwrap();
+
+    info!("Wait a sec for it to finish starting.");
+    thread::sleep(time::Duration::new(2, 0));
+
+    info!("Pull request comes into existance.");
+    send_webhook("http://localhost:9005", "pull_request", concat!(
+        r#" { "#,
+        r#" "action":"opened", "#,
+        r#" "repository":{"name":"testp","owner":{"login":"AelitaBot"}}, "#,
+        r#" "pull_request":{ "#,
+            r#" "number":1, "#,
+            r#" "title":"HA!", "#,
+            r#" "html_url":"http://gitea.com/AelitaBot/testp/pulls/1", "#,
+            r#" "head":{ "#,
+                r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{"ref":"master"} "#,
+        r#" } "#,
+        r#" } "#,
+    ));
+
+    info!("User posts comment to mark pull request reviewed.");
+    send_webhook("http://localhost:9005", "issue_comment", concat!(
+        r#" { "#,
+        r#" "action":"created", "#,
+        r#" "is_pull":true, "#,
+        r#" "repository":{"name":"testp","owner":{"login":"AelitaBot"}}, "#,
+        r#" "issue":{ "#,
+            r#" "number":1, "#,
+            r#" "title":"My PR!", "#,
+            r#" "body":"Test", "#,
+            r#" "state":"open", "#,
+            r#" "user":{"login":"testa"} "#,
+        r#" }, "#,
+        r#" "comment":{ "#,
+            r#" "user":{"login":"testu"}, "#,
+            r#" "body":"@AelitaBot r+" "#,
+        r#" } "#,
+        r#" } "#,
+    ));
+
+    info!("Aelita checks if user has permission to do that.");
+    single_request(&mut gitea_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Get,
+            "/collaborators/testu/permission",
+        );
+        *res.status_mut() = StatusCode::Ok;
+        res.send(br#"{"permission":"write"}"#).unwrap();
+    });
+
+    info!("Aelita looks up which branch the pull request is into.");
+    single_request(&mut gitea_server, |req, mut res| {
+        assert_api_request(&req, Method::Get, "/pulls/1");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(br#"{"number":1,"base":{"ref":"master"}}"#).unwrap();
+    });
+
+    info!("Aelita checks for the current contents of master.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Get, "/branches/master");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(
+            br#"{"commit":{"id":"aa218f56b14c9653891f9e74264a383fa43fefbd"}}"#
+        ).unwrap();
+    });
+
+    info!("Staging is already there.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Get, "/branches/staging");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(
+            br#"{"commit":{"id":"aa218f56b14c9653891f9e74264a383fa43fefbd"}}"#
+        ).unwrap();
+    });
+
+    info!("The temporary branch is not.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Get, "/branches/staging.aelita");
+        *res.status_mut() = StatusCode::NotFound;
+        res.send(&[]).unwrap();
+    });
+
+    info!("Aelita pushes the pull request to the temporary branch.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/branches");
+        #[derive(Deserialize)]
+        struct BranchCreateDesc {
+            new_branch_name: String,
+            old_ref_name: String,
+        }
+        let desc: BranchCreateDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Created;
+        res.send(&[]).unwrap();
+        assert_eq!("staging.aelita", &desc.new_branch_name[..]);
+        assert_eq!(
+            "55016813274e906e4cbfed97be83e19e6cd93d91",
+            &desc.old_ref_name[..]
+        );
+    });
+
+    info!("Aelita opens a pull request into staging.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/pulls");
+        #[derive(Deserialize)]
+        struct PullRequestCreateDesc {
+            head: String,
+            base: String,
+        }
+        let desc: PullRequestCreateDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Created;
+        res.send(br#"{"number":2,"merge_commit_sha":null}"#).unwrap();
+        assert_eq!("staging.aelita", &desc.head[..]);
+        assert_eq!("staging", &desc.base[..]);
+    });
+
+    info!("Aelita merges it.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/pulls/2/merge");
+        #[derive(Deserialize)]
+        struct MergeDesc {
+            #[serde(rename="Do")]
+            merge_style: String,
+            #[serde(rename="MergeMessageField")]
+            message: String,
+            head_commit_id: String,
+        }
+        let desc: MergeDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(&[]).unwrap();
+        assert_eq!("merge", &desc.merge_style[..]);
+        assert_eq!(
+            "55016813274e906e4cbfed97be83e19e6cd93d91",
+            &desc.head_commit_id[..]
+        );
+        assert!(desc.message.contains("a=@testa r=@testu"));
+    });
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Get, "/pulls/2");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(concat!(r#" { "#,
+            r#" "number":2, "#,
+            r#" "merge_commit_sha": "#,
+                r#" "ba218f56b14c9653891f9e74264a383fa43fefbd" "#,
+            r#" } "#).as_bytes()).unwrap();
+    });
+
+    info!("Aelita sends build trigger.");
+    single_request(&mut jenkins_server, |req, mut res| {
+        let path = "/job/testp/build?token=MY_BUILD_TOKEN".to_owned();
+        assert_eq!(
+            req.uri,
+            RequestUri::AbsolutePath(path)
+        );
+        assert_eq!(
+            req.headers.get::<Authorization<Basic>>().unwrap().0,
+            Basic{
+                username: "AelitaBot".to_owned(),
+                password: Some("MY_JENKINS_API_TOKEN".to_owned()),
+            }
+        );
+        *res.status_mut() = StatusCode::NoContent;
+        res.send(&[]).unwrap();
+    });
+
+    info!("Jenkins sends start notification to Aelita.");
+    let mut tcp_client = TcpStream::connect("localhost:9006").unwrap();
+    tcp_client.write(
+        br#"{"name":"testp","build":{"phase":"STARTED","full_url":"http://jenkins.com/job/1/","scm":{"commit":"ba218f56b14c9653891f9e74264a383fa43fefbd"}}}"#
+    ).unwrap();
+    drop(tcp_client);
+
+    info!("Jenkins sends finished notification to Aelita.");
+    let mut tcp_client = TcpStream::connect("localhost:9006").unwrap();
+    tcp_client.write(
+        br#"{"name":"testp","build":{"phase":"COMPLETED","status":"SUCCESS","full_url":"http://jenkins.com/job/1/","scm":{"commit":"ba218f56b14c9653891f9e74264a383fa43fefbd"}}}"#
+    ).unwrap();
+    drop(tcp_client);
+
+    info!("Aelita pushes the tested commit to a temporary branch.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Get, "/branches/master.aelita");
+        *res.status_mut() = StatusCode::NotFound;
+        res.send(&[]).unwrap();
+    });
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/branches");
+        *res.status_mut() = StatusCode::Created;
+        res.send(&[]).unwrap();
+    });
+
+    info!("Aelita fast-forwards master to it.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/pulls");
+        #[derive(Deserialize)]
+        struct PullRequestCreateDesc {
+            head: String,
+            base: String,
+        }
+        let desc: PullRequestCreateDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Created;
+        res.send(br#"{"number":3,"merge_commit_sha":null}"#).unwrap();
+        assert_eq!("master.aelita", &desc.head[..]);
+        assert_eq!("master", &desc.base[..]);
+    });
+    let master_string = single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/pulls/3/merge");
+        #[derive(Deserialize)]
+        struct MergeDesc {
+            #[serde(rename="Do")]
+            merge_style: String,
+            head_commit_id: String,
+        }
+        let desc: MergeDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(&[]).unwrap();
+        assert_eq!("fast-forward-only", &desc.merge_style[..]);
+        desc.head_commit_id
+    });
+    assert_eq!("ba218f56b14c9653891f9e74264a383fa43fefbd", master_string);
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Get, "/pulls/3");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(concat!(r#" { "#,
+            r#" "number":3, "#,
+            r#" "merge_commit_sha": "#,
+                r#" "ba218f56b14c9653891f9e74264a383fa43fefbd" "#,
+            r#" } "#).as_bytes()).unwrap();
+    });
+
+    aelita.kill().unwrap();
+}
@@ -120,6 +120,9 @@ fn one_item_github_round_trip() {
             r#" "number":1, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master" "#,
             r#" } "#,
         r#" } "#,
     r#" } "#);
@@ -337,6 +340,9 @@ fn one_item_team_github_round_trip() {
             r#" "number":1, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master" "#,
             r#" } "#,
         r#" } "#,
     r#" } "#);
@@ -575,6 +581,9 @@ fn one_item_github_round_trip_status() {
             r#" "number":1, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master" "#,
             r#" } "#,
         r#" } "#,
     r#" } "#);
@@ -797,6 +806,9 @@ fn one_item_github_round_trip_cloud() {
             r#" "number":1, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master" "#,
             r#" } "#,
         r#" } "#,
     r#" } "#);
@@ -1080,6 +1092,9 @@ fn one_item_github_round_trip_cloud_no_staging() {
             r#" "number":1, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master" "#,
             r#" } "#,
         r#" } "#,
     r#" } "#);
@@ -1430,6 +1445,9 @@ fn one_item_team_github_round_trip_with_postgres() {
             r#" "number":1, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master" "#,
             r#" } "#,
         r#" } "#,
     r#" } "#);
@@ -1701,6 +1719,8 @@ fn one_item_github_round_trip_cloud_with_postgres_12f() {
              "MY_PERSONAL_ACCESS_TOKEN")
         .env("AELITA_UI_GITHUB_SECRET",
              "ME_SECRET_LOL")
+        .env("AELITA_UI_GITHUB_SYNC_INTERVAL",
+             "0")
         .env("AELITA_CI_TYPE",
              "github_status")
         .env("AELITA_CI_GITHUB_LISTEN",
@@ -1777,6 +1797,9 @@ fn one_item_github_round_trip_cloud_with_postgres_12f() {
             r#" "number":1, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master" "#,
             r#" } "#,
         r#" } "#,
     r#" } "#);
@@ -2103,6 +2126,8 @@ fn one_item_github_round_trip_cloud_with_sqlite_12f() {
              "MY_PERSONAL_ACCESS_TOKEN")
         .env("AELITA_UI_GITHUB_SECRET",
              "ME_SECRET_LOL")
+        .env("AELITA_UI_GITHUB_SYNC_INTERVAL",
+             "0")
         .env("AELITA_CI_TYPE",
              "github_status")
         .env("AELITA_CI_GITHUB_LISTEN",
@@ -2176,6 +2201,9 @@ fn one_item_github_round_trip_cloud_with_sqlite_12f() {
             r#" "number":1, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master" "#,
             r#" } "#,
         r#" } "#,
     r#" } "#);
@@ -2437,6 +2465,165 @@ fn one_item_github_round_trip_cloud_with_sqlite_12f() {
     aelita.kill().unwrap();
 }
 
+/// Post a signed `@AelitaBot r+` from testu on a PR of AelitaBot/testp.
+fn post_approve_comment(number: u32) {
+    let http_client = Client::new();
+    let mut http_headers = Headers::new();
+    http_headers.set_raw("X-Github-Event", vec![b"issue_comment".to_vec()]);
+    let body = concat!(r#" { "#,
+        r#" "issue":{ "#,
+            r#" "number":NUMBER, "#,
+            r#" "title":"My PR!", "#,
+            r#" "body":"Test", "#,
+            r#" "pull_request":{ "#,
+                r#" "html_url":"http://github.com/testu/testp/pull_request/NUMBER" "#,
+            r#" }, "#,
+            r#" "state":"opened", "#,
+            r#" "user":{ "#,
+                r#" "login":"testu", "#,
+                r#" "type":"User" "#,
+            r#" } "#,
+        r#" }, "#,
+        r#" "comment":{ "#,
+            r#" "user":{ "#,
+                r#" "login":"testu", "#,
+                r#" "type":"User" "#,
+            r#" }, "#,
+            r#" "body":"@AelitaBot r+" "#,
+        r#" }, "#,
+        r#" "repository":{ "#,
+            r#" "name":"testp", "#,
+            r#" "owner":{ "#,
+                r#" "login":"AelitaBot", "#,
+                r#" "type":"User" "#,
+            r#" } "#,
+        r#" } "#,
+    r#" } "#).replace("NUMBER", &number.to_string());
+    http_headers.set_raw("X-Hub-Signature", vec![
+        format!("sha1={}", openssl::crypto::hmac::hmac(
+            openssl::crypto::hash::Type::SHA1,
+            "ME_SECRET_LOL".as_bytes(),
+            body.as_bytes(),
+        ).to_hex()).into_bytes()
+    ]);
+    http_client.post("http://localhost:9001")
+        .body(body.as_bytes())
+        .headers(http_headers)
+        .send()
+        .unwrap();
+}
+
+#[test]
+fn unconfigured_branch_github_round_trip() {
+    let _lock = ONE_AT_A_TIME.lock();
+    START.call_once(|| env_logger::init().unwrap());
+
+    if !Path::new(EXECUTABLE).exists() {
+        panic!("Integration tests require the executable to be built.");
+    }
+
+    let mut github_server = HttpListener::new(&"localhost:9011").unwrap();
+    let _jenkins_server = HttpListener::new(&"localhost:9012").unwrap();
+
+    Command::new("/bin/rm")
+        .current_dir("./tests/")
+        .arg("db.sqlite")
+        .output()
+        .unwrap();
+
+    let executable = Path::new(EXECUTABLE).canonicalize().unwrap();
+    let mut aelita = Command::new(executable)
+        .current_dir("./tests/")
+        .arg("test-github-round-trip.toml")
+        .spawn()
+        .unwrap();
+
+    info!("Wait a sec for it to finish starting.");
+    thread::sleep(time::Duration::new(2, 0));
+
+    info!("Pull request against a release branch comes into existance.");
+    let http_client = Client::new();
+    let mut http_headers = Headers::new();
+    http_headers.set_raw("X-Github-Event", vec![b"pull_request".to_vec()]);
+    let body = concat!(r#" { "#,
+        r#" "action":"opened", "#,
+        r#" "repository":{ "#,
+            r#" "name":"testp", "#,
+            r#" "owner":{"login":"AelitaBot","type":"User"} "#,
+        r#" }, "#,
+        r#" "pull_request":{ "#,
+            r#" "title":"HA!", "#,
+            r#" "html_url":"http://github.com/testu/testp/pull_request/1", "#,
+            r#" "state":"opened", "#,
+            r#" "number":1, "#,
+            r#" "head":{ "#,
+                r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"release-1.x" "#,
+            r#" } "#,
+        r#" } "#,
+    r#" } "#);
+    http_headers.set_raw("X-Hub-Signature", vec![
+        format!("sha1={}", openssl::crypto::hmac::hmac(
+            openssl::crypto::hash::Type::SHA1,
+            "ME_SECRET_LOL".as_bytes(),
+            body.as_bytes(),
+        ).to_hex()).into_bytes()
+    ]);
+    http_client.post("http://localhost:9001")
+        .body(body.as_bytes())
+        .headers(http_headers)
+        .send()
+        .unwrap();
+
+    info!("User approves it. Aelita knows its branch, and ignores it.");
+    post_approve_comment(1);
+
+    info!("User approves a PR that Aelita has not heard of.");
+    post_approve_comment(2);
+
+    info!("Aelita asks which branch it is against.");
+    single_request(&mut github_server, |req, mut res| {
+        assert_eq!(
+            req.uri,
+            RequestUri::AbsolutePath(
+                "/repos/AelitaBot/testp/pulls/2".to_owned()
+            )
+        );
+        *res.status_mut() = StatusCode::Ok;
+        res.send(concat!(r#" { "#,
+            r#" "title":"HA!", "#,
+            r#" "html_url":"http://github.com/testu/testp/pull_request/2", "#,
+            r#" "state":"open", "#,
+            r#" "number":2, "#,
+            r#" "head":{ "#,
+                r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" }, "#,
+            r#" "base":{ "#,
+                r#" "ref":"release-1.x" "#,
+            r#" } "#,
+        r#" } "#).as_bytes()).unwrap();
+    });
+
+    info!("User approves a third one.");
+    post_approve_comment(3);
+
+    info!("Neither of the others got as far as a permission check.");
+    single_request(&mut github_server, |req, mut res| {
+        assert_eq!(
+            req.uri,
+            RequestUri::AbsolutePath(
+                "/repos/AelitaBot/testp/pulls/3".to_owned()
+            )
+        );
+        *res.status_mut() = StatusCode::NotFound;
+        res.send(&[]).unwrap();
+    });
+
+    aelita.kill().unwrap();
+}
+
 #[test]
 fn test_null_body() {
     let _lock = ONE_AT_A_TIME.lock();